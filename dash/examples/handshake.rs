@@ -1,18 +1,19 @@
 extern crate dashcore;
 
-use std::io::{BufReader, Write};
-use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpStream};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{env, process};
 
-use dashcore::consensus::{Decodable, encode};
-use dashcore::network::{address, constants, message, message_network};
+use dashcore::network::address::Address;
+use dashcore::network::constants::{Network, ServiceFlags};
+use dashcore::network::peer::{Peer, PeerConfig, PeerEvent, PeerOutput};
 use dashcore::secp256k1;
 use dashcore::secp256k1::rand::Rng;
 
 fn main() {
-    // This example establishes a connection to a Bitcoin node, sends the intial
-    // "version" message, waits for the reply, and finally closes the connection.
+    // This example connects to a Dash node, lets the `Peer` state machine perform the
+    // "version"/"verack" handshake over a `TcpStream`, and closes the connection once connected.
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         eprintln!("not enough arguments");
@@ -26,85 +27,62 @@ fn main() {
         process::exit(1);
     });
 
-    let version_message = build_version_message(address);
-
-    let first_message = message::RawNetworkMessage {
-        magic: constants::Network::Dash.magic(),
-        payload: version_message,
-    };
-
-    if let Ok(mut stream) = TcpStream::connect(address) {
-        // Send the message
-        let _ = stream.write_all(encode::serialize(&first_message).as_slice());
-        println!("Sent version message");
+    let mut stream = TcpStream::connect(address).unwrap_or_else(|error| {
+        eprintln!("Failed to open connection: {}", error);
+        process::exit(1);
+    });
+    stream.set_read_timeout(Some(Duration::from_secs(1))).expect("non-zero timeout");
 
-        // Setup StreamReader
-        let read_stream = stream.try_clone().unwrap();
-        let mut stream_reader = BufReader::new(read_stream);
-        loop {
-            // Loop an retrieve new messages
-            let reply = message::RawNetworkMessage::consensus_decode(&mut stream_reader).unwrap();
-            match reply.payload {
-                message::NetworkMessage::Version(_) => {
-                    println!("Received version message: {:?}", reply.payload);
+    let mut config = PeerConfig::new(Network::Dash);
+    config.user_agent = String::from("/rust-example/");
+    // Random nonce, used to detect connections to ourselves.
+    let nonce: u64 = secp256k1::rand::thread_rng().gen();
+    let remote = Address::new(&address, ServiceFlags::NONE);
 
-                    let second_message = message::RawNetworkMessage {
-                        magic: constants::Network::Dash.magic(),
-                        payload: message::NetworkMessage::Verack,
-                    };
+    let (mut peer, output) = Peer::outbound(config, remote, nonce, now());
+    let mut connected = handle_output(&mut stream, output);
 
-                    let _ = stream.write_all(encode::serialize(&second_message).as_slice());
-                    println!("Sent verack message");
-                }
-                message::NetworkMessage::Verack => {
-                    println!("Received verack message: {:?}", reply.payload);
-                    break;
-                }
-                _ => {
-                    println!("Received unknown message: {:?}", reply.payload);
-                    break;
-                }
+    let mut buf = [0u8; 4096];
+    while !connected {
+        let output = match stream.read(&mut buf) {
+            Ok(0) => {
+                println!("Connection closed by peer");
+                break;
             }
+            Ok(n) => peer.receive(&buf[..n], now()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                peer.tick(now()),
+            Err(e) => {
+                eprintln!("Read error: {}", e);
+                break;
+            }
+        };
+        connected = handle_output(&mut stream, output);
+        if peer.disconnect_reason().is_some() {
+            break;
         }
-        let _ = stream.shutdown(Shutdown::Both);
-    } else {
-        eprintln!("Failed to open connection");
     }
+    let _ = stream.shutdown(Shutdown::Both);
 }
 
-fn build_version_message(address: SocketAddr) -> message::NetworkMessage {
-    // Building version message, see https://en.bitcoin.it/wiki/Protocol_documentation#version
-    let my_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
-
-    // "bitfield of features to be enabled for this connection"
-    let services = constants::ServiceFlags::NONE;
-
-    // "standard UNIX timestamp in seconds"
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time error").as_secs();
-
-    // "The network address of the node receiving this message"
-    let addr_recv = address::Address::new(&address, constants::ServiceFlags::NONE);
-
-    // "The network address of the node emitting this message"
-    let addr_from = address::Address::new(&my_address, constants::ServiceFlags::NONE);
-
-    // "Node random nonce, randomly generated every time a version packet is sent. This nonce is used to detect connections to self."
-    let nonce: u64 = secp256k1::rand::thread_rng().gen();
-
-    // "User Agent (0x00 if string is 0 bytes long)"
-    let user_agent = String::from("rust-example");
-
-    // "The last block received by the emitting node"
-    let start_height: i32 = 0;
-
-    // Construct the message
-    message::NetworkMessage::Version(message_network::VersionMessage::new(
-        services,
-        timestamp as i64,
-        addr_recv,
-        addr_from,
-        nonce,
-        user_agent,
-        start_height,
-    ))
+/// Writes the peer's output to the stream and prints its events; returns whether connected.
+fn handle_output(stream: &mut TcpStream, output: PeerOutput) -> bool {
+    for message in &output.messages {
+        println!("Sending {} message", message.cmd());
+    }
+    let _ = stream.write_all(&output.to_bytes());
+    let mut connected = false;
+    for event in output.events {
+        match event {
+            PeerEvent::Connected => {
+                println!("Handshake complete");
+                connected = true;
+            }
+            PeerEvent::Disconnected(reason) => println!("Disconnected: {}", reason),
+            event => println!("Received {:?}", event),
+        }
+    }
+    connected
 }
+
+fn now() -> Duration { SystemTime::now().duration_since(UNIX_EPOCH).expect("Time error") }
@@ -14,6 +14,7 @@ use hashes::{Hash, sha256, siphash24};
 use internals::impl_array_newtype;
 
 use crate::consensus::encode::{self, Decodable, Encodable, VarInt};
+use crate::consensus::validation::MAX_DIP0001_BLOCK_SIZE;
 use crate::internal_macros::{impl_bytes_newtype, impl_consensus_encoding};
 use crate::prelude::*;
 use crate::{Block, BlockHash, Transaction, block, io};
@@ -369,6 +370,211 @@ impl BlockTransactions {
     }
 }
 
+/// The size of the smallest serializable transaction, an empty one.
+const MIN_TRANSACTION_SIZE: usize = 10;
+
+/// An error reconstructing a block from a compact block.
+#[derive(Clone, PartialEq, Eq, Debug, Copy, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ReconstructionError {
+    /// The compact block has no transactions, or more than fit in a block.
+    InvalidTransactionCount,
+    /// A prefilled transaction index is out of range.
+    InvalidPrefill,
+    /// Two short IDs of the compact block are equal; the full block must be requested.
+    ShortIdCollision,
+    /// The [`BlockTransactions`] are for another block.
+    WrongBlock,
+    /// The [`BlockTransactions`] do not have as many transactions as were requested.
+    WrongTransactionCount {
+        /// The number of requested transactions.
+        expected: usize,
+        /// The number of provided transactions.
+        actual: usize,
+    },
+    /// The merkle root of the reconstructed block does not match its header; a mempool
+    /// transaction may have collided with a short ID, so the full block must be requested.
+    MerkleRootMismatch,
+}
+
+impl fmt::Display for ReconstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReconstructionError::InvalidTransactionCount =>
+                write!(f, "invalid number of transactions in compact block"),
+            ReconstructionError::InvalidPrefill =>
+                write!(f, "prefilled transaction index out of range"),
+            ReconstructionError::ShortIdCollision =>
+                write!(f, "duplicate short transaction ID in compact block"),
+            ReconstructionError::WrongBlock => write!(f, "block transactions for another block"),
+            ReconstructionError::WrongTransactionCount { expected, actual } =>
+                write!(f, "got {} block transactions, expected {}", actual, expected),
+            ReconstructionError::MerkleRootMismatch =>
+                write!(f, "reconstructed block does not match its merkle root"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReconstructionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Reconstructs a block from a [`HeaderAndShortIds`] and the transactions of our mempool.
+///
+/// This is the receiving side of compact block relay. The transactions of the block that are
+/// neither prefilled nor found in the mempool by their short ID are requested with
+/// [`request`](CompactBlockReconstructor::request), and the block is completed with the
+/// [`BlockTransactions`] response by [`fill`](CompactBlockReconstructor::fill).
+///
+/// When two mempool transactions have the short ID of a block transaction, neither is used and
+/// the transaction is requested instead.
+#[derive(Clone, Debug)]
+pub struct CompactBlockReconstructor {
+    header: block::Header,
+    /// The transactions of the block found so far, by index.
+    txs: Vec<Option<Transaction>>,
+    /// Indexes of the short IDs by short ID.
+    short_ids: BTreeMap<ShortId, usize>,
+    siphash_keys: (u64, u64),
+    /// Whether a mempool transaction was found for each index, even if it later collided.
+    seen: Vec<bool>,
+    collisions: usize,
+}
+
+impl CompactBlockReconstructor {
+    /// Creates a reconstructor for `compact`, filling in the transactions of `mempool`.
+    ///
+    /// Short IDs are computed from txids, as Dash compact blocks have version 1.
+    pub fn new<I, T>(
+        compact: &HeaderAndShortIds,
+        mempool: I,
+    ) -> Result<CompactBlockReconstructor, ReconstructionError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Borrow<Transaction>,
+    {
+        let tx_count = compact.short_ids.len() + compact.prefilled_txs.len();
+        if tx_count == 0 || tx_count > MAX_DIP0001_BLOCK_SIZE / MIN_TRANSACTION_SIZE {
+            return Err(ReconstructionError::InvalidTransactionCount);
+        }
+
+        let mut txs = vec![None; tx_count];
+        let mut next_idx = 0;
+        for prefilled in &compact.prefilled_txs {
+            let idx = next_idx + usize::from(prefilled.idx);
+            if idx >= tx_count {
+                return Err(ReconstructionError::InvalidPrefill);
+            }
+            txs[idx] = Some(prefilled.tx.clone());
+            next_idx = idx + 1;
+        }
+
+        let mut short_ids = BTreeMap::new();
+        let mut short_id_iter = compact.short_ids.iter();
+        for (idx, tx) in txs.iter().enumerate() {
+            if tx.is_none() {
+                let short_id = short_id_iter.next().expect("one short ID per missing tx");
+                if short_ids.insert(*short_id, idx).is_some() {
+                    return Err(ReconstructionError::ShortIdCollision);
+                }
+            }
+        }
+
+        let mut reconstructor = CompactBlockReconstructor {
+            header: compact.header,
+            seen: vec![false; tx_count],
+            txs,
+            short_ids,
+            siphash_keys: ShortId::calculate_siphash_keys(&compact.header, compact.nonce),
+            collisions: 0,
+        };
+        reconstructor.add_transactions(mempool);
+        Ok(reconstructor)
+    }
+
+    /// Fills in more candidate transactions, for example recently rejected ones.
+    pub fn add_transactions<I, T>(&mut self, txs: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: Borrow<Transaction>,
+    {
+        for tx in txs {
+            let tx = tx.borrow();
+            let short_id = ShortId::with_siphash_keys(&tx.txid().to_raw_hash(), self.siphash_keys);
+            let idx = match self.short_ids.get(&short_id) {
+                Some(idx) => *idx,
+                None => continue,
+            };
+            if !self.seen[idx] {
+                self.seen[idx] = true;
+                self.txs[idx] = Some(tx.clone());
+            } else if self.txs[idx].as_ref().is_some_and(|known| known.txid() != tx.txid()) {
+                self.txs[idx] = None;
+                self.collisions += 1;
+            }
+        }
+    }
+
+    /// Returns the hash of the block being reconstructed.
+    pub fn block_hash(&self) -> BlockHash { self.header.block_hash() }
+
+    /// Returns the number of short IDs that matched several mempool transactions.
+    pub fn collisions(&self) -> usize { self.collisions }
+
+    /// Returns the indexes of the transactions still missing.
+    pub fn missing_indexes(&self) -> Vec<u64> {
+        self.txs.iter().enumerate().filter(|(_, tx)| tx.is_none()).map(|(i, _)| i as u64).collect()
+    }
+
+    /// Returns whether all transactions of the block are known.
+    pub fn is_complete(&self) -> bool { self.txs.iter().all(Option::is_some) }
+
+    /// Returns the `getblocktxn` request for the missing transactions, if any.
+    pub fn request(&self) -> Option<BlockTransactionsRequest> {
+        let indexes = self.missing_indexes();
+        if indexes.is_empty() {
+            return None;
+        }
+        Some(BlockTransactionsRequest { block_hash: self.block_hash(), indexes })
+    }
+
+    /// Returns the block if no transactions are missing, verifying its merkle root.
+    pub fn block(&self) -> Result<Block, ReconstructionError> { self.assemble(&[]) }
+
+    /// Completes the block with the requested transactions, verifying its merkle root.
+    pub fn fill(&self, response: &BlockTransactions) -> Result<Block, ReconstructionError> {
+        if response.block_hash != self.block_hash() {
+            return Err(ReconstructionError::WrongBlock);
+        }
+        self.assemble(&response.transactions)
+    }
+
+    fn assemble(&self, missing: &[Transaction]) -> Result<Block, ReconstructionError> {
+        let expected = self.txs.iter().filter(|tx| tx.is_none()).count();
+        if missing.len() != expected {
+            return Err(ReconstructionError::WrongTransactionCount {
+                expected,
+                actual: missing.len(),
+            });
+        }
+        let mut missing = missing.iter();
+        let txdata = self
+            .txs
+            .iter()
+            .map(|tx| match tx {
+                Some(tx) => tx.clone(),
+                None => missing.next().expect("counted").clone(),
+            })
+            .collect();
+        let block = Block { header: self.header, txdata };
+        if !block.check_merkle_root() {
+            return Err(ReconstructionError::MerkleRootMismatch);
+        }
+        Ok(block)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use hashes::hex::FromHex;
@@ -434,6 +640,78 @@ mod test {
         assert_eq!(idxs, vec![0, 1]);
     }
 
+    #[test]
+    fn test_reconstruct_block() {
+        let mut block = dummy_block();
+        block.txdata.push(dummy_tx(&[5]));
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        let compact = HeaderAndShortIds::from_block(&block, 42, 1, &[2]).unwrap();
+
+        // Everything is in the mempool, in any order, along with unrelated transactions.
+        let mempool = [&block.txdata[3], &block.txdata[1], &dummy_tx(&[6])];
+        let reconstructor = CompactBlockReconstructor::new(&compact, mempool).unwrap();
+        assert!(reconstructor.is_complete());
+        assert_eq!(reconstructor.request(), None);
+        assert_eq!(reconstructor.block(), Ok(block.clone()));
+
+        // Missing transactions are requested.
+        let reconstructor = CompactBlockReconstructor::new(&compact, [&block.txdata[3]]).unwrap();
+        assert_eq!(reconstructor.block_hash(), block.block_hash());
+        let request = reconstructor.request().unwrap();
+        assert_eq!(request.indexes, vec![1]);
+        assert_eq!(
+            reconstructor.block(),
+            Err(ReconstructionError::WrongTransactionCount { expected: 1, actual: 0 })
+        );
+        let response = BlockTransactions::from_request(&request, &block).unwrap();
+        assert_eq!(reconstructor.fill(&response), Ok(block.clone()));
+
+        let wrong_block = BlockTransactions { block_hash: BlockHash::all_zeros(), ..response };
+        assert_eq!(reconstructor.fill(&wrong_block), Err(ReconstructionError::WrongBlock));
+        let wrong_tx = BlockTransactions {
+            block_hash: block.block_hash(),
+            transactions: vec![dummy_tx(&[7])],
+        };
+        assert_eq!(reconstructor.fill(&wrong_tx), Err(ReconstructionError::MerkleRootMismatch));
+    }
+
+    #[test]
+    fn test_reconstruct_collisions() {
+        let mut block = dummy_block();
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        let compact = HeaderAndShortIds::from_block(&block, 42, 1, &[]).unwrap();
+
+        let mut reconstructor =
+            CompactBlockReconstructor::new(&compact, [&block.txdata[1]]).unwrap();
+        assert_eq!(reconstructor.missing_indexes(), vec![2]);
+        // Pretend another transaction has the short ID of transaction 1.
+        let other = dummy_tx(&[9]);
+        let short_id =
+            ShortId::with_siphash_keys(&other.txid().to_raw_hash(), reconstructor.siphash_keys);
+        reconstructor.short_ids.insert(short_id, 1);
+        reconstructor.add_transactions([&other, &block.txdata[1], &block.txdata[2]]);
+        assert_eq!(reconstructor.collisions(), 1);
+        assert_eq!(reconstructor.missing_indexes(), vec![1]);
+
+        let mut duplicate = compact.clone();
+        duplicate.short_ids[1] = duplicate.short_ids[0];
+        assert_eq!(
+            CompactBlockReconstructor::new(&duplicate, [&block.txdata[1]]).unwrap_err(),
+            ReconstructionError::ShortIdCollision
+        );
+        let mut invalid = compact.clone();
+        invalid.prefilled_txs[0].idx = 3;
+        assert_eq!(
+            CompactBlockReconstructor::new(&invalid, [&block.txdata[1]]).unwrap_err(),
+            ReconstructionError::InvalidPrefill
+        );
+        let empty = HeaderAndShortIds { short_ids: vec![], prefilled_txs: vec![], ..compact };
+        assert_eq!(
+            CompactBlockReconstructor::new(&empty, [&block.txdata[1]]).unwrap_err(),
+            ReconstructionError::InvalidTransactionCount
+        );
+    }
+
     #[test]
     fn test_compact_block_vector() {
         // Tested with Elements implementation of compact blocks.
@@ -48,8 +48,9 @@ use internals::write_err;
 
 use crate::blockdata::block::Block;
 use crate::blockdata::script::Script;
-use crate::blockdata::transaction::OutPoint;
-use crate::consensus::encode::VarInt;
+use crate::blockdata::transaction::special_transaction::TransactionPayload;
+use crate::blockdata::transaction::{OutPoint, Transaction};
+use crate::consensus::encode::{VarInt, serialize};
 use crate::consensus::{Decodable, Encodable};
 use crate::hash_types::{BlockHash, FilterHash, FilterHeader};
 use crate::io;
@@ -131,6 +132,28 @@ impl BlockFilter {
         Ok(BlockFilter { content: out })
     }
 
+    /// Computes a BASIC filter the way Dash Core does, see [`new_script_filter`] and
+    /// [`BlockFilterWriter::add_special_transaction_elements`].
+    ///
+    /// This is the filter Dash Core serves in `cfilter` messages.
+    ///
+    /// [`new_script_filter`]: BlockFilter::new_script_filter
+    pub fn new_basic_filter<M, S>(block: &Block, script_for_coin: M) -> Result<BlockFilter, Error>
+    where
+        M: Fn(&OutPoint) -> Result<S, Error>,
+        S: Borrow<Script>,
+    {
+        let mut out = Vec::new();
+        let mut writer = BlockFilterWriter::new(&mut out, block);
+
+        writer.add_output_scripts();
+        writer.add_special_transaction_elements();
+        writer.add_input_scripts(script_for_coin)?;
+        writer.finish()?;
+
+        Ok(BlockFilter { content: out })
+    }
+
     /// Computes this filter's ID in a chain of filters (see [BIP 157]).
     ///
     /// [BIP 157]: <https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki#Filter_Headers>
@@ -176,18 +199,38 @@ impl<'a, W: io::Write> BlockFilterWriter<'a, W> {
         BlockFilterWriter { block, writer }
     }
 
-    /// Adds output scripts of the block to filter (excluding OP_RETURN scripts).
+    /// Adds output scripts of the block to filter (excluding empty and OP_RETURN scripts).
     pub fn add_output_scripts(&mut self) {
         for transaction in &self.block.txdata {
             for output in &transaction.output {
-                if !output.script_pubkey.is_op_return() {
+                if !output.script_pubkey.is_empty() && !output.script_pubkey.is_op_return() {
                     self.add_element(output.script_pubkey.as_bytes());
                 }
             }
         }
     }
 
-    /// Adds consumed output scripts of a block to filter.
+    /// Adds the data of special transaction payloads that Dash Core adds to its filters.
+    ///
+    /// These are the same fields that Dash Core matches against bloom filters:
+    ///
+    /// * ProRegTx: the serialized collateral outpoint, the owner and voting key hashes and the
+    ///   payout script,
+    /// * ProUpServTx: the proTxHash and the operator payout script,
+    /// * ProUpRegTx: the proTxHash, the voting key hash and the payout script,
+    /// * ProUpRevTx: the proTxHash,
+    /// * AssetLockTx: the credit output scripts (excluding OP_RETURN scripts).
+    ///
+    /// Empty scripts are left out, as for outputs and inputs.
+    pub fn add_special_transaction_elements(&mut self) {
+        for transaction in &self.block.txdata {
+            for element in special_transaction_elements(transaction) {
+                self.writer.add_element(&element);
+            }
+        }
+    }
+
+    /// Adds consumed output scripts of a block to filter (excluding empty scripts).
     pub fn add_input_scripts<M, S>(&mut self, script_for_coin: M) -> Result<(), Error>
     where
         M: Fn(&OutPoint) -> Result<S, Error>,
@@ -202,6 +245,7 @@ impl<'a, W: io::Write> BlockFilterWriter<'a, W> {
             .map(script_for_coin)
         {
             match script {
+                Ok(script) if script.borrow().is_empty() => {}
                 Ok(script) => self.add_element(script.borrow().as_bytes()),
                 Err(e) => return Err(e),
             }
@@ -216,6 +260,37 @@ impl<'a, W: io::Write> BlockFilterWriter<'a, W> {
     pub fn finish(&mut self) -> Result<usize, io::Error> { self.writer.finish() }
 }
 
+/// Returns the elements of a special transaction payload that Dash Core adds to its filters, see
+/// [`BlockFilterWriter::add_special_transaction_elements`].
+pub(crate) fn special_transaction_elements(transaction: &Transaction) -> Vec<Vec<u8>> {
+    let mut elements = match transaction.special_transaction_payload {
+        Some(TransactionPayload::ProviderRegistrationPayloadType(ref payload)) => vec![
+            serialize(&payload.collateral_outpoint),
+            payload.owner_key_hash.to_byte_array().to_vec(),
+            payload.voting_key_hash.to_byte_array().to_vec(),
+            payload.script_payout.to_bytes(),
+        ],
+        Some(TransactionPayload::ProviderUpdateServicePayloadType(ref payload)) =>
+            vec![payload.pro_tx_hash.to_byte_array().to_vec(), payload.script_payout.to_bytes()],
+        Some(TransactionPayload::ProviderUpdateRegistrarPayloadType(ref payload)) => vec![
+            payload.pro_tx_hash.to_byte_array().to_vec(),
+            payload.voting_key_hash.to_byte_array().to_vec(),
+            payload.script_payout.to_bytes(),
+        ],
+        Some(TransactionPayload::ProviderUpdateRevocationPayloadType(ref payload)) =>
+            vec![payload.pro_tx_hash.to_byte_array().to_vec()],
+        Some(TransactionPayload::AssetLockPayloadType(ref payload)) => payload
+            .credit_outputs
+            .iter()
+            .filter(|output| !output.script_pubkey.is_op_return())
+            .map(|output| output.script_pubkey.to_bytes())
+            .collect(),
+        _ => Vec::new(),
+    };
+    elements.retain(|element| !element.is_empty());
+    elements
+}
+
 /// Reads and interprets a block filter.
 pub struct BlockFilterReader {
     reader: GcsFilterReader,
@@ -630,6 +705,127 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_basic_filter_special_transactions() {
+        use crate::blockdata::block::{self, Version};
+        use crate::blockdata::transaction::special_transaction::asset_lock::AssetLockPayload;
+        use crate::blockdata::transaction::special_transaction::provider_update_service::ProviderUpdateServicePayload;
+        use crate::bls_sig_utils::BLSSignature;
+        use crate::hash_types::{InputsHash, PubkeyHash, TxMerkleNode, Txid};
+        use crate::pow::CompactTarget;
+        use crate::{Transaction, TxIn, TxOut};
+
+        let payout = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([3; 20]));
+        let credit = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([4; 20]));
+        let pro_tx_hash = Txid::from_byte_array([9; 32]);
+        let special_tx = |payload| Transaction {
+            version: 3,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+            special_transaction_payload: Some(payload),
+        };
+        let block = Block {
+            header: block::Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::hash(&[0]),
+                merkle_root: TxMerkleNode::hash(&[1]),
+                time: 2,
+                bits: CompactTarget::from_consensus(3),
+                nonce: 4,
+            },
+            txdata: vec![
+                special_tx(TransactionPayload::ProviderUpdateServicePayloadType(
+                    ProviderUpdateServicePayload {
+                        version: 1,
+                        pro_tx_hash,
+                        ip_address: 0,
+                        port: 9999,
+                        script_payout: payout.clone(),
+                        inputs_hash: InputsHash::all_zeros(),
+                        payload_sig: BLSSignature::from([0; 96]),
+                    },
+                )),
+                special_tx(TransactionPayload::AssetLockPayloadType(AssetLockPayload {
+                    version: 1,
+                    credit_outputs: vec![TxOut { value: 1000, script_pubkey: credit.clone() }],
+                })),
+            ],
+        };
+        let spent = ScriptBuf::from(vec![0x51]);
+        let script_for_coin = |_: &OutPoint| -> Result<_, Error> { Ok(spent.clone()) };
+        let block_hash = block.block_hash();
+
+        let basic = BlockFilter::new_basic_filter(&block, script_for_coin).unwrap();
+        let elements: [&[u8]; 4] =
+            [payout.as_bytes(), pro_tx_hash.as_byte_array(), credit.as_bytes(), spent.as_bytes()];
+        assert!(basic.match_all(&block_hash, elements.iter().copied()).unwrap());
+
+        // The plain script filter only contains the spent script.
+        let script = BlockFilter::new_script_filter(&block, script_for_coin).unwrap();
+        assert!(script.match_any(&block_hash, [spent.as_bytes()].iter().copied()).unwrap());
+        assert!(!script.match_any(&block_hash, elements[..3].iter().copied()).unwrap());
+    }
+
+    #[test]
+    fn test_basic_filter_skips_empty_scripts() {
+        use crate::blockdata::block::{self, Version};
+        use crate::blockdata::transaction::special_transaction::provider_update_service::ProviderUpdateServicePayload;
+        use crate::bls_sig_utils::BLSSignature;
+        use crate::hash_types::{InputsHash, TxMerkleNode, Txid};
+        use crate::pow::CompactTarget;
+        use crate::{Transaction, TxIn, TxOut};
+
+        let pro_tx_hash = Txid::from_byte_array([9; 32]);
+        let block = Block {
+            header: block::Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::hash(&[0]),
+                merkle_root: TxMerkleNode::hash(&[1]),
+                time: 2,
+                bits: CompactTarget::from_consensus(3),
+                nonce: 4,
+            },
+            txdata: vec![
+                Transaction {
+                    version: 1,
+                    lock_time: 0,
+                    input: vec![TxIn::default()],
+                    output: vec![TxOut { value: 50, script_pubkey: ScriptBuf::new() }],
+                    special_transaction_payload: None,
+                },
+                Transaction {
+                    version: 3,
+                    lock_time: 0,
+                    input: vec![TxIn::default()],
+                    output: vec![],
+                    special_transaction_payload: Some(
+                        TransactionPayload::ProviderUpdateServicePayloadType(
+                            ProviderUpdateServicePayload {
+                                version: 1,
+                                pro_tx_hash,
+                                ip_address: 0,
+                                port: 9999,
+                                script_payout: ScriptBuf::new(),
+                                inputs_hash: InputsHash::all_zeros(),
+                                payload_sig: BLSSignature::from([0; 96]),
+                            },
+                        ),
+                    ),
+                },
+            ],
+        };
+        let script_for_coin = |_: &OutPoint| -> Result<_, Error> { Ok(ScriptBuf::new()) };
+
+        // Only the proTxHash is left, like in Dash Core's `BasicFilterElements`.
+        let basic = BlockFilter::new_basic_filter(&block, script_for_coin).unwrap();
+        assert_eq!(basic.content[0], 1);
+        let query = [pro_tx_hash.as_byte_array()];
+        assert!(basic.match_all(&block.block_hash(), query.iter().map(|h| &h[..])).unwrap());
+        let script = BlockFilter::new_script_filter(&block, script_for_coin).unwrap();
+        assert_eq!(script.content, vec![0]);
+    }
+
     #[test]
     fn test_filter() {
         let mut patterns = BTreeSet::new();
@@ -14,6 +14,7 @@ pub mod locktime;
 pub mod opcodes;
 pub mod script;
 pub mod transaction;
+pub mod utxo;
 pub mod weight;
 pub mod witness;
 
@@ -326,6 +326,24 @@ impl Script {
         crate::Amount::from_sat(sats)
     }
 
+    /// Counts the signature operations in this script the legacy, inaccurate way.
+    ///
+    /// This mirrors Dash Core's `GetSigOpCount(false)`: every `OP_CHECKMULTISIG(VERIFY)` counts as
+    /// 20 signature operations and counting stops at the first invalid instruction.
+    pub fn count_sigops_legacy(&self) -> usize {
+        let mut n = 0;
+        for instruction in self.instructions() {
+            match instruction {
+                Ok(Instruction::Op(OP_CHECKSIG)) | Ok(Instruction::Op(OP_CHECKSIGVERIFY)) => n += 1,
+                Ok(Instruction::Op(OP_CHECKMULTISIG))
+                | Ok(Instruction::Op(OP_CHECKMULTISIGVERIFY)) => n += 20,
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        n
+    }
+
     /// Iterates over the script instructions.
     ///
     /// Each returned item is a nested enum covering opcodes, datapushes and errors.
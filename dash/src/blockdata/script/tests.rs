@@ -344,6 +344,26 @@ fn script_hashes() {
     );
 }
 
+#[test]
+fn count_sigops_legacy() {
+    // p2pkh
+    let p2pkh = ScriptBuf::from_hex("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac").unwrap();
+    assert_eq!(p2pkh.count_sigops_legacy(), 1);
+    // 1-of-2 bare multisig counts as 20 regardless of the number of keys
+    let multisig = Builder::new()
+        .push_int(1)
+        .push_slice([2u8; 33])
+        .push_slice([3u8; 33])
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .into_script();
+    assert_eq!(multisig.count_sigops_legacy(), 21);
+    // counting stops at an invalid push
+    assert_eq!(ScriptBuf::from_hex("ac4cffac").unwrap().count_sigops_legacy(), 1);
+    assert_eq!(ScriptBuf::new().count_sigops_legacy(), 0);
+}
+
 #[test]
 fn provably_unspendable_test() {
     // p2pk
@@ -0,0 +1,110 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! CoinJoin denominations.
+//!
+//! Mixed outputs use one of five standard amounts, each slightly above a power of ten so they
+//! stand out from regular payments. On the wire a denomination is a single bit, `1 << i` for
+//! the `i`-th entry of [`STANDARD_DENOMINATIONS`].
+//!
+
+use crate::Amount;
+
+/// The standard denominations, largest first: 10.0001, 1.00001, 0.100001, 0.0100001 and
+/// 0.00100001 DASH.
+pub const STANDARD_DENOMINATIONS: [Amount; 5] = [
+    Amount::from_sat(1_000_010_000),
+    Amount::from_sat(100_001_000),
+    Amount::from_sat(10_000_100),
+    Amount::from_sat(1_000_010),
+    Amount::from_sat(100_001),
+];
+
+/// Maximum number of inputs, and of outputs, a participant may add to a session.
+pub const COINJOIN_ENTRY_MAX_SIZE: usize = 9;
+
+/// Returns the smallest standard denomination.
+pub fn smallest_denomination() -> Amount {
+    STANDARD_DENOMINATIONS[STANDARD_DENOMINATIONS.len() - 1]
+}
+
+/// Returns the amount paid by a collateral transaction, a tenth of the smallest denomination.
+pub fn collateral_amount() -> Amount { Amount::from_sat(smallest_denomination().to_sat() / 10) }
+
+/// Returns the largest amount a collateral output may hold.
+pub fn max_collateral_amount() -> Amount { Amount::from_sat(collateral_amount().to_sat() * 4) }
+
+/// Returns whether `amount` can be used as collateral.
+pub fn is_collateral_amount(amount: Amount) -> bool {
+    amount >= collateral_amount() && amount <= max_collateral_amount()
+}
+
+/// Returns the most a participant can mix in a single session.
+pub fn max_pool_amount() -> Amount {
+    Amount::from_sat(STANDARD_DENOMINATIONS[0].to_sat() * COINJOIN_ENTRY_MAX_SIZE as u64)
+}
+
+/// Returns whether `amount` is one of the standard denominations.
+pub fn is_denominated_amount(amount: Amount) -> bool { amount_to_denomination(amount).is_some() }
+
+/// Returns whether `denomination` is a single valid denomination bit.
+pub fn is_valid_denomination(denomination: u32) -> bool {
+    denomination_to_amount(denomination).is_some()
+}
+
+/// Returns the denomination bit of `amount`, `None` if it is not a standard denomination.
+pub fn amount_to_denomination(amount: Amount) -> Option<u32> {
+    STANDARD_DENOMINATIONS.iter().position(|denom| *denom == amount).map(|i| 1 << i)
+}
+
+/// Returns the amount of the denomination bit `denomination`.
+pub fn denomination_to_amount(denomination: u32) -> Option<Amount> {
+    if !denomination.is_power_of_two() {
+        return None;
+    }
+    STANDARD_DENOMINATIONS.get(denomination.trailing_zeros() as usize).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Denomination;
+
+    #[test]
+    fn standard_denominations() {
+        let expected = ["10.0001", "1.00001", "0.100001", "0.0100001", "0.00100001"];
+        for (i, dash) in expected.iter().enumerate() {
+            let amount = Amount::from_str_in(dash, Denomination::Dash).unwrap();
+            assert_eq!(STANDARD_DENOMINATIONS[i], amount);
+            assert!(is_denominated_amount(amount));
+            assert_eq!(amount_to_denomination(amount), Some(1 << i));
+            assert_eq!(denomination_to_amount(1 << i), Some(amount));
+        }
+        assert!(!is_denominated_amount(Amount::from_sat(100_000_000)));
+        assert!(!is_valid_denomination(0));
+        assert!(!is_valid_denomination(3));
+        assert!(!is_valid_denomination(1 << 5));
+    }
+
+    #[test]
+    fn collateral_and_pool_limits() {
+        assert_eq!(collateral_amount(), Amount::from_sat(10_000));
+        assert_eq!(max_collateral_amount(), Amount::from_sat(40_000));
+        assert!(is_collateral_amount(Amount::from_sat(10_000)));
+        assert!(is_collateral_amount(Amount::from_sat(40_000)));
+        assert!(!is_collateral_amount(Amount::from_sat(9_999)));
+        assert!(!is_collateral_amount(Amount::from_sat(40_001)));
+        assert_eq!(max_pool_amount(), Amount::from_sat(9_000_090_000));
+    }
+}
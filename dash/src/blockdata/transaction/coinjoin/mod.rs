@@ -0,0 +1,23 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash CoinJoin.
+//!
+//! CoinJoin mixes coins by having several participants contribute inputs of a standard
+//! denomination to a single transaction, coordinated by a masternode, which pays every
+//! participant back outputs of the same denomination.
+//!
+
+pub mod denominations;
+pub mod verifier;
@@ -0,0 +1,522 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! CoinJoin final transaction verification.
+//!
+//! Before a participant signs the transaction a masternode sends in `dsf`, it must make sure
+//! the transaction spends its inputs only to pay back its outputs. Signing with
+//! `SIGHASH_ALL|SIGHASH_ANYONECANPAY` commits to every output, so once the checks pass the
+//! participant's funds can only end up where it asked.
+//!
+
+use core::fmt;
+
+use secp256k1::{Message, Secp256k1, Signing};
+
+use crate::blockdata::script::Builder;
+use crate::blockdata::transaction::coinjoin::denominations::{
+    COINJOIN_ENTRY_MAX_SIZE, denomination_to_amount,
+};
+use crate::blockdata::transaction::txin::TxIn;
+use crate::blockdata::transaction::txout::TxOut;
+use crate::blockdata::transaction::{OutPoint, Transaction};
+use crate::crypto::ecdsa;
+use crate::crypto::key::PrivateKey;
+use crate::prelude::*;
+use crate::sighash::{EcdsaSighashType, SighashCache};
+use crate::{Amount, ScriptBuf};
+
+/// Default maximum number of participants in a mixing session.
+pub const COINJOIN_MAX_PARTICIPANTS: usize = 20;
+
+/// Maximum size of a standard transaction, in bytes.
+pub const MAX_STANDARD_TX_SIZE: usize = 100_000;
+
+/// Why a participant refuses to sign a mixing transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CoinJoinError {
+    /// The session denomination is not a single standard denomination bit.
+    InvalidDenomination(u32),
+    /// One of our inputs or outputs is not of the session denomination.
+    NotDenominated(Amount),
+    /// We added more inputs or outputs than a participant may.
+    EntryTooLarge,
+    /// An entry's inputs and prevouts differ in number.
+    PrevoutCountMismatch,
+    /// One of our inputs was added twice.
+    DuplicateOwnInput(OutPoint),
+    /// The transaction is a special transaction.
+    SpecialTransaction,
+    /// The transaction's inputs and outputs differ in number.
+    SizeMismatch {
+        /// Number of inputs.
+        inputs: usize,
+        /// Number of outputs.
+        outputs: usize,
+    },
+    /// The transaction has more inputs than the session allows.
+    TooManyInputs(usize),
+    /// The transaction exceeds the standard size, in bytes.
+    TooLarge(usize),
+    /// The transaction spends an outpoint twice.
+    DuplicateInput(OutPoint),
+    /// The transaction has an output that is not a P2PKH output of the session denomination.
+    UnexpectedOutput(TxOut),
+    /// One of our inputs is missing.
+    MissingInput(OutPoint),
+    /// One of our outputs is missing.
+    MissingOutput(TxOut),
+    /// Our inputs and outputs differ in value, so we would pay a fee.
+    Fee {
+        /// Value of our inputs.
+        inputs: Amount,
+        /// Value of our outputs.
+        outputs: Amount,
+    },
+    /// No key, or the wrong key, was given to sign one of our inputs.
+    MissingKey(OutPoint),
+    /// One of our inputs cannot be signed as it is not P2PKH.
+    UnsupportedScript(OutPoint),
+}
+
+impl fmt::Display for CoinJoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CoinJoinError::InvalidDenomination(denom) =>
+                write!(f, "invalid session denomination {}", denom),
+            CoinJoinError::NotDenominated(amount) =>
+                write!(f, "{} is not of the session denomination", amount),
+            CoinJoinError::EntryTooLarge => write!(f, "too many inputs or outputs for one entry"),
+            CoinJoinError::PrevoutCountMismatch =>
+                write!(f, "entry inputs and prevouts differ in number"),
+            CoinJoinError::DuplicateOwnInput(ref outpoint) =>
+                write!(f, "input {} added twice", outpoint),
+            CoinJoinError::SpecialTransaction => write!(f, "mixing transaction has a payload"),
+            CoinJoinError::SizeMismatch { inputs, outputs } =>
+                write!(f, "mixing transaction has {} inputs but {} outputs", inputs, outputs),
+            CoinJoinError::TooManyInputs(count) =>
+                write!(f, "mixing transaction has too many inputs: {}", count),
+            CoinJoinError::TooLarge(size) =>
+                write!(f, "mixing transaction of {} bytes is too large", size),
+            CoinJoinError::DuplicateInput(ref outpoint) =>
+                write!(f, "mixing transaction spends {} twice", outpoint),
+            CoinJoinError::UnexpectedOutput(ref output) =>
+                write!(f, "unexpected output of {} to {}", output.value, output.script_pubkey),
+            CoinJoinError::MissingInput(ref outpoint) => write!(f, "input {} is missing", outpoint),
+            CoinJoinError::MissingOutput(ref output) =>
+                write!(f, "output of {} to {} is missing", output.value, output.script_pubkey),
+            CoinJoinError::Fee { inputs, outputs } =>
+                write!(f, "inputs of {} do not match outputs of {}", inputs, outputs),
+            CoinJoinError::MissingKey(ref outpoint) => write!(f, "no key for input {}", outpoint),
+            CoinJoinError::UnsupportedScript(ref outpoint) =>
+                write!(f, "input {} does not spend a P2PKH output", outpoint),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CoinJoinError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Checks a mixing session's final transaction and signs our inputs in it
+///
+/// The verifier is given our inputs, with the outputs they spend, and our outputs. It only
+/// approves a transaction that contains all of them, whose inputs and outputs match in number,
+/// and whose every output is a P2PKH output of the session denomination. As every participant's
+/// inputs are denominated too, such a transaction pays no fee and nobody but the participants.
+#[derive(Clone, Debug)]
+pub struct CoinJoinSessionVerifier {
+    denomination: Amount,
+    max_participants: usize,
+    inputs: Vec<(OutPoint, TxOut)>,
+    outputs: Vec<TxOut>,
+}
+
+impl CoinJoinSessionVerifier {
+    /// Creates a verifier for a session of the denomination bit `denomination`.
+    pub fn new(denomination: u32) -> Result<CoinJoinSessionVerifier, CoinJoinError> {
+        let denomination = denomination_to_amount(denomination)
+            .ok_or(CoinJoinError::InvalidDenomination(denomination))?;
+        Ok(CoinJoinSessionVerifier {
+            denomination,
+            max_participants: COINJOIN_MAX_PARTICIPANTS,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        })
+    }
+
+    /// Sets the maximum number of participants, which bounds the transaction's size.
+    pub fn with_max_participants(mut self, max_participants: usize) -> CoinJoinSessionVerifier {
+        self.max_participants = max_participants;
+        self
+    }
+
+    /// Returns the amount of the session denomination.
+    pub fn denomination(&self) -> Amount { self.denomination }
+
+    /// Adds one of our inputs, spending `prevout`.
+    pub fn add_input(&mut self, outpoint: OutPoint, prevout: TxOut) -> Result<(), CoinJoinError> {
+        self.check_denominated(prevout.value)?;
+        if self.inputs.len() >= COINJOIN_ENTRY_MAX_SIZE {
+            return Err(CoinJoinError::EntryTooLarge);
+        }
+        if self.inputs.iter().any(|(o, _)| *o == outpoint) {
+            return Err(CoinJoinError::DuplicateOwnInput(outpoint));
+        }
+        self.inputs.push((outpoint, prevout));
+        Ok(())
+    }
+
+    /// Adds one of our outputs.
+    pub fn add_output(&mut self, output: TxOut) -> Result<(), CoinJoinError> {
+        self.check_denominated(output.value)?;
+        if self.outputs.len() >= COINJOIN_ENTRY_MAX_SIZE {
+            return Err(CoinJoinError::EntryTooLarge);
+        }
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    /// Adds the inputs and outputs of an entry we sent in `dsi`; `prevouts` are the outputs
+    /// spent by the entry's inputs, in the same order.
+    #[cfg(feature = "std")]
+    pub fn add_entry(
+        &mut self,
+        entry: &crate::network::message_coinjoin::CoinJoinEntry,
+        prevouts: &[TxOut],
+    ) -> Result<(), CoinJoinError> {
+        if entry.inputs.len() != prevouts.len() {
+            return Err(CoinJoinError::PrevoutCountMismatch);
+        }
+        for (input, prevout) in entry.inputs.iter().zip(prevouts) {
+            self.add_input(input.previous_output, prevout.clone())?;
+        }
+        for output in &entry.outputs {
+            self.add_output(output.clone())?;
+        }
+        Ok(())
+    }
+
+    fn check_denominated(&self, value: u64) -> Result<(), CoinJoinError> {
+        let amount = Amount::from_sat(value);
+        if amount == self.denomination {
+            Ok(())
+        } else {
+            Err(CoinJoinError::NotDenominated(amount))
+        }
+    }
+
+    /// Checks the final transaction received in `dsf`.
+    pub fn verify(&self, tx: &Transaction) -> Result<(), CoinJoinError> {
+        if tx.special_transaction_payload.is_some() {
+            return Err(CoinJoinError::SpecialTransaction);
+        }
+        if tx.input.len() != tx.output.len() {
+            return Err(CoinJoinError::SizeMismatch {
+                inputs: tx.input.len(),
+                outputs: tx.output.len(),
+            });
+        }
+        if tx.input.len() > COINJOIN_ENTRY_MAX_SIZE * self.max_participants {
+            return Err(CoinJoinError::TooManyInputs(tx.input.len()));
+        }
+        let size = tx.size();
+        if size > MAX_STANDARD_TX_SIZE {
+            return Err(CoinJoinError::TooLarge(size));
+        }
+
+        let mut spent = BTreeSet::new();
+        for input in &tx.input {
+            if !spent.insert(input.previous_output) {
+                return Err(CoinJoinError::DuplicateInput(input.previous_output));
+            }
+        }
+        for output in &tx.output {
+            if output.value != self.denomination.to_sat() || !output.script_pubkey.is_p2pkh() {
+                return Err(CoinJoinError::UnexpectedOutput(output.clone()));
+            }
+        }
+
+        for (outpoint, _) in &self.inputs {
+            if !spent.contains(outpoint) {
+                return Err(CoinJoinError::MissingInput(*outpoint));
+            }
+        }
+        let mut unmatched: Vec<&TxOut> = tx.output.iter().collect();
+        for expected in &self.outputs {
+            match unmatched.iter().position(|output| *output == expected) {
+                Some(i) => {
+                    unmatched.swap_remove(i);
+                }
+                None => return Err(CoinJoinError::MissingOutput(expected.clone())),
+            }
+        }
+
+        // Every input and output is of the session denomination, so matching counts mean
+        // matching values.
+        if self.inputs.len() != self.outputs.len() {
+            let value = |count: usize| Amount::from_sat(self.denomination.to_sat() * count as u64);
+            return Err(CoinJoinError::Fee {
+                inputs: value(self.inputs.len()),
+                outputs: value(self.outputs.len()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the final transaction and signs our inputs in it.
+    ///
+    /// Returns our inputs with their `script_sig` set, in transaction order, ready to be sent
+    /// back in `dss`. Each input is signed with `SIGHASH_ALL|SIGHASH_ANYONECANPAY` by the key
+    /// `keys` holds for its outpoint, which must match the P2PKH output it spends.
+    pub fn verify_and_sign<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        tx: &Transaction,
+        keys: &BTreeMap<OutPoint, PrivateKey>,
+    ) -> Result<Vec<TxIn>, CoinJoinError> {
+        self.verify(tx)?;
+
+        let cache = SighashCache::new(tx);
+        let sighash_type = EcdsaSighashType::AllPlusAnyoneCanPay;
+        let mut signed = Vec::with_capacity(self.inputs.len());
+        for (index, input) in tx.input.iter().enumerate() {
+            let Some((outpoint, prevout)) =
+                self.inputs.iter().find(|(outpoint, _)| *outpoint == input.previous_output)
+            else {
+                continue;
+            };
+            if !prevout.script_pubkey.is_p2pkh() {
+                return Err(CoinJoinError::UnsupportedScript(*outpoint));
+            }
+            let key = keys.get(outpoint).ok_or(CoinJoinError::MissingKey(*outpoint))?;
+            let public_key = key.public_key(secp);
+            if ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) != prevout.script_pubkey {
+                return Err(CoinJoinError::MissingKey(*outpoint));
+            }
+
+            let sighash = cache
+                .legacy_signature_hash(index, &prevout.script_pubkey, sighash_type.to_u32())
+                .expect("index is within the transaction's inputs");
+            let message = Message::from(sighash);
+            let signature = ecdsa::Signature {
+                sig: secp.sign_ecdsa(&message, &key.inner),
+                hash_ty: sighash_type,
+            };
+            let script_sig = Builder::new()
+                .push_slice(signature.serialize())
+                .push_key(&public_key)
+                .into_script();
+            signed.push(TxIn { script_sig, ..input.clone() });
+        }
+        Ok(signed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::Network;
+    use crate::blockdata::transaction::special_transaction::TransactionPayload::AssetLockPayloadType;
+    use crate::blockdata::transaction::special_transaction::asset_lock::AssetLockPayload;
+    use crate::hash_types::Txid;
+
+    const DENOM: u32 = 1 << 1;
+
+    struct Participant {
+        key: PrivateKey,
+        script: ScriptBuf,
+    }
+
+    fn participant(seed: u8) -> Participant {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::new(
+            secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap(),
+            Network::Testnet,
+        );
+        let script = ScriptBuf::new_p2pkh(&key.public_key(&secp).pubkey_hash());
+        Participant { key, script }
+    }
+
+    fn denominated(script: &ScriptBuf) -> TxOut {
+        TxOut { value: 100_001_000, script_pubkey: script.clone() }
+    }
+
+    fn outpoint(seed: u8) -> OutPoint { OutPoint { txid: Txid::hash(&[seed]), vout: 0 } }
+
+    /// Our input spends `outpoint(1)` back to a fresh address; another participant mixes too.
+    fn session() -> (CoinJoinSessionVerifier, Transaction, BTreeMap<OutPoint, PrivateKey>) {
+        let us = participant(1);
+        let our_new_address = participant(2);
+        let them = participant(3);
+
+        let mut verifier = CoinJoinSessionVerifier::new(DENOM).unwrap();
+        verifier.add_input(outpoint(1), denominated(&us.script)).unwrap();
+        verifier.add_output(denominated(&our_new_address.script)).unwrap();
+
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![
+                TxIn { previous_output: outpoint(3), ..Default::default() },
+                TxIn { previous_output: outpoint(1), ..Default::default() },
+            ],
+            output: vec![denominated(&them.script), denominated(&our_new_address.script)],
+            special_transaction_payload: None,
+        };
+        let keys = BTreeMap::from([(outpoint(1), us.key)]);
+        (verifier, tx, keys)
+    }
+
+    #[test]
+    fn builder_rules() {
+        assert_eq!(
+            CoinJoinSessionVerifier::new(3).unwrap_err(),
+            CoinJoinError::InvalidDenomination(3)
+        );
+        let mut verifier = CoinJoinSessionVerifier::new(DENOM).unwrap();
+        let script = participant(1).script;
+        assert_eq!(
+            verifier.add_output(TxOut { value: 10_000_100, script_pubkey: script.clone() }),
+            Err(CoinJoinError::NotDenominated(Amount::from_sat(10_000_100)))
+        );
+        verifier.add_input(outpoint(1), denominated(&script)).unwrap();
+        assert_eq!(
+            verifier.add_input(outpoint(1), denominated(&script)),
+            Err(CoinJoinError::DuplicateOwnInput(outpoint(1)))
+        );
+        for i in 2..=COINJOIN_ENTRY_MAX_SIZE as u8 {
+            verifier.add_input(outpoint(i), denominated(&script)).unwrap();
+        }
+        assert_eq!(
+            verifier.add_input(outpoint(100), denominated(&script)),
+            Err(CoinJoinError::EntryTooLarge)
+        );
+    }
+
+    #[test]
+    fn verify_and_sign_valid_session() {
+        let secp = Secp256k1::new();
+        let (verifier, tx, keys) = session();
+        let signed = verifier.verify_and_sign(&secp, &tx, &keys).unwrap();
+        assert_eq!(signed.len(), 1);
+        assert_eq!(signed[0].previous_output, outpoint(1));
+
+        // The script_sig is <sig||0x81> <pubkey> and the signature checks out.
+        let instructions: Vec<_> =
+            signed[0].script_sig.instructions().map(|i| i.unwrap()).collect();
+        let sig_bytes = instructions[0].push_bytes().unwrap().as_bytes();
+        let signature = ecdsa::Signature::from_slice(sig_bytes).unwrap();
+        assert_eq!(signature.hash_ty, EcdsaSighashType::AllPlusAnyoneCanPay);
+        let public_key = keys[&outpoint(1)].public_key(&secp);
+        let sighash =
+            SighashCache::new(&tx).legacy_signature_hash(1, &participant(1).script, 0x81).unwrap();
+        secp.verify_ecdsa(&Message::from(sighash), &signature.sig, &public_key.inner).unwrap();
+
+        // Anyone-can-pay: other participants' signatures do not change what we signed.
+        let mut other_signed = tx.clone();
+        other_signed.input[0].script_sig = ScriptBuf::from_bytes(vec![0x51]);
+        assert_eq!(verifier.verify_and_sign(&secp, &other_signed, &keys).unwrap(), signed);
+    }
+
+    #[test]
+    fn rejects_bad_transactions() {
+        let secp = Secp256k1::new();
+        let (verifier, tx, keys) = session();
+        let reject = |f: &dyn Fn(&mut Transaction)| {
+            let mut tx = tx.clone();
+            f(&mut tx);
+            verifier.verify_and_sign(&secp, &tx, &keys).unwrap_err()
+        };
+
+        assert_eq!(
+            reject(&|tx| {
+                tx.input.remove(1);
+            }),
+            CoinJoinError::SizeMismatch { inputs: 1, outputs: 2 }
+        );
+        assert_eq!(
+            reject(&|tx| tx.input[1].previous_output = outpoint(4)),
+            CoinJoinError::MissingInput(outpoint(1))
+        );
+        assert_eq!(
+            reject(&|tx| tx.input[0].previous_output = outpoint(1)),
+            CoinJoinError::DuplicateInput(outpoint(1))
+        );
+        assert_eq!(
+            reject(&|tx| tx.output[1] = denominated(&participant(5).script)),
+            CoinJoinError::MissingOutput(denominated(&participant(2).script))
+        );
+        // Paying a fee, or anyone outside the session, shows up as an undenominated output.
+        let short = TxOut { value: 100_000_000, ..tx.output[0].clone() };
+        assert_eq!(
+            reject(&|tx| tx.output[0].value = 100_000_000),
+            CoinJoinError::UnexpectedOutput(short)
+        );
+        let p2sh = ScriptBuf::new_p2sh(&crate::ScriptHash::hash(&[6]));
+        assert_eq!(
+            reject(&|tx| tx.output[0].script_pubkey = p2sh.clone()),
+            CoinJoinError::UnexpectedOutput(denominated(&p2sh))
+        );
+        assert_eq!(
+            reject(&|tx| {
+                tx.special_transaction_payload = Some(AssetLockPayloadType(AssetLockPayload {
+                    version: 1,
+                    credit_outputs: vec![],
+                }))
+            }),
+            CoinJoinError::SpecialTransaction
+        );
+        let many = verifier.clone().with_max_participants(0);
+        assert_eq!(many.verify(&tx), Err(CoinJoinError::TooManyInputs(2)));
+
+        // An output of ours expected twice must appear twice.
+        let mut greedy = verifier.clone();
+        greedy.add_output(denominated(&participant(2).script)).unwrap();
+        assert_eq!(
+            greedy.verify(&tx),
+            Err(CoinJoinError::MissingOutput(denominated(&participant(2).script)))
+        );
+
+        // Keys must be present and match the outputs we spend.
+        assert_eq!(
+            verifier.verify_and_sign(&secp, &tx, &BTreeMap::new()),
+            Err(CoinJoinError::MissingKey(outpoint(1)))
+        );
+        let wrong = BTreeMap::from([(outpoint(1), participant(9).key)]);
+        assert_eq!(
+            verifier.verify_and_sign(&secp, &tx, &wrong),
+            Err(CoinJoinError::MissingKey(outpoint(1)))
+        );
+    }
+
+    #[test]
+    fn rejects_fee_from_own_entry() {
+        let (mut verifier, mut tx, _) = session();
+        verifier.add_input(outpoint(8), denominated(&participant(1).script)).unwrap();
+        tx.input.push(TxIn { previous_output: outpoint(8), ..Default::default() });
+        tx.output.push(denominated(&participant(10).script));
+        assert_eq!(
+            verifier.verify(&tx),
+            Err(CoinJoinError::Fee {
+                inputs: Amount::from_sat(200_002_000),
+                outputs: Amount::from_sat(100_001_000),
+            })
+        );
+    }
+}
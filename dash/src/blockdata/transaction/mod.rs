@@ -26,6 +26,7 @@
 //! This module provides the structures and functions needed to support transactions.
 //!
 
+pub mod coinjoin;
 pub mod hash_type;
 pub mod outpoint;
 pub mod special_transaction;
@@ -25,7 +25,7 @@ use crate::consensus::encode::{
     write_fixed_bitset,
 };
 use crate::consensus::{Decodable, Encodable, encode};
-use crate::hash_types::{QuorumHash, QuorumVVecHash};
+use crate::hash_types::{QuorumCommitmentHash, QuorumHash, QuorumVVecHash};
 use crate::prelude::*;
 use crate::{VarInt, io};
 
@@ -61,6 +61,19 @@ impl QuorumFinalizationCommitment {
         }
         size
     }
+
+    /// The hash committing to the outcome of the DKG session, signed by `quorum_sig` and `sig`.
+    ///
+    /// It equals the commitment hash of the premature commitments this commitment was built from.
+    pub fn commitment_hash(&self) -> QuorumCommitmentHash {
+        crate::llmq::build_commitment_hash(
+            self.llmq_type,
+            &self.quorum_hash,
+            &self.valid_members,
+            &self.quorum_public_key,
+            &self.quorum_vvec_hash,
+        )
+    }
 }
 
 impl Encodable for QuorumFinalizationCommitment {
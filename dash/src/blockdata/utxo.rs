@@ -0,0 +1,614 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Unspent transaction output set.
+//!
+//! A [`UtxoSet`] tracks the spendable outputs of a chain. Connecting a [`Block`] checks that
+//! every input spends an existing, mature output, moves the spent outputs into a [`BlockUndo`]
+//! and adds the new outputs; disconnecting the block with its undo data restores the previous
+//! state, so the set can follow reorgs. Outputs are kept in a [`UtxoStorage`], which is an
+//! in-memory map by default and can be replaced by a disk backend.
+//!
+
+use core::fmt;
+
+use internals::write_err;
+
+use crate::amount::Amount;
+use crate::blockdata::block::Block;
+use crate::blockdata::constants::COINBASE_MATURITY;
+use crate::blockdata::transaction::Transaction;
+use crate::blockdata::transaction::outpoint::OutPoint;
+use crate::blockdata::transaction::txout::TxOut;
+use crate::hash_types::Txid;
+use crate::internal_macros::impl_consensus_encoding;
+use crate::io;
+use crate::prelude::*;
+
+/// An unspent output together with the context needed to spend it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    /// The output itself.
+    pub output: TxOut,
+    /// Height of the block that created the output.
+    pub height: u32,
+    /// Whether the output was created by a coinbase transaction.
+    pub is_coinbase: bool,
+}
+
+impl_consensus_encoding!(Utxo, output, height, is_coinbase);
+
+impl Utxo {
+    /// Returns whether the output may be spent in a block at `spend_height`.
+    ///
+    /// Coinbase outputs can only be spent [`COINBASE_MATURITY`] blocks after they were mined.
+    pub fn is_mature(&self, spend_height: u32) -> bool {
+        !self.is_coinbase || spend_height.saturating_sub(self.height) >= COINBASE_MATURITY
+    }
+}
+
+/// The outputs spent by one transaction, in input order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxUndo {
+    /// Spent outputs, one per input.
+    pub spent: Vec<Utxo>,
+}
+
+impl_consensus_encoding!(TxUndo, spent);
+
+impl TxUndo {
+    /// Returns the total value of the spent outputs, or `None` if it overflows.
+    pub fn input_value(&self) -> Option<Amount> {
+        self.spent.iter().try_fold(Amount::ZERO, |sum, utxo| {
+            sum.checked_add(Amount::from_sat(utxo.output.value))
+        })
+    }
+}
+
+/// Undo data for a connected block, needed to disconnect it again.
+///
+/// Holds one [`TxUndo`] per non-coinbase transaction, in block order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockUndo {
+    /// Undo data of every transaction but the coinbase.
+    pub txs: Vec<TxUndo>,
+}
+
+impl_consensus_encoding!(BlockUndo, txs);
+
+impl BlockUndo {
+    /// Returns the fee paid by every non-coinbase transaction of `block`, in block order.
+    ///
+    /// `block` must be the block this undo data was created for, otherwise
+    /// [`UtxoError::UndoMismatch`] is returned.
+    pub fn fees(&self, block: &Block) -> Result<Vec<Amount>, UtxoError> {
+        if block.txdata.len() != self.txs.len() + 1 {
+            return Err(UtxoError::UndoMismatch);
+        }
+        block
+            .txdata
+            .iter()
+            .skip(1)
+            .zip(&self.txs)
+            .map(|(tx, undo)| {
+                let txid = tx.txid();
+                if undo.spent.len() != tx.input.len() {
+                    return Err(UtxoError::UndoMismatch);
+                }
+                let input_value = undo.input_value().ok_or(UtxoError::ValueOverflow(txid))?;
+                fee(tx, txid, input_value)
+            })
+            .collect()
+    }
+}
+
+/// An error returned while connecting or disconnecting blocks.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UtxoError {
+    /// The block has no transactions.
+    EmptyBlock,
+    /// An input spends an output that does not exist.
+    MissingInput {
+        /// The spending transaction.
+        txid: Txid,
+        /// The missing output.
+        outpoint: OutPoint,
+    },
+    /// An output is spent twice within the block.
+    DoubleSpend {
+        /// The second spending transaction.
+        txid: Txid,
+        /// The output spent twice.
+        outpoint: OutPoint,
+    },
+    /// A coinbase output is spent before it reached [`COINBASE_MATURITY`].
+    ImmatureCoinbase {
+        /// The spending transaction.
+        txid: Txid,
+        /// The coinbase output.
+        outpoint: OutPoint,
+        /// Height the coinbase was mined at.
+        coinbase_height: u32,
+        /// Height of the spending block.
+        spend_height: u32,
+    },
+    /// A transaction creates an output that already exists and is unspent.
+    OutputAlreadyExists(OutPoint),
+    /// A transaction spends less than it creates.
+    NegativeFee {
+        /// The transaction.
+        txid: Txid,
+        /// Total value of the spent outputs.
+        input_value: Amount,
+        /// Total value of the created outputs.
+        output_value: Amount,
+    },
+    /// Summing input or output values overflowed.
+    ValueOverflow(Txid),
+    /// The undo data does not belong to the block being disconnected.
+    UndoMismatch,
+    /// The storage backend failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UtxoError::EmptyBlock => write!(f, "block has no transactions"),
+            UtxoError::MissingInput { txid, outpoint } =>
+                write!(f, "transaction {} spends missing output {}", txid, outpoint),
+            UtxoError::DoubleSpend { txid, outpoint } => write!(
+                f,
+                "transaction {} spends output {} already spent in the block",
+                txid, outpoint
+            ),
+            UtxoError::ImmatureCoinbase { txid, outpoint, coinbase_height, spend_height } =>
+                write!(
+                    f,
+                    "transaction {} spends coinbase output {} from height {} at height {}",
+                    txid, outpoint, coinbase_height, spend_height
+                ),
+            UtxoError::OutputAlreadyExists(outpoint) =>
+                write!(f, "output {} already exists", outpoint),
+            UtxoError::NegativeFee { txid, input_value, output_value } => write!(
+                f,
+                "transaction {} spends {} but creates {}",
+                txid, input_value, output_value
+            ),
+            UtxoError::ValueOverflow(txid) => write!(f, "value overflow in transaction {}", txid),
+            UtxoError::UndoMismatch => write!(f, "undo data does not match the block"),
+            UtxoError::Io(e) => write_err!(f, "UTXO storage error"; e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UtxoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UtxoError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for UtxoError {
+    fn from(error: io::Error) -> Self { UtxoError::Io(error) }
+}
+
+/// Backend storing the outputs of a [`UtxoSet`].
+pub trait UtxoStorage {
+    /// Returns the unspent output at `outpoint`, if any.
+    fn get(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, io::Error>;
+
+    /// Stores an unspent output.
+    fn insert(&mut self, outpoint: OutPoint, utxo: Utxo) -> Result<(), io::Error>;
+
+    /// Removes an output, returning it if it was present.
+    fn remove(&mut self, outpoint: &OutPoint) -> Result<Option<Utxo>, io::Error>;
+}
+
+/// In-memory [`UtxoStorage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUtxoStorage {
+    utxos: BTreeMap<OutPoint, Utxo>,
+}
+
+impl MemoryUtxoStorage {
+    /// Creates an empty storage.
+    pub fn new() -> Self { MemoryUtxoStorage::default() }
+
+    /// Returns the number of unspent outputs.
+    pub fn len(&self) -> usize { self.utxos.len() }
+
+    /// Returns whether there are no unspent outputs.
+    pub fn is_empty(&self) -> bool { self.utxos.is_empty() }
+
+    /// Iterates over the unspent outputs in outpoint order.
+    pub fn iter(&self) -> impl Iterator<Item = (&OutPoint, &Utxo)> { self.utxos.iter() }
+}
+
+impl UtxoStorage for MemoryUtxoStorage {
+    fn get(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, io::Error> {
+        Ok(self.utxos.get(outpoint).cloned())
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, utxo: Utxo) -> Result<(), io::Error> {
+        self.utxos.insert(outpoint, utxo);
+        Ok(())
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) -> Result<Option<Utxo>, io::Error> {
+        Ok(self.utxos.remove(outpoint))
+    }
+}
+
+/// A set of unspent transaction outputs that can connect and disconnect blocks.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet<S = MemoryUtxoStorage> {
+    storage: S,
+}
+
+impl UtxoSet<MemoryUtxoStorage> {
+    /// Creates an empty in-memory set.
+    pub fn new() -> Self { UtxoSet::default() }
+}
+
+impl<S: UtxoStorage> UtxoSet<S> {
+    /// Creates a set backed by `storage`.
+    pub fn with_storage(storage: S) -> Self { UtxoSet { storage } }
+
+    /// Returns the storage backend.
+    pub fn storage(&self) -> &S { &self.storage }
+
+    /// Consumes the set, returning the storage backend.
+    pub fn into_storage(self) -> S { self.storage }
+
+    /// Returns the unspent output at `outpoint`, if any.
+    pub fn get(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, UtxoError> {
+        Ok(self.storage.get(outpoint)?)
+    }
+
+    /// Computes the fee of a transaction spending outputs of the set.
+    ///
+    /// Transactions without inputs, such as quorum commitments, pay no fee.
+    pub fn transaction_fee(&self, tx: &Transaction) -> Result<Amount, UtxoError> {
+        let txid = tx.txid();
+        let mut input_value = Amount::ZERO;
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            let utxo =
+                self.storage.get(&outpoint)?.ok_or(UtxoError::MissingInput { txid, outpoint })?;
+            input_value = input_value
+                .checked_add(Amount::from_sat(utxo.output.value))
+                .ok_or(UtxoError::ValueOverflow(txid))?;
+        }
+        fee(tx, txid, input_value)
+    }
+
+    /// Connects `block` at `height`, returning the undo data needed to disconnect it.
+    ///
+    /// The whole block is checked before the storage is modified, so on error the set is left
+    /// unchanged (unless the storage backend itself fails). Outputs created earlier in the block
+    /// may be spent by later transactions. `OP_RETURN` outputs are never added to the set.
+    pub fn connect_block(&mut self, height: u32, block: &Block) -> Result<BlockUndo, UtxoError> {
+        if block.txdata.is_empty() {
+            return Err(UtxoError::EmptyBlock);
+        }
+
+        let mut created: BTreeMap<OutPoint, Utxo> = BTreeMap::new();
+        let mut spent: BTreeSet<OutPoint> = BTreeSet::new();
+        let mut undo = BlockUndo { txs: Vec::with_capacity(block.txdata.len() - 1) };
+
+        for (index, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.txid();
+            let is_coinbase = index == 0;
+
+            if !is_coinbase {
+                let mut tx_undo = TxUndo { spent: Vec::with_capacity(tx.input.len()) };
+                let mut input_value = Amount::ZERO;
+                for input in &tx.input {
+                    let outpoint = input.previous_output;
+                    if !spent.insert(outpoint) {
+                        return Err(UtxoError::DoubleSpend { txid, outpoint });
+                    }
+                    let utxo = match created.remove(&outpoint) {
+                        Some(utxo) => utxo,
+                        None => self
+                            .storage
+                            .get(&outpoint)?
+                            .ok_or(UtxoError::MissingInput { txid, outpoint })?,
+                    };
+                    if !utxo.is_mature(height) {
+                        return Err(UtxoError::ImmatureCoinbase {
+                            txid,
+                            outpoint,
+                            coinbase_height: utxo.height,
+                            spend_height: height,
+                        });
+                    }
+                    input_value = input_value
+                        .checked_add(Amount::from_sat(utxo.output.value))
+                        .ok_or(UtxoError::ValueOverflow(txid))?;
+                    tx_undo.spent.push(utxo);
+                }
+                fee(tx, txid, input_value)?;
+                undo.txs.push(tx_undo);
+            }
+
+            for (vout, output) in tx.output.iter().enumerate() {
+                if output.script_pubkey.is_op_return() {
+                    continue;
+                }
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if created.contains_key(&outpoint)
+                    || (!spent.contains(&outpoint) && self.storage.get(&outpoint)?.is_some())
+                {
+                    return Err(UtxoError::OutputAlreadyExists(outpoint));
+                }
+                created.insert(outpoint, Utxo { output: output.clone(), height, is_coinbase });
+            }
+        }
+
+        for outpoint in &spent {
+            self.storage.remove(outpoint)?;
+        }
+        for (outpoint, utxo) in created {
+            self.storage.insert(outpoint, utxo)?;
+        }
+        Ok(undo)
+    }
+
+    /// Disconnects `block`, which must be the last connected block, using its undo data.
+    pub fn disconnect_block(&mut self, block: &Block, undo: &BlockUndo) -> Result<(), UtxoError> {
+        if block.txdata.is_empty() || undo.txs.len() != block.txdata.len() - 1 {
+            return Err(UtxoError::UndoMismatch);
+        }
+        if block
+            .txdata
+            .iter()
+            .skip(1)
+            .zip(&undo.txs)
+            .any(|(tx, tx_undo)| tx.input.len() != tx_undo.spent.len())
+        {
+            return Err(UtxoError::UndoMismatch);
+        }
+
+        for (index, tx) in block.txdata.iter().enumerate().rev() {
+            let txid = tx.txid();
+            for vout in 0..tx.output.len() {
+                self.storage.remove(&OutPoint::new(txid, vout as u32))?;
+            }
+            if index > 0 {
+                for (input, utxo) in tx.input.iter().zip(&undo.txs[index - 1].spent) {
+                    self.storage.insert(input.previous_output, utxo.clone())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fee(tx: &Transaction, txid: Txid, input_value: Amount) -> Result<Amount, UtxoError> {
+    // Special transactions without inputs (quorum commitments, asset unlocks) are funded by
+    // the protocol rather than by spent outputs.
+    if tx.input.is_empty() {
+        return Ok(Amount::ZERO);
+    }
+    let output_value = tx
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |sum, output| sum.checked_add(Amount::from_sat(output.value)))
+        .ok_or(UtxoError::ValueOverflow(txid))?;
+    input_value.checked_sub(output_value).ok_or(UtxoError::NegativeFee {
+        txid,
+        input_value,
+        output_value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::blockdata::block::{Header, Version};
+    use crate::blockdata::script::ScriptBuf;
+    use crate::blockdata::transaction::txin::TxIn;
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::hash_types::{BlockHash, TxMerkleNode};
+    use crate::pow::CompactTarget;
+
+    fn coinbase(height: u32, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: height,
+            input: vec![TxIn::default()],
+            output: vec![TxOut { value, script_pubkey: ScriptBuf::new() }],
+            special_transaction_payload: None,
+        }
+    }
+
+    fn spend(inputs: &[OutPoint], values: &[u64]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: inputs
+                .iter()
+                .map(|outpoint| TxIn { previous_output: *outpoint, ..Default::default() })
+                .collect(),
+            output: values
+                .iter()
+                .map(|value| TxOut { value: *value, script_pubkey: ScriptBuf::new() })
+                .collect(),
+            special_transaction_payload: None,
+        }
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    /// Returns a set holding a mature coinbase output of 50 DASH.
+    fn funded_set() -> (UtxoSet, OutPoint) {
+        let mut set = UtxoSet::new();
+        let cb = coinbase(1, 50_0000_0000);
+        set.connect_block(1, &block(vec![cb.clone()])).unwrap();
+        (set, OutPoint::new(cb.txid(), 0))
+    }
+
+    #[test]
+    fn connect_and_disconnect() {
+        let (mut set, funding) = funded_set();
+        let before = set.storage().clone();
+
+        let tx1 = spend(&[funding], &[30_0000_0000, 19_9999_0000]);
+        let tx2 = spend(&[OutPoint::new(tx1.txid(), 0)], &[29_9998_0000]);
+        let cb = coinbase(200, 50_0000_3000);
+        let blk = block(vec![cb.clone(), tx1.clone(), tx2.clone()]);
+
+        let undo = set.connect_block(200, &blk).unwrap();
+        assert_eq!(undo.txs.len(), 2);
+        assert_eq!(
+            undo.fees(&blk).unwrap(),
+            vec![Amount::from_sat(10_000), Amount::from_sat(20_000)]
+        );
+        assert!(set.get(&funding).unwrap().is_none());
+        assert!(set.get(&OutPoint::new(tx1.txid(), 0)).unwrap().is_none());
+        assert!(set.get(&OutPoint::new(tx1.txid(), 1)).unwrap().is_some());
+        let created = set.get(&OutPoint::new(tx2.txid(), 0)).unwrap().unwrap();
+        assert_eq!(created.height, 200);
+        assert!(!created.is_coinbase);
+        assert!(set.get(&OutPoint::new(cb.txid(), 0)).unwrap().unwrap().is_coinbase);
+        assert_eq!(set.storage().len(), 3);
+
+        let encoded = serialize(&undo);
+        assert_eq!(deserialize::<BlockUndo>(&encoded).unwrap(), undo);
+
+        set.disconnect_block(&blk, &undo).unwrap();
+        assert_eq!(set.storage(), &before);
+    }
+
+    #[test]
+    fn missing_input() {
+        let (mut set, _) = funded_set();
+        let outpoint = OutPoint::new(Txid::hash(&[1]), 0);
+        let tx = spend(&[outpoint], &[1]);
+        let err = set.connect_block(200, &block(vec![coinbase(200, 1), tx.clone()])).unwrap_err();
+        assert!(matches!(err, UtxoError::MissingInput { txid, outpoint: o }
+            if txid == tx.txid() && o == outpoint));
+    }
+
+    #[test]
+    fn double_spend_leaves_set_unchanged() {
+        let (mut set, funding) = funded_set();
+        let before = set.storage().clone();
+        let tx1 = spend(&[funding], &[1]);
+        let tx2 = spend(&[funding], &[2]);
+        let err =
+            set.connect_block(200, &block(vec![coinbase(200, 1), tx1, tx2.clone()])).unwrap_err();
+        assert!(matches!(err, UtxoError::DoubleSpend { txid, outpoint }
+            if txid == tx2.txid() && outpoint == funding));
+        assert_eq!(set.storage(), &before);
+    }
+
+    #[test]
+    fn coinbase_maturity() {
+        let (mut set, funding) = funded_set();
+        let tx = spend(&[funding], &[1]);
+
+        let height = COINBASE_MATURITY;
+        let err =
+            set.connect_block(height, &block(vec![coinbase(height, 1), tx.clone()])).unwrap_err();
+        assert!(matches!(
+            err,
+            UtxoError::ImmatureCoinbase { coinbase_height: 1, spend_height, .. } if spend_height == height
+        ));
+
+        let height = COINBASE_MATURITY + 1;
+        set.connect_block(height, &block(vec![coinbase(height, 1), tx])).unwrap();
+    }
+
+    #[test]
+    fn negative_fee() {
+        let (mut set, funding) = funded_set();
+        let tx = spend(&[funding], &[50_0000_0001]);
+        assert!(matches!(set.transaction_fee(&tx), Err(UtxoError::NegativeFee { .. })));
+        let err = set.connect_block(200, &block(vec![coinbase(200, 1), tx])).unwrap_err();
+        assert!(matches!(err, UtxoError::NegativeFee { .. }));
+
+        let tx = spend(&[funding], &[49_0000_0000]);
+        assert_eq!(set.transaction_fee(&tx).unwrap(), Amount::from_sat(1_0000_0000));
+    }
+
+    #[test]
+    fn op_return_outputs_are_skipped() {
+        let (mut set, funding) = funded_set();
+        let mut tx = spend(&[funding], &[1, 0]);
+        tx.output[1].script_pubkey = ScriptBuf::new_op_return(&[0u8; 4]);
+        let blk = block(vec![coinbase(200, 1), tx.clone()]);
+        let undo = set.connect_block(200, &blk).unwrap();
+        assert!(set.get(&OutPoint::new(tx.txid(), 1)).unwrap().is_none());
+        set.disconnect_block(&blk, &undo).unwrap();
+        assert!(set.get(&funding).unwrap().is_some());
+    }
+
+    #[test]
+    fn duplicate_output() {
+        let (mut set, _) = funded_set();
+        let err = set.connect_block(2, &block(vec![coinbase(1, 50_0000_0000)])).unwrap_err();
+        assert!(matches!(err, UtxoError::OutputAlreadyExists(_)));
+    }
+
+    #[test]
+    fn undo_mismatch() {
+        let (mut set, funding) = funded_set();
+        let blk = block(vec![coinbase(200, 1), spend(&[funding], &[1])]);
+        assert!(matches!(
+            set.disconnect_block(&blk, &BlockUndo::default()),
+            Err(UtxoError::UndoMismatch)
+        ));
+        assert!(matches!(set.connect_block(200, &block(vec![])), Err(UtxoError::EmptyBlock)));
+    }
+
+    #[test]
+    fn fees_with_foreign_undo_data() {
+        let (mut set, funding) = funded_set();
+        let blk = block(vec![coinbase(200, 1), spend(&[funding], &[49_0000_0000])]);
+        let mut undo = set.connect_block(200, &blk).unwrap();
+        assert_eq!(undo.fees(&blk).unwrap(), vec![Amount::from_sat(1_0000_0000)]);
+
+        assert!(matches!(BlockUndo::default().fees(&blk), Err(UtxoError::UndoMismatch)));
+        undo.txs[0].spent[0].output.value = 1;
+        assert!(matches!(undo.fees(&blk), Err(UtxoError::NegativeFee { .. })));
+        undo.txs[0].spent[0].output.value = u64::MAX;
+        let spent = undo.txs[0].spent[0].clone();
+        undo.txs[0].spent.push(spent);
+        assert!(matches!(undo.fees(&blk), Err(UtxoError::UndoMismatch)));
+        let blk = block(vec![coinbase(200, 1), spend(&[funding, funding], &[1])]);
+        assert!(matches!(undo.fees(&blk), Err(UtxoError::ValueOverflow(_))));
+    }
+}
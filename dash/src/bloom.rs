@@ -0,0 +1,584 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP37 bloom filters.
+//!
+//! An SPV client sends a [`BloomFilter`] to its peers in a `filterload` message, and they only
+//! relay transactions, and include in `merkleblock` messages transactions, that match it. This
+//! module implements both sides: building a filter of the elements a wallet is interested in,
+//! and matching transactions against a filter, including the Dash special transaction payload
+//! fields that Dash Core matches.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use dashcore::bloom::BloomFilter;
+//! use dashcore::network::message_bloom::BloomFlags;
+//!
+//! let mut filter = BloomFilter::new(10, 0.0001, 0, BloomFlags::All);
+//! filter.insert(&[0x42; 20]);
+//! assert!(filter.contains(&[0x42; 20]));
+//! let message = filter.to_filter_load();
+//! assert!(filter.is_within_size_constraints());
+//! # let _ = message;
+//! ```
+//!
+
+use core::f64::consts::LN_2;
+use std::collections::HashSet;
+
+use hashes::Hash;
+
+use crate::Transaction;
+use crate::blockdata::block::Block;
+use crate::blockdata::opcodes::all::OP_CHECKMULTISIG;
+use crate::blockdata::script::{Instruction, Script};
+use crate::blockdata::transaction::OutPoint;
+use crate::blockdata::transaction::special_transaction::TransactionPayload;
+use crate::blockdata::transaction::txout::TxOut;
+use crate::consensus::encode::serialize;
+use crate::hash_types::Txid;
+use crate::merkle_tree::MerkleBlock;
+use crate::network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+use crate::prelude::*;
+
+/// The maximum size of a bloom filter, in bytes.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// The maximum number of hash functions of a bloom filter.
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Multiplier of the hash function number in the MurmurHash3 seed.
+const SEED_MULTIPLIER: u32 = 0xFBA4_C795;
+
+/// A BIP37 bloom filter.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `elements` elements with the false positive rate
+    /// `fp_rate`, capped to [`MAX_BLOOM_FILTER_SIZE`] and [`MAX_HASH_FUNCS`].
+    ///
+    /// `tweak` should be random, so that filters of different clients differ.
+    pub fn new(elements: u32, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = f64::from(elements.max(1));
+        let bits = (-1.0 / (LN_2 * LN_2) * elements * fp_rate.ln()) as usize;
+        let size = bits.min(MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((size * 8) as f64 / elements * LN_2) as u32;
+        BloomFilter {
+            data: vec![0; size],
+            hash_funcs: hash_funcs.min(MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    /// Returns the filter as a `filterload` message.
+    pub fn to_filter_load(&self) -> FilterLoad {
+        FilterLoad {
+            filter: self.data.clone(),
+            hash_funcs: self.hash_funcs,
+            tweak: self.tweak,
+            flags: self.flags,
+        }
+    }
+
+    /// Returns the update flags of the filter.
+    pub fn flags(&self) -> BloomFlags { self.flags }
+
+    /// Returns whether the filter is small enough to be accepted by peers.
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.data.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    fn bit_index(&self, hash_num: u32, data: &[u8]) -> usize {
+        let seed = hash_num.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.data.len() * 8)
+    }
+
+    /// Adds an element to the filter.
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        for i in 0..self.hash_funcs {
+            let index = self.bit_index(i, data);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+    }
+
+    /// Adds the element of a `filteradd` message to the filter.
+    pub fn add(&mut self, message: &FilterAdd) { self.insert(&message.data) }
+
+    /// Adds an outpoint to the filter.
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) { self.insert(&serialize(outpoint)) }
+
+    /// Returns whether the filter matches an element.
+    ///
+    /// An empty filter matches everything.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|i| {
+            let index = self.bit_index(i, data);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+
+    /// Returns whether the filter matches an outpoint.
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Returns whether the filter matches any data push of `script`.
+    fn contains_script_data(&self, script: &Script) -> bool {
+        script.instructions().map_while(Result::ok).any(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => !bytes.is_empty() && self.contains(bytes.as_bytes()),
+            Instruction::Op(_) => false,
+        })
+    }
+
+    /// Returns whether `tx` matches the filter, updating the filter according to its flags.
+    ///
+    /// Like Dash Core, a transaction matches if the filter contains its txid, a data push of
+    /// one of its output scripts, an outpoint it spends, a data push of one of its input scripts
+    /// or, for masternode provider transactions, one of the payload fields. When an output
+    /// matches, its outpoint is added to the filter, so that transactions spending it match as
+    /// well; with [`BloomFlags::PubkeyOnly`] only for pay-to-pubkey and multisig outputs.
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        let txid = tx.txid();
+        let mut found = self.contains(txid.as_byte_array());
+        found = found || self.special_transaction_matches_and_update(tx);
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            found = self.process_tx_out(output, txid, vout as u32) || found;
+        }
+        if found {
+            return true;
+        }
+
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output)
+                || self.contains_script_data(&input.script_sig)
+        })
+    }
+
+    /// Matches the script of an output, adding its outpoint as the flags allow.
+    fn process_tx_out(&mut self, output: &TxOut, txid: Txid, vout: u32) -> bool {
+        if !self.contains_script_data(&output.script_pubkey) {
+            return false;
+        }
+        let update = match self.flags {
+            BloomFlags::All => true,
+            BloomFlags::PubkeyOnly =>
+                output.script_pubkey.is_p2pk() || is_multisig(&output.script_pubkey),
+            BloomFlags::None => false,
+        };
+        if update {
+            self.insert_outpoint(&OutPoint { txid, vout });
+        }
+        true
+    }
+
+    /// Matches the payload of masternode provider and asset lock transactions.
+    ///
+    /// Asset lock credit outputs are matched like regular outputs, their outpoints being
+    /// numbered by their position among the credit outputs.
+    fn special_transaction_matches_and_update(&mut self, tx: &Transaction) -> bool {
+        let update_all = self.flags == BloomFlags::All;
+        match tx.special_transaction_payload {
+            Some(TransactionPayload::ProviderRegistrationPayloadType(ref payload)) => {
+                let matches = self.contains_outpoint(&payload.collateral_outpoint)
+                    || self.contains(payload.owner_key_hash.as_byte_array())
+                    || self.contains(payload.voting_key_hash.as_byte_array())
+                    || self.contains_script_data(&payload.script_payout);
+                if matches && update_all {
+                    self.insert(tx.txid().as_byte_array());
+                }
+                matches
+            }
+            Some(TransactionPayload::ProviderUpdateServicePayloadType(ref payload)) => {
+                if self.contains(payload.pro_tx_hash.as_byte_array()) {
+                    return true;
+                }
+                let matches = self.contains_script_data(&payload.script_payout);
+                if matches && update_all {
+                    self.insert(payload.pro_tx_hash.as_byte_array());
+                }
+                matches
+            }
+            Some(TransactionPayload::ProviderUpdateRegistrarPayloadType(ref payload)) => {
+                if self.contains(payload.pro_tx_hash.as_byte_array()) {
+                    return true;
+                }
+                let matches = self.contains(payload.voting_key_hash.as_byte_array())
+                    || self.contains_script_data(&payload.script_payout);
+                if matches && update_all {
+                    self.insert(payload.pro_tx_hash.as_byte_array());
+                }
+                matches
+            }
+            Some(TransactionPayload::ProviderUpdateRevocationPayloadType(ref payload)) =>
+                self.contains(payload.pro_tx_hash.as_byte_array()),
+            Some(TransactionPayload::AssetLockPayloadType(ref payload)) => {
+                let txid = tx.txid();
+                let mut found = false;
+                for (index, output) in payload.credit_outputs.iter().enumerate() {
+                    found = self.process_tx_out(output, txid, index as u32) || found;
+                }
+                found
+            }
+            _ => false,
+        }
+    }
+
+    /// Matches the transactions of `block` in order, updating the filter, and returns the
+    /// `merkleblock` a peer would send for it.
+    pub fn merkle_block(&mut self, block: &Block) -> MerkleBlock {
+        let matched: HashSet<_> = block
+            .txdata
+            .iter()
+            .filter(|tx| self.is_relevant_and_update(tx))
+            .map(Transaction::txid)
+            .collect();
+        MerkleBlock::from_block_with_predicate(block, |txid| matched.contains(txid))
+    }
+}
+
+impl From<FilterLoad> for BloomFilter {
+    fn from(load: FilterLoad) -> BloomFilter {
+        BloomFilter {
+            data: load.filter,
+            hash_funcs: load.hash_funcs,
+            tweak: load.tweak,
+            flags: load.flags,
+        }
+    }
+}
+
+/// Returns whether `script` is a bare multisig script, `m <pubkey>... n OP_CHECKMULTISIG`.
+fn is_multisig(script: &Script) -> bool {
+    let instructions: Vec<_> = match script.instructions().collect::<Result<_, _>>() {
+        Ok(instructions) => instructions,
+        Err(_) => return false,
+    };
+    let small_int = |instruction: &Instruction| match *instruction {
+        Instruction::Op(op) => match op.to_u8() {
+            n @ 0x51..=0x60 => Some(usize::from(n - 0x50)),
+            _ => None,
+        },
+        Instruction::PushBytes(_) => None,
+    };
+    let (Some(first), Some(last)) = (instructions.first(), instructions.last()) else {
+        return false;
+    };
+    if instructions.len() < 4 || *last != Instruction::Op(OP_CHECKMULTISIG) {
+        return false;
+    }
+    let keys = &instructions[1..instructions.len() - 2];
+    let (Some(required), Some(total)) =
+        (small_int(first), small_int(&instructions[instructions.len() - 2]))
+    else {
+        return false;
+    };
+    required <= total
+        && total == keys.len()
+        && keys.iter().all(|key| {
+            matches!(key, Instruction::PushBytes(bytes) if bytes.len() == 33 || bytes.len() == 65)
+        })
+}
+
+/// Computes the 32-bit MurmurHash3 of `data`.
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h1 = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        let mut k1 = u32::from_le_bytes(block.try_into().expect("4 bytes"));
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut k1 = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k1 ^= u32::from(*byte) << (8 * i);
+        }
+        h1 ^= k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85eb_ca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2_ae35);
+    h1 ^ (h1 >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use super::*;
+    use crate::blockdata::script::ScriptBuf;
+    use crate::blockdata::transaction::special_transaction::asset_lock::AssetLockPayload;
+    use crate::blockdata::transaction::special_transaction::provider_update_revocation::ProviderUpdateRevocationPayload;
+    use crate::blockdata::transaction::special_transaction::provider_update_service::ProviderUpdateServicePayload;
+    use crate::blockdata::transaction::txin::TxIn;
+    use crate::bls_sig_utils::BLSSignature;
+    use crate::consensus::encode::serialize_hex;
+    use crate::hash_types::{InputsHash, PubkeyHash, Txid};
+
+    fn hex(s: &str) -> Vec<u8> { Vec::from_hex(s).unwrap() }
+
+    fn tx(
+        input: Vec<TxIn>,
+        output: Vec<TxOut>,
+        payload: Option<TransactionPayload>,
+    ) -> Transaction {
+        Transaction {
+            version: 3,
+            lock_time: 0,
+            input,
+            output,
+            special_transaction_payload: payload,
+        }
+    }
+
+    #[test]
+    fn murmur3_vectors() {
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x0000_0000, 0x0000_0000, ""),
+            (0x6a39_6f08, 0xFBA4_C795, ""),
+            (0x81f1_6f39, 0xffff_ffff, ""),
+            (0x514E_28B7, 0x0000_0000, "00"),
+            (0xEA3F_0B17, 0xFBA4_C795, "00"),
+            (0xFD6C_F10D, 0x0000_0000, "ff"),
+            (0x16C6_B7AB, 0x0000_0000, "0011"),
+            (0x8EB5_1C3D, 0x0000_0000, "001122"),
+            (0xB447_1BF8, 0x0000_0000, "00112233"),
+            (0xE230_1FA8, 0x0000_0000, "0011223344"),
+            (0xFC2E_4A15, 0x0000_0000, "001122334455"),
+            (0xB074_502C, 0x0000_0000, "00112233445566"),
+            (0x8034_D2A0, 0x0000_0000, "0011223344556677"),
+            (0xB469_8DEF, 0x0000_0000, "001122334455667788"),
+        ];
+        for &(expected, seed, data) in vectors {
+            assert_eq!(murmur3(seed, &hex(data)), expected, "seed {:x} data {}", seed, data);
+        }
+    }
+
+    #[test]
+    fn create_insert_serialize() {
+        for (tweak, expected) in
+            [(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")]
+        {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+            filter.insert(&hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8"));
+            assert!(filter.contains(&hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+            // One bit different in the first byte.
+            assert!(!filter.contains(&hex("19108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+            filter.insert(&hex("b5a2c786d9ef4658287ced5914b37a1b4aa32eee"));
+            filter.add(&FilterAdd { data: hex("b9300670b4c5366e95b2699e8b18bc75e5f729c5") });
+            assert!(filter.contains(&hex("b9300670b4c5366e95b2699e8b18bc75e5f729c5")));
+
+            let load = filter.to_filter_load();
+            assert_eq!(serialize_hex(&load), expected);
+            assert_eq!(BloomFilter::from(load), filter);
+        }
+    }
+
+    #[test]
+    fn size_constraints() {
+        let filter = BloomFilter::new(1_000_000, 0.000_001, 0, BloomFlags::None);
+        assert_eq!(filter.to_filter_load().filter.len(), MAX_BLOOM_FILTER_SIZE);
+        assert!(filter.is_within_size_constraints());
+
+        let filter = BloomFilter::new(1, 1e-100, 0, BloomFlags::None);
+        assert_eq!(filter.to_filter_load().hash_funcs, MAX_HASH_FUNCS);
+
+        // An empty filter matches everything.
+        let empty = BloomFilter::from(FilterLoad {
+            filter: vec![],
+            hash_funcs: 0,
+            tweak: 0,
+            flags: BloomFlags::None,
+        });
+        assert!(empty.contains(b"anything"));
+    }
+
+    #[test]
+    fn relevant_outputs_and_spends() {
+        let key_hash = PubkeyHash::from_byte_array([7; 20]);
+        let funding = tx(
+            vec![TxIn::default()],
+            vec![TxOut { value: 1000, script_pubkey: ScriptBuf::new_p2pkh(&key_hash) }],
+            None,
+        );
+        let spend = tx(
+            vec![TxIn {
+                previous_output: OutPoint { txid: funding.txid(), vout: 0 },
+                ..Default::default()
+            }],
+            vec![],
+            None,
+        );
+
+        // With `All`, the matched output is added and its spend matches too.
+        let mut filter = BloomFilter::new(10, 0.000_001, 5, BloomFlags::All);
+        filter.insert(key_hash.as_byte_array());
+        assert!(!filter.is_relevant_and_update(&spend));
+        assert!(filter.is_relevant_and_update(&funding));
+        assert!(filter.contains_outpoint(&OutPoint { txid: funding.txid(), vout: 0 }));
+        assert!(filter.is_relevant_and_update(&spend));
+
+        // P2PKH outputs are not added with `PubkeyOnly`.
+        let mut filter = BloomFilter::new(10, 0.000_001, 5, BloomFlags::PubkeyOnly);
+        filter.insert(key_hash.as_byte_array());
+        assert!(filter.is_relevant_and_update(&funding));
+        assert!(!filter.is_relevant_and_update(&spend));
+
+        // Matching by txid.
+        let mut filter = BloomFilter::new(10, 0.000_001, 5, BloomFlags::None);
+        filter.insert(spend.txid().as_byte_array());
+        assert!(filter.is_relevant_and_update(&spend));
+        assert!(!filter.is_relevant_and_update(&funding));
+    }
+
+    #[test]
+    fn multisig_detection() {
+        let key = [2u8; 33];
+        let script = ScriptBuf::builder()
+            .push_int(1)
+            .push_slice(key)
+            .push_slice(key)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert!(is_multisig(&script));
+        let script = ScriptBuf::builder()
+            .push_int(1)
+            .push_slice(key)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert!(!is_multisig(&script));
+        assert!(!is_multisig(&ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([7; 20]))));
+    }
+
+    #[test]
+    fn provider_transactions() {
+        let pro_tx_hash = Txid::from_byte_array([9; 32]);
+        let payout = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([3; 20]));
+        let update_service = tx(
+            vec![],
+            vec![],
+            Some(TransactionPayload::ProviderUpdateServicePayloadType(
+                ProviderUpdateServicePayload {
+                    version: 1,
+                    pro_tx_hash,
+                    ip_address: 0,
+                    port: 9999,
+                    script_payout: payout.clone(),
+                    inputs_hash: InputsHash::all_zeros(),
+                    payload_sig: BLSSignature::from([0; 96]),
+                },
+            )),
+        );
+        let revocation = tx(
+            vec![],
+            vec![],
+            Some(TransactionPayload::ProviderUpdateRevocationPayloadType(
+                ProviderUpdateRevocationPayload {
+                    version: 1,
+                    pro_tx_hash,
+                    reason: 0,
+                    inputs_hash: InputsHash::all_zeros(),
+                    payload_sig: BLSSignature::from([0; 96]),
+                },
+            )),
+        );
+
+        // The operator payout matches, and adds the proTxHash with `All`.
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        filter.insert(&[3; 20]);
+        assert!(!filter.is_relevant_and_update(&revocation));
+        assert!(filter.is_relevant_and_update(&update_service));
+        assert!(filter.contains(pro_tx_hash.as_byte_array()));
+        assert!(filter.is_relevant_and_update(&revocation));
+
+        // Not with `None`.
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::None);
+        filter.insert(&[3; 20]);
+        assert!(filter.is_relevant_and_update(&update_service));
+        assert!(!filter.is_relevant_and_update(&revocation));
+
+        // Matching by proTxHash.
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::None);
+        filter.insert(pro_tx_hash.as_byte_array());
+        assert!(filter.is_relevant_and_update(&update_service));
+        assert!(filter.is_relevant_and_update(&revocation));
+    }
+
+    #[test]
+    fn asset_lock_credit_outputs() {
+        let credit = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([4; 20]));
+        let asset_lock = tx(
+            vec![],
+            vec![TxOut { value: 0, script_pubkey: ScriptBuf::new_op_return(&[]) }],
+            Some(TransactionPayload::AssetLockPayloadType(AssetLockPayload {
+                version: 1,
+                credit_outputs: vec![
+                    TxOut { value: 1, script_pubkey: ScriptBuf::new() },
+                    TxOut { value: 2, script_pubkey: credit },
+                ],
+            })),
+        );
+        let txid = asset_lock.txid();
+        let spend = |vout| {
+            let input = TxIn { previous_output: OutPoint { txid, vout }, ..Default::default() };
+            tx(vec![input], vec![], None)
+        };
+
+        // The credit output matches, and adds its outpoint with `All`.
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        assert!(!filter.is_relevant_and_update(&asset_lock));
+        filter.insert(&[4; 20]);
+        assert!(filter.is_relevant_and_update(&asset_lock));
+        assert!(filter.contains_outpoint(&OutPoint { txid, vout: 1 }));
+        assert!(!filter.contains_outpoint(&OutPoint { txid, vout: 0 }));
+        assert!(filter.is_relevant_and_update(&spend(1)));
+
+        // Not with `None`, nor with `PubkeyOnly` for a P2PKH credit output.
+        for flags in [BloomFlags::None, BloomFlags::PubkeyOnly] {
+            let mut filter = BloomFilter::new(10, 0.000_001, 0, flags);
+            filter.insert(&[4; 20]);
+            assert!(filter.is_relevant_and_update(&asset_lock));
+            assert!(!filter.is_relevant_and_update(&spend(1)));
+        }
+    }
+}
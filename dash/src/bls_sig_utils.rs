@@ -13,8 +13,8 @@
 //
 
 //! Dash BLS elements
-//! Convenience wrappers around fixed size arrays of 48 and 96 bytes representing the public key
-//! and signature.
+//! Convenience wrappers around fixed size arrays of 32, 48 and 96 bytes representing the secret
+//! key, public key and signature.
 //!
 
 use hex::{FromHexError, ToHex};
@@ -65,6 +65,17 @@ pub struct BLSSignature([u8; 96]);
 impl_array_newtype!(BLSSignature, u8, 96);
 impl_bytes_newtype!(BLSSignature, 96);
 
+/// A BLS Secret key is 32 bytes in the scheme used for Dash Core
+///
+/// On the wire these only appear as the secret key shares a DKG participant reveals when
+/// justifying itself against complaints.
+#[rustversion::attr(since(1.48), derive(PartialEq, Eq, Ord, PartialOrd, Hash))]
+#[derive(Clone, Copy)]
+pub struct BLSSecretKey([u8; 32]);
+
+impl_array_newtype!(BLSSecretKey, u8, 32);
+impl_bytes_newtype!(BLSSecretKey, 32);
+
 macro_rules! impl_elementencode {
     ($element:ident, $len:expr) => {
         impl $crate::consensus::Encodable for $element {
@@ -131,6 +142,60 @@ macro_rules! impl_eq_ord_hash {
 impl_eq_ord_hash!(BLSPublicKey, 48);
 #[rustversion::before(1.48)]
 impl_eq_ord_hash!(BLSSignature, 96);
+#[rustversion::before(1.48)]
+impl_eq_ord_hash!(BLSSecretKey, 32);
 
 impl_elementencode!(BLSPublicKey, 48);
 impl_elementencode!(BLSSignature, 96);
+impl_elementencode!(BLSSecretKey, 32);
+
+#[cfg(feature = "bls")]
+impl BLSPublicKey {
+    /// Decodes the compressed G1 point into a `blsful` public key.
+    pub fn to_bls_public_key(
+        &self,
+    ) -> Result<blsful::PublicKey<blsful::Bls12381G2Impl>, blsful::BlsError> {
+        blsful::PublicKey::try_from(&self.0[..])
+    }
+}
+
+#[cfg(feature = "bls")]
+impl From<&blsful::PublicKey<blsful::Bls12381G2Impl>> for BLSPublicKey {
+    fn from(public_key: &blsful::PublicKey<blsful::Bls12381G2Impl>) -> Self {
+        let mut bytes = [0u8; 48];
+        bytes.copy_from_slice(&crate::prelude::Vec::<u8>::from(public_key));
+        BLSPublicKey(bytes)
+    }
+}
+
+#[cfg(feature = "bls")]
+impl BLSSignature {
+    /// Decodes the compressed G2 point into a `blsful` signature using the basic scheme, which is
+    /// the scheme used by Dash Core since v19.
+    pub fn to_bls_signature(
+        &self,
+    ) -> Result<blsful::Signature<blsful::Bls12381G2Impl>, blsful::BlsError> {
+        let point: Option<blsful::inner_types::G2Projective> =
+            blsful::inner_types::G2Projective::from_compressed(&self.0).into();
+        point
+            .map(blsful::Signature::Basic)
+            .ok_or_else(|| blsful::BlsError::InvalidInputs("invalid BLS signature".into()))
+    }
+
+    /// Verifies this signature over `msg` against `public_key`.
+    ///
+    /// Returns `false` if either element fails to decode or the signature does not match.
+    pub fn verify(&self, public_key: &BLSPublicKey, msg: &[u8]) -> bool {
+        match (self.to_bls_signature(), public_key.to_bls_public_key()) {
+            (Ok(signature), Ok(public_key)) => signature.verify(&public_key, msg).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "bls")]
+impl From<&blsful::Signature<blsful::Bls12381G2Impl>> for BLSSignature {
+    fn from(signature: &blsful::Signature<blsful::Bls12381G2Impl>) -> Self {
+        BLSSignature(signature.as_raw_value().to_compressed())
+    }
+}
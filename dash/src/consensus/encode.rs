@@ -40,13 +40,19 @@ use hashes::{Hash, hash160, sha256, sha256d};
 use internals::write_err;
 
 use crate::bip152::{PrefilledTransaction, ShortId};
+use crate::bls_sig_utils::{BLSPublicKey, BLSSecretKey, BLSSignature};
 use crate::blockdata::transaction::Transaction;
-use crate::hash_types::{BlockHash, FilterHash, FilterHeader, TxMerkleNode};
+use crate::blockdata::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+use crate::blockdata::utxo::{TxUndo, Utxo};
+use crate::hash_types::{BlockHash, FilterHash, FilterHeader, ProTxHash, TxMerkleNode};
 use crate::io::{self, Cursor, Read};
 #[cfg(feature = "std")]
 use crate::network::{
     address::{AddrV2Message, Address},
     message_blockdata::Inventory,
+    message_qrinfo::QuorumSnapshot,
+    message_signing::{BatchedSigShares, SigSessionAnnouncement, SigShare, SigSharesInv},
+    message_sml::{DeletedQuorum, MasternodeListEntry, MnListDiff, QuorumCLSigObject},
 };
 use crate::prelude::*;
 use crate::taproot::TapLeafHash;
@@ -668,11 +674,20 @@ impl_vec!(TxOut);
 impl_vec!(TxIn);
 impl_vec!(Vec<u8>);
 impl_vec!(u64);
+impl_vec!(u16);
+impl_vec!(i32);
 impl_vec!(TapLeafHash);
 impl_vec!(VarInt);
 impl_vec!(ShortId);
 impl_vec!(OutPoint);
 impl_vec!(PrefilledTransaction);
+impl_vec!(Utxo);
+impl_vec!(TxUndo);
+impl_vec!(ProTxHash);
+impl_vec!(QuorumFinalizationCommitment);
+impl_vec!(BLSPublicKey);
+impl_vec!((u32, BLSSecretKey));
+impl_vec!((u16, BLSSignature));
 
 #[cfg(feature = "std")]
 impl_vec!(Inventory);
@@ -680,6 +695,24 @@ impl_vec!(Inventory);
 impl_vec!((u32, Address));
 #[cfg(feature = "std")]
 impl_vec!(AddrV2Message);
+#[cfg(feature = "std")]
+impl_vec!(MasternodeListEntry);
+#[cfg(feature = "std")]
+impl_vec!(DeletedQuorum);
+#[cfg(feature = "std")]
+impl_vec!(QuorumCLSigObject);
+#[cfg(feature = "std")]
+impl_vec!(MnListDiff);
+#[cfg(feature = "std")]
+impl_vec!(QuorumSnapshot);
+#[cfg(feature = "std")]
+impl_vec!(SigSessionAnnouncement);
+#[cfg(feature = "std")]
+impl_vec!(SigSharesInv);
+#[cfg(feature = "std")]
+impl_vec!(BatchedSigShares);
+#[cfg(feature = "std")]
+impl_vec!(SigShare);
 
 pub(crate) fn consensus_encode_with_size<S: io::Write>(
     data: &[u8],
@@ -22,6 +22,8 @@ pub mod encode;
 pub mod params;
 #[cfg(feature = "serde")]
 pub mod serde;
+pub mod validation;
+pub mod versionbits;
 
 pub use self::encode::{
     Decodable, Encodable, ReadExt, WriteExt, deserialize, deserialize_partial, serialize,
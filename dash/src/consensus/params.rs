@@ -22,6 +22,8 @@
 //!
 
 use crate::Work;
+use crate::consensus::versionbits::Deployment;
+use crate::llmq::LLMQType;
 use crate::network::constants::Network;
 
 /// Parameters that influence chain consensus.
@@ -38,12 +40,19 @@ pub struct Params {
     pub bip65_height: u32,
     /// Block height at which BIP66 becomes active.
     pub bip66_height: u32,
+    /// Block height at which DIP1 (2MB blocks) becomes active.
+    pub dip0001_height: u32,
+    /// Block height at which DIP3 (deterministic masternode lists and special transactions)
+    /// becomes active.
+    pub dip0003_height: u32,
     /// Minimum blocks including miner confirmation of the total of 2016 blocks in a retargeting period,
     /// (nPowTargetTimespan / nPowTargetSpacing) which is also used for BIP9 deployments.
     /// Examples: 1916 for 95%, 1512 for testchains.
     pub rule_change_activation_threshold: u32,
     /// Number of blocks with the same set of rules.
     pub miner_confirmation_window: u32,
+    /// Version bits deployments.
+    pub deployments: Vec<Deployment>,
     /// Proof of work limit value. It contains the lowest possible difficulty.
     ///
     /// Note that this value differs from Bitcoin Core's powLimit field in that this value is
@@ -61,6 +70,10 @@ pub struct Params {
     pub allow_min_difficulty_blocks: bool,
     /// Determines whether retargeting is disabled for this network or not.
     pub no_pow_retargeting: bool,
+    /// Quorum type used to sign ChainLocks.
+    pub llmq_type_chain_locks: LLMQType,
+    /// Quorum type used to sign InstantSend locks.
+    pub llmq_type_instant_send: LLMQType,
 }
 
 impl Params {
@@ -70,30 +83,40 @@ impl Params {
             Network::Dash => Params {
                 network: Network::Dash,
                 bip16_time: 1333238400,                 // Apr 1 2012
-                bip34_height: 227931, // 000000000000024b89b42a942fe0d9fea3bb44ab7bd1b19115dd6a759c0808b8
-                bip65_height: 388381, // 000000000000000004c2b624ed5d7756c508d90fd0da2c7c679febfa6c4735f0
-                bip66_height: 363725, // 00000000000000000379eaa19dce8c9b722d46ae6a57c2f1a988119488b50931
+                bip34_height: 951, // 000001f35e70f7c5705f64c6c5cc3dea9449e74d5b5c7cf74dad1bcca14a8012
+                bip65_height: 619382, // 00000000000076d8fcea02ec0963de4abfd01e771fec0863f960c2c64fe6f357
+                bip66_height: 245817, // 00000000000b1fa2dfa312863570e13fae9ca7b5566cb27e55422620b469aefa
+                dip0001_height: 782208,
+                dip0003_height: 1028160,
                 rule_change_activation_threshold: 1916, // 95%
                 miner_confirmation_window: 2016,
+                deployments: Deployment::for_network(network),
                 pow_limit: Work::MAINNET_MIN,
                 pow_target_spacing: 10 * 60,            // 10 minutes.
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: false,
                 no_pow_retargeting: false,
+                llmq_type_chain_locks: LLMQType::Llmqtype400_60,
+                llmq_type_instant_send: LLMQType::Llmqtype60_75,
             },
             Network::Testnet => Params {
                 network: Network::Testnet,
                 bip16_time: 1333238400,                 // Apr 1 2012
-                bip34_height: 21111, // 0000000023b3a96d3484e5abb3755c413e7d41500f8e2a5c3f0dd01299cd8ef8
-                bip65_height: 581885, // 00000000007f6655f22f98e72ed80d8b06dc761d5da09df0fa1dc4be4f861eb6
-                bip66_height: 330776, // 000000002104c8c45e99a8853285a3b592602a3ccde2b832481da85e9e4ba182
+                bip34_height: 76, // 000008ebb1db2598e897d17275285767717c6acfeac4c73def49fbea1ddcbcb6
+                bip65_height: 2431, // 0000039cf01242c7f921dcb4806a5994bc003b48c1973ae0c89b67809c2bb2ab
+                bip66_height: 2075, // 0000002acdd29a14583540cb72e1c5cc83783560e38fa7081495d474fe1671f7
+                dip0001_height: 5500,
+                dip0003_height: 7000,
                 rule_change_activation_threshold: 1512, // 75%
                 miner_confirmation_window: 2016,
+                deployments: Deployment::for_network(network),
                 pow_limit: Work::TESTNET_MIN,
                 pow_target_spacing: 10 * 60,            // 10 minutes.
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: false,
+                llmq_type_chain_locks: LLMQType::Llmqtype50_60,
+                llmq_type_instant_send: LLMQType::Llmqtype60_75,
             },
             Network::Devnet => Params {
                 network: Network::Devnet,
@@ -101,27 +124,37 @@ impl Params {
                 bip34_height: 1,
                 bip65_height: 1,
                 bip66_height: 1,
+                dip0001_height: 2,
+                dip0003_height: 2,
                 rule_change_activation_threshold: 1916, // 95%
                 miner_confirmation_window: 2016,
+                deployments: Deployment::for_network(network),
                 pow_limit: Work::DEVNET_MIN,
                 pow_target_spacing: 10 * 60,            // 10 minutes.
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: false,
                 no_pow_retargeting: false,
+                llmq_type_chain_locks: LLMQType::LlmqtypeDevnet,
+                llmq_type_instant_send: LLMQType::LlmqtypeDevnetDIP0024,
             },
             Network::Regtest => Params {
                 network: Network::Regtest,
                 bip16_time: 1333238400,  // Apr 1 2012
-                bip34_height: 100000000, // not activated on regtest
-                bip65_height: 1351,
-                bip66_height: 1251,                    // used only in rpc tests
+                bip34_height: 1,         // always active unless overridden
+                bip65_height: 1,
+                bip66_height: 1,
+                dip0001_height: 2000,
+                dip0003_height: 432,
                 rule_change_activation_threshold: 108, // 75%
                 miner_confirmation_window: 144,
+                deployments: Deployment::for_network(network),
                 pow_limit: Work::REGTEST_MIN,
                 pow_target_spacing: 10 * 60,            // 10 minutes.
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: true,
+                llmq_type_chain_locks: LLMQType::LlmqtypeTest,
+                llmq_type_instant_send: LLMQType::LlmqtypeTestDIP0024,
             },
         }
     }
@@ -0,0 +1,550 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Block validation.
+//!
+//! This module implements the context-free and contextual block checks Dash Core performs in
+//! `CheckBlock` and `ContextualCheckBlock`/`ContextualCheckBlockHeader` that can be done without
+//! access to the UTXO set or the masternode list.
+//!
+
+use core::fmt;
+
+use crate::blockdata::block::{Bip34Error, Block, Version};
+use crate::blockdata::script::Builder;
+use crate::blockdata::transaction::special_transaction::{TransactionPayload, TransactionType};
+use crate::consensus::Params;
+use crate::hash_types::Txid;
+use crate::prelude::*;
+
+/// Maximum serialized block size before DIP1 activation.
+pub const MAX_LEGACY_BLOCK_SIZE: usize = 1_000_000;
+/// Maximum serialized block size once DIP1 is active.
+pub const MAX_DIP0001_BLOCK_SIZE: usize = 2_000_000;
+/// How far in the future, in seconds, a block time may be relative to the adjusted time.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Returns the maximum serialized block size.
+pub fn max_block_size(dip0001_active: bool) -> usize {
+    if dip0001_active { MAX_DIP0001_BLOCK_SIZE } else { MAX_LEGACY_BLOCK_SIZE }
+}
+
+/// Returns the maximum number of legacy signature operations in a block.
+pub fn max_block_sigops(dip0001_active: bool) -> usize { max_block_size(dip0001_active) / 50 }
+
+/// The chain state a block is validated against.
+#[derive(Debug, Clone, Copy)]
+pub struct ChainContext<'a> {
+    /// Consensus parameters of the chain.
+    pub params: &'a Params,
+    /// Height of the block being validated.
+    pub height: u32,
+    /// Median time past of the previous block.
+    pub median_time_past: u32,
+    /// Network-adjusted current time.
+    pub adjusted_time: u32,
+    /// Whether the `v20` deployment is active for the block, see
+    /// [`VersionBitsTracker`](crate::consensus::versionbits::VersionBitsTracker).
+    pub v20_active: bool,
+}
+
+impl ChainContext<'_> {
+    /// Whether DIP1 (2MB blocks) is active for the block.
+    pub fn dip0001_active(&self) -> bool { self.height >= self.params.dip0001_height }
+
+    /// Whether DIP3 (special transactions) is active for the block.
+    pub fn dip0003_active(&self) -> bool { self.height >= self.params.dip0003_height }
+
+    /// Returns whether transactions of type `tx_type` may be included in the block.
+    pub fn is_tx_type_active(&self, tx_type: TransactionType) -> bool {
+        match tx_type {
+            TransactionType::Classic => true,
+            TransactionType::AssetLock | TransactionType::AssetUnlock => self.v20_active,
+            _ => self.dip0003_active(),
+        }
+    }
+}
+
+/// A block validation failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BlockValidationError {
+    /// The block contains no transactions.
+    NoTransactions,
+    /// The header merkle root does not commit to the transactions.
+    BadMerkleRoot,
+    /// The serialized block exceeds the maximum block size.
+    OversizedBlock {
+        /// Serialized size of the block.
+        size: usize,
+        /// Maximum allowed size.
+        max: usize,
+    },
+    /// The block exceeds the legacy signature operations limit.
+    TooManySigops {
+        /// Number of signature operations in the block.
+        count: usize,
+        /// Maximum allowed number of signature operations.
+        max: usize,
+    },
+    /// The first transaction is not a coinbase.
+    FirstTransactionNotCoinbase,
+    /// A transaction other than the first one is a coinbase.
+    MultipleCoinbases {
+        /// Index of the offending transaction.
+        index: usize,
+    },
+    /// The coinbase is not a CbTx special transaction although DIP3 is active.
+    CoinbaseNotCbTx,
+    /// The CbTx payload version is too low for the active deployments.
+    BadCoinbasePayloadVersion(u16),
+    /// The CbTx payload height does not match the block height.
+    CoinbaseHeightMismatch {
+        /// Height of the block.
+        expected: u32,
+        /// Height found in the coinbase.
+        found: u64,
+    },
+    /// The BIP34 height could not be read from the coinbase.
+    Bip34(Bip34Error),
+    /// Two transactions have the same txid.
+    DuplicateTransaction(Txid),
+    /// The block version is no longer accepted at this height.
+    ObsoleteVersion(Version),
+    /// The block time is not after the median time past.
+    TimeTooOld {
+        /// Block time.
+        time: u32,
+        /// Median time past of the previous block.
+        median_time_past: u32,
+    },
+    /// The block time is too far in the future.
+    TimeTooNew {
+        /// Block time.
+        time: u32,
+        /// Maximum allowed block time.
+        max: u32,
+    },
+    /// A special transaction appears before its deployment is active.
+    SpecialTransactionNotActive {
+        /// Index of the offending transaction.
+        index: usize,
+        /// Type of the offending transaction.
+        tx_type: TransactionType,
+    },
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BlockValidationError::*;
+
+        match *self {
+            NoTransactions => f.write_str("block contains no transactions"),
+            BadMerkleRoot => f.write_str("block merkle root mismatch"),
+            OversizedBlock { size, max } =>
+                write!(f, "block size {} exceeds the maximum of {}", size, max),
+            TooManySigops { count, max } =>
+                write!(f, "block has {} sigops, exceeding the maximum of {}", count, max),
+            FirstTransactionNotCoinbase => f.write_str("first transaction is not a coinbase"),
+            MultipleCoinbases { index } => write!(f, "transaction {} is a second coinbase", index),
+            CoinbaseNotCbTx => f.write_str("coinbase is not a CbTx special transaction"),
+            BadCoinbasePayloadVersion(version) =>
+                write!(f, "invalid coinbase payload version {}", version),
+            CoinbaseHeightMismatch { expected, found } =>
+                write!(f, "coinbase height {} does not match block height {}", found, expected),
+            Bip34(ref e) => write!(f, "invalid BIP34 coinbase height: {}", e),
+            DuplicateTransaction(txid) => write!(f, "duplicate transaction {}", txid),
+            ObsoleteVersion(version) =>
+                write!(f, "block version {} is obsolete", version.to_consensus()),
+            TimeTooOld { time, median_time_past } =>
+                write!(f, "block time {} is not after median time past {}", time, median_time_past),
+            TimeTooNew { time, max } =>
+                write!(f, "block time {} is after the maximum of {}", time, max),
+            SpecialTransactionNotActive { index, tx_type } =>
+                write!(f, "transaction {} of type {} is not active yet", index, tx_type),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BlockValidationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlockValidationError::Bip34(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Bip34Error> for BlockValidationError {
+    fn from(e: Bip34Error) -> Self { BlockValidationError::Bip34(e) }
+}
+
+/// Validates a block against the chain context it is connected to.
+///
+/// Proof of work, the masternode list and the UTXO set are not checked.
+pub fn validate_block(block: &Block, ctx: &ChainContext) -> Result<(), BlockValidationError> {
+    check_header(block, ctx)?;
+
+    if block.txdata.is_empty() {
+        return Err(BlockValidationError::NoTransactions);
+    }
+    if !block.check_merkle_root() {
+        return Err(BlockValidationError::BadMerkleRoot);
+    }
+
+    let size = block.size();
+    let max = max_block_size(ctx.dip0001_active());
+    if size > max {
+        return Err(BlockValidationError::OversizedBlock { size, max });
+    }
+
+    if !block.txdata[0].is_coin_base() {
+        return Err(BlockValidationError::FirstTransactionNotCoinbase);
+    }
+    if let Some(index) = block.txdata.iter().skip(1).position(|tx| tx.is_coin_base()) {
+        return Err(BlockValidationError::MultipleCoinbases { index: index + 1 });
+    }
+
+    let mut txids = BTreeSet::new();
+    let mut sigops = 0;
+    for (index, tx) in block.txdata.iter().enumerate() {
+        if !txids.insert(tx.txid()) {
+            return Err(BlockValidationError::DuplicateTransaction(tx.txid()));
+        }
+        let tx_type = tx.tx_type();
+        if !ctx.is_tx_type_active(tx_type) {
+            return Err(BlockValidationError::SpecialTransactionNotActive { index, tx_type });
+        }
+        sigops += tx.input.iter().map(|txin| txin.script_sig.count_sigops_legacy()).sum::<usize>();
+        sigops +=
+            tx.output.iter().map(|txout| txout.script_pubkey.count_sigops_legacy()).sum::<usize>();
+    }
+    let max = max_block_sigops(ctx.dip0001_active());
+    if sigops > max {
+        return Err(BlockValidationError::TooManySigops { count: sigops, max });
+    }
+
+    check_coinbase_height(block, ctx)
+}
+
+fn check_header(block: &Block, ctx: &ChainContext) -> Result<(), BlockValidationError> {
+    let version = block.header.version.to_consensus();
+    let params = ctx.params;
+    if (version < 2 && ctx.height >= params.bip34_height)
+        || (version < 3 && ctx.height >= params.bip66_height)
+        || (version < 4 && ctx.height >= params.bip65_height)
+    {
+        return Err(BlockValidationError::ObsoleteVersion(block.header.version));
+    }
+
+    let time = block.header.time;
+    if time <= ctx.median_time_past {
+        return Err(BlockValidationError::TimeTooOld {
+            time,
+            median_time_past: ctx.median_time_past,
+        });
+    }
+    let max = ctx.adjusted_time.saturating_add(MAX_FUTURE_BLOCK_TIME);
+    if time > max {
+        return Err(BlockValidationError::TimeTooNew { time, max });
+    }
+    Ok(())
+}
+
+fn check_coinbase_height(block: &Block, ctx: &ChainContext) -> Result<(), BlockValidationError> {
+    let coinbase = &block.txdata[0];
+    if ctx.dip0003_active() {
+        let payload = match &coinbase.special_transaction_payload {
+            Some(TransactionPayload::CoinbasePayloadType(payload)) => payload,
+            _ => return Err(BlockValidationError::CoinbaseNotCbTx),
+        };
+        if ctx.v20_active && payload.version < 3 {
+            return Err(BlockValidationError::BadCoinbasePayloadVersion(payload.version));
+        }
+        if payload.height != ctx.height {
+            return Err(BlockValidationError::CoinbaseHeightMismatch {
+                expected: ctx.height,
+                found: payload.height.into(),
+            });
+        }
+    }
+    if ctx.height >= ctx.params.bip34_height {
+        // Like Dash Core, the script must start with the height as a script number push, which
+        // is `OP_1` to `OP_16` for the first heights.
+        let expected = Builder::new().push_int(i64::from(ctx.height)).into_script();
+        if !coinbase.input[0].script_sig.as_bytes().starts_with(expected.as_bytes()) {
+            let found = block.bip34_block_height()?;
+            return Err(BlockValidationError::CoinbaseHeightMismatch {
+                expected: ctx.height,
+                found,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::blockdata::block::Header;
+    use crate::blockdata::script::ScriptBuf;
+    use crate::blockdata::transaction::Transaction;
+    use crate::blockdata::transaction::outpoint::OutPoint;
+    use crate::blockdata::transaction::special_transaction::asset_lock::AssetLockPayload;
+    use crate::blockdata::transaction::special_transaction::coinbase::CoinbasePayload;
+    use crate::blockdata::transaction::txin::TxIn;
+    use crate::blockdata::transaction::txout::TxOut;
+    use crate::hash_types::{BlockHash, MerkleRootMasternodeList, MerkleRootQuorums, TxMerkleNode};
+    use crate::pow::CompactTarget;
+    use crate::{Network, opcodes};
+
+    const HEIGHT: u32 = 2500;
+    const TIME: u32 = 1_700_000_000;
+
+    fn coinbase(height: u32, payload_version: u16) -> Transaction {
+        Transaction {
+            version: 3,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(height as i64).into_script(),
+                ..Default::default()
+            }],
+            output: vec![TxOut { value: 50, script_pubkey: ScriptBuf::new() }],
+            special_transaction_payload: Some(TransactionPayload::CoinbasePayloadType(
+                CoinbasePayload {
+                    version: payload_version,
+                    height,
+                    merkle_root_masternode_list: MerkleRootMasternodeList::all_zeros(),
+                    merkle_root_quorums: MerkleRootQuorums::all_zeros(),
+                    best_cl_height: Some(0),
+                    best_cl_signature: Some([0; 96].into()),
+                    asset_locked_amount: Some(0),
+                },
+            )),
+        }
+    }
+
+    fn spend(n: u8) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&[n]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 1,
+                script_pubkey: Builder::new().push_opcode(opcodes::all::OP_CHECKSIG).into_script(),
+            }],
+            special_transaction_payload: None,
+        }
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: Header {
+                version: Version::from_consensus(0x20000000),
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: TIME,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap_or(block.header.merkle_root);
+        block
+    }
+
+    fn context(params: &Params) -> ChainContext<'_> {
+        ChainContext {
+            params,
+            height: HEIGHT,
+            median_time_past: TIME - 600,
+            adjusted_time: TIME,
+            v20_active: true,
+        }
+    }
+
+    #[test]
+    fn valid_block() {
+        let params = Params::new(Network::Regtest);
+        let ctx = context(&params);
+        validate_block(&block(vec![coinbase(HEIGHT, 3), spend(1), spend(2)]), &ctx).unwrap();
+    }
+
+    #[test]
+    fn structural_errors() {
+        let params = Params::new(Network::Regtest);
+        let ctx = context(&params);
+
+        assert_eq!(validate_block(&block(vec![]), &ctx), Err(BlockValidationError::NoTransactions));
+
+        let mut bad_merkle = block(vec![coinbase(HEIGHT, 3)]);
+        bad_merkle.header.merkle_root = TxMerkleNode::all_zeros();
+        assert_eq!(validate_block(&bad_merkle, &ctx), Err(BlockValidationError::BadMerkleRoot));
+
+        assert_eq!(
+            validate_block(&block(vec![spend(1), coinbase(HEIGHT, 3)]), &ctx),
+            Err(BlockValidationError::FirstTransactionNotCoinbase)
+        );
+        assert_eq!(
+            validate_block(&block(vec![coinbase(HEIGHT, 3), coinbase(HEIGHT + 1, 3)]), &ctx),
+            Err(BlockValidationError::MultipleCoinbases { index: 1 })
+        );
+        assert_eq!(
+            validate_block(&block(vec![coinbase(HEIGHT, 3), spend(1), spend(1)]), &ctx),
+            Err(BlockValidationError::DuplicateTransaction(spend(1).txid()))
+        );
+    }
+
+    #[test]
+    fn size_and_sigop_limits() {
+        let params = Params::new(Network::Regtest);
+        let ctx = context(&params);
+
+        let mut big = spend(1);
+        big.output[0].script_pubkey = ScriptBuf::from(vec![0x6a; MAX_LEGACY_BLOCK_SIZE]);
+        let blk = block(vec![coinbase(HEIGHT, 3), big]);
+        validate_block(&blk, &ctx).unwrap();
+        let pre_dip1 = ChainContext { height: params.dip0001_height - 1, ..ctx };
+        assert!(matches!(
+            validate_block(&blk, &pre_dip1),
+            Err(BlockValidationError::OversizedBlock { max: MAX_LEGACY_BLOCK_SIZE, .. })
+        ));
+
+        let mut sigops = spend(1);
+        sigops.output[0].script_pubkey =
+            ScriptBuf::from(vec![opcodes::all::OP_CHECKMULTISIG.to_u8(); 2001]);
+        assert_eq!(
+            validate_block(&block(vec![coinbase(HEIGHT, 3), sigops]), &ctx),
+            Err(BlockValidationError::TooManySigops { count: 40_020, max: 40_000 })
+        );
+    }
+
+    #[test]
+    fn coinbase_rules() {
+        let params = Params::new(Network::Regtest);
+        let ctx = context(&params);
+
+        let mut classic = coinbase(HEIGHT, 3);
+        classic.special_transaction_payload = None;
+        assert_eq!(
+            validate_block(&block(vec![classic.clone()]), &ctx),
+            Err(BlockValidationError::CoinbaseNotCbTx)
+        );
+        let pre_dip3 = ChainContext { height: params.dip0003_height - 1, ..ctx };
+        classic.input[0].script_sig =
+            Builder::new().push_int(i64::from(pre_dip3.height)).into_script();
+        validate_block(&block(vec![classic]), &pre_dip3).unwrap();
+
+        assert_eq!(
+            validate_block(&block(vec![coinbase(HEIGHT + 1, 3)]), &ctx),
+            Err(BlockValidationError::CoinbaseHeightMismatch {
+                expected: HEIGHT,
+                found: u64::from(HEIGHT) + 1
+            })
+        );
+        assert_eq!(
+            validate_block(&block(vec![coinbase(HEIGHT, 2)]), &ctx),
+            Err(BlockValidationError::BadCoinbasePayloadVersion(2))
+        );
+
+        let mut cb = coinbase(HEIGHT, 3);
+        cb.input[0].script_sig = Builder::new().push_int(17).into_script();
+        assert_eq!(
+            validate_block(&block(vec![cb.clone()]), &ctx),
+            Err(BlockValidationError::CoinbaseHeightMismatch { expected: HEIGHT, found: 17 })
+        );
+        let mut bip34_params = params.clone();
+        bip34_params.bip34_height = HEIGHT + 1;
+        let mut pre_bip34 = ChainContext { params: &bip34_params, ..ctx };
+        validate_block(&block(vec![cb]), &pre_bip34).unwrap();
+        pre_bip34.height = HEIGHT + 1;
+        assert!(validate_block(&block(vec![coinbase(HEIGHT + 1, 3)]), &pre_bip34).is_ok());
+    }
+
+    #[test]
+    fn header_rules() {
+        let params = Params::new(Network::Regtest);
+        let ctx = context(&params);
+
+        let mut blk = block(vec![coinbase(HEIGHT, 3)]);
+        blk.header.time = ctx.median_time_past;
+        assert!(matches!(validate_block(&blk, &ctx), Err(BlockValidationError::TimeTooOld { .. })));
+        blk.header.time = TIME + MAX_FUTURE_BLOCK_TIME + 1;
+        assert!(matches!(validate_block(&blk, &ctx), Err(BlockValidationError::TimeTooNew { .. })));
+
+        let mut bip65_params = params.clone();
+        bip65_params.bip65_height = 0;
+        let mut blk = block(vec![coinbase(HEIGHT, 3)]);
+        blk.header.version = Version::from_consensus(3);
+        assert_eq!(
+            validate_block(&blk, &ChainContext { params: &bip65_params, ..ctx }),
+            Err(BlockValidationError::ObsoleteVersion(Version::from_consensus(3)))
+        );
+    }
+
+    #[test]
+    fn regtest_soft_forks() {
+        // BIP34, BIP65 and BIP66 are active from the first block on regtest.
+        let params = Params::new(Network::Regtest);
+        assert_eq!((params.bip34_height, params.bip65_height, params.bip66_height), (1, 1, 1));
+        let ctx = ChainContext { height: 1, ..context(&params) };
+
+        // Before DIP3, so a classic coinbase whose script starts with `OP_1`.
+        let mut cb = coinbase(1, 3);
+        cb.special_transaction_payload = None;
+        validate_block(&block(vec![cb.clone()]), &ctx).unwrap();
+
+        let mut blk = block(vec![cb.clone()]);
+        blk.header.version = Version::from_consensus(3);
+        assert_eq!(
+            validate_block(&blk, &ctx),
+            Err(BlockValidationError::ObsoleteVersion(Version::from_consensus(3)))
+        );
+
+        cb.input[0].script_sig = Builder::new().push_int(17).into_script();
+        assert_eq!(
+            validate_block(&block(vec![cb]), &ctx),
+            Err(BlockValidationError::CoinbaseHeightMismatch { expected: 1, found: 17 })
+        );
+    }
+
+    #[test]
+    fn special_transaction_activation() {
+        let params = Params::new(Network::Regtest);
+        let ctx = context(&params);
+
+        let mut asset_lock = spend(1);
+        asset_lock.version = 3;
+        asset_lock.special_transaction_payload =
+            Some(TransactionPayload::AssetLockPayloadType(AssetLockPayload {
+                version: 1,
+                credit_outputs: vec![],
+            }));
+        let blk = block(vec![coinbase(HEIGHT, 2), asset_lock]);
+        assert_eq!(
+            validate_block(&blk, &ChainContext { v20_active: false, ..ctx }),
+            Err(BlockValidationError::SpecialTransactionNotActive {
+                index: 1,
+                tx_type: TransactionType::AssetLock
+            })
+        );
+    }
+}
@@ -0,0 +1,523 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Version bits deployments.
+//!
+//! This module implements the [BIP9](https://github.com/bitcoin/bips/blob/master/bip-0009.mediawiki)
+//! deployment state machine as extended by Dash Core: deployments may use their own window size,
+//! an activation threshold that decreases with every failed attempt, and may require a masternode
+//! hard fork (EHF) signal before miners can start signalling.
+//!
+
+use core::fmt;
+
+use crate::blockdata::block::{Header, Version};
+use crate::consensus::Params;
+use crate::prelude::*;
+
+/// Start time used for deployments that are active from genesis.
+pub const ALWAYS_ACTIVE: i64 = -1;
+/// Start time used for deployments that can never activate.
+pub const NEVER_ACTIVE: i64 = -2;
+/// Timeout used for deployments that never time out.
+pub const NO_TIMEOUT: i64 = i64::MAX;
+
+/// Number of blocks used to compute the median time past.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// A version bits deployment, mirroring Dash Core's `Consensus::BIP9Deployment`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deployment {
+    /// Name of the deployment, as reported by Dash Core.
+    pub name: &'static str,
+    /// Bit position to select the particular bit in the block version.
+    pub bit: u8,
+    /// Median time past at which signalling starts, or [`ALWAYS_ACTIVE`] / [`NEVER_ACTIVE`].
+    pub start_time: i64,
+    /// Median time past at which the deployment fails if it is not locked in.
+    pub timeout: i64,
+    /// Height before which the deployment cannot become active once locked in.
+    pub min_activation_height: u32,
+    /// Window size, or `None` to use [`Params::miner_confirmation_window`].
+    pub window_size: Option<u32>,
+    /// Initial threshold, or `None` to use [`Params::rule_change_activation_threshold`].
+    pub threshold_start: Option<u32>,
+    /// Lowest threshold the dynamic threshold falls off to, or `None` for a fixed threshold.
+    pub threshold_min: Option<u32>,
+    /// Coefficient controlling how fast the threshold falls off.
+    pub falloff_coeff: u32,
+    /// Whether signalling only starts after a masternode hard fork signal for this bit.
+    pub use_ehf: bool,
+}
+
+impl Deployment {
+    /// `v20` deployment (basic BLS scheme, Platform credit pool, ...).
+    pub fn v20(network: crate::Network) -> Deployment {
+        use crate::Network::*;
+        let (start_time, window_size, threshold_start, threshold_min) = match network {
+            Dash => (1700006400, 4032, 3226, 2420),
+            Testnet => (1693526400, 100, 80, 60),
+            Devnet => (1661990400, 120, 80, 60),
+            Regtest => (0, 400, 384, 288),
+        };
+        Deployment {
+            name: "v20",
+            bit: 9,
+            start_time,
+            timeout: NO_TIMEOUT,
+            min_activation_height: 0,
+            window_size: Some(window_size),
+            threshold_start: Some(threshold_start),
+            threshold_min: Some(threshold_min),
+            falloff_coeff: 5,
+            use_ehf: false,
+        }
+    }
+
+    /// `mn_rr` deployment (masternode reward reallocation), gated by a masternode hard fork
+    /// signal.
+    pub fn mn_rr(network: crate::Network) -> Deployment {
+        use crate::Network::*;
+        let (start_time, window_size, threshold_start, threshold_min) = match network {
+            Dash => (1704067200, 4032, 3226, 2420),
+            Testnet => (1693526400, 100, 80, 60),
+            Devnet => (1661990400, 120, 80, 60),
+            Regtest => (0, 12, 9, 7),
+        };
+        Deployment {
+            name: "mn_rr",
+            bit: 10,
+            start_time,
+            timeout: NO_TIMEOUT,
+            min_activation_height: 0,
+            window_size: Some(window_size),
+            threshold_start: Some(threshold_start),
+            threshold_min: Some(threshold_min),
+            falloff_coeff: 5,
+            use_ehf: true,
+        }
+    }
+
+    /// The deployments defined for `network`.
+    pub fn for_network(network: crate::Network) -> Vec<Deployment> {
+        vec![Deployment::v20(network), Deployment::mn_rr(network)]
+    }
+
+    fn window(&self, params: &Params) -> u32 {
+        self.window_size.unwrap_or(params.miner_confirmation_window)
+    }
+
+    /// Returns the threshold for the `attempt`-th window since signalling started.
+    ///
+    /// The threshold falls off quadratically from `threshold_start` towards `threshold_min`.
+    pub fn threshold(&self, params: &Params, attempt: u32) -> u32 {
+        let start = self.threshold_start.unwrap_or(params.rule_change_activation_threshold);
+        let min = match self.threshold_min {
+            Some(min) if min < start && self.falloff_coeff > 0 => min,
+            _ => return start,
+        };
+        let attempt = u64::from(attempt);
+        let falloff = attempt * attempt * u64::from(self.window(params))
+            / 100
+            / u64::from(self.falloff_coeff);
+        let threshold = u64::from(start).saturating_sub(falloff);
+        threshold.max(u64::from(min)) as u32
+    }
+}
+
+/// State of a deployment, see BIP9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ThresholdState {
+    /// First state each deployment starts out as.
+    Defined,
+    /// Blocks are signalling for the deployment.
+    Started,
+    /// Threshold was reached; the deployment activates after one more window.
+    LockedIn,
+    /// Final state once the deployment activated.
+    Active,
+    /// Final state once the deployment timed out.
+    Failed,
+}
+
+impl fmt::Display for ThresholdState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ThresholdState::Defined => "defined",
+            ThresholdState::Started => "started",
+            ThresholdState::LockedIn => "locked_in",
+            ThresholdState::Active => "active",
+            ThresholdState::Failed => "failed",
+        })
+    }
+}
+
+/// Signalling progress of a deployment in the current window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeploymentStatus {
+    /// State for the current window.
+    pub state: ThresholdState,
+    /// Height of the first block of the window in which the current state began.
+    pub since: u32,
+    /// Window size of the deployment.
+    pub period: u32,
+    /// Number of signalling blocks required to lock in during the current window.
+    pub threshold: u32,
+    /// Number of blocks of the current window seen so far.
+    pub elapsed: u32,
+    /// Number of signalling blocks in the current window so far.
+    pub count: u32,
+}
+
+impl DeploymentStatus {
+    /// Whether the deployment can still lock in during the current window.
+    pub fn possible(&self) -> bool { self.period - self.threshold >= self.elapsed - self.count }
+}
+
+/// An error returned by the [`VersionBitsTracker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VersionBitsError {
+    /// The header does not extend the tracked chain.
+    UnexpectedHeight {
+        /// Height the tracker expected.
+        expected: u32,
+        /// Height passed by the caller.
+        got: u32,
+    },
+    /// No deployment uses this bit.
+    UnknownDeployment(u8),
+}
+
+impl fmt::Display for VersionBitsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VersionBitsError::UnexpectedHeight { expected, got } =>
+                write!(f, "expected header at height {}, got {}", expected, got),
+            VersionBitsError::UnknownDeployment(bit) =>
+                write!(f, "no deployment uses version bit {}", bit),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VersionBitsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+#[derive(Debug, Clone)]
+struct Tracked {
+    deployment: Deployment,
+    state: ThresholdState,
+    since: u32,
+    started_at: u32,
+    count: u32,
+}
+
+/// Tracks the state of version bits deployments from a stream of headers.
+///
+/// Headers must be connected in order starting from genesis. Masternode hard fork signals,
+/// which are carried by `MnHF` special transactions, are passed in with
+/// [`VersionBitsTracker::record_ehf_signal`].
+#[derive(Debug, Clone)]
+pub struct VersionBitsTracker {
+    params: Params,
+    deployments: Vec<Tracked>,
+    ehf_signals: BTreeMap<u8, u32>,
+    recent_times: Vec<u32>,
+    next_height: u32,
+}
+
+impl VersionBitsTracker {
+    /// Creates a tracker for the deployments defined in `params`.
+    pub fn new(params: Params) -> Self {
+        let deployments = params.deployments.clone();
+        VersionBitsTracker::with_deployments(params, deployments)
+    }
+
+    /// Creates a tracker for a custom set of deployments.
+    pub fn with_deployments(params: Params, deployments: Vec<Deployment>) -> Self {
+        let deployments = deployments
+            .into_iter()
+            .map(|deployment| {
+                let state = match deployment.start_time {
+                    ALWAYS_ACTIVE => ThresholdState::Active,
+                    NEVER_ACTIVE => ThresholdState::Failed,
+                    _ => ThresholdState::Defined,
+                };
+                Tracked { deployment, state, since: 0, started_at: 0, count: 0 }
+            })
+            .collect();
+        VersionBitsTracker {
+            params,
+            deployments,
+            ehf_signals: BTreeMap::new(),
+            recent_times: Vec::with_capacity(MEDIAN_TIME_SPAN),
+            next_height: 0,
+        }
+    }
+
+    /// Height of the next header expected by the tracker.
+    pub fn next_height(&self) -> u32 { self.next_height }
+
+    /// Records a masternode hard fork signal for `bit` mined at `height`.
+    pub fn record_ehf_signal(&mut self, bit: u8, height: u32) {
+        let signal_height = self.ehf_signals.entry(bit).or_insert(height);
+        *signal_height = (*signal_height).min(height);
+    }
+
+    /// Connects the header at `height`, which must be [`Self::next_height`].
+    pub fn connect_header(&mut self, height: u32, header: &Header) -> Result<(), VersionBitsError> {
+        if height != self.next_height {
+            return Err(VersionBitsError::UnexpectedHeight {
+                expected: self.next_height,
+                got: height,
+            });
+        }
+        if self.recent_times.len() == MEDIAN_TIME_SPAN {
+            self.recent_times.remove(0);
+        }
+        self.recent_times.push(header.time);
+        let median_time_past = self.median_time_past();
+
+        for tracked in &mut self.deployments {
+            let deployment = tracked.deployment;
+            let period = deployment.window(&self.params);
+            if tracked.state == ThresholdState::Started
+                && header.version.is_signalling_soft_fork(deployment.bit)
+            {
+                tracked.count += 1;
+            }
+            if (height + 1) % period != 0 {
+                continue;
+            }
+
+            let next_state = match tracked.state {
+                ThresholdState::Defined => {
+                    let ehf_signalled = !deployment.use_ehf
+                        || self.ehf_signals.get(&deployment.bit).is_some_and(|h| *h <= height);
+                    if median_time_past >= deployment.start_time && ehf_signalled {
+                        tracked.started_at = height + 1;
+                        ThresholdState::Started
+                    } else {
+                        ThresholdState::Defined
+                    }
+                }
+                ThresholdState::Started => {
+                    let attempt = (height + 1 - period - tracked.started_at) / period;
+                    if tracked.count >= deployment.threshold(&self.params, attempt) {
+                        ThresholdState::LockedIn
+                    } else if median_time_past >= deployment.timeout {
+                        ThresholdState::Failed
+                    } else {
+                        ThresholdState::Started
+                    }
+                }
+                ThresholdState::LockedIn if height + 1 >= deployment.min_activation_height =>
+                    ThresholdState::Active,
+                state => state,
+            };
+            tracked.count = 0;
+            if next_state != tracked.state {
+                tracked.state = next_state;
+                tracked.since = height + 1;
+            }
+        }
+        self.next_height += 1;
+        Ok(())
+    }
+
+    /// Returns the state of the deployment using `bit` for the next header.
+    pub fn state(&self, bit: u8) -> Result<ThresholdState, VersionBitsError> {
+        self.tracked(bit).map(|tracked| tracked.state)
+    }
+
+    /// Returns the signalling progress of the deployment using `bit`.
+    pub fn status(&self, bit: u8) -> Result<DeploymentStatus, VersionBitsError> {
+        let tracked = self.tracked(bit)?;
+        let period = tracked.deployment.window(&self.params);
+        let window_start = self.next_height - self.next_height % period;
+        let attempt = window_start.saturating_sub(tracked.started_at) / period;
+        Ok(DeploymentStatus {
+            state: tracked.state,
+            since: tracked.since,
+            period,
+            threshold: tracked.deployment.threshold(&self.params, attempt),
+            elapsed: self.next_height - window_start,
+            count: tracked.count,
+        })
+    }
+
+    /// Returns the deployments with their current state.
+    pub fn deployments(&self) -> impl Iterator<Item = (&Deployment, ThresholdState)> {
+        self.deployments.iter().map(|tracked| (&tracked.deployment, tracked.state))
+    }
+
+    /// Returns the block version a miner should use to signal all started deployments.
+    pub fn compute_block_version(&self) -> Version {
+        let bits = self
+            .deployments
+            .iter()
+            .filter(|tracked| {
+                matches!(tracked.state, ThresholdState::Started | ThresholdState::LockedIn)
+            })
+            .fold(0u32, |bits, tracked| bits | 1 << tracked.deployment.bit);
+        Version::from_consensus(Version::NO_SOFT_FORK_SIGNALLING.to_consensus() | bits as i32)
+    }
+
+    fn tracked(&self, bit: u8) -> Result<&Tracked, VersionBitsError> {
+        self.deployments
+            .iter()
+            .find(|tracked| tracked.deployment.bit == bit)
+            .ok_or(VersionBitsError::UnknownDeployment(bit))
+    }
+
+    fn median_time_past(&self) -> i64 {
+        let mut times = self.recent_times.clone();
+        times.sort_unstable();
+        i64::from(times[times.len() / 2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::Network;
+    use crate::hash_types::{BlockHash, TxMerkleNode};
+    use crate::pow::CompactTarget;
+
+    fn header(version: i32, time: u32) -> Header {
+        Header {
+            version: Version::from_consensus(version),
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        }
+    }
+
+    fn deployment(use_ehf: bool) -> Deployment {
+        Deployment {
+            name: "test",
+            bit: 3,
+            start_time: 1000,
+            timeout: 1_000_000,
+            min_activation_height: 0,
+            window_size: Some(10),
+            threshold_start: Some(8),
+            threshold_min: Some(5),
+            falloff_coeff: 1,
+            use_ehf,
+        }
+    }
+
+    fn connect(tracker: &mut VersionBitsTracker, count: u32, signalling: u32, time: u32) {
+        for i in 0..count {
+            let version = if i < signalling { 0x20000000 | 1 << 3 } else { 0x20000000 };
+            tracker.connect_header(tracker.next_height(), &header(version, time)).unwrap();
+        }
+    }
+
+    #[test]
+    fn dynamic_threshold() {
+        let params = Params::new(Network::Regtest);
+        let deployment = deployment(false);
+        // start - attempt^2 * window / 100 / falloff, floored at the minimum.
+        assert_eq!(deployment.threshold(&params, 0), 8);
+        assert_eq!(deployment.threshold(&params, 5), 6);
+        assert_eq!(deployment.threshold(&params, 10), 5);
+
+        let fixed = Deployment { threshold_min: None, window_size: None, ..deployment };
+        assert_eq!(fixed.threshold(&params, 10), 8);
+        let default = Deployment { threshold_start: None, ..fixed };
+        assert_eq!(default.threshold(&params, 0), params.rule_change_activation_threshold);
+    }
+
+    #[test]
+    fn state_transitions() {
+        let params = Params::new(Network::Regtest);
+        let mut tracker = VersionBitsTracker::with_deployments(params, vec![deployment(false)]);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Defined));
+        assert_eq!(tracker.state(4), Err(VersionBitsError::UnknownDeployment(4)));
+
+        // Before the start time signalling is ignored.
+        connect(&mut tracker, 10, 10, 500);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Defined));
+        connect(&mut tracker, 10, 10, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Started));
+        assert_eq!(tracker.compute_block_version(), Version::from_consensus(0x20000008));
+
+        // 7 out of 10 is below the initial threshold of 8.
+        connect(&mut tracker, 10, 7, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Started));
+        let status = tracker.status(3).unwrap();
+        assert_eq!((status.threshold, status.elapsed, status.count), (8, 0, 0));
+
+        connect(&mut tracker, 4, 4, 2000);
+        let status = tracker.status(3).unwrap();
+        assert_eq!((status.elapsed, status.count), (4, 4));
+        assert!(status.possible());
+        connect(&mut tracker, 6, 4, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::LockedIn));
+        assert_eq!(tracker.status(3).unwrap().since, 40);
+
+        connect(&mut tracker, 10, 0, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Active));
+        connect(&mut tracker, 10, 0, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Active));
+        assert_eq!(tracker.status(3).unwrap().since, 50);
+
+        assert_eq!(
+            tracker.connect_header(0, &header(0x20000000, 0)),
+            Err(VersionBitsError::UnexpectedHeight { expected: 60, got: 0 })
+        );
+    }
+
+    #[test]
+    fn timeout_fails_deployment() {
+        let params = Params::new(Network::Regtest);
+        let mut tracker = VersionBitsTracker::with_deployments(params, vec![deployment(false)]);
+        connect(&mut tracker, 10, 0, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Started));
+        connect(&mut tracker, 10, 0, 2_000_000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Failed));
+    }
+
+    #[test]
+    fn ehf_gates_start() {
+        let params = Params::new(Network::Regtest);
+        let mut tracker = VersionBitsTracker::with_deployments(params, vec![deployment(true)]);
+        connect(&mut tracker, 20, 10, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Defined));
+        tracker.record_ehf_signal(3, 25);
+        connect(&mut tracker, 10, 10, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::Started));
+        connect(&mut tracker, 10, 10, 2000);
+        assert_eq!(tracker.state(3), Ok(ThresholdState::LockedIn));
+    }
+
+    #[test]
+    fn network_deployments() {
+        for network in [Network::Dash, Network::Testnet, Network::Devnet, Network::Regtest] {
+            let params = Params::new(network);
+            assert_eq!(params.deployments, Deployment::for_network(network));
+            let tracker = VersionBitsTracker::new(params);
+            assert_eq!(tracker.state(9), Ok(ThresholdState::Defined));
+            assert_eq!(tracker.deployments().count(), 2);
+        }
+    }
+}
@@ -3,3 +3,4 @@
 
 pub mod chain_lock;
 pub mod instant_lock;
+pub mod spork;
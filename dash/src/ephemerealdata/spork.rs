@@ -0,0 +1,365 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash sporks.
+//!
+//! Sporks are network-wide switches signed by the spork key, used to enable features such as
+//! InstantSend or ChainLocks without a hard fork. A spork is active once its value, usually a
+//! timestamp, is in the past.
+//!
+
+use core::fmt;
+use core::str::FromStr;
+
+use hashes::{Hash, sha256d};
+
+use crate::address::Payload;
+use crate::consensus::{Decodable, Encodable, encode};
+use crate::hash_types::{PubkeyHash, SporkHash};
+use crate::internal_macros::impl_consensus_encoding;
+use crate::prelude::*;
+use crate::{Address, Network, io, signer};
+
+/// Value of a spork that is off: 2099-01-01.
+pub const SPORK_DISABLED: i64 = 4_070_908_800;
+
+/// How far in the future a spork may be signed, in seconds.
+pub const MAX_SPORK_FUTURE_DRIFT: i64 = 2 * 60 * 60;
+
+/// A spork identifier
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SporkId {
+    /// `SPORK_2_INSTANTSEND_ENABLED`
+    InstantSendEnabled,
+    /// `SPORK_3_INSTANTSEND_BLOCK_FILTERING`
+    InstantSendBlockFiltering,
+    /// `SPORK_9_SUPERBLOCKS_ENABLED`
+    SuperblocksEnabled,
+    /// `SPORK_17_QUORUM_DKG_ENABLED`
+    QuorumDkgEnabled,
+    /// `SPORK_19_CHAINLOCKS_ENABLED`
+    ChainLocksEnabled,
+    /// `SPORK_21_QUORUM_ALL_CONNECTED`
+    QuorumAllConnected,
+    /// `SPORK_23_QUORUM_POSE`
+    QuorumPoSe,
+    /// `SPORK_24_TEST_EHF`
+    TestEhf,
+    /// A spork this library does not know about
+    Unknown(i32),
+}
+
+impl SporkId {
+    /// Returns the numeric identifier used on the wire.
+    pub fn to_i32(self) -> i32 {
+        match self {
+            SporkId::InstantSendEnabled => 10001,
+            SporkId::InstantSendBlockFiltering => 10002,
+            SporkId::SuperblocksEnabled => 10008,
+            SporkId::QuorumDkgEnabled => 10016,
+            SporkId::ChainLocksEnabled => 10018,
+            SporkId::QuorumAllConnected => 10020,
+            SporkId::QuorumPoSe => 10022,
+            SporkId::TestEhf => 10023,
+            SporkId::Unknown(id) => id,
+        }
+    }
+
+    /// Parses the numeric identifier used on the wire.
+    pub fn from_i32(id: i32) -> SporkId {
+        match id {
+            10001 => SporkId::InstantSendEnabled,
+            10002 => SporkId::InstantSendBlockFiltering,
+            10008 => SporkId::SuperblocksEnabled,
+            10016 => SporkId::QuorumDkgEnabled,
+            10018 => SporkId::ChainLocksEnabled,
+            10020 => SporkId::QuorumAllConnected,
+            10022 => SporkId::QuorumPoSe,
+            10023 => SporkId::TestEhf,
+            id => SporkId::Unknown(id),
+        }
+    }
+
+    /// Returns the value the spork has until a signed message is received.
+    ///
+    /// Every spork starts out disabled.
+    pub fn default_value(self) -> i64 { SPORK_DISABLED }
+}
+
+impl fmt::Display for SporkId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SporkId::InstantSendEnabled => write!(f, "SPORK_2_INSTANTSEND_ENABLED"),
+            SporkId::InstantSendBlockFiltering => write!(f, "SPORK_3_INSTANTSEND_BLOCK_FILTERING"),
+            SporkId::SuperblocksEnabled => write!(f, "SPORK_9_SUPERBLOCKS_ENABLED"),
+            SporkId::QuorumDkgEnabled => write!(f, "SPORK_17_QUORUM_DKG_ENABLED"),
+            SporkId::ChainLocksEnabled => write!(f, "SPORK_19_CHAINLOCKS_ENABLED"),
+            SporkId::QuorumAllConnected => write!(f, "SPORK_21_QUORUM_ALL_CONNECTED"),
+            SporkId::QuorumPoSe => write!(f, "SPORK_23_QUORUM_POSE"),
+            SporkId::TestEhf => write!(f, "SPORK_24_TEST_EHF"),
+            SporkId::Unknown(id) => write!(f, "Unknown({})", id),
+        }
+    }
+}
+
+impl Encodable for SporkId {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        self.to_i32().consensus_encode(w)
+    }
+}
+
+impl Decodable for SporkId {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        Ok(SporkId::from_i32(i32::consensus_decode(r)?))
+    }
+}
+
+/// A signed spork value, as relayed in the `spork` message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SporkMessage {
+    /// The spork set
+    pub spork_id: SporkId,
+    /// The new value, usually the time from which the spork is active
+    pub value: i64,
+    /// Time the message was signed, in seconds since the epoch
+    pub time_signed: i64,
+    /// Compact ECDSA signature by the spork key
+    pub signature: Vec<u8>,
+}
+
+impl_consensus_encoding!(SporkMessage, spork_id, value, time_signed, signature);
+
+impl SporkMessage {
+    /// Computes the hash identifying this message, signature included.
+    pub fn hash(&self) -> SporkHash {
+        let mut engine = SporkHash::engine();
+        self.consensus_encode(&mut engine).expect("engines don't error");
+        SporkHash::from_engine(engine)
+    }
+
+    /// Computes the hash signed by the spork key.
+    pub fn signature_hash(&self) -> sha256d::Hash {
+        let mut engine = sha256d::Hash::engine();
+        self.spork_id.consensus_encode(&mut engine).expect("engines don't error");
+        self.value.consensus_encode(&mut engine).expect("engines don't error");
+        self.time_signed.consensus_encode(&mut engine).expect("engines don't error");
+        sha256d::Hash::from_engine(engine)
+    }
+
+    /// Signs the message with the spork key, replacing any previous signature.
+    pub fn sign(&mut self, spork_key: &[u8]) -> Result<(), anyhow::Error> {
+        let hash = self.signature_hash();
+        self.signature = signer::sign_hash(hash.as_byte_array(), spork_key)?.to_vec();
+        Ok(())
+    }
+
+    /// Verifies the message was signed by the key hashing to `spork_key_id`.
+    pub fn verify(&self, spork_key_id: &PubkeyHash) -> Result<(), anyhow::Error> {
+        signer::verify_hash_signature(
+            self.signature_hash().as_byte_array(),
+            &self.signature,
+            spork_key_id.as_byte_array(),
+        )
+    }
+}
+
+/// Returns the address of the key signing sporks on `network`.
+pub fn spork_address(network: Network) -> &'static str {
+    match network {
+        Network::Dash => "Xgtyuk76vhuFW2iT7UAiHgNdWXCf3J34wh",
+        Network::Testnet | Network::Devnet => "yjPtiKh2uwk3bDutTEA2q9mCtXyiZRWn55",
+        Network::Regtest => "yj949n1UH6fDhw6HtVE5VMj2iSTaSWBMcW",
+    }
+}
+
+/// An error processing a spork message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SporkError {
+    /// The message is not signed by the spork key.
+    InvalidSignature,
+    /// The message is signed too far in the future.
+    TooFarInFuture {
+        /// Time the message claims to be signed.
+        time_signed: i64,
+        /// Current time.
+        now: i64,
+    },
+}
+
+impl fmt::Display for SporkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SporkError::InvalidSignature => write!(f, "spork is not signed by the spork key"),
+            SporkError::TooFarInFuture { time_signed, now } =>
+                write!(f, "spork signed at {} is too far past current time {}", time_signed, now),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SporkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Tracks the latest validly signed value of every spork
+#[derive(Clone, Debug)]
+pub struct SporkManager {
+    spork_key_id: PubkeyHash,
+    sporks: BTreeMap<SporkId, SporkMessage>,
+}
+
+impl SporkManager {
+    /// Creates a manager accepting sporks signed by the spork key of `network`.
+    pub fn new(network: Network) -> SporkManager {
+        let address = Address::from_str(spork_address(network))
+            .expect("valid spork address")
+            .require_network(network)
+            .expect("spork address matches its network");
+        match *address.payload() {
+            Payload::PubkeyHash(key_id) => SporkManager::with_spork_key_id(key_id),
+            _ => unreachable!("spork addresses are P2PKH"),
+        }
+    }
+
+    /// Creates a manager accepting sporks signed by the key hashing to `spork_key_id`.
+    pub fn with_spork_key_id(spork_key_id: PubkeyHash) -> SporkManager {
+        SporkManager { spork_key_id, sporks: BTreeMap::new() }
+    }
+
+    /// Processes a received spork message.
+    ///
+    /// Returns whether the message updated the spork; messages not newer than the current one
+    /// are ignored. `now` is the current time in seconds since the epoch.
+    pub fn process_spork(&mut self, spork: SporkMessage, now: i64) -> Result<bool, SporkError> {
+        if spork.time_signed > now.saturating_add(MAX_SPORK_FUTURE_DRIFT) {
+            return Err(SporkError::TooFarInFuture { time_signed: spork.time_signed, now });
+        }
+        if self.sporks.get(&spork.spork_id).is_some_and(|s| s.time_signed >= spork.time_signed) {
+            return Ok(false);
+        }
+        spork.verify(&self.spork_key_id).map_err(|_| SporkError::InvalidSignature)?;
+        self.sporks.insert(spork.spork_id, spork);
+        Ok(true)
+    }
+
+    /// Returns the latest message received for `spork_id`.
+    pub fn get(&self, spork_id: SporkId) -> Option<&SporkMessage> { self.sporks.get(&spork_id) }
+
+    /// Returns the current value of `spork_id`, its default if no message was received.
+    pub fn value(&self, spork_id: SporkId) -> i64 {
+        self.get(spork_id).map_or_else(|| spork_id.default_value(), |spork| spork.value)
+    }
+
+    /// Returns whether `spork_id` is active at `now`.
+    pub fn is_active(&self, spork_id: SporkId, now: i64) -> bool { self.value(spork_id) < now }
+
+    /// Returns whether InstantSend is enabled at `now`.
+    pub fn is_instantsend_enabled(&self, now: i64) -> bool {
+        self.is_active(SporkId::InstantSendEnabled, now)
+    }
+
+    /// Returns the latest messages received, to relay in response to `getsporks`.
+    pub fn sporks(&self) -> impl Iterator<Item = &SporkMessage> { self.sporks.values() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+
+    const SPORK_KEY: [u8; 32] = [9; 32];
+    const NOW: i64 = 1_700_000_000;
+
+    fn manager() -> SporkManager {
+        let secp = secp256k1::Secp256k1::new();
+        let secret = secp256k1::SecretKey::from_slice(&SPORK_KEY).unwrap();
+        SporkManager::with_spork_key_id(
+            crate::PublicKey::new(secret.public_key(&secp)).pubkey_hash(),
+        )
+    }
+
+    fn signed(spork_id: SporkId, value: i64, time_signed: i64) -> SporkMessage {
+        let mut spork = SporkMessage { spork_id, value, time_signed, signature: vec![] };
+        spork.sign(&SPORK_KEY).unwrap();
+        spork
+    }
+
+    #[test]
+    fn spork_encoding() {
+        let spork = signed(SporkId::ChainLocksEnabled, 0, NOW);
+        let encoded = serialize(&spork);
+        assert_eq!(&encoded[..4], &10018i32.to_le_bytes());
+        assert_eq!(encoded.len(), 4 + 8 + 8 + 66);
+        assert_eq!(deserialize::<SporkMessage>(&encoded).unwrap(), spork);
+
+        let unknown = SporkMessage { spork_id: SporkId::Unknown(10042), ..spork };
+        assert_eq!(deserialize::<SporkMessage>(&serialize(&unknown)).unwrap(), unknown);
+        assert_eq!(SporkId::from_i32(10001), SporkId::InstantSendEnabled);
+        assert_eq!(SporkId::QuorumPoSe.to_string(), "SPORK_23_QUORUM_POSE");
+        assert_eq!(SporkId::from_i32(10023), SporkId::TestEhf);
+        assert_eq!(SporkId::TestEhf.to_string(), "SPORK_24_TEST_EHF");
+        assert_eq!(SporkId::TestEhf.default_value(), SPORK_DISABLED);
+    }
+
+    #[test]
+    fn network_spork_keys() {
+        for network in [Network::Dash, Network::Testnet, Network::Devnet, Network::Regtest] {
+            let manager = SporkManager::new(network);
+            assert_eq!(manager.value(SporkId::ChainLocksEnabled), SPORK_DISABLED);
+        }
+    }
+
+    #[test]
+    fn manager_keeps_latest_valid_value() {
+        let mut manager = manager();
+        assert!(!manager.is_instantsend_enabled(NOW));
+        assert!(!manager.is_active(SporkId::SuperblocksEnabled, NOW));
+        assert_eq!(manager.value(SporkId::InstantSendBlockFiltering), SPORK_DISABLED);
+
+        assert_eq!(
+            manager.process_spork(signed(SporkId::InstantSendEnabled, SPORK_DISABLED, NOW), NOW),
+            Ok(true)
+        );
+        assert!(!manager.is_instantsend_enabled(NOW));
+
+        // Older and replayed messages are ignored.
+        assert_eq!(
+            manager.process_spork(signed(SporkId::InstantSendEnabled, 0, NOW - 1), NOW),
+            Ok(false)
+        );
+        assert_eq!(
+            manager.process_spork(signed(SporkId::InstantSendEnabled, 0, NOW), NOW),
+            Ok(false)
+        );
+        assert!(!manager.is_instantsend_enabled(NOW));
+
+        let newer = signed(SporkId::InstantSendEnabled, 0, NOW + 10);
+        assert_eq!(manager.process_spork(newer.clone(), NOW), Ok(true));
+        assert!(manager.is_instantsend_enabled(NOW));
+        assert_eq!(manager.sporks().collect::<Vec<_>>(), vec![&newer]);
+
+        let mut forged = signed(SporkId::InstantSendEnabled, SPORK_DISABLED, NOW + 20);
+        forged.value = SPORK_DISABLED - 1;
+        assert_eq!(manager.process_spork(forged, NOW), Err(SporkError::InvalidSignature));
+
+        let future = signed(SporkId::QuorumDkgEnabled, 0, NOW + MAX_SPORK_FUTURE_DRIFT + 1);
+        assert!(matches!(
+            manager.process_spork(future, NOW),
+            Err(SporkError::TooFarInFuture { .. })
+        ));
+        assert!(manager.is_instantsend_enabled(NOW));
+        assert!(!manager.is_active(SporkId::QuorumDkgEnabled, NOW));
+    }
+}
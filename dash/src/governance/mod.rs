@@ -0,0 +1,32 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash governance.
+//!
+//! Governance objects (budget proposals and superblock triggers) and the masternode votes cast
+//! on them are relayed over the p2p network but never stored in the blockchain, except for the
+//! superblock payments triggers make.
+//!
+
+pub mod object;
+pub mod payload;
+pub mod proposal;
+pub mod trigger;
+pub mod vote;
+
+pub use self::object::{GovernanceObject, GovernanceObjectType};
+pub use self::payload::PayloadError;
+pub use self::proposal::Proposal;
+pub use self::trigger::{SuperblockError, SuperblockPayment, Trigger};
+pub use self::vote::{GovernanceVote, VoteOutcome, VoteSignal};
@@ -0,0 +1,247 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash governance objects.
+//!
+//! A governance object carries an opaque JSON document (`data`) describing a budget proposal or
+//! a superblock trigger, together with the collateral transaction that paid for it.
+//!
+
+use core::fmt;
+
+use hashes::{Hash, sha256d};
+use internals::hex::display::DisplayHex;
+
+use crate::consensus::{Decodable, Encodable, encode};
+use crate::hash_types::{GovernanceObjectHash, Txid};
+use crate::prelude::*;
+use crate::{OutPoint, io};
+
+/// Maximum size of a governance object's data, in bytes.
+pub const MAX_GOVERNANCE_OBJECT_DATA_SIZE: usize = 16 * 1024;
+
+/// The kind of a governance object
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GovernanceObjectType {
+    /// An object of unknown kind
+    Unknown,
+    /// A budget proposal, voted on by masternodes
+    Proposal,
+    /// A superblock trigger, listing the proposals paid by a superblock
+    Trigger,
+}
+
+impl GovernanceObjectType {
+    /// Returns the numeric value used on the wire.
+    pub fn to_i32(self) -> i32 {
+        match self {
+            GovernanceObjectType::Unknown => 0,
+            GovernanceObjectType::Proposal => 1,
+            GovernanceObjectType::Trigger => 2,
+        }
+    }
+
+    /// Parses the numeric value used on the wire.
+    pub fn from_i32(value: i32) -> Option<GovernanceObjectType> {
+        match value {
+            0 => Some(GovernanceObjectType::Unknown),
+            1 => Some(GovernanceObjectType::Proposal),
+            2 => Some(GovernanceObjectType::Trigger),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GovernanceObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GovernanceObjectType::Unknown => write!(f, "unknown"),
+            GovernanceObjectType::Proposal => write!(f, "proposal"),
+            GovernanceObjectType::Trigger => write!(f, "trigger"),
+        }
+    }
+}
+
+impl Encodable for GovernanceObjectType {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        self.to_i32().consensus_encode(w)
+    }
+}
+
+impl Decodable for GovernanceObjectType {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        GovernanceObjectType::from_i32(i32::consensus_decode(r)?)
+            .ok_or(encode::Error::ParseFailed("unknown governance object type"))
+    }
+}
+
+/// A governance object, as relayed in the `govobj` message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GovernanceObject {
+    /// Hash of the parent object, all zeros for root objects
+    pub parent_hash: GovernanceObjectHash,
+    /// Object revision in the system
+    pub revision: i32,
+    /// Time the object was created, in seconds since the epoch
+    pub time: i64,
+    /// Transaction burning the collateral fee, all zeros for triggers
+    pub collateral_hash: Txid,
+    /// The object's JSON document as raw bytes
+    pub data: Vec<u8>,
+    /// The kind of object
+    pub object_type: GovernanceObjectType,
+    /// Collateral outpoint of the masternode that signed the object, null for proposals
+    pub masternode_outpoint: OutPoint,
+    /// Masternode signature, empty for proposals
+    pub signature: Vec<u8>,
+}
+
+impl Encodable for GovernanceObject {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.parent_hash.consensus_encode(w)?;
+        len += self.revision.consensus_encode(w)?;
+        len += self.time.consensus_encode(w)?;
+        len += self.collateral_hash.consensus_encode(w)?;
+        len += self.data.consensus_encode(w)?;
+        len += self.object_type.consensus_encode(w)?;
+        len += self.masternode_outpoint.consensus_encode(w)?;
+        len += self.signature.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for GovernanceObject {
+    fn consensus_decode_from_finite_reader<R: io::Read + ?Sized>(
+        r: &mut R,
+    ) -> Result<Self, encode::Error> {
+        let parent_hash = Decodable::consensus_decode_from_finite_reader(r)?;
+        let revision = Decodable::consensus_decode_from_finite_reader(r)?;
+        let time = Decodable::consensus_decode_from_finite_reader(r)?;
+        let collateral_hash = Decodable::consensus_decode_from_finite_reader(r)?;
+        let data: Vec<u8> = Decodable::consensus_decode_from_finite_reader(r)?;
+        if data.len() > MAX_GOVERNANCE_OBJECT_DATA_SIZE {
+            return Err(encode::Error::ParseFailed("governance object data too large"));
+        }
+        Ok(GovernanceObject {
+            parent_hash,
+            revision,
+            time,
+            collateral_hash,
+            data,
+            object_type: Decodable::consensus_decode_from_finite_reader(r)?,
+            masternode_outpoint: Decodable::consensus_decode_from_finite_reader(r)?,
+            signature: Decodable::consensus_decode_from_finite_reader(r)?,
+        })
+    }
+}
+
+impl GovernanceObject {
+    /// Computes the hash identifying this object.
+    ///
+    /// The hash commits to the data as a hex string and to the signature, but not to the
+    /// collateral or the object type.
+    pub fn hash(&self) -> GovernanceObjectHash {
+        let mut engine = GovernanceObjectHash::engine();
+        self.parent_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.revision.consensus_encode(&mut engine).expect("engines don't error");
+        self.time.consensus_encode(&mut engine).expect("engines don't error");
+        self.data_hex().consensus_encode(&mut engine).expect("engines don't error");
+        encode_legacy_outpoint(&self.masternode_outpoint, &mut engine);
+        self.signature.consensus_encode(&mut engine).expect("engines don't error");
+        GovernanceObjectHash::from_engine(engine)
+    }
+
+    /// Computes the hash a masternode signs: the serialized object without its signature.
+    pub fn signature_hash(&self) -> sha256d::Hash {
+        let mut engine = sha256d::Hash::engine();
+        self.parent_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.revision.consensus_encode(&mut engine).expect("engines don't error");
+        self.time.consensus_encode(&mut engine).expect("engines don't error");
+        self.collateral_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.data.consensus_encode(&mut engine).expect("engines don't error");
+        self.object_type.consensus_encode(&mut engine).expect("engines don't error");
+        self.masternode_outpoint.consensus_encode(&mut engine).expect("engines don't error");
+        sha256d::Hash::from_engine(engine)
+    }
+
+    /// Returns the data as a lower-case hex string.
+    pub fn data_hex(&self) -> String { self.data.to_lower_hex_string() }
+
+    /// Returns the data as a JSON string, if it is valid UTF-8.
+    pub fn data_json(&self) -> Option<&str> { core::str::from_utf8(&self.data).ok() }
+}
+
+/// Encodes an outpoint the way Dash Core hashes a `CTxIn` built from it: the outpoint, an empty
+/// script and a final sequence.
+pub(crate) fn encode_legacy_outpoint<W: io::Write>(outpoint: &OutPoint, engine: &mut W) {
+    outpoint.consensus_encode(engine).expect("engines don't error");
+    0u8.consensus_encode(engine).expect("engines don't error");
+    u32::MAX.consensus_encode(engine).expect("engines don't error");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+
+    fn proposal() -> GovernanceObject {
+        GovernanceObject {
+            parent_hash: GovernanceObjectHash::all_zeros(),
+            revision: 1,
+            time: 1_700_000_000,
+            collateral_hash: Txid::hash(&[1]),
+            data: br#"{"name":"test"}"#.to_vec(),
+            object_type: GovernanceObjectType::Proposal,
+            masternode_outpoint: OutPoint::null(),
+            signature: vec![],
+        }
+    }
+
+    #[test]
+    fn object_encoding() {
+        let object = proposal();
+        let encoded = serialize(&object);
+        assert_eq!(encoded.len(), 32 + 4 + 8 + 32 + 16 + 4 + 36 + 1);
+        assert_eq!(deserialize::<GovernanceObject>(&encoded).unwrap(), object);
+
+        let mut bad_type = encoded.clone();
+        bad_type[92..96].copy_from_slice(&7i32.to_le_bytes());
+        assert!(deserialize::<GovernanceObject>(&bad_type).is_err());
+
+        let mut large = object.clone();
+        large.data = vec![b' '; MAX_GOVERNANCE_OBJECT_DATA_SIZE];
+        assert_eq!(deserialize::<GovernanceObject>(&serialize(&large)).unwrap(), large);
+        large.data.push(b' ');
+        assert!(deserialize::<GovernanceObject>(&serialize(&large)).is_err());
+    }
+
+    #[test]
+    fn object_hashes() {
+        let object = proposal();
+        assert_eq!(object.data_hex(), "7b226e616d65223a2274657374227d");
+        assert_eq!(object.data_json(), Some(r#"{"name":"test"}"#));
+
+        // The identifying hash ignores the collateral, the signature hash does not.
+        let mut recollateralized = object.clone();
+        recollateralized.collateral_hash = Txid::hash(&[2]);
+        assert_eq!(object.hash(), recollateralized.hash());
+        assert_ne!(object.signature_hash(), recollateralized.signature_hash());
+
+        // The signature hash ignores the signature, the identifying hash does not.
+        let mut signed = object.clone();
+        signed.signature = vec![3; 65];
+        assert_ne!(object.hash(), signed.hash());
+        assert_eq!(object.signature_hash(), signed.signature_hash());
+    }
+}
@@ -0,0 +1,181 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Governance object payloads.
+//!
+//! Proposals and triggers carry a small, flat JSON document. This module holds the error type
+//! shared by their parsers and the field accessors they use; parsing and serializing the
+//! documents requires the `serde` feature.
+//!
+
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde_json::Value;
+
+use crate::Amount;
+#[cfg(feature = "serde")]
+use crate::amount::Denomination;
+use crate::governance::GovernanceObjectType;
+use crate::prelude::*;
+
+/// An error parsing or validating a proposal or trigger payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PayloadError {
+    /// The object is not of the expected type.
+    WrongObjectType(GovernanceObjectType),
+    /// The payload exceeds the maximum size, in bytes.
+    TooLarge(usize),
+    /// The payload is not valid UTF-8.
+    InvalidUtf8,
+    /// The payload is not valid JSON.
+    InvalidJson,
+    /// A required field is missing.
+    MissingField(&'static str),
+    /// A field has the wrong type or an unparsable value.
+    InvalidField(&'static str),
+    /// The proposal name is empty, too long or contains forbidden characters.
+    InvalidName(String),
+    /// The proposal URL is empty, too long or contains whitespace.
+    InvalidUrl(String),
+    /// The end epoch does not come after the start epoch.
+    InvalidEpochs {
+        /// Start of the payment period.
+        start: i64,
+        /// End of the payment period.
+        end: i64,
+    },
+    /// A payment address is not valid for the network.
+    WrongNetwork(String),
+    /// A payment address is neither P2PKH nor P2SH.
+    UnsupportedAddress(String),
+    /// A payment amount is zero.
+    ZeroAmount,
+    /// The payments exceed the superblock budget.
+    ExceedsBudget {
+        /// Amount requested.
+        amount: Amount,
+        /// Budget available.
+        budget: Amount,
+    },
+    /// The payment lists of a trigger have different lengths.
+    PaymentCountMismatch,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PayloadError::WrongObjectType(object_type) =>
+                write!(f, "unexpected governance object type {}", object_type),
+            PayloadError::TooLarge(size) => write!(f, "payload of {} bytes is too large", size),
+            PayloadError::InvalidUtf8 => write!(f, "payload is not valid UTF-8"),
+            PayloadError::InvalidJson => write!(f, "payload is not valid JSON"),
+            PayloadError::MissingField(field) => write!(f, "missing field {}", field),
+            PayloadError::InvalidField(field) => write!(f, "invalid field {}", field),
+            PayloadError::InvalidName(ref name) => write!(f, "invalid proposal name {:?}", name),
+            PayloadError::InvalidUrl(ref url) => write!(f, "invalid proposal url {:?}", url),
+            PayloadError::InvalidEpochs { start, end } =>
+                write!(f, "end epoch {} is not after start epoch {}", end, start),
+            PayloadError::WrongNetwork(ref address) =>
+                write!(f, "address {} is for another network", address),
+            PayloadError::UnsupportedAddress(ref address) =>
+                write!(f, "address {} is neither P2PKH nor P2SH", address),
+            PayloadError::ZeroAmount => write!(f, "payment amount is zero"),
+            PayloadError::ExceedsBudget { amount, budget } =>
+                write!(f, "payments of {} exceed the superblock budget of {}", amount, budget),
+            PayloadError::PaymentCountMismatch =>
+                write!(f, "payment addresses, amounts and proposal hashes differ in number"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Parses a complete JSON document.
+#[cfg(feature = "serde")]
+pub(crate) fn parse_json(json: &str) -> Result<Value, PayloadError> {
+    serde_json::from_str(json).map_err(|_| PayloadError::InvalidJson)
+}
+
+/// Returns the string field `key` of the object `fields`.
+#[cfg(feature = "serde")]
+pub(crate) fn str_field<'a>(fields: &'a Value, key: &'static str) -> Result<&'a str, PayloadError> {
+    match fields.get(key) {
+        Some(Value::String(s)) => Ok(s),
+        Some(_) => Err(PayloadError::InvalidField(key)),
+        None => Err(PayloadError::MissingField(key)),
+    }
+}
+
+/// Returns the integer field `key` of the object `fields`.
+#[cfg(feature = "serde")]
+pub(crate) fn int_field(fields: &Value, key: &'static str) -> Result<i64, PayloadError> {
+    match fields.get(key) {
+        Some(value) => value.as_i64().ok_or(PayloadError::InvalidField(key)),
+        None => Err(PayloadError::MissingField(key)),
+    }
+}
+
+/// Returns the number field `key` of the object `fields` as an amount of DASH.
+#[cfg(feature = "serde")]
+pub(crate) fn amount_field(fields: &Value, key: &'static str) -> Result<Amount, PayloadError> {
+    let value = match fields.get(key) {
+        Some(Value::Number(n)) => n.as_f64().ok_or(PayloadError::InvalidField(key))?,
+        Some(_) => return Err(PayloadError::InvalidField(key)),
+        None => return Err(PayloadError::MissingField(key)),
+    };
+    Amount::from_float_in(value, Denomination::Dash).map_err(|_| PayloadError::InvalidField(key))
+}
+
+/// Returns `amount` as a JSON number of DASH.
+#[cfg(feature = "serde")]
+pub(crate) fn amount_value(amount: Amount) -> Value {
+    let number = amount.to_string_in(Denomination::Dash).parse().expect("amounts are JSON numbers");
+    Value::Number(number)
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let fields =
+            parse_json(r#"{"s":"a\"b\u00e9","i":-7,"f":-1.5e3,"a":12.5,"tiny":1e-9}"#).unwrap();
+        assert_eq!(str_field(&fields, "s"), Ok("a\"b\u{e9}"));
+        assert_eq!(str_field(&fields, "i"), Err(PayloadError::InvalidField("i")));
+        assert_eq!(int_field(&fields, "i"), Ok(-7));
+        assert_eq!(int_field(&fields, "f"), Err(PayloadError::InvalidField("f")));
+        assert_eq!(amount_field(&fields, "a"), Ok(Amount::from_sat(1_250_000_000)));
+        assert_eq!(amount_field(&fields, "f"), Err(PayloadError::InvalidField("f")));
+        assert_eq!(amount_field(&fields, "tiny"), Err(PayloadError::InvalidField("tiny")));
+        assert_eq!(amount_field(&fields, "s"), Err(PayloadError::InvalidField("s")));
+        assert_eq!(str_field(&fields, "missing"), Err(PayloadError::MissingField("missing")));
+
+        for amount in [1, 1_000, 1_250_000_000, 2_100_000_000_000_000] {
+            let amount = Amount::from_sat(amount);
+            let fields = parse_json(&format!(r#"{{"a":{}}}"#, amount_value(amount))).unwrap();
+            assert_eq!(amount_field(&fields, "a"), Ok(amount));
+        }
+        assert_eq!(amount_value(Amount::from_sat(1_000_000_000)).to_string(), "10");
+
+        for bad in ["", "{", r#"{"a":}"#, r#"{"a":1,}"#, "[1 2]", "01", r#""\x""#, "{} {}"] {
+            assert_eq!(parse_json(bad), Err(PayloadError::InvalidJson), "{}", bad);
+        }
+    }
+}
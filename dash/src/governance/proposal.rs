@@ -0,0 +1,283 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash budget proposals.
+//!
+//! A proposal asks for `payment_amount` to be paid to `payment_address` by every superblock
+//! between `start_epoch` and `end_epoch`.
+//!
+
+#[cfg(feature = "serde")]
+use core::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde_json::{Value, json};
+
+use crate::address::{AddressType, NetworkUnchecked};
+use crate::governance::payload::PayloadError;
+#[cfg(feature = "serde")]
+use crate::governance::payload::{amount_field, amount_value, int_field, parse_json, str_field};
+#[cfg(feature = "serde")]
+use crate::governance::{GovernanceObject, GovernanceObjectType};
+use crate::prelude::*;
+use crate::{Address, Amount, Network};
+
+/// Maximum size of a proposal's JSON document, in bytes.
+pub const MAX_PROPOSAL_DATA_SIZE: usize = 512;
+
+/// Maximum length of a proposal name.
+pub const MAX_PROPOSAL_NAME_SIZE: usize = 40;
+
+/// Minimum length of a proposal URL.
+pub const MIN_PROPOSAL_URL_SIZE: usize = 4;
+
+/// A budget proposal, the payload of a [`GovernanceObjectType::Proposal`] object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proposal {
+    /// Short name identifying the proposal
+    pub name: String,
+    /// Start of the payment period, in seconds since the epoch
+    pub start_epoch: i64,
+    /// End of the payment period, in seconds since the epoch
+    pub end_epoch: i64,
+    /// Address receiving the payments
+    pub payment_address: Address<NetworkUnchecked>,
+    /// Amount paid by each superblock
+    pub payment_amount: Amount,
+    /// Link to the proposal's description
+    pub url: String,
+}
+
+impl Proposal {
+    /// Parses the payload of a governance object, which must be a proposal.
+    #[cfg(feature = "serde")]
+    pub fn from_object(object: &GovernanceObject) -> Result<Proposal, PayloadError> {
+        if object.object_type != GovernanceObjectType::Proposal {
+            return Err(PayloadError::WrongObjectType(object.object_type));
+        }
+        Proposal::from_json(object.data_json().ok_or(PayloadError::InvalidUtf8)?)
+    }
+
+    /// Parses a proposal's JSON document.
+    ///
+    /// Both the current flat object and the legacy `[["proposal", {..}]]` wrapping are
+    /// accepted. Only the document's syntax is checked, see [`Proposal::validate`].
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Proposal, PayloadError> {
+        if json.len() > MAX_PROPOSAL_DATA_SIZE {
+            return Err(PayloadError::TooLarge(json.len()));
+        }
+        let document = parse_json(json)?;
+        let fields = match document {
+            Value::Array(ref outer) => match outer.first() {
+                Some(Value::Array(inner)) => inner.get(1).ok_or(PayloadError::InvalidJson)?,
+                _ => return Err(PayloadError::InvalidJson),
+            },
+            ref object => object,
+        };
+
+        if int_field(fields, "type")? != 1 {
+            return Err(PayloadError::InvalidField("type"));
+        }
+        let payment_address = Address::from_str(str_field(fields, "payment_address")?)
+            .map_err(|_| PayloadError::InvalidField("payment_address"))?;
+        Ok(Proposal {
+            name: str_field(fields, "name")?.to_owned(),
+            start_epoch: int_field(fields, "start_epoch")?,
+            end_epoch: int_field(fields, "end_epoch")?,
+            payment_address,
+            payment_amount: amount_field(fields, "payment_amount")?,
+            url: str_field(fields, "url")?.to_owned(),
+        })
+    }
+
+    /// Serializes the proposal to its JSON document, with the fields in alphabetical order.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        json!({
+            "end_epoch": self.end_epoch,
+            "name": self.name,
+            "payment_address": self.payment_address.assume_checked_ref().to_string(),
+            "payment_amount": amount_value(self.payment_amount),
+            "start_epoch": self.start_epoch,
+            "type": 1,
+            "url": self.url,
+        })
+        .to_string()
+    }
+
+    /// Checks the proposal against the network's rules.
+    ///
+    /// The name may only use `[A-Za-z0-9_-]`, the URL may not contain whitespace, the payment
+    /// period must not be empty and the address must be a P2PKH or P2SH address of `network`.
+    /// A single payment may not exceed the superblock `budget`.
+    pub fn validate(&self, network: Network, budget: Amount) -> Result<Address, PayloadError> {
+        if self.name.is_empty()
+            || self.name.len() > MAX_PROPOSAL_NAME_SIZE
+            || !self.name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(PayloadError::InvalidName(self.name.clone()));
+        }
+        if self.url.len() < MIN_PROPOSAL_URL_SIZE || self.url.contains(char::is_whitespace) {
+            return Err(PayloadError::InvalidUrl(self.url.clone()));
+        }
+        if self.end_epoch <= self.start_epoch {
+            return Err(PayloadError::InvalidEpochs {
+                start: self.start_epoch,
+                end: self.end_epoch,
+            });
+        }
+        let address = validate_payment_address(&self.payment_address, network)?;
+        validate_payment_amount(self.payment_amount, budget)?;
+        Ok(address)
+    }
+
+    /// Returns whether a superblock at `time` falls in the payment period.
+    pub fn is_active_at(&self, time: i64) -> bool {
+        self.start_epoch <= time && time <= self.end_epoch
+    }
+}
+
+/// Checks that a payment address is a P2PKH or P2SH address of `network`.
+pub(crate) fn validate_payment_address(
+    address: &Address<NetworkUnchecked>,
+    network: Network,
+) -> Result<Address, PayloadError> {
+    let displayed = || address.assume_checked_ref().to_string();
+    if !address.is_valid_for_network(network) {
+        return Err(PayloadError::WrongNetwork(displayed()));
+    }
+    let address = address.clone().assume_checked();
+    match address.address_type() {
+        Some(AddressType::P2pkh) | Some(AddressType::P2sh) => Ok(address),
+        _ => Err(PayloadError::UnsupportedAddress(displayed())),
+    }
+}
+
+/// Checks that a payment is positive and within the superblock budget.
+pub(crate) fn validate_payment_amount(amount: Amount, budget: Amount) -> Result<(), PayloadError> {
+    if amount == Amount::ZERO {
+        return Err(PayloadError::ZeroAmount);
+    }
+    if amount > budget {
+        return Err(PayloadError::ExceedsBudget { amount, budget });
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::OutPoint;
+    use crate::hash_types::{GovernanceObjectHash, Txid};
+
+    const TESTNET_ADDRESS: &str = "yTWY6DsS4HBGs2JwDtnvVcpykLkbvtjUte";
+
+    fn json() -> String {
+        format!(
+            r#"{{"end_epoch":1700100000,"name":"dash-tooling","payment_address":"{}","payment_amount":12.5,"start_epoch":1700000000,"type":1,"url":"https://dashcentral.org/p/dash-tooling"}}"#,
+            TESTNET_ADDRESS
+        )
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let proposal = Proposal::from_json(&json()).unwrap();
+        assert_eq!(proposal.name, "dash-tooling");
+        assert_eq!(proposal.payment_amount, Amount::from_sat(1_250_000_000));
+        assert!(proposal.is_active_at(1700050000));
+        assert!(!proposal.is_active_at(1700100001));
+        assert_eq!(proposal.to_json(), json());
+
+        let quoted = Proposal { name: "a \"quoted\" \\ name\n".to_owned(), ..proposal.clone() };
+        assert_eq!(Proposal::from_json(&quoted.to_json()).unwrap(), quoted);
+
+        let legacy = format!(r#"[["proposal",{}]]"#, json());
+        assert_eq!(Proposal::from_json(&legacy).unwrap(), proposal);
+
+        let object = GovernanceObject {
+            parent_hash: GovernanceObjectHash::all_zeros(),
+            revision: 1,
+            time: 1_700_000_000,
+            collateral_hash: Txid::all_zeros(),
+            data: json().into_bytes(),
+            object_type: GovernanceObjectType::Proposal,
+            masternode_outpoint: OutPoint::null(),
+            signature: vec![],
+        };
+        assert_eq!(Proposal::from_object(&object).unwrap(), proposal);
+        let trigger = GovernanceObject { object_type: GovernanceObjectType::Trigger, ..object };
+        assert_eq!(
+            Proposal::from_object(&trigger),
+            Err(PayloadError::WrongObjectType(GovernanceObjectType::Trigger))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            (json().replace("\"type\":1", "\"type\":2"), PayloadError::InvalidField("type")),
+            (json().replace("12.5", "-1"), PayloadError::InvalidField("payment_amount")),
+            (json().replace("12.5", "0.000000001"), PayloadError::InvalidField("payment_amount")),
+            (json().replace("12.5", "\"12.5\""), PayloadError::InvalidField("payment_amount")),
+            (
+                json().replace(TESTNET_ADDRESS, "nope"),
+                PayloadError::InvalidField("payment_address"),
+            ),
+            (json().replace("\"name\"", "\"title\""), PayloadError::MissingField("name")),
+            (format!("{:513}", "{}"), PayloadError::TooLarge(513)),
+        ];
+        for (json, err) in cases {
+            assert_eq!(Proposal::from_json(&json), Err(err), "{}", json);
+        }
+    }
+
+    #[test]
+    fn validation() {
+        let budget = Amount::from_sat(100_000_000_000);
+        let proposal = Proposal::from_json(&json()).unwrap();
+        assert_eq!(
+            proposal.validate(Network::Testnet, budget).unwrap().to_string(),
+            TESTNET_ADDRESS
+        );
+        assert_eq!(
+            proposal.validate(Network::Dash, budget),
+            Err(PayloadError::WrongNetwork(TESTNET_ADDRESS.to_owned()))
+        );
+        assert_eq!(
+            proposal.validate(Network::Testnet, Amount::from_sat(1_000_000_000)),
+            Err(PayloadError::ExceedsBudget {
+                amount: proposal.payment_amount,
+                budget: Amount::from_sat(1_000_000_000),
+            })
+        );
+
+        let invalid = |f: fn(&mut Proposal)| {
+            let mut proposal = proposal.clone();
+            f(&mut proposal);
+            proposal.validate(Network::Testnet, budget).unwrap_err()
+        };
+        assert!(matches!(invalid(|p| p.name = "has space".into()), PayloadError::InvalidName(_)));
+        assert!(matches!(invalid(|p| p.name = "x".repeat(41)), PayloadError::InvalidName(_)));
+        assert!(matches!(invalid(|p| p.name.clear()), PayloadError::InvalidName(_)));
+        assert!(matches!(invalid(|p| p.url = "a b.org".into()), PayloadError::InvalidUrl(_)));
+        assert!(matches!(
+            invalid(|p| p.end_epoch = p.start_epoch),
+            PayloadError::InvalidEpochs { .. }
+        ));
+        assert_eq!(invalid(|p| p.payment_amount = Amount::ZERO), PayloadError::ZeroAmount);
+    }
+}
@@ -0,0 +1,398 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash superblock triggers.
+//!
+//! A trigger lists the payments a superblock must make. Once masternodes have voted a trigger
+//! into place, its payments are appended to the coinbase of the block at `event_block_height`.
+//!
+
+use core::fmt;
+#[cfg(feature = "serde")]
+use core::str::FromStr;
+
+use internals::write_err;
+#[cfg(feature = "serde")]
+use serde_json::{Map, Value};
+
+use crate::address::NetworkUnchecked;
+#[cfg(feature = "serde")]
+use crate::amount::Denomination;
+#[cfg(feature = "serde")]
+use crate::governance::object::MAX_GOVERNANCE_OBJECT_DATA_SIZE;
+use crate::governance::payload::PayloadError;
+#[cfg(feature = "serde")]
+use crate::governance::payload::{int_field, parse_json, str_field};
+use crate::governance::proposal::{validate_payment_address, validate_payment_amount};
+#[cfg(feature = "serde")]
+use crate::governance::{GovernanceObject, GovernanceObjectType};
+use crate::hash_types::GovernanceObjectHash;
+use crate::prelude::*;
+use crate::{Address, Amount, Network, Transaction, TxOut};
+
+/// A payment made by a superblock
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuperblockPayment {
+    /// Address receiving the payment
+    pub address: Address<NetworkUnchecked>,
+    /// Amount paid
+    pub amount: Amount,
+    /// The proposal funded by the payment, if the trigger lists it
+    pub proposal_hash: Option<GovernanceObjectHash>,
+}
+
+/// A superblock trigger, the payload of a [`GovernanceObjectType::Trigger`] object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trigger {
+    /// Height of the superblock making the payments
+    pub event_block_height: u32,
+    /// Payments made by the superblock, in coinbase output order
+    pub payments: Vec<SuperblockPayment>,
+}
+
+/// An error checking a superblock against its trigger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SuperblockError {
+    /// The block is not the one the trigger is for.
+    WrongHeight {
+        /// Height named by the trigger.
+        expected: u32,
+        /// Height of the block checked.
+        found: u32,
+    },
+    /// The transaction checked is not a coinbase.
+    NotCoinbase,
+    /// The trigger is invalid.
+    Payload(PayloadError),
+    /// A payment required by the trigger is missing from the coinbase.
+    MissingPayment(TxOut),
+}
+
+impl fmt::Display for SuperblockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SuperblockError::WrongHeight { expected, found } =>
+                write!(f, "trigger is for height {}, not {}", expected, found),
+            SuperblockError::NotCoinbase => write!(f, "transaction is not a coinbase"),
+            SuperblockError::Payload(ref e) => write_err!(f, "invalid trigger"; e),
+            SuperblockError::MissingPayment(ref output) =>
+                write!(f, "coinbase lacks payment of {} to {}", output.value, output.script_pubkey),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SuperblockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SuperblockError::Payload(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PayloadError> for SuperblockError {
+    fn from(e: PayloadError) -> Self { SuperblockError::Payload(e) }
+}
+
+impl Trigger {
+    /// Parses the payload of a governance object, which must be a trigger.
+    #[cfg(feature = "serde")]
+    pub fn from_object(object: &GovernanceObject) -> Result<Trigger, PayloadError> {
+        if object.object_type != GovernanceObjectType::Trigger {
+            return Err(PayloadError::WrongObjectType(object.object_type));
+        }
+        Trigger::from_json(object.data_json().ok_or(PayloadError::InvalidUtf8)?)
+    }
+
+    /// Parses a trigger's JSON document.
+    ///
+    /// Addresses, amounts and proposal hashes are `|` separated lists of equal length; the
+    /// proposal hashes may be omitted. Only the document's syntax is checked, see
+    /// [`Trigger::validate`].
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Trigger, PayloadError> {
+        if json.len() > MAX_GOVERNANCE_OBJECT_DATA_SIZE {
+            return Err(PayloadError::TooLarge(json.len()));
+        }
+        let fields = parse_json(json)?;
+        if int_field(&fields, "type")? != 2 {
+            return Err(PayloadError::InvalidField("type"));
+        }
+        let event_block_height = u32::try_from(int_field(&fields, "event_block_height")?)
+            .map_err(|_| PayloadError::InvalidField("event_block_height"))?;
+
+        let addresses = str_field(&fields, "payment_addresses")?
+            .split('|')
+            .map(|s| {
+                Address::from_str(s).map_err(|_| PayloadError::InvalidField("payment_addresses"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let amounts = str_field(&fields, "payment_amounts")?
+            .split('|')
+            .map(|s| {
+                Amount::from_str_in(s, Denomination::Dash)
+                    .map_err(|_| PayloadError::InvalidField("payment_amounts"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let hashes = match fields.get("proposal_hashes") {
+            None => vec![None; addresses.len()],
+            Some(_) => str_field(&fields, "proposal_hashes")?
+                .split('|')
+                .map(|s| {
+                    GovernanceObjectHash::from_str(s)
+                        .map(Some)
+                        .map_err(|_| PayloadError::InvalidField("proposal_hashes"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        if addresses.len() != amounts.len() || addresses.len() != hashes.len() {
+            return Err(PayloadError::PaymentCountMismatch);
+        }
+
+        let payments = addresses
+            .into_iter()
+            .zip(amounts)
+            .zip(hashes)
+            .map(|((address, amount), proposal_hash)| SuperblockPayment {
+                address,
+                amount,
+                proposal_hash,
+            })
+            .collect();
+        Ok(Trigger { event_block_height, payments })
+    }
+
+    /// Serializes the trigger to its JSON document, with the fields in alphabetical order.
+    ///
+    /// Proposal hashes are only written if every payment has one.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let join = |f: &dyn Fn(&SuperblockPayment) -> String| {
+            Value::from(self.payments.iter().map(f).collect::<Vec<_>>().join("|"))
+        };
+        let mut document = Map::new();
+        document.insert("event_block_height".to_owned(), self.event_block_height.into());
+        document.insert(
+            "payment_addresses".to_owned(),
+            join(&|p| p.address.assume_checked_ref().to_string()),
+        );
+        document.insert(
+            "payment_amounts".to_owned(),
+            join(&|p| p.amount.to_string_in(Denomination::Dash)),
+        );
+        if !self.payments.is_empty() && self.payments.iter().all(|p| p.proposal_hash.is_some()) {
+            document.insert(
+                "proposal_hashes".to_owned(),
+                join(&|p| p.proposal_hash.expect("checked above").to_string()),
+            );
+        }
+        document.insert("type".to_owned(), 2.into());
+        Value::Object(document).to_string()
+    }
+
+    /// Returns the total amount paid by the superblock, `None` on overflow.
+    pub fn total_payments(&self) -> Option<Amount> {
+        self.payments.iter().try_fold(Amount::ZERO, |total, p| total.checked_add(p.amount))
+    }
+
+    /// Checks the trigger against the network's rules.
+    ///
+    /// There must be at least one payment, every payment must be positive and go to a P2PKH or
+    /// P2SH address of `network`, and the payments together may not exceed `budget`.
+    pub fn validate(&self, network: Network, budget: Amount) -> Result<(), PayloadError> {
+        if self.payments.is_empty() {
+            return Err(PayloadError::MissingField("payment_addresses"));
+        }
+        for payment in &self.payments {
+            validate_payment_address(&payment.address, network)?;
+            validate_payment_amount(payment.amount, budget)?;
+        }
+        let total = self.total_payments().ok_or(PayloadError::InvalidField("payment_amounts"))?;
+        validate_payment_amount(total, budget)
+    }
+
+    /// Computes the outputs the superblock's coinbase must contain.
+    pub fn expected_outputs(&self, network: Network) -> Result<Vec<TxOut>, PayloadError> {
+        self.payments
+            .iter()
+            .map(|payment| {
+                let address = validate_payment_address(&payment.address, network)?;
+                Ok(TxOut { value: payment.amount.to_sat(), script_pubkey: address.script_pubkey() })
+            })
+            .collect()
+    }
+
+    /// Checks that the coinbase of the superblock at `height` makes every payment.
+    ///
+    /// Each expected output must match a coinbase output exactly; the coinbase may contain other
+    /// outputs, such as the miner and masternode rewards, in any order. Like Dash Core's
+    /// `CSuperblock::IsValid`, equal payments may be matched by the same output.
+    pub fn check_coinbase(
+        &self,
+        network: Network,
+        budget: Amount,
+        height: u32,
+        coinbase: &Transaction,
+    ) -> Result<(), SuperblockError> {
+        if height != self.event_block_height {
+            return Err(SuperblockError::WrongHeight {
+                expected: self.event_block_height,
+                found: height,
+            });
+        }
+        if !coinbase.is_coin_base() {
+            return Err(SuperblockError::NotCoinbase);
+        }
+        self.validate(network, budget)?;
+
+        for expected in self.expected_outputs(network)? {
+            if !coinbase.output.contains(&expected) {
+                return Err(SuperblockError::MissingPayment(expected));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::blockdata::locktime::absolute::LockTime;
+    use crate::{OutPoint, ScriptBuf, TxIn};
+
+    const FIRST: &str = "yTWY6DsS4HBGs2JwDtnvVcpykLkbvtjUte";
+    const SECOND: &str = "yTb47qEBpNmgXvYYsHEN4nh8yJwa5iC4Cs";
+
+    fn json() -> String {
+        format!(
+            r#"{{"event_block_height":1000,"payment_addresses":"{}|{}","payment_amounts":"10|2.5","proposal_hashes":"{}|{}","type":2}}"#,
+            FIRST,
+            SECOND,
+            GovernanceObjectHash::hash(&[1]),
+            GovernanceObjectHash::hash(&[2]),
+        )
+    }
+
+    fn coinbase(outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 3,
+            lock_time: LockTime::ZERO.to_consensus_u32(),
+            input: vec![TxIn { previous_output: OutPoint::null(), ..Default::default() }],
+            output: outputs,
+            special_transaction_payload: None,
+        }
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let trigger = Trigger::from_json(&json()).unwrap();
+        assert_eq!(trigger.event_block_height, 1000);
+        assert_eq!(trigger.payments.len(), 2);
+        assert_eq!(trigger.payments[1].amount, Amount::from_sat(250_000_000));
+        assert_eq!(trigger.payments[0].proposal_hash, Some(GovernanceObjectHash::hash(&[1])));
+        assert_eq!(trigger.total_payments(), Some(Amount::from_sat(1_250_000_000)));
+        assert_eq!(trigger.to_json(), json());
+
+        let without_hashes = json().replace(
+            &format!(
+                r#","proposal_hashes":"{}|{}""#,
+                GovernanceObjectHash::hash(&[1]),
+                GovernanceObjectHash::hash(&[2])
+            ),
+            "",
+        );
+        let trigger = Trigger::from_json(&without_hashes).unwrap();
+        assert!(trigger.payments.iter().all(|p| p.proposal_hash.is_none()));
+        assert_eq!(trigger.to_json(), without_hashes);
+
+        assert_eq!(
+            Trigger::from_json(&json().replace("10|2.5", "10")),
+            Err(PayloadError::PaymentCountMismatch)
+        );
+        assert_eq!(
+            Trigger::from_json(&json().replace("10|2.5", "10|x")),
+            Err(PayloadError::InvalidField("payment_amounts"))
+        );
+        assert_eq!(
+            Trigger::from_json(&json().replace("\"type\":2", "\"type\":1")),
+            Err(PayloadError::InvalidField("type"))
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let trigger = Trigger::from_json(&json()).unwrap();
+        let budget = Amount::from_sat(1_250_000_000);
+        assert_eq!(trigger.validate(Network::Testnet, budget), Ok(()));
+        assert_eq!(
+            trigger.validate(Network::Testnet, Amount::from_sat(1_000_000_000)),
+            Err(PayloadError::ExceedsBudget {
+                amount: Amount::from_sat(1_250_000_000),
+                budget: Amount::from_sat(1_000_000_000),
+            })
+        );
+        assert_eq!(
+            trigger.validate(Network::Dash, budget),
+            Err(PayloadError::WrongNetwork(FIRST.to_owned()))
+        );
+    }
+
+    #[test]
+    fn check_coinbase() {
+        let trigger = Trigger::from_json(&json()).unwrap();
+        let budget = Amount::from_sat(2_000_000_000);
+        let expected = trigger.expected_outputs(Network::Testnet).unwrap();
+        assert_eq!(expected[0].value, 1_000_000_000);
+        assert_eq!(
+            expected[1].script_pubkey,
+            Address::from_str(SECOND).unwrap().assume_checked().script_pubkey()
+        );
+
+        // Miner output first, payments in any order.
+        let miner = TxOut { value: 5, script_pubkey: ScriptBuf::new() };
+        let mut outputs = vec![miner, expected[1].clone(), expected[0].clone()];
+        let tx = coinbase(outputs.clone());
+        assert_eq!(trigger.check_coinbase(Network::Testnet, budget, 1000, &tx), Ok(()));
+        assert_eq!(
+            trigger.check_coinbase(Network::Testnet, budget, 1001, &tx),
+            Err(SuperblockError::WrongHeight { expected: 1000, found: 1001 })
+        );
+
+        outputs[2].value -= 1;
+        let tx = coinbase(outputs);
+        assert_eq!(
+            trigger.check_coinbase(Network::Testnet, budget, 1000, &tx),
+            Err(SuperblockError::MissingPayment(expected[0].clone()))
+        );
+
+        let mut not_coinbase = tx.clone();
+        not_coinbase.input[0].previous_output.vout = 0;
+        assert_eq!(
+            trigger.check_coinbase(Network::Testnet, budget, 1000, &not_coinbase),
+            Err(SuperblockError::NotCoinbase)
+        );
+
+        // As in Dash Core, one output matches the same payment listed twice.
+        let doubled = Trigger {
+            event_block_height: 1000,
+            payments: vec![trigger.payments[0].clone(), trigger.payments[0].clone()],
+        };
+        let tx = coinbase(vec![expected[0].clone()]);
+        assert_eq!(doubled.check_coinbase(Network::Testnet, budget, 1000, &tx), Ok(()));
+    }
+}
@@ -0,0 +1,287 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash governance votes.
+//!
+//! Masternodes vote on governance objects with their voting key. A vote is identified by
+//! [`GovernanceVote::hash`] and signed with ECDSA, over a hash on testnet and over a signed
+//! message string on every other network.
+//!
+
+use core::fmt;
+
+use hashes::{Hash, sha256d};
+
+use crate::consensus::{Decodable, Encodable, encode};
+use crate::governance::object::encode_legacy_outpoint;
+use crate::hash_types::{GovernanceObjectHash, GovernanceVoteHash, PubkeyHash};
+use crate::internal_macros::impl_consensus_encoding;
+use crate::prelude::*;
+use crate::sign_message::signed_msg_hash;
+use crate::{Network, OutPoint, io, signer};
+
+macro_rules! impl_vote_enum {
+    ($name:ident, $what:literal, $($variant:ident = $value:literal => $display:literal),* $(,)?) => {
+        impl $name {
+            /// Returns the numeric value used on the wire.
+            pub fn to_i32(self) -> i32 {
+                match self {
+                    $($name::$variant => $value,)*
+                }
+            }
+
+            /// Parses the numeric value used on the wire.
+            pub fn from_i32(value: i32) -> Option<$name> {
+                match value {
+                    $($value => Some($name::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    $($name::$variant => write!(f, $display),)*
+                }
+            }
+        }
+
+        impl Encodable for $name {
+            fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+                self.to_i32().consensus_encode(w)
+            }
+        }
+
+        impl Decodable for $name {
+            fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+                $name::from_i32(i32::consensus_decode(r)?).ok_or(encode::Error::ParseFailed($what))
+            }
+        }
+    };
+}
+
+/// What a vote is about
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VoteSignal {
+    /// No signal
+    None,
+    /// The object should be paid by the next superblock
+    Funding,
+    /// The object is valid and should be kept
+    Valid,
+    /// The object should be deleted
+    Delete,
+    /// The object is endorsed by the network
+    Endorsed,
+}
+impl_vote_enum!(VoteSignal, "unknown governance vote signal",
+    None = 0 => "none",
+    Funding = 1 => "funding",
+    Valid = 2 => "valid",
+    Delete = 3 => "delete",
+    Endorsed = 4 => "endorsed",
+);
+
+/// How a masternode voted
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VoteOutcome {
+    /// No outcome
+    None,
+    /// In favour
+    Yes,
+    /// Against
+    No,
+    /// Neither for nor against
+    Abstain,
+}
+impl_vote_enum!(VoteOutcome, "unknown governance vote outcome",
+    None = 0 => "none",
+    Yes = 1 => "yes",
+    No = 2 => "no",
+    Abstain = 3 => "abstain",
+);
+
+/// A masternode vote on a governance object, as relayed in the `govobjvote` message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GovernanceVote {
+    /// Collateral outpoint of the voting masternode
+    pub masternode_outpoint: OutPoint,
+    /// Hash of the governance object voted on
+    pub parent_hash: GovernanceObjectHash,
+    /// How the masternode voted
+    pub vote_outcome: VoteOutcome,
+    /// What the vote is about
+    pub vote_signal: VoteSignal,
+    /// Time the vote was cast, in seconds since the epoch
+    pub time: i64,
+    /// Compact ECDSA signature by the masternode's voting key
+    pub signature: Vec<u8>,
+}
+
+impl_consensus_encoding!(
+    GovernanceVote,
+    masternode_outpoint,
+    parent_hash,
+    vote_outcome,
+    vote_signal,
+    time,
+    signature
+);
+
+impl GovernanceVote {
+    /// Computes the hash identifying this vote.
+    ///
+    /// The hash does not commit to the signature, so a vote cannot be replayed under a new
+    /// identity by re-signing it.
+    pub fn hash(&self) -> GovernanceVoteHash {
+        let mut engine = GovernanceVoteHash::engine();
+        encode_legacy_outpoint(&self.masternode_outpoint, &mut engine);
+        self.parent_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.vote_signal.consensus_encode(&mut engine).expect("engines don't error");
+        self.vote_outcome.consensus_encode(&mut engine).expect("engines don't error");
+        self.time.consensus_encode(&mut engine).expect("engines don't error");
+        GovernanceVoteHash::from_engine(engine)
+    }
+
+    /// Computes the hash signed on testnet: the serialized vote without its signature.
+    pub fn signature_hash(&self) -> sha256d::Hash {
+        let mut engine = sha256d::Hash::engine();
+        self.masternode_outpoint.consensus_encode(&mut engine).expect("engines don't error");
+        self.parent_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.vote_outcome.consensus_encode(&mut engine).expect("engines don't error");
+        self.vote_signal.consensus_encode(&mut engine).expect("engines don't error");
+        self.time.consensus_encode(&mut engine).expect("engines don't error");
+        sha256d::Hash::from_engine(engine)
+    }
+
+    /// Returns the message signed on networks other than testnet.
+    pub fn signing_message(&self) -> String {
+        format!(
+            "{}-{}|{}|{}|{}|{}",
+            self.masternode_outpoint.txid,
+            self.masternode_outpoint.vout,
+            self.parent_hash,
+            self.vote_signal.to_i32(),
+            self.vote_outcome.to_i32(),
+            self.time
+        )
+    }
+
+    /// Returns the hash the voting key signs on `network`.
+    pub fn signing_hash(&self, network: Network) -> sha256d::Hash {
+        match network {
+            Network::Testnet => self.signature_hash(),
+            _ => signed_msg_hash(&self.signing_message()),
+        }
+    }
+
+    /// Signs the vote with the masternode's voting key, replacing any previous signature.
+    pub fn sign(&mut self, voting_key: &[u8], network: Network) -> Result<(), anyhow::Error> {
+        let hash = self.signing_hash(network);
+        self.signature = signer::sign_hash(hash.as_byte_array(), voting_key)?.to_vec();
+        Ok(())
+    }
+
+    /// Verifies the vote was signed by the voting key hashing to `voting_key_id`.
+    pub fn verify(
+        &self,
+        voting_key_id: &PubkeyHash,
+        network: Network,
+    ) -> Result<(), anyhow::Error> {
+        let hash = self.signing_hash(network);
+        signer::verify_hash_signature(
+            hash.as_byte_array(),
+            &self.signature,
+            voting_key_id.as_byte_array(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::hash_types::Txid;
+
+    const VOTING_KEY: [u8; 32] = [7; 32];
+
+    fn vote() -> GovernanceVote {
+        GovernanceVote {
+            masternode_outpoint: OutPoint { txid: Txid::hash(&[1]), vout: 1 },
+            parent_hash: GovernanceObjectHash::hash(&[2]),
+            vote_outcome: VoteOutcome::Yes,
+            vote_signal: VoteSignal::Funding,
+            time: 1_700_000_000,
+            signature: vec![],
+        }
+    }
+
+    fn voting_key_id() -> PubkeyHash {
+        let secp = secp256k1::Secp256k1::new();
+        let secret = secp256k1::SecretKey::from_slice(&VOTING_KEY).unwrap();
+        crate::PublicKey::new(secret.public_key(&secp)).pubkey_hash()
+    }
+
+    #[test]
+    fn vote_encoding() {
+        let mut vote = vote();
+        vote.signature = vec![3; 65];
+        let encoded = serialize(&vote);
+        assert_eq!(encoded.len(), 36 + 32 + 4 + 4 + 8 + 66);
+        assert_eq!(deserialize::<GovernanceVote>(&encoded).unwrap(), vote);
+
+        let mut bad_signal = encoded.clone();
+        bad_signal[72..76].copy_from_slice(&5i32.to_le_bytes());
+        assert!(deserialize::<GovernanceVote>(&bad_signal).is_err());
+    }
+
+    #[test]
+    fn vote_hashes() {
+        let vote = vote();
+        let mut signed = vote.clone();
+        signed.signature = vec![3; 65];
+        assert_eq!(vote.hash(), signed.hash());
+        assert_eq!(vote.signature_hash(), signed.signature_hash());
+
+        let mut changed = vote.clone();
+        changed.vote_outcome = VoteOutcome::No;
+        assert_ne!(vote.hash(), changed.hash());
+        assert_ne!(vote.signature_hash(), changed.signature_hash());
+
+        assert_eq!(
+            vote.signing_message(),
+            format!("{}-1|{}|1|1|1700000000", Txid::hash(&[1]), GovernanceObjectHash::hash(&[2]))
+        );
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key_id = voting_key_id();
+        for network in [Network::Dash, Network::Testnet] {
+            let mut vote = vote();
+            vote.sign(&VOTING_KEY, network).unwrap();
+            assert_eq!(vote.signature.len(), 65);
+            assert!(vote.verify(&key_id, network).is_ok());
+
+            let other_network =
+                if network == Network::Testnet { Network::Dash } else { Network::Testnet };
+            assert!(vote.verify(&key_id, other_network).is_err());
+            assert!(vote.verify(&PubkeyHash::all_zeros(), network).is_err());
+
+            vote.vote_outcome = VoteOutcome::No;
+            assert!(vote.verify(&key_id, network).is_err());
+        }
+    }
+}
@@ -142,9 +142,21 @@ mod newtypes {
         pub struct QuorumVVecHash(sha256d::Hash);
         /// A hash of a quorum signing request id
         pub struct QuorumSigningRequestId(sha256d::Hash);
+        /// The hash a quorum actually signs, committing to the quorum, request id and message
+        pub struct QuorumSignHash(sha256d::Hash);
         /// ProTxHash is a pro-tx hash
         #[hash_newtype(forward)]
         pub struct ProTxHash(sha256d::Hash);
+        /// The hash identifying a governance object
+        pub struct GovernanceObjectHash(sha256d::Hash);
+        /// The hash identifying a governance vote
+        pub struct GovernanceVoteHash(sha256d::Hash);
+        /// The hash identifying a spork message
+        pub struct SporkHash(sha256d::Hash);
+        /// The hash a member signs for a DKG contribution, complaint or justification
+        pub struct DKGMessageSignHash(sha256d::Hash);
+        /// The hash committing to the outcome of a DKG session, signed by quorum commitments
+        pub struct QuorumCommitmentHash(sha256d::Hash);
     }
 
     impl_hashencode!(Txid);
@@ -166,8 +178,15 @@ mod newtypes {
     impl_hashencode!(QuorumHash);
     impl_hashencode!(QuorumVVecHash);
     impl_hashencode!(QuorumSigningRequestId);
+    impl_hashencode!(QuorumSignHash);
     impl_hashencode!(PubkeyHash);
     impl_hashencode!(CycleHash);
+    impl_hashencode!(ProTxHash);
+    impl_hashencode!(GovernanceObjectHash);
+    impl_hashencode!(GovernanceVoteHash);
+    impl_hashencode!(SporkHash);
+    impl_hashencode!(DKGMessageSignHash);
+    impl_hashencode!(QuorumCommitmentHash);
 
     impl_asref_push_bytes!(PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash);
 
@@ -105,6 +105,8 @@ pub mod bip152;
 pub mod bip158;
 pub mod bip32;
 pub mod blockdata;
+#[cfg(feature = "std")]
+pub mod bloom;
 pub mod consensus;
 // Private until we either make this a crate or flatten it - still to be decided.
 pub mod bls_sig_utils;
@@ -112,11 +114,14 @@ pub(crate) mod crypto;
 mod dip9;
 pub mod ephemerealdata;
 pub mod error;
+pub mod governance;
 pub mod hash_types;
+pub mod llmq;
 pub mod merkle_tree;
 pub mod policy;
 pub mod pow;
 pub mod psbt;
+pub mod rescan;
 pub mod sign_message;
 pub mod signer;
 pub mod string;
@@ -0,0 +1,581 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! ChainLock tracking and ChainLock-aware fork choice.
+//!
+//! Dash never reorganizes past a block that is covered by a ChainLock
+//! ([DIP8](https://github.com/dashpay/dips/blob/master/dip-0008.md)). The [`ChainLockManager`]
+//! keeps track of received ChainLocks and of the active header chain, rejects branches that
+//! would conflict with a lock and checks the ChainLock committed to in coinbase payloads
+//! ([DIP29](https://github.com/dashpay/dips/blob/master/dip-0029.md)).
+//!
+
+use core::fmt;
+
+#[cfg(feature = "bls")]
+use hashes::Hash;
+
+use super::LLMQType;
+#[cfg(feature = "bls")]
+use super::{QuorumKeyProvider, SIGN_HEIGHT_OFFSET};
+use crate::blockdata::block::Header;
+use crate::consensus::Params;
+use crate::ephemerealdata::chain_lock::ChainLock;
+use crate::hash_types::BlockHash;
+use crate::network::constants::Network;
+use crate::prelude::*;
+use crate::transaction::special_transaction::coinbase::CoinbasePayload;
+
+/// An error returned while processing ChainLocks or checking blocks against them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChainLockError {
+    /// A different ChainLock is already known for this height.
+    ConflictingChainLock {
+        /// Height of the ChainLock.
+        height: u32,
+        /// Hash of the block locked by the already known ChainLock.
+        locked: BlockHash,
+        /// Hash of the block locked by the rejected ChainLock.
+        received: BlockHash,
+    },
+    /// A header conflicts with the ChainLock at its height.
+    ConflictsWithChainLock {
+        /// Height of the header.
+        height: u32,
+        /// Hash of the locked block.
+        locked: BlockHash,
+        /// Hash of the conflicting header.
+        received: BlockHash,
+    },
+    /// The branch would disconnect a ChainLocked block from the active chain.
+    ReorgBelowChainLock {
+        /// Height the branch forks off the active chain.
+        fork_height: u32,
+        /// Height of the ChainLock that would be reorganized.
+        chain_lock_height: u32,
+    },
+    /// The branch does not connect to the active chain.
+    DisconnectedBranch {
+        /// Height the branch claims to fork off the active chain.
+        fork_height: u32,
+    },
+    /// No quorum was found to verify the ChainLock against.
+    QuorumNotFound {
+        /// Height of the ChainLock.
+        height: u32,
+    },
+    /// The ChainLock signature is invalid.
+    InvalidSignature {
+        /// Height of the ChainLock.
+        height: u32,
+    },
+    /// The coinbase payload claims a ChainLock above the block containing it.
+    InvalidCoinbaseChainLockHeight {
+        /// Height of the block.
+        block_height: u32,
+        /// Height difference committed to in the coinbase payload.
+        height_diff: u32,
+    },
+    /// The coinbase payload ChainLock signature differs from the known ChainLock.
+    CoinbaseChainLockMismatch {
+        /// Height of the ChainLock committed to in the coinbase payload.
+        height: u32,
+    },
+}
+
+impl fmt::Display for ChainLockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChainLockError::ConflictingChainLock { height, locked, received } => write!(
+                f,
+                "ChainLock for block {} at height {} conflicts with known ChainLock for {}",
+                received, height, locked
+            ),
+            ChainLockError::ConflictsWithChainLock { height, locked, received } => write!(
+                f,
+                "block {} at height {} conflicts with ChainLocked block {}",
+                received, height, locked
+            ),
+            ChainLockError::ReorgBelowChainLock { fork_height, chain_lock_height } => write!(
+                f,
+                "branch forking at height {} would reorganize ChainLocked height {}",
+                fork_height, chain_lock_height
+            ),
+            ChainLockError::DisconnectedBranch { fork_height } =>
+                write!(f, "branch does not connect to the active chain at height {}", fork_height),
+            ChainLockError::QuorumNotFound { height } =>
+                write!(f, "no quorum found to verify ChainLock at height {}", height),
+            ChainLockError::InvalidSignature { height } =>
+                write!(f, "invalid ChainLock signature at height {}", height),
+            ChainLockError::InvalidCoinbaseChainLockHeight { block_height, height_diff } => write!(
+                f,
+                "coinbase ChainLock height diff {} is invalid for block height {}",
+                height_diff, block_height
+            ),
+            ChainLockError::CoinbaseChainLockMismatch { height } =>
+                write!(f, "coinbase ChainLock signature at height {} does not match", height),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ChainLockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Tracks ChainLocks and the active header chain, and enforces ChainLock-aware fork choice.
+///
+/// The active chain is kept as a height-indexed map of block hashes. It does not need to start at
+/// genesis; it only needs to be contiguous from the first accepted height.
+#[derive(Debug, Clone)]
+pub struct ChainLockManager {
+    llmq_type: LLMQType,
+    chain_locks: BTreeMap<u32, ChainLock>,
+    active_chain: BTreeMap<u32, BlockHash>,
+}
+
+impl ChainLockManager {
+    /// Creates an empty manager for the given network.
+    pub fn new(network: Network) -> Self {
+        ChainLockManager {
+            llmq_type: Params::new(network).llmq_type_chain_locks,
+            chain_locks: BTreeMap::new(),
+            active_chain: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the quorum type ChainLocks are signed with.
+    pub fn llmq_type(&self) -> LLMQType { self.llmq_type }
+
+    /// Returns the ChainLock with the highest height.
+    pub fn best_chain_lock(&self) -> Option<&ChainLock> { self.chain_locks.values().next_back() }
+
+    /// Returns the ChainLock at `height`, if any.
+    pub fn chain_lock_at(&self, height: u32) -> Option<&ChainLock> { self.chain_locks.get(&height) }
+
+    /// Returns the hash of the active chain block at `height`, if known.
+    pub fn active_block_hash(&self, height: u32) -> Option<&BlockHash> {
+        self.active_chain.get(&height)
+    }
+
+    /// Returns the height and hash of the active chain tip.
+    pub fn active_tip(&self) -> Option<(u32, &BlockHash)> {
+        self.active_chain.iter().next_back().map(|(height, hash)| (*height, hash))
+    }
+
+    /// Records a ChainLock without verifying its signature.
+    ///
+    /// Returns `true` if the ChainLock is the new best ChainLock. Like Dash Core, ChainLocks at or
+    /// below the best ChainLock are ignored, so the active chain is never rewound below the best
+    /// locked height. If the locked block conflicts with the active chain, the active chain is
+    /// truncated below the locked height so that the locked branch can be accepted with
+    /// [`Self::accept_branch`].
+    pub fn process_chain_lock(&mut self, chain_lock: ChainLock) -> Result<bool, ChainLockError> {
+        let height = chain_lock.block_height;
+        if let Some(known) = self.chain_locks.get(&height) {
+            if known.block_hash != chain_lock.block_hash {
+                return Err(ChainLockError::ConflictingChainLock {
+                    height,
+                    locked: known.block_hash,
+                    received: chain_lock.block_hash,
+                });
+            }
+            return Ok(false);
+        }
+        if self.best_chain_lock().is_some_and(|best| best.block_height >= height) {
+            return Ok(false);
+        }
+
+        if self.active_chain.get(&height).is_some_and(|hash| *hash != chain_lock.block_hash) {
+            self.active_chain.split_off(&height);
+        }
+
+        self.chain_locks.insert(height, chain_lock);
+        Ok(true)
+    }
+
+    /// Verifies the ChainLock signature against the quorum responsible for it.
+    #[cfg(feature = "bls")]
+    pub fn verify_chain_lock<P: QuorumKeyProvider + ?Sized>(
+        &self,
+        chain_lock: &ChainLock,
+        provider: &P,
+    ) -> Result<(), ChainLockError> {
+        let height = chain_lock.block_height;
+        let request_id = chain_lock.request_id().expect("engines don't error");
+        let quorums =
+            provider.active_quorums(self.llmq_type, height.saturating_sub(SIGN_HEIGHT_OFFSET));
+        let quorum = super::select_quorum_for_signing(self.llmq_type, &quorums, &request_id)
+            .ok_or(ChainLockError::QuorumNotFound { height })?;
+        if super::verify_recovered_signature(
+            self.llmq_type,
+            quorum,
+            &request_id,
+            chain_lock.block_hash.as_byte_array(),
+            &chain_lock.signature,
+        ) {
+            Ok(())
+        } else {
+            Err(ChainLockError::InvalidSignature { height })
+        }
+    }
+
+    /// Verifies the ChainLock signature and records it, see [`Self::process_chain_lock`].
+    #[cfg(feature = "bls")]
+    pub fn verify_and_process_chain_lock<P: QuorumKeyProvider + ?Sized>(
+        &mut self,
+        chain_lock: ChainLock,
+        provider: &P,
+    ) -> Result<bool, ChainLockError> {
+        self.verify_chain_lock(&chain_lock, provider)?;
+        self.process_chain_lock(chain_lock)
+    }
+
+    /// Returns whether the block `block_hash` at `height` is covered by a ChainLock.
+    ///
+    /// A block is ChainLocked if it is locked directly, or if it is part of the active chain
+    /// below a locked active chain block.
+    pub fn is_chainlocked(&self, height: u32, block_hash: &BlockHash) -> bool {
+        if let Some(chain_lock) = self.chain_locks.get(&height) {
+            return chain_lock.block_hash == *block_hash;
+        }
+        if self.active_chain.get(&height) != Some(block_hash) {
+            return false;
+        }
+        self.chain_locks
+            .range(height..)
+            .any(|(h, cl)| self.active_chain.get(h) == Some(&cl.block_hash))
+    }
+
+    /// Returns whether some block at `height` is covered by the best ChainLock.
+    pub fn is_height_chainlocked(&self, height: u32) -> bool {
+        self.best_chain_lock().is_some_and(|best| best.block_height >= height)
+    }
+
+    /// Checks that a branch of headers forking off the active chain after `fork_height` does not
+    /// conflict with any known ChainLock.
+    ///
+    /// The first header is expected at height `fork_height + 1`. Under Dash fork choice such a
+    /// branch must be rejected regardless of its chain work.
+    pub fn check_branch(&self, fork_height: u32, headers: &[Header]) -> Result<(), ChainLockError> {
+        if let Some(first) = headers.first() {
+            match self.active_chain.get(&fork_height) {
+                Some(hash) if *hash != first.prev_blockhash =>
+                    return Err(ChainLockError::DisconnectedBranch { fork_height }),
+                None if !self.active_chain.is_empty() =>
+                    return Err(ChainLockError::DisconnectedBranch { fork_height }),
+                _ => {}
+            }
+        }
+
+        let mut prev_hash = None;
+        for (height, header) in (fork_height + 1..).zip(headers) {
+            if prev_hash.is_some_and(|prev| prev != header.prev_blockhash) {
+                return Err(ChainLockError::DisconnectedBranch { fork_height });
+            }
+            let hash = header.block_hash();
+            if let Some(chain_lock) = self.chain_locks.get(&height) {
+                if chain_lock.block_hash != hash {
+                    return Err(ChainLockError::ConflictsWithChainLock {
+                        height,
+                        locked: chain_lock.block_hash,
+                        received: hash,
+                    });
+                }
+            }
+            prev_hash = Some(hash);
+        }
+
+        let tip_height = fork_height + headers.len() as u32;
+        for (height, chain_lock) in self.chain_locks.range(tip_height + 1..) {
+            if self.active_chain.get(height) == Some(&chain_lock.block_hash)
+                && self.active_chain.contains_key(&(fork_height + 1))
+            {
+                return Err(ChainLockError::ReorgBelowChainLock {
+                    fork_height,
+                    chain_lock_height: *height,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the branch with [`Self::check_branch`] and makes it the active chain.
+    pub fn accept_branch(
+        &mut self,
+        fork_height: u32,
+        headers: &[Header],
+    ) -> Result<(), ChainLockError> {
+        self.check_branch(fork_height, headers)?;
+        if headers.is_empty() {
+            return Ok(());
+        }
+        self.active_chain.split_off(&(fork_height + 1));
+        for (height, header) in (fork_height + 1..).zip(headers) {
+            self.active_chain.insert(height, header.block_hash());
+        }
+        Ok(())
+    }
+
+    /// Checks the ChainLock committed to by the coinbase payload of the block at `block_height`.
+    ///
+    /// Returns the ChainLock the payload commits to if the locked block is part of the active
+    /// chain, so that it can be verified and processed like a ChainLock received from the
+    /// network. Payloads without a ChainLock, or with an empty signature, yield `None`.
+    pub fn check_coinbase_chain_lock(
+        &self,
+        block_height: u32,
+        payload: &CoinbasePayload,
+    ) -> Result<Option<ChainLock>, ChainLockError> {
+        let (height_diff, signature) = match (payload.best_cl_height, payload.best_cl_signature) {
+            (Some(height_diff), Some(signature)) => (height_diff, signature),
+            _ => return Ok(None),
+        };
+        if signature.as_bytes().iter().all(|b| *b == 0) {
+            return Ok(None);
+        }
+        let height = block_height
+            .checked_sub(height_diff)
+            .and_then(|h| h.checked_sub(1))
+            .ok_or(ChainLockError::InvalidCoinbaseChainLockHeight { block_height, height_diff })?;
+
+        if let Some(known) = self.chain_locks.get(&height) {
+            if known.signature != signature {
+                return Err(ChainLockError::CoinbaseChainLockMismatch { height });
+            }
+        }
+        Ok(self.active_chain.get(&height).map(|block_hash| ChainLock {
+            block_height: height,
+            block_hash: *block_hash,
+            signature,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::blockdata::block::Version;
+    use crate::bls_sig_utils::BLSSignature;
+    use crate::hash_types::{MerkleRootMasternodeList, TxMerkleNode};
+    use crate::pow::CompactTarget;
+
+    fn branch(prev: BlockHash, len: usize, nonce: u32) -> Vec<Header> {
+        let mut prev = prev;
+        (0..len)
+            .map(|_| {
+                let header = Header {
+                    version: Version::ONE,
+                    prev_blockhash: prev,
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: 0,
+                    bits: CompactTarget::from_consensus(0x207fffff),
+                    nonce,
+                };
+                prev = header.block_hash();
+                header
+            })
+            .collect()
+    }
+
+    fn lock(height: u32, block_hash: BlockHash) -> ChainLock {
+        ChainLock { block_height: height, block_hash, signature: BLSSignature::from([1; 96]) }
+    }
+
+    #[test]
+    fn tracks_best_chain_lock() {
+        let mut manager = ChainLockManager::new(Network::Regtest);
+        assert_eq!(manager.llmq_type(), LLMQType::LlmqtypeTest);
+        let headers = branch(BlockHash::all_zeros(), 10, 0);
+        manager.accept_branch(0, &headers).unwrap();
+
+        assert!(manager.process_chain_lock(lock(5, headers[4].block_hash())).unwrap());
+        assert!(!manager.process_chain_lock(lock(5, headers[4].block_hash())).unwrap());
+        assert!(!manager.process_chain_lock(lock(3, headers[2].block_hash())).unwrap());
+        assert_eq!(manager.best_chain_lock().unwrap().block_height, 5);
+        assert!(matches!(
+            manager.process_chain_lock(lock(5, headers[5].block_hash())),
+            Err(ChainLockError::ConflictingChainLock { height: 5, .. })
+        ));
+
+        assert!(manager.is_chainlocked(5, &headers[4].block_hash()));
+        assert!(manager.is_chainlocked(4, &headers[3].block_hash()));
+        assert!(!manager.is_chainlocked(4, &headers[4].block_hash()));
+        assert!(!manager.is_chainlocked(6, &headers[5].block_hash()));
+        assert!(manager.is_height_chainlocked(5));
+        assert!(!manager.is_height_chainlocked(6));
+    }
+
+    #[test]
+    fn rejects_conflicting_branches() {
+        let mut manager = ChainLockManager::new(Network::Dash);
+        let headers = branch(BlockHash::all_zeros(), 10, 0);
+        manager.accept_branch(0, &headers).unwrap();
+        manager.process_chain_lock(lock(6, headers[5].block_hash())).unwrap();
+
+        // A longer branch forking below the lock is rejected regardless of its length.
+        let fork = branch(headers[2].block_hash(), 20, 1);
+        assert_eq!(
+            manager.check_branch(3, &fork),
+            Err(ChainLockError::ConflictsWithChainLock {
+                height: 6,
+                locked: headers[5].block_hash(),
+                received: fork[2].block_hash(),
+            })
+        );
+        // A short branch that drops the locked block is rejected as well.
+        let fork = branch(headers[2].block_hash(), 1, 1);
+        assert_eq!(
+            manager.check_branch(3, &fork),
+            Err(ChainLockError::ReorgBelowChainLock { fork_height: 3, chain_lock_height: 6 })
+        );
+        // Branches above the lock are fine.
+        let fork = branch(headers[7].block_hash(), 5, 1);
+        manager.accept_branch(8, &fork).unwrap();
+        assert_eq!(manager.active_tip().unwrap(), (13, &fork[4].block_hash()));
+        // Branches that do not connect are rejected.
+        assert_eq!(
+            manager.check_branch(8, &branch(BlockHash::all_zeros(), 1, 2)),
+            Err(ChainLockError::DisconnectedBranch { fork_height: 8 })
+        );
+    }
+
+    #[test]
+    fn chain_lock_on_other_branch_truncates_active_chain() {
+        let mut manager = ChainLockManager::new(Network::Dash);
+        let headers = branch(BlockHash::all_zeros(), 10, 0);
+        manager.accept_branch(0, &headers).unwrap();
+        let fork = branch(headers[2].block_hash(), 5, 1);
+
+        manager.process_chain_lock(lock(5, fork[1].block_hash())).unwrap();
+        assert_eq!(manager.active_tip().unwrap().0, 4);
+        manager.accept_branch(3, &fork).unwrap();
+        assert!(manager.is_chainlocked(4, &fork[0].block_hash()));
+    }
+
+    #[test]
+    fn older_chain_lock_does_not_rewind() {
+        let mut manager = ChainLockManager::new(Network::Dash);
+        let headers = branch(BlockHash::all_zeros(), 10, 0);
+        manager.accept_branch(0, &headers).unwrap();
+        manager.process_chain_lock(lock(7, headers[6].block_hash())).unwrap();
+
+        // A lower ChainLock on another branch is ignored and does not drop locked blocks.
+        let fork = branch(headers[2].block_hash(), 5, 1);
+        assert!(!manager.process_chain_lock(lock(5, fork[1].block_hash())).unwrap());
+        assert_eq!(manager.active_tip().unwrap(), (10, &headers[9].block_hash()));
+        assert_eq!(manager.chain_lock_at(5), None);
+        assert!(manager.is_chainlocked(5, &headers[4].block_hash()));
+    }
+
+    #[test]
+    fn coinbase_chain_lock() {
+        let mut manager = ChainLockManager::new(Network::Dash);
+        let headers = branch(BlockHash::all_zeros(), 10, 0);
+        manager.accept_branch(0, &headers).unwrap();
+
+        let mut payload = CoinbasePayload {
+            version: 3,
+            height: 10,
+            merkle_root_masternode_list: MerkleRootMasternodeList::all_zeros(),
+            merkle_root_quorums: crate::hash_types::MerkleRootQuorums::all_zeros(),
+            best_cl_height: Some(2),
+            best_cl_signature: Some(BLSSignature::from([1; 96])),
+            asset_locked_amount: Some(0),
+        };
+        assert_eq!(
+            manager.check_coinbase_chain_lock(10, &payload).unwrap(),
+            Some(lock(7, headers[6].block_hash()))
+        );
+
+        manager.process_chain_lock(lock(7, headers[6].block_hash())).unwrap();
+        payload.best_cl_signature = Some(BLSSignature::from([2; 96]));
+        assert_eq!(
+            manager.check_coinbase_chain_lock(10, &payload),
+            Err(ChainLockError::CoinbaseChainLockMismatch { height: 7 })
+        );
+
+        payload.best_cl_height = Some(10);
+        assert_eq!(
+            manager.check_coinbase_chain_lock(10, &payload),
+            Err(ChainLockError::InvalidCoinbaseChainLockHeight {
+                block_height: 10,
+                height_diff: 10
+            })
+        );
+
+        payload.best_cl_signature = Some(BLSSignature::from([0; 96]));
+        assert_eq!(manager.check_coinbase_chain_lock(10, &payload), Ok(None));
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn verify_chain_lock_signature() {
+        use blsful::{Bls12381G2Impl, SecretKey, SignatureSchemes};
+
+        use crate::bls_sig_utils::BLSPublicKey;
+        use crate::hash_types::QuorumHash;
+        use crate::llmq::build_sign_hash;
+        use crate::llmq::tests::quorum;
+        use crate::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+
+        struct Provider(Vec<QuorumFinalizationCommitment>);
+
+        impl QuorumKeyProvider for Provider {
+            fn active_quorums(
+                &self,
+                llmq_type: LLMQType,
+                height: u32,
+            ) -> Vec<QuorumFinalizationCommitment> {
+                assert_eq!(llmq_type, LLMQType::LlmqtypeTest);
+                assert_eq!(height, 100 - SIGN_HEIGHT_OFFSET);
+                self.0.clone()
+            }
+        }
+
+        let secret_key = SecretKey::<Bls12381G2Impl>::from_hash(b"chainlock quorum");
+        let public_key = BLSPublicKey::from(&secret_key.public_key());
+        let quorum_hash = QuorumHash::hash(b"quorum");
+        let provider =
+            Provider(vec![quorum(LLMQType::LlmqtypeTest, quorum_hash, None, public_key)]);
+
+        let mut chain_lock = lock(100, BlockHash::hash(b"block"));
+        let sign_hash = build_sign_hash(
+            LLMQType::LlmqtypeTest,
+            &quorum_hash,
+            &chain_lock.request_id().unwrap(),
+            chain_lock.block_hash.as_byte_array(),
+        );
+        let signature =
+            secret_key.sign(SignatureSchemes::Basic, sign_hash.as_byte_array()).unwrap();
+        chain_lock.signature = BLSSignature::from(&signature);
+
+        let mut manager = ChainLockManager::new(Network::Regtest);
+        assert!(manager.verify_and_process_chain_lock(chain_lock.clone(), &provider).unwrap());
+
+        chain_lock.block_hash = BlockHash::hash(b"other block");
+        chain_lock.block_height = 100;
+        assert_eq!(
+            manager.verify_chain_lock(&chain_lock, &provider),
+            Err(ChainLockError::InvalidSignature { height: 100 })
+        );
+        assert_eq!(
+            manager.verify_chain_lock(&chain_lock, &Provider(vec![])),
+            Err(ChainLockError::QuorumNotFound { height: 100 })
+        );
+    }
+}
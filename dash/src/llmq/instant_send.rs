@@ -0,0 +1,606 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! InstantSend lock tracking.
+//!
+//! An InstantSend lock ([DIP10](https://github.com/dashpay/dips/blob/master/dip-0010.md)) makes a
+//! transaction final before it is mined: no other transaction spending the same inputs can be
+//! included in a block. The [`InstantSendManager`] stores locks by txid and by locked outpoint,
+//! detects conflicts, and forgets locks once the locked transaction is covered by a ChainLock.
+//!
+
+use core::fmt;
+
+use super::LLMQType;
+#[cfg(feature = "bls")]
+use super::{QuorumKeyProvider, SIGN_HEIGHT_OFFSET};
+use crate::blockdata::block::Block;
+use crate::blockdata::transaction::Transaction;
+use crate::blockdata::transaction::outpoint::OutPoint;
+use crate::consensus::Params;
+use crate::ephemerealdata::instant_lock::InstantLock;
+use crate::hash_types::Txid;
+use crate::network::constants::Network;
+use crate::prelude::*;
+
+/// An error returned while processing InstantSend locks or the transactions they lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InstantSendError {
+    /// The lock does not lock any input.
+    NoInputs {
+        /// Transaction the lock is for.
+        txid: Txid,
+    },
+    /// The lock locks the same input twice.
+    DuplicateInput {
+        /// Transaction the lock is for.
+        txid: Txid,
+        /// The duplicated input.
+        outpoint: OutPoint,
+    },
+    /// The lock spends an input that is already locked for another transaction.
+    ConflictingLock {
+        /// Transaction the rejected lock is for.
+        txid: Txid,
+        /// The input locked twice.
+        outpoint: OutPoint,
+        /// Transaction the input is locked for.
+        locked_txid: Txid,
+    },
+    /// A transaction spends an input that is locked for another transaction.
+    ConflictingTransaction {
+        /// The conflicting transaction.
+        txid: Txid,
+        /// The locked input spent by the transaction.
+        outpoint: OutPoint,
+        /// Transaction the input is locked for.
+        locked_txid: Txid,
+    },
+    /// The lock inputs do not match the inputs of the locked transaction.
+    InputsMismatch {
+        /// The locked transaction.
+        txid: Txid,
+    },
+    /// No quorum was found to verify the lock against.
+    QuorumNotFound {
+        /// Transaction the lock is for.
+        txid: Txid,
+    },
+    /// The lock signature is invalid.
+    InvalidSignature {
+        /// Transaction the lock is for.
+        txid: Txid,
+    },
+}
+
+impl fmt::Display for InstantSendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InstantSendError::NoInputs { txid } =>
+                write!(f, "InstantSend lock for {} has no inputs", txid),
+            InstantSendError::DuplicateInput { txid, outpoint } =>
+                write!(f, "InstantSend lock for {} locks input {} twice", txid, outpoint),
+            InstantSendError::ConflictingLock { txid, outpoint, locked_txid } => write!(
+                f,
+                "InstantSend lock for {} conflicts with lock for {} on input {}",
+                txid, locked_txid, outpoint
+            ),
+            InstantSendError::ConflictingTransaction { txid, outpoint, locked_txid } => write!(
+                f,
+                "transaction {} spends input {} locked for {}",
+                txid, outpoint, locked_txid
+            ),
+            InstantSendError::InputsMismatch { txid } =>
+                write!(f, "InstantSend lock inputs do not match transaction {}", txid),
+            InstantSendError::QuorumNotFound { txid } =>
+                write!(f, "no quorum found to verify InstantSend lock for {}", txid),
+            InstantSendError::InvalidSignature { txid } =>
+                write!(f, "invalid InstantSend lock signature for {}", txid),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InstantSendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Stores InstantSend locks and answers whether transactions are instantly final.
+///
+/// Locks may arrive before their transaction; such locks are kept and checked against the
+/// transaction once it is passed to [`Self::process_transaction`]. Locks, and the transactions
+/// seen by the manager, are dropped once their transaction is mined in a ChainLocked block.
+///
+/// A ChainLock overrides InstantSend: a ChainLocked block conflicting with a lock is connected
+/// with [`Self::connect_chain_locked_block`], which drops the conflicting locks.
+#[derive(Debug, Clone)]
+pub struct InstantSendManager {
+    llmq_type: LLMQType,
+    locks: BTreeMap<Txid, InstantLock>,
+    locked_outpoints: BTreeMap<OutPoint, Txid>,
+    transactions: BTreeMap<Txid, Vec<OutPoint>>,
+    mined_heights: BTreeMap<Txid, u32>,
+}
+
+impl InstantSendManager {
+    /// Creates an empty manager for the given network.
+    pub fn new(network: Network) -> Self {
+        InstantSendManager {
+            llmq_type: Params::new(network).llmq_type_instant_send,
+            locks: BTreeMap::new(),
+            locked_outpoints: BTreeMap::new(),
+            transactions: BTreeMap::new(),
+            mined_heights: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the quorum type InstantSend locks are signed with.
+    pub fn llmq_type(&self) -> LLMQType { self.llmq_type }
+
+    /// Returns whether the transaction is InstantSend locked.
+    pub fn is_locked(&self, txid: &Txid) -> bool { self.locks.contains_key(txid) }
+
+    /// Returns the lock for the transaction, if any.
+    pub fn instant_lock(&self, txid: &Txid) -> Option<&InstantLock> { self.locks.get(txid) }
+
+    /// Returns the lock covering the outpoint, if any.
+    pub fn lock_for_outpoint(&self, outpoint: &OutPoint) -> Option<&InstantLock> {
+        self.locked_outpoints.get(outpoint).and_then(|txid| self.locks.get(txid))
+    }
+
+    /// Returns the locks whose transaction has not been seen yet.
+    pub fn pending_locks(&self) -> impl Iterator<Item = &InstantLock> {
+        self.locks.values().filter(move |lock| !self.transactions.contains_key(&lock.txid))
+    }
+
+    /// Records a lock without verifying its signature.
+    ///
+    /// Returns `true` if the lock was not known yet.
+    pub fn process_instant_lock(&mut self, lock: InstantLock) -> Result<bool, InstantSendError> {
+        let txid = lock.txid;
+        if self.locks.contains_key(&txid) {
+            return Ok(false);
+        }
+        if lock.inputs.is_empty() {
+            return Err(InstantSendError::NoInputs { txid });
+        }
+        let mut inputs = BTreeSet::new();
+        for outpoint in &lock.inputs {
+            if !inputs.insert(*outpoint) {
+                return Err(InstantSendError::DuplicateInput { txid, outpoint: *outpoint });
+            }
+            if let Some(locked_txid) = self.locked_outpoints.get(outpoint) {
+                return Err(InstantSendError::ConflictingLock {
+                    txid,
+                    outpoint: *outpoint,
+                    locked_txid: *locked_txid,
+                });
+            }
+        }
+        if let Some(tx_inputs) = self.transactions.get(&txid) {
+            if tx_inputs.iter().copied().collect::<BTreeSet<_>>() != inputs {
+                return Err(InstantSendError::InputsMismatch { txid });
+            }
+        }
+
+        for outpoint in inputs {
+            self.locked_outpoints.insert(outpoint, txid);
+        }
+        self.locks.insert(txid, lock);
+        Ok(true)
+    }
+
+    /// Verifies the lock signature against the quorum responsible for it.
+    ///
+    /// `cycle_height` is the height of the block identified by the lock's cycle hash and
+    /// `tip_height` the height of the current chain tip.
+    #[cfg(feature = "bls")]
+    pub fn verify_instant_lock<P: QuorumKeyProvider + ?Sized>(
+        &self,
+        lock: &InstantLock,
+        provider: &P,
+        cycle_height: u32,
+        tip_height: u32,
+    ) -> Result<(), InstantSendError> {
+        use hashes::Hash;
+
+        let txid = lock.txid;
+        let dkg_interval = self.llmq_type.dkg_interval();
+        let sign_height = if cycle_height + dkg_interval < tip_height {
+            cycle_height + dkg_interval - 1
+        } else {
+            tip_height
+        };
+        let request_id = lock.request_id().expect("engines don't error");
+        let quorums =
+            provider.active_quorums(self.llmq_type, sign_height.saturating_sub(SIGN_HEIGHT_OFFSET));
+        let quorum = super::select_quorum_for_signing(self.llmq_type, &quorums, &request_id)
+            .ok_or(InstantSendError::QuorumNotFound { txid })?;
+        if super::verify_recovered_signature(
+            self.llmq_type,
+            quorum,
+            &request_id,
+            txid.as_byte_array(),
+            &lock.signature,
+        ) {
+            Ok(())
+        } else {
+            Err(InstantSendError::InvalidSignature { txid })
+        }
+    }
+
+    /// Verifies the lock signature and records it, see [`Self::process_instant_lock`].
+    #[cfg(feature = "bls")]
+    pub fn verify_and_process_instant_lock<P: QuorumKeyProvider + ?Sized>(
+        &mut self,
+        lock: InstantLock,
+        provider: &P,
+        cycle_height: u32,
+        tip_height: u32,
+    ) -> Result<bool, InstantSendError> {
+        self.verify_instant_lock(&lock, provider, cycle_height, tip_height)?;
+        self.process_instant_lock(lock)
+    }
+
+    /// Checks a transaction against the known locks and remembers its inputs.
+    ///
+    /// Returns `true` if the transaction is InstantSend locked.
+    pub fn process_transaction(&mut self, tx: &Transaction) -> Result<bool, InstantSendError> {
+        let txid = tx.txid();
+        self.check_transaction(txid, tx)?;
+        if !tx.is_coin_base() {
+            self.transactions
+                .entry(txid)
+                .or_insert_with(|| tx.input.iter().map(|input| input.previous_output).collect());
+        }
+        Ok(self.locks.contains_key(&txid))
+    }
+
+    /// Checks that no transaction in the block conflicts with a lock and records the height its
+    /// transactions were mined at.
+    ///
+    /// Heights are recorded for all transactions, so that a lock received after its transaction
+    /// was mined is still dropped by [`Self::process_chain_lock`].
+    pub fn connect_block(&mut self, height: u32, block: &Block) -> Result<(), InstantSendError> {
+        let txids: Vec<_> = block.txdata.iter().map(Transaction::txid).collect();
+        for (txid, tx) in txids.iter().zip(&block.txdata) {
+            self.check_transaction(*txid, tx)?;
+        }
+        self.record_mined(height, block);
+        Ok(())
+    }
+
+    /// Connects a ChainLocked block, which takes precedence over InstantSend locks.
+    ///
+    /// Locks conflicting with the block's transactions are dropped instead of rejecting the
+    /// block, as Dash Core does. Returns the txids of the dropped locks.
+    pub fn connect_chain_locked_block(&mut self, height: u32, block: &Block) -> Vec<Txid> {
+        let mut removed = Vec::new();
+        for tx in &block.txdata {
+            let txid = tx.txid();
+            while let Err(error) = self.check_transaction(txid, tx) {
+                let conflicting = match error {
+                    InstantSendError::ConflictingTransaction { locked_txid, .. } => locked_txid,
+                    InstantSendError::InputsMismatch { txid } => txid,
+                    _ => unreachable!("check_transaction only reports conflicts"),
+                };
+                self.remove_lock(&conflicting);
+                removed.push(conflicting);
+            }
+        }
+        self.record_mined(height, block);
+        removed
+    }
+
+    fn record_mined(&mut self, height: u32, block: &Block) {
+        for tx in block.txdata.iter().filter(|tx| !tx.is_coin_base()) {
+            self.mined_heights.insert(tx.txid(), height);
+        }
+    }
+
+    /// Removes a lock and the outpoints it locks; returns whether it was known.
+    fn remove_lock(&mut self, txid: &Txid) -> bool {
+        match self.locks.remove(txid) {
+            Some(lock) => {
+                for outpoint in &lock.inputs {
+                    self.locked_outpoints.remove(outpoint);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the mined heights recorded for the transactions of a disconnected block.
+    pub fn disconnect_block(&mut self, block: &Block) {
+        for tx in &block.txdata {
+            self.mined_heights.remove(&tx.txid());
+        }
+    }
+
+    /// Drops the locks and transactions mined at or below the ChainLocked `height`, as they no
+    /// longer need InstantSend protection.
+    ///
+    /// Returns the txids of the dropped locks.
+    pub fn process_chain_lock(&mut self, height: u32) -> Vec<Txid> {
+        let chain_locked: Vec<_> = self
+            .mined_heights
+            .iter()
+            .filter(|(_, mined_height)| **mined_height <= height)
+            .map(|(txid, _)| *txid)
+            .collect();
+
+        let mut removed = Vec::new();
+        for txid in chain_locked {
+            self.mined_heights.remove(&txid);
+            self.transactions.remove(&txid);
+            if self.remove_lock(&txid) {
+                removed.push(txid);
+            }
+        }
+        removed
+    }
+
+    fn check_transaction(&self, txid: Txid, tx: &Transaction) -> Result<(), InstantSendError> {
+        if tx.is_coin_base() {
+            return Ok(());
+        }
+        for input in &tx.input {
+            if let Some(locked_txid) = self.locked_outpoints.get(&input.previous_output) {
+                if *locked_txid != txid {
+                    return Err(InstantSendError::ConflictingTransaction {
+                        txid,
+                        outpoint: input.previous_output,
+                        locked_txid: *locked_txid,
+                    });
+                }
+            }
+        }
+        if let Some(lock) = self.locks.get(&txid) {
+            let tx_inputs: BTreeSet<_> =
+                tx.input.iter().map(|input| input.previous_output).collect();
+            if lock.inputs.iter().copied().collect::<BTreeSet<_>>() != tx_inputs {
+                return Err(InstantSendError::InputsMismatch { txid });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::blockdata::block::{Header, Version};
+    use crate::blockdata::script::ScriptBuf;
+    use crate::blockdata::transaction::txin::TxIn;
+    use crate::blockdata::transaction::txout::TxOut;
+    use crate::hash_types::{BlockHash, TxMerkleNode};
+    use crate::pow::CompactTarget;
+
+    fn tx(inputs: &[OutPoint], value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: inputs
+                .iter()
+                .map(|outpoint| TxIn { previous_output: *outpoint, ..Default::default() })
+                .collect(),
+            output: vec![TxOut { value, script_pubkey: ScriptBuf::new() }],
+            special_transaction_payload: None,
+        }
+    }
+
+    fn lock(tx: &Transaction) -> InstantLock {
+        InstantLock {
+            inputs: tx.input.iter().map(|input| input.previous_output).collect(),
+            txid: tx.txid(),
+            ..Default::default()
+        }
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    fn outpoint(n: u8) -> OutPoint { OutPoint::new(Txid::hash(&[n]), 0) }
+
+    #[test]
+    fn lock_before_and_after_transaction() {
+        let mut manager = InstantSendManager::new(Network::Dash);
+        assert_eq!(manager.llmq_type(), LLMQType::Llmqtype60_75);
+
+        // Lock arrives first.
+        let tx1 = tx(&[outpoint(1), outpoint(2)], 1);
+        assert!(manager.process_instant_lock(lock(&tx1)).unwrap());
+        assert!(!manager.process_instant_lock(lock(&tx1)).unwrap());
+        assert_eq!(manager.pending_locks().count(), 1);
+        assert!(manager.process_transaction(&tx1).unwrap());
+        assert_eq!(manager.pending_locks().count(), 0);
+        assert_eq!(manager.lock_for_outpoint(&outpoint(2)).unwrap().txid, tx1.txid());
+
+        // Transaction arrives first.
+        let tx2 = tx(&[outpoint(3)], 1);
+        assert!(!manager.process_transaction(&tx2).unwrap());
+        let mut bad_lock = lock(&tx2);
+        bad_lock.inputs.push(outpoint(4));
+        assert_eq!(
+            manager.process_instant_lock(bad_lock),
+            Err(InstantSendError::InputsMismatch { txid: tx2.txid() })
+        );
+        assert!(manager.process_instant_lock(lock(&tx2)).unwrap());
+        assert!(manager.is_locked(&tx2.txid()));
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let mut manager = InstantSendManager::new(Network::Dash);
+        let tx1 = tx(&[outpoint(1), outpoint(2)], 1);
+        let double_spend = tx(&[outpoint(2)], 2);
+        manager.process_instant_lock(lock(&tx1)).unwrap();
+
+        let conflict = InstantSendError::ConflictingTransaction {
+            txid: double_spend.txid(),
+            outpoint: outpoint(2),
+            locked_txid: tx1.txid(),
+        };
+        assert_eq!(manager.process_transaction(&double_spend), Err(conflict.clone()));
+        assert_eq!(manager.connect_block(10, &block(vec![double_spend.clone()])), Err(conflict));
+        assert_eq!(
+            manager.process_instant_lock(lock(&double_spend)),
+            Err(InstantSendError::ConflictingLock {
+                txid: double_spend.txid(),
+                outpoint: outpoint(2),
+                locked_txid: tx1.txid(),
+            })
+        );
+
+        let mut duplicate = lock(&tx(&[outpoint(5)], 1));
+        duplicate.inputs.push(outpoint(5));
+        assert!(matches!(
+            manager.process_instant_lock(duplicate),
+            Err(InstantSendError::DuplicateInput { .. })
+        ));
+        assert!(matches!(
+            manager.process_instant_lock(InstantLock::default()),
+            Err(InstantSendError::NoInputs { .. })
+        ));
+    }
+
+    #[test]
+    fn chain_lock_expires_locks() {
+        let mut manager = InstantSendManager::new(Network::Dash);
+        let tx1 = tx(&[outpoint(1)], 1);
+        let tx2 = tx(&[outpoint(2)], 1);
+        manager.process_instant_lock(lock(&tx1)).unwrap();
+        manager.process_instant_lock(lock(&tx2)).unwrap();
+
+        manager.connect_block(10, &block(vec![tx1.clone()])).unwrap();
+        manager.connect_block(11, &block(vec![tx2.clone()])).unwrap();
+        manager.disconnect_block(&block(vec![tx2.clone()]));
+
+        assert!(manager.process_chain_lock(9).is_empty());
+        assert_eq!(manager.process_chain_lock(11), vec![tx1.txid()]);
+        assert!(!manager.is_locked(&tx1.txid()));
+        assert!(manager.lock_for_outpoint(&outpoint(1)).is_none());
+        assert!(manager.is_locked(&tx2.txid()));
+    }
+
+    #[test]
+    fn lock_after_mined_transaction_expires() {
+        let mut manager = InstantSendManager::new(Network::Dash);
+        let tx1 = tx(&[outpoint(1)], 1);
+        manager.connect_block(10, &block(vec![tx1.clone()])).unwrap();
+        manager.process_instant_lock(lock(&tx1)).unwrap();
+
+        assert_eq!(manager.process_chain_lock(10), vec![tx1.txid()]);
+        assert!(!manager.is_locked(&tx1.txid()));
+    }
+
+    #[test]
+    fn chain_locked_block_overrides_lock() {
+        let mut manager = InstantSendManager::new(Network::Dash);
+        let tx1 = tx(&[outpoint(1), outpoint(2)], 1);
+        let double_spend = tx(&[outpoint(2)], 2);
+        manager.process_instant_lock(lock(&tx1)).unwrap();
+
+        let conflicting = block(vec![double_spend.clone()]);
+        assert!(manager.connect_block(10, &conflicting).is_err());
+        assert_eq!(manager.connect_chain_locked_block(10, &conflicting), vec![tx1.txid()]);
+        assert!(!manager.is_locked(&tx1.txid()));
+        assert!(manager.lock_for_outpoint(&outpoint(1)).is_none());
+        assert!(!manager.process_transaction(&double_spend).unwrap());
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn verify_instant_lock_signature() {
+        use blsful::{Bls12381G2Impl, SecretKey, SignatureSchemes};
+
+        use crate::bls_sig_utils::{BLSPublicKey, BLSSignature};
+        use crate::hash_types::QuorumHash;
+        use crate::llmq::build_sign_hash;
+        use crate::llmq::tests::quorum;
+        use crate::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+
+        struct Provider(Vec<QuorumFinalizationCommitment>);
+
+        impl QuorumKeyProvider for Provider {
+            fn active_quorums(
+                &self,
+                llmq_type: LLMQType,
+                height: u32,
+            ) -> Vec<QuorumFinalizationCommitment> {
+                assert_eq!(llmq_type, LLMQType::LlmqtypeTestDIP0024);
+                assert_eq!(height, 100 + 24 - 1 - SIGN_HEIGHT_OFFSET);
+                self.0.clone()
+            }
+        }
+
+        let secret_key = SecretKey::<Bls12381G2Impl>::from_hash(b"instantsend quorum");
+        let public_key = BLSPublicKey::from(&secret_key.public_key());
+        let quorums: Vec<_> = (0..2)
+            .map(|i| {
+                quorum(
+                    LLMQType::LlmqtypeTestDIP0024,
+                    QuorumHash::hash(&[i]),
+                    Some(i as i16),
+                    public_key,
+                )
+            })
+            .collect();
+        let provider = Provider(quorums.clone());
+
+        let mut is_lock = lock(&tx(&[outpoint(1)], 1));
+        let request_id = is_lock.request_id().unwrap();
+        let quorum = crate::llmq::select_quorum_for_signing(
+            LLMQType::LlmqtypeTestDIP0024,
+            &quorums,
+            &request_id,
+        )
+        .unwrap();
+        let sign_hash = build_sign_hash(
+            LLMQType::LlmqtypeTestDIP0024,
+            &quorum.quorum_hash,
+            &request_id,
+            is_lock.txid.as_byte_array(),
+        );
+        let signature =
+            secret_key.sign(SignatureSchemes::Basic, sign_hash.as_byte_array()).unwrap();
+        is_lock.signature = BLSSignature::from(&signature);
+
+        let mut manager = InstantSendManager::new(Network::Regtest);
+        assert!(
+            manager.verify_and_process_instant_lock(is_lock.clone(), &provider, 100, 200).unwrap()
+        );
+
+        is_lock.txid = Txid::hash(b"other");
+        assert_eq!(
+            manager.verify_instant_lock(&is_lock, &provider, 100, 200),
+            Err(InstantSendError::InvalidSignature { txid: is_lock.txid })
+        );
+    }
+}
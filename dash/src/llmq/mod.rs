@@ -0,0 +1,443 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Long-Living Masternode Quorums (LLMQ).
+//!
+//! This module contains the LLMQ parameters, the quorum signing hash construction and the quorum
+//! selection rules described in [DIP7](https://github.com/dashpay/dips/blob/master/dip-0007.md)
+//! and [DIP24](https://github.com/dashpay/dips/blob/master/dip-0024.md), together with the
+//! components built on top of them, such as ChainLock and InstantSend lock tracking.
+//!
+
+use core::fmt;
+
+use hashes::{Hash, HashEngine};
+
+use crate::bls_sig_utils::BLSPublicKey;
+#[cfg(feature = "bls")]
+use crate::bls_sig_utils::BLSSignature;
+use crate::consensus::Encodable;
+use crate::consensus::encode::{write_compact_size, write_fixed_bitset};
+use crate::hash_types::{
+    QuorumCommitmentHash, QuorumHash, QuorumSignHash, QuorumSigningRequestId, QuorumVVecHash,
+};
+use crate::prelude::*;
+use crate::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+
+pub mod chain_locks;
+pub mod instant_send;
+#[cfg(feature = "bls")]
+pub mod threshold;
+
+/// Quorums are selected from the masternode list this many blocks below the height of the signed
+/// message, so that all nodes agree on the set of active quorums.
+pub const SIGN_HEIGHT_OFFSET: u32 = 8;
+
+/// The type of a Long-Living Masternode Quorum, as defined in Dash Core's `chainparams.cpp`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(crate = "actual_serde"))]
+#[repr(u8)]
+pub enum LLMQType {
+    /// 50 members, 30 (60%) threshold, one per hour.
+    Llmqtype50_60 = 1,
+    /// 400 members, 240 (60%) threshold, one every 12 hours.
+    Llmqtype400_60 = 2,
+    /// 400 members, 340 (85%) threshold, one every 24 hours.
+    Llmqtype400_85 = 3,
+    /// 100 members, 67 (67%) threshold, one per hour.
+    Llmqtype100_67 = 4,
+    /// 60 members, 45 (75%) threshold, rotated, one every 12 hours.
+    Llmqtype60_75 = 5,
+    /// 25 members, 17 (67%) threshold, one per hour.
+    Llmqtype25_67 = 6,
+    /// 3 members, 2 (66%) threshold, used on regtest.
+    LlmqtypeTest = 100,
+    /// 12 members, 6 (50%) threshold, used on devnets.
+    LlmqtypeDevnet = 101,
+    /// 3 members, 2 (66%) threshold, used on regtest for v17 tests.
+    LlmqtypeTestV17 = 102,
+    /// 4 members, 3 (75%) threshold, rotated, used on regtest.
+    LlmqtypeTestDIP0024 = 103,
+    /// 3 members, 2 (66%) threshold, used on regtest for InstantSend.
+    LlmqtypeTestInstantSend = 104,
+    /// 8 members, 4 (50%) threshold, rotated, used on devnets.
+    LlmqtypeDevnetDIP0024 = 105,
+    /// 3 members, 2 (66%) threshold, used on regtest for platform.
+    LlmqtypeTestnetPlatform = 106,
+    /// 12 members, 8 (67%) threshold, used on devnets for platform.
+    LlmqtypeDevnetPlatform = 107,
+}
+
+impl LLMQType {
+    /// Number of members in a quorum of this type.
+    pub fn size(self) -> u32 {
+        use LLMQType::*;
+        match self {
+            Llmqtype50_60 => 50,
+            Llmqtype400_60 | Llmqtype400_85 => 400,
+            Llmqtype100_67 => 100,
+            Llmqtype60_75 => 60,
+            Llmqtype25_67 => 25,
+            LlmqtypeTest | LlmqtypeTestV17 | LlmqtypeTestInstantSend | LlmqtypeTestnetPlatform => 3,
+            LlmqtypeDevnet | LlmqtypeDevnetPlatform => 12,
+            LlmqtypeTestDIP0024 => 4,
+            LlmqtypeDevnetDIP0024 => 8,
+        }
+    }
+
+    /// Minimum number of signature shares required to recover a quorum signature.
+    pub fn threshold(self) -> u32 {
+        use LLMQType::*;
+        match self {
+            Llmqtype50_60 => 30,
+            Llmqtype400_60 => 240,
+            Llmqtype400_85 => 340,
+            Llmqtype100_67 => 67,
+            Llmqtype60_75 => 45,
+            Llmqtype25_67 => 17,
+            LlmqtypeTest | LlmqtypeTestV17 | LlmqtypeTestInstantSend | LlmqtypeTestnetPlatform => 2,
+            LlmqtypeDevnet => 6,
+            LlmqtypeTestDIP0024 => 3,
+            LlmqtypeDevnetDIP0024 => 4,
+            LlmqtypeDevnetPlatform => 8,
+        }
+    }
+
+    /// Number of blocks between two DKG sessions of this type.
+    pub fn dkg_interval(self) -> u32 {
+        use LLMQType::*;
+        match self {
+            Llmqtype400_60 | Llmqtype60_75 => 24 * 12,
+            Llmqtype400_85 => 24 * 24,
+            LlmqtypeDevnetDIP0024 => 48,
+            _ => 24,
+        }
+    }
+
+    /// Number of most recent quorums of this type that are used for signing.
+    pub fn signing_active_quorum_count(self) -> u32 {
+        use LLMQType::*;
+        match self {
+            Llmqtype50_60 | Llmqtype100_67 | Llmqtype25_67 => 24,
+            Llmqtype400_60 | Llmqtype400_85 => 4,
+            Llmqtype60_75 => 32,
+            LlmqtypeDevnet | LlmqtypeDevnetPlatform => 4,
+            LlmqtypeTest
+            | LlmqtypeTestV17
+            | LlmqtypeTestDIP0024
+            | LlmqtypeTestInstantSend
+            | LlmqtypeDevnetDIP0024
+            | LlmqtypeTestnetPlatform => 2,
+        }
+    }
+
+    /// Whether quorums of this type use DIP24 rotation.
+    pub fn use_rotation(self) -> bool {
+        matches!(
+            self,
+            LLMQType::Llmqtype60_75
+                | LLMQType::LlmqtypeTestDIP0024
+                | LLMQType::LlmqtypeDevnetDIP0024
+        )
+    }
+
+    /// The name Dash Core uses for this quorum type.
+    pub fn name(self) -> &'static str {
+        use LLMQType::*;
+        match self {
+            Llmqtype50_60 => "llmq_50_60",
+            Llmqtype400_60 => "llmq_400_60",
+            Llmqtype400_85 => "llmq_400_85",
+            Llmqtype100_67 => "llmq_100_67",
+            Llmqtype60_75 => "llmq_60_75",
+            Llmqtype25_67 => "llmq_25_67",
+            LlmqtypeTest => "llmq_test",
+            LlmqtypeDevnet => "llmq_devnet",
+            LlmqtypeTestV17 => "llmq_test_v17",
+            LlmqtypeTestDIP0024 => "llmq_test_dip0024",
+            LlmqtypeTestInstantSend => "llmq_test_instantsend",
+            LlmqtypeDevnetDIP0024 => "llmq_devnet_dip0024",
+            LlmqtypeTestnetPlatform => "llmq_test_platform",
+            LlmqtypeDevnetPlatform => "llmq_devnet_platform",
+        }
+    }
+}
+
+impl fmt::Display for LLMQType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(self.name()) }
+}
+
+impl From<LLMQType> for u8 {
+    fn from(llmq_type: LLMQType) -> Self { llmq_type as u8 }
+}
+
+impl TryFrom<u8> for LLMQType {
+    type Error = UnknownLLMQTypeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        use LLMQType::*;
+        Ok(match value {
+            1 => Llmqtype50_60,
+            2 => Llmqtype400_60,
+            3 => Llmqtype400_85,
+            4 => Llmqtype100_67,
+            5 => Llmqtype60_75,
+            6 => Llmqtype25_67,
+            100 => LlmqtypeTest,
+            101 => LlmqtypeDevnet,
+            102 => LlmqtypeTestV17,
+            103 => LlmqtypeTestDIP0024,
+            104 => LlmqtypeTestInstantSend,
+            105 => LlmqtypeDevnetDIP0024,
+            106 => LlmqtypeTestnetPlatform,
+            107 => LlmqtypeDevnetPlatform,
+            other => return Err(UnknownLLMQTypeError(other)),
+        })
+    }
+}
+
+/// Error returned when converting an unknown value into an [`LLMQType`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownLLMQTypeError(pub u8);
+
+impl fmt::Display for UnknownLLMQTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown LLMQ type: {}", self.0)
+    }
+}
+
+crate::error::impl_std_error!(UnknownLLMQTypeError);
+
+/// Provides the quorums that were active at a given height.
+///
+/// Implementations usually sit on top of a masternode list store. The returned commitments must
+/// be ordered from the most recent quorum to the oldest one, as Dash Core does when scanning
+/// quorums.
+pub trait QuorumKeyProvider {
+    /// Returns the signing-active quorums of type `llmq_type` at block `height`.
+    fn active_quorums(&self, llmq_type: LLMQType, height: u32)
+    -> Vec<QuorumFinalizationCommitment>;
+}
+
+/// Builds the hash signed by a quorum for the given request id and message hash.
+///
+/// This is `SHA256d(llmqType || quorumHash || requestId || msgHash)`. The quorum type may be
+/// given as its raw value, as carried by signing session messages.
+pub fn build_sign_hash(
+    llmq_type: impl Into<u8>,
+    quorum_hash: &QuorumHash,
+    request_id: &QuorumSigningRequestId,
+    msg_hash: &[u8; 32],
+) -> QuorumSignHash {
+    let mut engine = QuorumSignHash::engine();
+    llmq_type.into().consensus_encode(&mut engine).expect("engines don't error");
+    quorum_hash.consensus_encode(&mut engine).expect("engines don't error");
+    request_id.consensus_encode(&mut engine).expect("engines don't error");
+    engine.input(msg_hash);
+    QuorumSignHash::from_engine(engine)
+}
+
+/// Builds the hash committing to the outcome of a DKG session.
+///
+/// This is `SHA256d(llmqType || quorumHash || validMembers || quorumPublicKey || quorumVvecHash)`
+/// with `validMembers` serialized as a size-prefixed bitset. Members sign it in their premature
+/// commitments, and the final commitment carries the recovered and aggregated signatures over it.
+pub fn build_commitment_hash(
+    llmq_type: u8,
+    quorum_hash: &QuorumHash,
+    valid_members: &[bool],
+    quorum_public_key: &BLSPublicKey,
+    quorum_vvec_hash: &QuorumVVecHash,
+) -> QuorumCommitmentHash {
+    let mut engine = QuorumCommitmentHash::engine();
+    llmq_type.consensus_encode(&mut engine).expect("engines don't error");
+    quorum_hash.consensus_encode(&mut engine).expect("engines don't error");
+    write_compact_size(&mut engine, valid_members.len() as u32).expect("engines don't error");
+    write_fixed_bitset(&mut engine, valid_members, valid_members.len())
+        .expect("engines don't error");
+    quorum_public_key.consensus_encode(&mut engine).expect("engines don't error");
+    quorum_vvec_hash.consensus_encode(&mut engine).expect("engines don't error");
+    QuorumCommitmentHash::from_engine(engine)
+}
+
+/// Selects the quorum responsible for signing `request_id` out of the signing-active quorums.
+///
+/// Non-rotated quorum types pick the quorum with the lowest
+/// `SHA256d(llmqType || quorumHash || requestId)`, while rotated types use the high bits of the
+/// request id as the quorum index.
+pub fn select_quorum_for_signing<'a>(
+    llmq_type: LLMQType,
+    quorums: &'a [QuorumFinalizationCommitment],
+    request_id: &QuorumSigningRequestId,
+) -> Option<&'a QuorumFinalizationCommitment> {
+    let quorums = quorums.iter().filter(|q| q.llmq_type == u8::from(llmq_type));
+    if llmq_type.use_rotation() {
+        let n = llmq_type.signing_active_quorum_count().trailing_zeros();
+        let mut last_bytes = [0u8; 8];
+        last_bytes.copy_from_slice(&request_id.as_byte_array()[24..32]);
+        let b = u64::from_le_bytes(last_bytes);
+        let signer = ((1u64 << n) - 1) & (b >> (64 - n - 1));
+        quorums.into_iter().find(|q| q.quorum_index.map(|i| i as u64) == Some(signer))
+    } else {
+        quorums.min_by_key(|q| {
+            let mut engine = QuorumSignHash::engine();
+            u8::from(llmq_type).consensus_encode(&mut engine).expect("engines don't error");
+            q.quorum_hash.consensus_encode(&mut engine).expect("engines don't error");
+            request_id.consensus_encode(&mut engine).expect("engines don't error");
+            QuorumSignHash::from_engine(engine).to_byte_array()
+        })
+    }
+}
+
+/// Verifies a recovered quorum signature over `msg_hash` for `request_id`.
+#[cfg(feature = "bls")]
+pub fn verify_recovered_signature(
+    llmq_type: LLMQType,
+    quorum: &QuorumFinalizationCommitment,
+    request_id: &QuorumSigningRequestId,
+    msg_hash: &[u8; 32],
+    signature: &BLSSignature,
+) -> bool {
+    let sign_hash = build_sign_hash(llmq_type, &quorum.quorum_hash, request_id, msg_hash);
+    signature.verify(&quorum.quorum_public_key, sign_hash.as_byte_array())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::bls_sig_utils::BLSSignature;
+
+    pub(crate) fn quorum(
+        llmq_type: LLMQType,
+        quorum_hash: QuorumHash,
+        quorum_index: Option<i16>,
+        quorum_public_key: BLSPublicKey,
+    ) -> QuorumFinalizationCommitment {
+        QuorumFinalizationCommitment {
+            version: if quorum_index.is_some() { 2 } else { 1 },
+            llmq_type: llmq_type.into(),
+            quorum_hash,
+            quorum_index,
+            signers: vec![],
+            valid_members: vec![],
+            quorum_public_key,
+            quorum_vvec_hash: QuorumVVecHash::all_zeros(),
+            quorum_sig: BLSSignature::from([0; 96]),
+            sig: BLSSignature::from([0; 96]),
+        }
+    }
+
+    #[test]
+    fn llmq_type_roundtrip() {
+        for value in 0..=u8::MAX {
+            if let Ok(llmq_type) = LLMQType::try_from(value) {
+                assert_eq!(u8::from(llmq_type), value);
+                assert!(llmq_type.threshold() <= llmq_type.size());
+                if llmq_type.use_rotation() {
+                    assert!(llmq_type.signing_active_quorum_count().is_power_of_two());
+                }
+            }
+        }
+        assert_eq!(LLMQType::try_from(0), Err(UnknownLLMQTypeError(0)));
+        assert_eq!(LLMQType::Llmqtype60_75.to_string(), "llmq_60_75");
+    }
+
+    #[test]
+    fn select_non_rotated_quorum() {
+        let llmq_type = LLMQType::Llmqtype400_60;
+        let request_id = QuorumSigningRequestId::hash(b"request");
+        let quorums: Vec<_> = (0u8..4)
+            .map(|i| quorum(llmq_type, QuorumHash::hash(&[i]), None, BLSPublicKey::from([i; 48])))
+            .collect();
+
+        let selected = select_quorum_for_signing(llmq_type, &quorums, &request_id).unwrap();
+        let score = |q: &QuorumFinalizationCommitment| {
+            let mut data = vec![u8::from(llmq_type)];
+            data.extend_from_slice(q.quorum_hash.as_byte_array());
+            data.extend_from_slice(request_id.as_byte_array());
+            QuorumSignHash::hash(&data).to_byte_array()
+        };
+        assert!(quorums.iter().all(|q| score(selected) <= score(q)));
+
+        // Quorums of other types are never selected.
+        assert!(
+            select_quorum_for_signing(LLMQType::Llmqtype50_60, &quorums, &request_id).is_none()
+        );
+    }
+
+    #[test]
+    fn select_rotated_quorum() {
+        let llmq_type = LLMQType::Llmqtype60_75;
+        let quorums: Vec<_> = (0i16..32)
+            .map(|i| {
+                quorum(
+                    llmq_type,
+                    QuorumHash::hash(&i.to_le_bytes()),
+                    Some(i),
+                    BLSPublicKey::from([0; 48]),
+                )
+            })
+            .collect();
+
+        // n = 5, so the index is taken from bits 58..62 of the last 64 bits of the request id.
+        let mut id = [0u8; 32];
+        id[31] = 0b0011_0100;
+        let request_id = QuorumSigningRequestId::from_byte_array(id);
+        let selected = select_quorum_for_signing(llmq_type, &quorums, &request_id).unwrap();
+        assert_eq!(selected.quorum_index, Some(0b01101));
+    }
+
+    #[test]
+    fn sign_hash() {
+        let quorum_hash = QuorumHash::hash(b"quorum");
+        let request_id = QuorumSigningRequestId::hash(b"request");
+        let msg_hash = [7u8; 32];
+
+        let mut data = vec![u8::from(LLMQType::LlmqtypeTest)];
+        data.extend_from_slice(quorum_hash.as_byte_array());
+        data.extend_from_slice(request_id.as_byte_array());
+        data.extend_from_slice(&msg_hash);
+
+        assert_eq!(
+            build_sign_hash(LLMQType::LlmqtypeTest, &quorum_hash, &request_id, &msg_hash),
+            QuorumSignHash::hash(&data)
+        );
+    }
+
+    #[test]
+    fn commitment_hash() {
+        let quorum_hash = QuorumHash::hash(b"quorum");
+        let public_key = BLSPublicKey::from([3; 48]);
+        let vvec_hash = QuorumVVecHash::hash(b"vvec");
+        let valid_members = [true, false, true, true, false, false, false, false, true];
+
+        let mut data = vec![u8::from(LLMQType::LlmqtypeTest)];
+        data.extend_from_slice(quorum_hash.as_byte_array());
+        data.extend_from_slice(&[9, 0b0000_1101, 0b0000_0001]);
+        data.extend_from_slice(public_key.as_ref());
+        data.extend_from_slice(vvec_hash.as_byte_array());
+
+        assert_eq!(
+            build_commitment_hash(
+                LLMQType::LlmqtypeTest.into(),
+                &quorum_hash,
+                &valid_members,
+                &public_key,
+                &vvec_hash
+            ),
+            QuorumCommitmentHash::hash(&data)
+        );
+    }
+}
@@ -0,0 +1,212 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Threshold signature recovery.
+//!
+//! Every quorum member holds a share `f(id)` of the quorum secret key `f(0)`, where `f` is the
+//! polynomial of degree `threshold - 1` agreed on during the DKG and `id` is the member id
+//! derived from its proTxHash. A signature share is a signature by such a key share, so any
+//! `threshold` shares of the same hash can be combined by Lagrange interpolation at zero into the
+//! signature of the quorum public key.
+//!
+
+use core::fmt;
+
+use blsful::inner_types::{Field, G2Projective, Group, Scalar};
+use blsful::{Bls12381G2Impl, Signature};
+use hashes::Hash;
+
+use crate::bls_sig_utils::BLSSignature;
+use crate::hash_types::ProTxHash;
+use crate::prelude::*;
+
+/// Error recovering a threshold signature from signature shares.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ThresholdError {
+    /// Fewer distinct members signed than the threshold requires.
+    NotEnoughShares {
+        /// Number of distinct members that provided a share.
+        have: usize,
+        /// The threshold.
+        need: usize,
+    },
+    /// A member's share is not a valid signature.
+    InvalidShare(ProTxHash),
+    /// Two members have the same id, which only happens for the same proTxHash.
+    DuplicateMember(ProTxHash),
+}
+
+impl fmt::Display for ThresholdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ThresholdError::NotEnoughShares { have, need } =>
+                write!(f, "{} signature shares are not enough, {} are required", have, need),
+            ThresholdError::InvalidShare(ref pro_tx_hash) =>
+                write!(f, "invalid signature share from {}", pro_tx_hash),
+            ThresholdError::DuplicateMember(ref pro_tx_hash) =>
+                write!(f, "duplicate signature share from {}", pro_tx_hash),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ThresholdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Returns the id of the quorum member with the given proTxHash.
+///
+/// Like Dash Core, the id is the proTxHash bytes read as a big-endian integer modulo the group
+/// order.
+pub fn member_id(pro_tx_hash: &ProTxHash) -> Scalar {
+    let mut wide = [0u8; 64];
+    for (i, byte) in pro_tx_hash.as_byte_array().iter().rev().enumerate() {
+        wide[i] = *byte;
+    }
+    Scalar::from_bytes_wide(&wide)
+}
+
+/// Recovers the quorum signature from the signature shares of its members.
+///
+/// Shares after the first `threshold` distinct members are ignored, as any `threshold` valid
+/// shares recover the same signature. The shares themselves are not verified; an invalid share
+/// yields a signature that fails verification against the quorum public key.
+pub fn recover_threshold_signature(
+    shares: &[(ProTxHash, BLSSignature)],
+    threshold: usize,
+) -> Result<BLSSignature, ThresholdError> {
+    let mut ids = Vec::with_capacity(threshold);
+    let mut points = Vec::with_capacity(threshold);
+    for (pro_tx_hash, share) in shares {
+        if ids.len() == threshold {
+            break;
+        }
+        let id = member_id(pro_tx_hash);
+        if ids.contains(&id) {
+            return Err(ThresholdError::DuplicateMember(*pro_tx_hash));
+        }
+        let signature =
+            share.to_bls_signature().map_err(|_| ThresholdError::InvalidShare(*pro_tx_hash))?;
+        ids.push(id);
+        points.push(*signature.as_raw_value());
+    }
+    if ids.len() < threshold || threshold == 0 {
+        return Err(ThresholdError::NotEnoughShares { have: ids.len(), need: threshold });
+    }
+
+    let mut recovered = G2Projective::identity();
+    for (i, point) in points.iter().enumerate() {
+        recovered += *point * lagrange_coefficient_at_zero(&ids, i);
+    }
+    Ok(BLSSignature::from(&Signature::<Bls12381G2Impl>::Basic(recovered)))
+}
+
+/// Returns `prod_{j != i} x_j / (x_j - x_i)`, ids being distinct.
+fn lagrange_coefficient_at_zero(ids: &[Scalar], i: usize) -> Scalar {
+    let mut numerator = Scalar::ONE;
+    let mut denominator = Scalar::ONE;
+    for (j, id) in ids.iter().enumerate() {
+        if j != i {
+            numerator *= id;
+            denominator *= *id - ids[i];
+        }
+    }
+    numerator * denominator.invert().expect("ids are distinct")
+}
+
+#[cfg(test)]
+mod tests {
+    use blsful::{SecretKey, SignatureSchemes};
+
+    use super::*;
+    use crate::bls_sig_utils::BLSPublicKey;
+
+    /// Deals shares of the secret `coefficients[0]` to `members`, as the DKG would.
+    fn deal(coefficients: &[Scalar], members: &[ProTxHash]) -> Vec<SecretKey<Bls12381G2Impl>> {
+        members
+            .iter()
+            .map(|member| {
+                let x = member_id(member);
+                let share = coefficients.iter().rev().fold(Scalar::ZERO, |acc, c| acc * x + c);
+                SecretKey(share)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn member_id_is_big_endian() {
+        let mut bytes = [0u8; 32];
+        bytes[31] = 5;
+        assert_eq!(member_id(&ProTxHash::from_byte_array(bytes)), Scalar::from(5u64));
+        bytes[30] = 1;
+        assert_eq!(member_id(&ProTxHash::from_byte_array(bytes)), Scalar::from(261u64));
+    }
+
+    #[test]
+    fn recover_signature() {
+        let threshold = 3;
+        let coefficients: Vec<Scalar> =
+            (1u64..=threshold as u64).map(|c| Scalar::from(c * 1_000_003)).collect();
+        let quorum_key = SecretKey::<Bls12381G2Impl>(coefficients[0]);
+        let quorum_public_key = BLSPublicKey::from(&quorum_key.public_key());
+
+        let members: Vec<ProTxHash> = (0u8..5).map(|i| ProTxHash::hash(&[i])).collect();
+        let key_shares = deal(&coefficients, &members);
+        let msg = [42u8; 32];
+        let shares: Vec<(ProTxHash, BLSSignature)> = members
+            .iter()
+            .zip(&key_shares)
+            .map(|(member, key)| {
+                (*member, BLSSignature::from(&key.sign(SignatureSchemes::Basic, &msg).unwrap()))
+            })
+            .collect();
+
+        let expected = BLSSignature::from(&quorum_key.sign(SignatureSchemes::Basic, &msg).unwrap());
+        let recovered = recover_threshold_signature(&shares[..3], threshold).unwrap();
+        assert_eq!(recovered, expected);
+        assert!(recovered.verify(&quorum_public_key, &msg));
+
+        // Any subset of `threshold` members recovers the same signature.
+        let other = [shares[4], shares[1], shares[3]];
+        assert_eq!(recover_threshold_signature(&other, threshold).unwrap(), expected);
+        assert_eq!(recover_threshold_signature(&shares, threshold).unwrap(), expected);
+
+        // Fewer shares do not recover it.
+        assert_eq!(
+            recover_threshold_signature(&shares[..2], threshold),
+            Err(ThresholdError::NotEnoughShares { have: 2, need: 3 })
+        );
+        let below = recover_threshold_signature(&shares[..2], 2).unwrap();
+        assert!(!below.verify(&quorum_public_key, &msg));
+    }
+
+    #[test]
+    fn rejects_bad_shares() {
+        let member = ProTxHash::hash(b"member");
+        let share = BLSSignature::from(
+            &SecretKey::<Bls12381G2Impl>::from_hash(b"key")
+                .sign(SignatureSchemes::Basic, b"msg")
+                .unwrap(),
+        );
+        assert_eq!(
+            recover_threshold_signature(&[(member, share), (member, share)], 2),
+            Err(ThresholdError::DuplicateMember(member))
+        );
+        assert_eq!(
+            recover_threshold_signature(&[(member, BLSSignature::from([1; 96]))], 1),
+            Err(ThresholdError::InvalidShare(member))
+        );
+    }
+}
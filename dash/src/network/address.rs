@@ -68,6 +68,25 @@ impl Address {
             Ok(SocketAddr::V6(SocketAddrV6::new(ipv6, self.port, 0, 0)))
         }
     }
+
+    /// Converts the address to its [AddrV2] form.
+    ///
+    /// IPv4-mapped addresses become [AddrV2::Ipv4] and OnionCat addresses [AddrV2::TorV2].
+    pub fn to_addr_v2(&self) -> AddrV2 {
+        let addr = &self.address;
+        if addr[0..3] == ONION {
+            let mut id = [0u8; 10];
+            for (chunk, segment) in id.chunks_mut(2).zip(&addr[3..]) {
+                chunk.copy_from_slice(&segment.to_be_bytes());
+            }
+            return AddrV2::TorV2(id);
+        }
+        let ipv6 = Ipv6Addr::from(*addr);
+        match ipv6.to_ipv4_mapped() {
+            Some(ipv4) => AddrV2::Ipv4(ipv4),
+            None => AddrV2::Ipv6(ipv6),
+        }
+    }
 }
 
 impl Encodable for Address {
@@ -241,7 +260,7 @@ impl Decodable for AddrV2 {
                 }
                 let addr: [u16; 8] = read_be_address(r)?;
                 // check the first byte for the CJDNS marker
-                if addr[0] != u16::from_be_bytes([0xFC, 0x00]) {
+                if addr[0] >> 8 != 0xFC {
                     return Err(encode::Error::ParseFailed("Invalid CJDNS address"));
                 }
                 AddrV2::Cjdns(Ipv6Addr::new(
@@ -413,6 +432,21 @@ mod test {
         );
         let addr = Address::new(&onionaddr, ServiceFlags::NONE);
         assert!(addr.socket_addr().is_err());
+        assert_eq!(
+            addr.to_addr_v2(),
+            AddrV2::TorV2([0xed, 0xb1, 0x08, 0xe4, 0x35, 0x88, 0xe5, 0x46, 0x35, 0xca])
+        );
+    }
+
+    #[test]
+    fn to_addr_v2_test() {
+        let ipv4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 9999);
+        let addr = Address::new(&ipv4, ServiceFlags::NONE);
+        assert_eq!(addr.to_addr_v2(), AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let ipv6 = Ipv6Addr::from_str("2001:db8::1").unwrap();
+        let addr = Address::new(&SocketAddr::new(IpAddr::V6(ipv6), 9999), ServiceFlags::NONE);
+        assert_eq!(addr.to_addr_v2(), AddrV2::Ipv6(ipv6));
     }
 
     #[test]
@@ -533,6 +567,8 @@ mod test {
         // Valid CJDNS.
         let ip: AddrV2 = deserialize(&hex!("0610fc000001000200030004000500060007")).unwrap();
         assert_eq!(ip, AddrV2::Cjdns(Ipv6Addr::from_str("fc00:1:2:3:4:5:6:7").unwrap()));
+        let ip: AddrV2 = deserialize(&hex!("0610fc320001000200030004000500060007")).unwrap();
+        assert_eq!(ip, AddrV2::Cjdns(Ipv6Addr::from_str("fc32:1:2:3:4:5:6:7").unwrap()));
 
         // Invalid CJDNS, incorrect marker
         assert!(deserialize::<AddrV2>(&hex!("0610fd000001000200030004000500060007")).is_err());
@@ -0,0 +1,833 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Address manager.
+//!
+//! An [`AddressManager`] collects the addresses gossiped in `addr` and `addrv2` messages and
+//! selects peers to connect to, following the design of Dash Core's `addrman`:
+//!
+//! * Addresses we have only heard about live in the *new* table, addresses we have successfully
+//!   connected to in the *tried* table.
+//! * The bucket of an address in the new table depends on the network group of the address and
+//!   of the peer that told us about it, so a single peer can only fill a limited number of
+//!   buckets. The bucket in the tried table depends on the address and its network group.
+//! * Bucket positions are derived from a secret key, so peers cannot predict them.
+//! * Selection picks the tried or new table with equal probability and then random entries,
+//!   accepted with a chance that decreases with recent failed attempts.
+//!
+//! Unlike Dash Core an address is kept in at most one new bucket, and a tried address that is
+//! evicted by a collision is moved back to the new table without first testing it.
+//!
+//! # Serialization
+//!
+//! The manager is consensus encoded as a format version byte (currently 1), the 32-byte key and
+//! the list of entries. Each entry is its [`AddrV2Message`], the [`AddrV2`] of its source, the
+//! times of the last attempt, last counted attempt and last success as `u32`s, the number of
+//! attempts as a `u32` and whether it is in the tried table as a `bool`. Bucket positions are
+//! recomputed when decoding. [`AddressManager::save`] writes this encoding to a file preceded by
+//! its length and checksum, as [`CheckedData`].
+//!
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use hashes::{Hash, HashEngine, sha256d};
+
+use crate::consensus::encode::{self, CheckedData, Decodable, Encodable, VarInt, serialize};
+use crate::io;
+use crate::network::address::{AddrV2, AddrV2Message, Address};
+use crate::network::constants::ServiceFlags;
+use crate::prelude::*;
+
+/// Number of buckets of the tried table.
+pub const TRIED_BUCKET_COUNT: usize = 256;
+
+/// Number of buckets of the new table.
+pub const NEW_BUCKET_COUNT: usize = 1024;
+
+/// Number of entries in a bucket.
+pub const BUCKET_SIZE: usize = 64;
+
+/// Over how many tried buckets the addresses of one network group are spread.
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Over how many new buckets the addresses from one source network group are spread.
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+
+/// How old, in days, an address may be before it is considered terrible.
+const HORIZON_DAYS: u32 = 30;
+
+/// After how many failed attempts a never successful address is considered terrible.
+const RETRIES: u32 = 3;
+
+/// After how many failed attempts an address is considered terrible ...
+const MAX_FAILURES: u32 = 10;
+
+/// ... if it has not been successful for this many days.
+const MIN_FAIL_DAYS: u32 = 7;
+
+/// How much older gossiped addresses are assumed to be than claimed, unless self-announced.
+const TIME_PENALTY: u32 = 2 * 60 * 60;
+
+/// How often the time of a connected address is updated.
+const CONNECTED_UPDATE_INTERVAL: u32 = 20 * 60;
+
+const DAY: u32 = 24 * 60 * 60;
+
+/// Encoding version written by [`AddressManager::consensus_encode`].
+const FORMAT_VERSION: u8 = 1;
+
+/// Network classes, as used by Dash Core to start network groups.
+const NET_UNROUTABLE: u8 = 0;
+const NET_IPV4: u8 = 1;
+const NET_IPV6: u8 = 2;
+const NET_ONION: u8 = 3;
+const NET_I2P: u8 = 4;
+const NET_CJDNS: u8 = 5;
+
+/// Returns whether peers could be reachable at `addr` on the public network.
+pub fn is_routable(addr: &AddrV2) -> bool {
+    match *addr {
+        AddrV2::Ipv4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // Shared address space, RFC 6598.
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking, RFC 2544.
+                || (a == 198 && (b == 18 || b == 19))
+                || (a == 192 && b == 0 && c == 0)
+                || a >= 240
+                || a == 0)
+        }
+        AddrV2::Ipv6(ip) => {
+            let segments = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                // Unique local, RFC 4193.
+                || (segments[0] & 0xfe00) == 0xfc00
+                // Link local, RFC 4862.
+                || (segments[0] & 0xffc0) == 0xfe80
+                // Documentation, RFC 3849.
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+                // ORCHID, RFC 4843 and 7343.
+                || (segments[0] == 0x2001 && (segments[1] & 0xfff0) == 0x0010)
+                || (segments[0] == 0x2001 && (segments[1] & 0xfff0) == 0x0020))
+        }
+        AddrV2::TorV3(_) | AddrV2::I2p(_) | AddrV2::Cjdns(_) => true,
+        AddrV2::TorV2(_) | AddrV2::Unknown(..) => false,
+    }
+}
+
+/// Returns the network group of `addr`.
+///
+/// Peers in the same group are likely run by the same operator: IPv4 addresses are grouped by
+/// /16, IPv6 addresses by /32 (/36 for Hurricane Electric tunnels), and IPv4 addresses embedded
+/// in 6to4 and Teredo addresses like IPv4. Tor, I2P and CJDNS addresses are grouped by network
+/// and the first four bits of the address, as they are cheap to generate.
+pub fn network_group(addr: &AddrV2) -> Vec<u8> {
+    match *addr {
+        AddrV2::Ipv4(ip) => ipv4_group(ip.octets()),
+        AddrV2::Ipv6(ip) => {
+            let octets = ip.octets();
+            let segments = ip.segments();
+            if segments[0] == 0x2002 {
+                // 6to4 carries the IPv4 address in the next 32 bits.
+                ipv4_group([octets[2], octets[3], octets[4], octets[5]])
+            } else if segments[0] == 0x2001 && segments[1] == 0 {
+                // Teredo carries the inverted IPv4 address in the last 32 bits.
+                ipv4_group([!octets[12], !octets[13], !octets[14], !octets[15]])
+            } else if segments[0] == 0x2001 && segments[1] == 0x0470 {
+                vec![NET_IPV6, octets[0], octets[1], octets[2], octets[3], octets[4] | 0x0f]
+            } else {
+                vec![NET_IPV6, octets[0], octets[1], octets[2], octets[3]]
+            }
+        }
+        AddrV2::TorV3(ref key) => vec![NET_ONION, key[0] | 0x0f],
+        AddrV2::I2p(ref hash) => vec![NET_I2P, hash[0] | 0x0f],
+        AddrV2::Cjdns(ip) => vec![NET_CJDNS, ip.octets()[0] | 0x0f],
+        AddrV2::TorV2(_) | AddrV2::Unknown(..) => vec![NET_UNROUTABLE],
+    }
+}
+
+fn ipv4_group(octets: [u8; 4]) -> Vec<u8> { vec![NET_IPV4, octets[0], octets[1]] }
+
+/// What the manager knows about an address.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AddressInfo {
+    /// The address, with the time it was last seen and the services it offers.
+    pub address: AddrV2Message,
+    /// The address of the peer that told us about it.
+    pub source: AddrV2,
+    /// When we last tried to connect to it.
+    pub last_try: u32,
+    /// When an attempt was last counted in `attempts`.
+    pub last_count_attempt: u32,
+    /// When we last connected to it successfully.
+    pub last_success: u32,
+    /// Number of failed attempts since the last success.
+    pub attempts: u32,
+    /// Whether it is in the tried table.
+    pub in_tried: bool,
+}
+
+impl AddressInfo {
+    /// Returns whether the address is not worth keeping.
+    pub fn is_terrible(&self, now: u32) -> bool {
+        // Never remove an address tried in the last minute.
+        if self.last_try != 0 && now.saturating_sub(self.last_try) <= 60 {
+            return false;
+        }
+        let time = self.address.time;
+        // Came in a flying DeLorean.
+        if time > now.saturating_add(10 * 60) {
+            return true;
+        }
+        // Not seen in recent history.
+        if time == 0 || now.saturating_sub(time) > HORIZON_DAYS * DAY {
+            return true;
+        }
+        // Tried several times and never succeeded.
+        if self.last_success == 0 && self.attempts >= RETRIES {
+            return true;
+        }
+        // Failed too many times recently.
+        now.saturating_sub(self.last_success) > MIN_FAIL_DAYS * DAY && self.attempts >= MAX_FAILURES
+    }
+
+    /// Returns the relative chance this address should be selected.
+    pub fn chance(&self, now: u32) -> f64 {
+        let mut chance = 1.0;
+        // Deprioritize very recent attempts.
+        if now.saturating_sub(self.last_try) < 10 * 60 {
+            chance *= 0.01;
+        }
+        // Deprioritize 66% after each failed attempt, but at most 1/28th.
+        chance * 0.66f64.powi(self.attempts.min(8) as i32)
+    }
+}
+
+type AddressKey = (AddrV2, u16);
+
+/// Tables of gossiped addresses, for peer selection.
+#[derive(Clone, Debug)]
+pub struct AddressManager {
+    key: [u8; 32],
+    rng: u64,
+    entries: HashMap<u32, AddressInfo>,
+    ids: HashMap<AddressKey, u32>,
+    next_id: u32,
+    new_table: Vec<Option<u32>>,
+    tried_table: Vec<Option<u32>>,
+    new_count: usize,
+    tried_count: usize,
+    last_good: u32,
+}
+
+impl AddressManager {
+    /// Creates an empty manager.
+    ///
+    /// `key` must be random and secret; it determines bucket positions and is persisted with
+    /// the manager. `seed` must be random as well; it seeds the selection of addresses.
+    pub fn new(key: [u8; 32], seed: u64) -> AddressManager {
+        AddressManager {
+            key,
+            rng: seed,
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            new_table: vec![None; NEW_BUCKET_COUNT * BUCKET_SIZE],
+            tried_table: vec![None; TRIED_BUCKET_COUNT * BUCKET_SIZE],
+            new_count: 0,
+            tried_count: 0,
+            last_good: 1,
+        }
+    }
+
+    /// Returns the number of known addresses.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Returns whether no addresses are known.
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Returns the number of addresses in the new table.
+    pub fn new_count(&self) -> usize { self.new_count }
+
+    /// Returns the number of addresses in the tried table.
+    pub fn tried_count(&self) -> usize { self.tried_count }
+
+    /// Returns what is known about an address.
+    pub fn get(&self, addr: &AddrV2, port: u16) -> Option<&AddressInfo> {
+        self.ids.get(&(addr.clone(), port)).map(|id| &self.entries[id])
+    }
+
+    /// Adds addresses from an `addrv2` message sent by `source`; returns how many were new.
+    ///
+    /// Unroutable addresses are ignored. Addresses not announced by themselves are assumed to be
+    /// two hours older than claimed.
+    pub fn add(&mut self, addresses: &[AddrV2Message], source: &AddrV2, now: u32) -> usize {
+        addresses.iter().filter(|address| self.add_one(address, source, now)).count()
+    }
+
+    /// Adds addresses from an `addr` message sent by `source`; returns how many were new.
+    pub fn add_legacy(&mut self, addresses: &[(u32, Address)], source: &AddrV2, now: u32) -> usize {
+        let addresses: Vec<AddrV2Message> = addresses
+            .iter()
+            .map(|(time, address)| AddrV2Message {
+                time: *time,
+                services: address.services,
+                addr: address.to_addr_v2(),
+                port: address.port,
+            })
+            .collect();
+        self.add(&addresses, source, now)
+    }
+
+    fn add_one(&mut self, address: &AddrV2Message, source: &AddrV2, now: u32) -> bool {
+        if !is_routable(&address.addr) {
+            return false;
+        }
+        let penalty = if address.addr == *source { 0 } else { TIME_PENALTY };
+        let key = (address.addr.clone(), address.port);
+        if let Some(&id) = self.ids.get(&key) {
+            let info = self.entries.get_mut(&id).expect("indexed entry");
+            // Refresh the time less often for addresses that are not currently online.
+            let online = now.saturating_sub(address.time) < DAY;
+            let interval = if online { 60 * 60 } else { DAY };
+            if address.time != 0
+                && (info.address.time == 0
+                    || info.address.time < address.time.saturating_sub(interval + penalty))
+            {
+                info.address.time = address.time.saturating_sub(penalty);
+            }
+            info.address.services |= address.services;
+            return false;
+        }
+
+        let mut address = address.clone();
+        address.time = address.time.saturating_sub(penalty);
+        let info = AddressInfo {
+            address,
+            source: source.clone(),
+            last_try: 0,
+            last_count_attempt: 0,
+            last_success: 0,
+            attempts: 0,
+            in_tried: false,
+        };
+        let slot = self.new_slot(&info);
+        if let Some(existing) = self.new_table[slot] {
+            if !self.entries[&existing].is_terrible(now) {
+                return false;
+            }
+            self.delete(existing);
+        }
+        let id = self.insert(info);
+        self.new_table[slot] = Some(id);
+        self.new_count += 1;
+        true
+    }
+
+    /// Records a successful connection, moving the address to the tried table.
+    pub fn good(&mut self, addr: &AddrV2, port: u16, now: u32) {
+        let Some(&id) = self.ids.get(&(addr.clone(), port)) else { return };
+        self.last_good = now;
+        let info = self.entries.get_mut(&id).expect("indexed entry");
+        info.last_success = now;
+        info.last_try = now;
+        info.attempts = 0;
+        if info.in_tried {
+            return;
+        }
+
+        let info = info.clone();
+        let new_slot = self.new_slot(&info);
+        if self.new_table[new_slot] == Some(id) {
+            self.new_table[new_slot] = None;
+            self.new_count -= 1;
+        }
+        let tried_slot = self.tried_slot(&info);
+        if let Some(evicted) = self.tried_table[tried_slot].take() {
+            // Move the evicted address back to the new table, replacing whatever is there.
+            self.tried_count -= 1;
+            let evicted_info = self.entries.get_mut(&evicted).expect("indexed entry");
+            evicted_info.in_tried = false;
+            let evicted_info = evicted_info.clone();
+            let slot = self.new_slot(&evicted_info);
+            if let Some(existing) = self.new_table[slot] {
+                self.delete(existing);
+            }
+            self.new_table[slot] = Some(evicted);
+            self.new_count += 1;
+        }
+        self.tried_table[tried_slot] = Some(id);
+        self.tried_count += 1;
+        self.entries.get_mut(&id).expect("indexed entry").in_tried = true;
+    }
+
+    /// Records a connection attempt; `count_failure` counts it against the address.
+    ///
+    /// Failures are counted at most once per successful connection to any address, so that
+    /// losing connectivity does not make every address look bad.
+    pub fn attempt(&mut self, addr: &AddrV2, port: u16, count_failure: bool, now: u32) {
+        let Some(&id) = self.ids.get(&(addr.clone(), port)) else { return };
+        let info = self.entries.get_mut(&id).expect("indexed entry");
+        info.last_try = now;
+        if count_failure && info.last_count_attempt < self.last_good {
+            info.last_count_attempt = now;
+            info.attempts += 1;
+        }
+    }
+
+    /// Records that we are still connected to an address, refreshing its time.
+    pub fn connected(&mut self, addr: &AddrV2, port: u16, now: u32) {
+        let Some(&id) = self.ids.get(&(addr.clone(), port)) else { return };
+        let info = self.entries.get_mut(&id).expect("indexed entry");
+        if now.saturating_sub(info.address.time) > CONNECTED_UPDATE_INTERVAL {
+            info.address.time = now;
+        }
+    }
+
+    /// Updates the services an address offers, as learned from its `version` message.
+    pub fn set_services(&mut self, addr: &AddrV2, port: u16, services: ServiceFlags) {
+        if let Some(&id) = self.ids.get(&(addr.clone(), port)) {
+            self.entries.get_mut(&id).expect("indexed entry").address.services = services;
+        }
+    }
+
+    /// Selects an address to connect to, or `None` if there are none.
+    ///
+    /// With `new_only` only addresses we never connected to are considered.
+    pub fn select(&mut self, new_only: bool, now: u32) -> Option<AddrV2Message> {
+        if self.new_count == 0 && (new_only || self.tried_count == 0) {
+            return None;
+        }
+        let use_tried = !new_only
+            && self.tried_count > 0
+            && (self.new_count == 0 || self.next_random() & 1 == 0);
+        let bucket_count = if use_tried { TRIED_BUCKET_COUNT } else { NEW_BUCKET_COUNT };
+
+        let mut chance_factor = 1.0;
+        loop {
+            let bucket = self.next_random() as usize % bucket_count;
+            let start = self.next_random() as usize % BUCKET_SIZE;
+            let table = if use_tried { &self.tried_table } else { &self.new_table };
+            let found = (0..BUCKET_SIZE)
+                .find_map(|i| table[bucket * BUCKET_SIZE + (start + i) % BUCKET_SIZE]);
+            let Some(id) = found else { continue };
+            let info = &self.entries[&id];
+            let chance = chance_factor * info.chance(now);
+            let address = info.address.clone();
+            if ((self.next_random() >> 34) as f64) < chance * (1u64 << 30) as f64 {
+                return Some(address);
+            }
+            chance_factor *= 1.2;
+        }
+    }
+
+    /// Returns up to `max_count` random addresses, but at most `max_pct` percent of all known
+    /// addresses, skipping terrible ones, to answer `getaddr`.
+    pub fn addresses(&mut self, max_count: usize, max_pct: usize, now: u32) -> Vec<AddrV2Message> {
+        let mut ids: Vec<u32> = self.entries.keys().copied().collect();
+        ids.sort_unstable();
+        let limit = max_count.min(ids.len() * max_pct.min(100) / 100);
+        let mut result = Vec::with_capacity(limit);
+        for i in 0..ids.len() {
+            if result.len() >= limit {
+                break;
+            }
+            let j = i + self.next_random() as usize % (ids.len() - i);
+            ids.swap(i, j);
+            let info = &self.entries[&ids[i]];
+            if !info.is_terrible(now) {
+                result.push(info.address.clone());
+            }
+        }
+        result
+    }
+
+    /// Writes the manager to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+        fs::write(path, serialize(&CheckedData(serialize(self))))
+    }
+
+    /// Reads a manager written by [`AddressManager::save`], seeding its selection with `seed`.
+    pub fn load<P: AsRef<Path>>(path: P, seed: u64) -> Result<AddressManager, encode::Error> {
+        let bytes = fs::read(path).map_err(encode::Error::Io)?;
+        let data: CheckedData = encode::deserialize(&bytes)?;
+        let mut manager: AddressManager = encode::deserialize(&data.0)?;
+        manager.reseed(seed);
+        Ok(manager)
+    }
+
+    /// Seeds the selection of addresses with the random `seed`.
+    ///
+    /// Managers decoded with [`Decodable`] start with a fixed seed and should be reseeded.
+    pub fn reseed(&mut self, seed: u64) { self.rng = seed; }
+
+    fn insert(&mut self, info: AddressInfo) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert((info.address.addr.clone(), info.address.port), id);
+        self.entries.insert(id, info);
+        id
+    }
+
+    fn delete(&mut self, id: u32) {
+        let info = self.entries.remove(&id).expect("indexed entry");
+        self.ids.remove(&(info.address.addr.clone(), info.address.port));
+        let (table, count, slot) = if info.in_tried {
+            let slot = self.tried_slot(&info);
+            (&mut self.tried_table, &mut self.tried_count, slot)
+        } else {
+            let slot = self.new_slot(&info);
+            (&mut self.new_table, &mut self.new_count, slot)
+        };
+        if table[slot] == Some(id) {
+            table[slot] = None;
+            *count -= 1;
+        }
+    }
+
+    /// Returns the first 8 bytes of the double SHA256 of the key followed by `parts`.
+    fn cheap_hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut engine = sha256d::Hash::engine();
+        engine.input(&self.key);
+        for part in parts {
+            engine.input(part);
+        }
+        let hash = sha256d::Hash::from_engine(engine).to_byte_array();
+        u64::from_le_bytes(hash[..8].try_into().expect("8 bytes"))
+    }
+
+    fn new_slot(&self, info: &AddressInfo) -> usize {
+        let group = network_group(&info.address.addr);
+        let source_group = network_group(&info.source);
+        let hash1 = self.cheap_hash(&[&group, &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let hash2 = self.cheap_hash(&[&source_group, &hash1.to_le_bytes()]);
+        let bucket = (hash2 % NEW_BUCKET_COUNT as u64) as usize;
+        bucket * BUCKET_SIZE + self.position(b'N', bucket, info)
+    }
+
+    fn tried_slot(&self, info: &AddressInfo) -> usize {
+        let key = address_key(&info.address);
+        let group = network_group(&info.address.addr);
+        let hash1 = self.cheap_hash(&[&key]) % TRIED_BUCKETS_PER_GROUP;
+        let hash2 = self.cheap_hash(&[&group, &hash1.to_le_bytes()]);
+        let bucket = (hash2 % TRIED_BUCKET_COUNT as u64) as usize;
+        bucket * BUCKET_SIZE + self.position(b'K', bucket, info)
+    }
+
+    fn position(&self, table: u8, bucket: usize, info: &AddressInfo) -> usize {
+        let key = address_key(&info.address);
+        let hash = self.cheap_hash(&[&[table], &(bucket as u32).to_le_bytes(), &key]);
+        (hash % BUCKET_SIZE as u64) as usize
+    }
+
+    /// Returns the next SplitMix64 output.
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns the bytes identifying an address and port.
+fn address_key(address: &AddrV2Message) -> Vec<u8> {
+    let mut key = serialize(&address.addr);
+    key.extend_from_slice(&address.port.to_be_bytes());
+    key
+}
+
+impl Encodable for AddressManager {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = FORMAT_VERSION.consensus_encode(w)?;
+        len += self.key.consensus_encode(w)?;
+        let mut ids: Vec<&u32> = self.entries.keys().collect();
+        ids.sort_unstable();
+        len += VarInt(ids.len() as u64).consensus_encode(w)?;
+        for id in ids {
+            let info = &self.entries[id];
+            len += info.address.consensus_encode(w)?;
+            len += info.source.consensus_encode(w)?;
+            len += info.last_try.consensus_encode(w)?;
+            len += info.last_count_attempt.consensus_encode(w)?;
+            len += info.last_success.consensus_encode(w)?;
+            len += info.attempts.consensus_encode(w)?;
+            len += info.in_tried.consensus_encode(w)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for AddressManager {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let version = u8::consensus_decode(r)?;
+        if version != FORMAT_VERSION {
+            return Err(encode::Error::ParseFailed("unsupported address manager format version"));
+        }
+        let mut manager = AddressManager::new(Decodable::consensus_decode(r)?, 0);
+        let count = VarInt::consensus_decode(r)?.0;
+        if count > ((NEW_BUCKET_COUNT + TRIED_BUCKET_COUNT) * BUCKET_SIZE) as u64 {
+            return Err(encode::Error::ParseFailed("too many address manager entries"));
+        }
+        for _ in 0..count {
+            let info = AddressInfo {
+                address: Decodable::consensus_decode(r)?,
+                source: Decodable::consensus_decode(r)?,
+                last_try: Decodable::consensus_decode(r)?,
+                last_count_attempt: Decodable::consensus_decode(r)?,
+                last_success: Decodable::consensus_decode(r)?,
+                attempts: Decodable::consensus_decode(r)?,
+                in_tried: Decodable::consensus_decode(r)?,
+            };
+            manager.restore(info);
+        }
+        Ok(manager)
+    }
+}
+
+impl AddressManager {
+    /// Places a decoded entry, dropping it if its position is taken.
+    fn restore(&mut self, mut info: AddressInfo) {
+        if self.ids.contains_key(&(info.address.addr.clone(), info.address.port)) {
+            return;
+        }
+        if info.in_tried {
+            let slot = self.tried_slot(&info);
+            if self.tried_table[slot].is_none() {
+                let id = self.insert(info);
+                self.tried_table[slot] = Some(id);
+                self.tried_count += 1;
+                return;
+            }
+            info.in_tried = false;
+        }
+        let slot = self.new_slot(&info);
+        if self.new_table[slot].is_none() {
+            let id = self.insert(info);
+            self.new_table[slot] = Some(id);
+            self.new_count += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn ipv4(a: u8, b: u8, c: u8, d: u8) -> AddrV2 { AddrV2::Ipv4(Ipv4Addr::new(a, b, c, d)) }
+
+    fn message(addr: AddrV2, time: u32) -> AddrV2Message {
+        AddrV2Message { time, services: ServiceFlags::NETWORK, addr, port: 9999 }
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(network_group(&ipv4(1, 2, 3, 4)), [NET_IPV4, 1, 2]);
+        assert_eq!(network_group(&ipv4(1, 2, 200, 1)), network_group(&ipv4(1, 2, 3, 4)));
+        let ipv6: Ipv6Addr = "2a01:4f8:1:2::1".parse().unwrap();
+        assert_eq!(network_group(&AddrV2::Ipv6(ipv6)), [NET_IPV6, 0x2a, 0x01, 0x04, 0xf8]);
+        let six_to_four: Ipv6Addr = "2002:0102:0304::1".parse().unwrap();
+        assert_eq!(network_group(&AddrV2::Ipv6(six_to_four)), [NET_IPV4, 1, 2]);
+        let teredo: Ipv6Addr = "2001:0:4136:e378:8000:63bf:fefd:fcfb".parse().unwrap();
+        assert_eq!(network_group(&AddrV2::Ipv6(teredo)), [NET_IPV4, 1, 2]);
+        assert_eq!(network_group(&AddrV2::TorV3([0x12; 32])), [NET_ONION, 0x1f]);
+        assert_eq!(network_group(&AddrV2::I2p([0xa0; 32])), [NET_I2P, 0xaf]);
+
+        assert!(is_routable(&ipv4(1, 2, 3, 4)));
+        assert!(!is_routable(&ipv4(10, 0, 0, 1)));
+        assert!(!is_routable(&ipv4(127, 0, 0, 1)));
+        assert!(!is_routable(&ipv4(100, 64, 0, 1)));
+        assert!(!is_routable(&AddrV2::Ipv6("fd00::1".parse().unwrap())));
+        assert!(!is_routable(&AddrV2::Ipv6("2001:db8::1".parse().unwrap())));
+        assert!(is_routable(&AddrV2::TorV3([0; 32])));
+    }
+
+    #[test]
+    fn add_and_update() {
+        let mut addrman = AddressManager::new([1; 32], 1);
+        let source = ipv4(5, 6, 7, 8);
+        let addresses = [message(ipv4(1, 2, 3, 4), NOW), message(ipv4(10, 0, 0, 1), NOW)];
+        assert_eq!(addrman.add(&addresses, &source, NOW), 1);
+        assert_eq!((addrman.len(), addrman.new_count(), addrman.tried_count()), (1, 1, 0));
+
+        // Gossiped addresses get the time penalty, self-announced ones do not.
+        let info = addrman.get(&ipv4(1, 2, 3, 4), 9999).unwrap();
+        assert_eq!(info.address.time, NOW - TIME_PENALTY);
+        assert_eq!(info.source, source);
+
+        // Adding again only refreshes the time and merges services.
+        let mut again = message(ipv4(1, 2, 3, 4), NOW + DAY);
+        again.services = ServiceFlags::BLOOM;
+        assert_eq!(addrman.add(&[again], &ipv4(1, 2, 3, 4), NOW + DAY), 0);
+        let info = addrman.get(&ipv4(1, 2, 3, 4), 9999).unwrap();
+        assert_eq!(info.address.time, NOW + DAY);
+        assert_eq!(info.address.services, ServiceFlags::NETWORK | ServiceFlags::BLOOM);
+
+        // Legacy `addr` entries are converted.
+        let legacy = Address::new(&"9.8.7.6:9999".parse().unwrap(), ServiceFlags::NETWORK);
+        assert_eq!(addrman.add_legacy(&[(NOW, legacy)], &source, NOW), 1);
+        assert!(addrman.get(&ipv4(9, 8, 7, 6), 9999).is_some());
+    }
+
+    #[test]
+    fn good_attempt_and_terrible() {
+        let mut addrman = AddressManager::new([2; 32], 2);
+        let addr = ipv4(1, 2, 3, 4);
+        addrman.add(&[message(addr.clone(), NOW)], &addr, NOW);
+
+        // Failures count once per success elsewhere.
+        addrman.attempt(&addr, 9999, true, NOW + 100);
+        addrman.attempt(&addr, 9999, true, NOW + 200);
+        assert_eq!(addrman.get(&addr, 9999).unwrap().attempts, 1);
+        assert!(!addrman.get(&addr, 9999).unwrap().is_terrible(NOW + 200));
+
+        addrman.good(&addr, 9999, NOW + 300);
+        let info = addrman.get(&addr, 9999).unwrap();
+        assert!(info.in_tried);
+        assert_eq!((info.attempts, info.last_success), (0, NOW + 300));
+        assert_eq!((addrman.new_count(), addrman.tried_count()), (0, 1));
+
+        // Addresses slightly in the future are fine, too far ahead they are terrible.
+        let mut info = info.clone();
+        let later = NOW + 1000;
+        info.address.time = later + 5 * 60;
+        assert!(!info.is_terrible(later));
+        info.address.time = later + 11 * 60;
+        assert!(info.is_terrible(later));
+        info.address.time = NOW;
+
+        // Old addresses are terrible, unless just tried.
+        assert!(info.is_terrible(NOW + (HORIZON_DAYS + 1) * DAY));
+        info.last_try = NOW + (HORIZON_DAYS + 1) * DAY;
+        assert!(!info.is_terrible(NOW + (HORIZON_DAYS + 1) * DAY));
+        info.last_try = 0;
+        info.last_success = 0;
+        info.attempts = RETRIES;
+        assert!(info.is_terrible(NOW + 300));
+        assert!(info.chance(NOW + 300) < 0.3);
+    }
+
+    #[test]
+    fn source_groups_limit_buckets() {
+        // One source group can only reach `NEW_BUCKETS_PER_SOURCE_GROUP` buckets.
+        let mut addrman = AddressManager::new([3; 32], 3);
+        let source = ipv4(5, 6, 7, 8);
+        let mut buckets = std::collections::HashSet::new();
+        for i in 0..2000u32 {
+            let [a, b, c, d] = (0x0100_0000 + i * 65_537).to_be_bytes();
+            let info = AddressInfo {
+                address: message(ipv4(a, b, c, d), NOW),
+                source: source.clone(),
+                last_try: 0,
+                last_count_attempt: 0,
+                last_success: 0,
+                attempts: 0,
+                in_tried: false,
+            };
+            buckets.insert(addrman.new_slot(&info) / BUCKET_SIZE);
+        }
+        assert!(buckets.len() <= NEW_BUCKETS_PER_SOURCE_GROUP as usize);
+
+        let addresses: Vec<AddrV2Message> = (0..2000u32)
+            .map(|i| {
+                let [a, b, c, d] = (0x0100_0000 + i * 65_537).to_be_bytes();
+                message(ipv4(a, b, c, d), NOW)
+            })
+            .collect();
+        let added = addrman.add(&addresses, &source, NOW);
+        assert_eq!(addrman.new_count(), added);
+        assert!(added <= NEW_BUCKETS_PER_SOURCE_GROUP as usize * BUCKET_SIZE);
+    }
+
+    #[test]
+    fn select() {
+        let mut addrman = AddressManager::new([4; 32], 4);
+        assert_eq!(addrman.select(false, NOW), None);
+
+        let addresses: Vec<AddrV2Message> =
+            (11..=60u8).map(|i| message(ipv4(i, i, 1, 1), NOW)).collect();
+        // Self-announced, so that every address has its own source group.
+        for address in &addresses {
+            assert_eq!(addrman.add(&[address.clone()], &address.addr, NOW), 1);
+        }
+        addrman.good(&ipv4(11, 11, 1, 1), 9999, NOW);
+        assert_eq!(addrman.tried_count(), 1);
+
+        let mut selected_tried = false;
+        for _ in 0..100 {
+            let selected = addrman.select(false, NOW + 3600).unwrap();
+            assert!(addresses.iter().any(|a| a.addr == selected.addr));
+            selected_tried |= selected.addr == ipv4(11, 11, 1, 1);
+            assert_ne!(addrman.select(true, NOW + 3600).unwrap().addr, ipv4(11, 11, 1, 1));
+        }
+        assert!(selected_tried);
+
+        let sample = addrman.addresses(1000, 23, NOW);
+        assert_eq!(sample.len(), 50 * 23 / 100);
+        assert_eq!(addrman.addresses(5, 100, NOW).len(), 5);
+        // All addresses are too old to be shared.
+        assert!(addrman.addresses(1000, 100, NOW + 2 * HORIZON_DAYS * DAY).is_empty());
+    }
+
+    #[test]
+    fn serialization() {
+        let mut addrman = AddressManager::new([5; 32], 5);
+        let addresses: Vec<AddrV2Message> = (21..=40u8)
+            .map(|i| message(ipv4(i, 2, 3, 4), NOW))
+            .chain(Some(message(AddrV2::TorV3([9; 32]), NOW)))
+            .collect();
+        for address in &addresses {
+            assert_eq!(addrman.add(&[address.clone()], &address.addr, NOW), 1);
+        }
+        addrman.good(&ipv4(23, 2, 3, 4), 9999, NOW);
+        addrman.attempt(&ipv4(24, 2, 3, 4), 9999, true, NOW);
+
+        let decoded: AddressManager = encode::deserialize(&serialize(&addrman)).unwrap();
+        assert_eq!(decoded.len(), addrman.len());
+        assert_eq!((decoded.new_count(), decoded.tried_count()), (20, 1));
+        for address in &addresses {
+            assert_eq!(decoded.get(&address.addr, 9999), addrman.get(&address.addr, 9999));
+        }
+        assert_eq!(serialize(&decoded), serialize(&addrman));
+
+        let path = std::env::temp_dir().join(format!("addrman-test-{}.dat", std::process::id()));
+        addrman.save(&path).unwrap();
+        let mut loaded = AddressManager::load(&path, 6).unwrap();
+        assert_eq!(serialize(&loaded), serialize(&addrman));
+        let mut reseeded = decoded.clone();
+        reseeded.reseed(6);
+        for _ in 0..10 {
+            assert_eq!(loaded.select(false, NOW), reseeded.select(false, NOW));
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        assert!(AddressManager::load(&path, 6).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
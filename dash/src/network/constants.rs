@@ -58,6 +58,7 @@ use crate::prelude::{String, ToOwned};
 /// Increasing it implies that your software also supports every feature prior to this version.
 /// Doing so without support may lead to you incorrectly banning other peers or other peers banning you.
 /// These are the features required for each version:
+/// 70230 - `mnlistdiff` carries quorum ChainLock signatures, the layout of [`crate::network::message_sml`]
 /// 70016 - Support receiving `wtxidrelay` message between `version` and `verack` message
 /// 70015 - Support receiving invalid compact blocks from a peer without banning them
 /// 70014 - Support compact block messages `sendcmpct`, `cmpctblock`, `getblocktxn` and `blocktxn`
@@ -68,7 +69,22 @@ use crate::prelude::{String, ToOwned};
 /// 70001 - Support bloom filter messages `filterload`, `filterclear` `filteradd`, `merkleblock` and FILTERED_BLOCK inventory type
 /// 60002 - Support `mempool` message
 /// 60001 - Support `pong` message and nonce in `ping` message
-pub const PROTOCOL_VERSION: u32 = 70220;
+pub const PROTOCOL_VERSION: u32 = 70230;
+
+/// Protocol version from which the `version` message carries the `mnauth` challenge.
+pub const MNAUTH_CHALLENGE_VERSION: u32 = 70214;
+
+/// Protocol version from which the `version` message carries the masternode connection flag.
+pub const MASTERNODE_CONNECTION_VERSION: u32 = 70218;
+
+/// Protocol version from which `mnauth` signatures also commit to the signer's protocol version.
+pub const MNAUTH_NODE_VERSION: u32 = 70218;
+
+/// Oldest protocol version Dash Core accepts from its peers.
+pub const MIN_PEER_PROTO_VERSION: u32 = 70215;
+
+/// Protocol version from which peers negotiate `addrv2` with `sendaddrv2`.
+pub const ADDRV2_PROTO_VERSION: u32 = 70223;
 
 /// The cryptocurrency network to act on.
 #[derive(Copy, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Debug)]
@@ -129,6 +145,24 @@ impl Network {
             Network::Regtest => 0xDAB5BFFA,
         }
     }
+
+    /// Returns the default P2P port of the network.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use dashcore::network::constants::Network;
+    ///
+    /// assert_eq!(Network::Dash.default_port(), 9999);
+    /// ```
+    pub fn default_port(self) -> u16 {
+        match self {
+            Network::Dash => 9999,
+            Network::Testnet => 19999,
+            Network::Devnet => 19799,
+            Network::Regtest => 19899,
+        }
+    }
 }
 
 /// An error in parsing network string.
@@ -237,6 +271,10 @@ impl ServiceFlags {
     /// See BIP159 for details on how this is implemented.
     pub const NETWORK_LIMITED: ServiceFlags = ServiceFlags(1 << 10);
 
+    /// HEADERS_COMPRESSED means the node supports the `getheaders2`, `sendheaders2` and
+    /// `headers2` messages, which exchange compressed block headers.
+    pub const HEADERS_COMPRESSED: ServiceFlags = ServiceFlags(1 << 11);
+
     // NOTE: When adding new flags, remember to update the Display impl accordingly.
 
     /// Add [ServiceFlags] together.
@@ -296,6 +334,7 @@ impl fmt::Display for ServiceFlags {
         write_flag!(WITNESS);
         write_flag!(COMPACT_FILTERS);
         write_flag!(NETWORK_LIMITED);
+        write_flag!(HEADERS_COMPRESSED);
         // If there are unknown flags left, we append them in hex.
         if flags != ServiceFlags::NONE {
             if !first {
@@ -390,6 +429,7 @@ mod tests {
             ServiceFlags::WITNESS,
             ServiceFlags::COMPACT_FILTERS,
             ServiceFlags::NETWORK_LIMITED,
+            ServiceFlags::HEADERS_COMPRESSED,
         ];
 
         let mut flags = ServiceFlags::NONE;
@@ -419,5 +459,7 @@ mod tests {
         assert_eq!("ServiceFlags(NETWORK|BLOOM|WITNESS)", flag.to_string());
         let flag = ServiceFlags::WITNESS | 0xf0.into();
         assert_eq!("ServiceFlags(WITNESS|COMPACT_FILTERS|0xb0)", flag.to_string());
+        let flag = ServiceFlags::NETWORK | ServiceFlags::HEADERS_COMPRESSED;
+        assert_eq!("ServiceFlags(NETWORK|HEADERS_COMPRESSED)", flag.to_string());
     }
 }
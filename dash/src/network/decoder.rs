@@ -0,0 +1,470 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Incremental message framing.
+//!
+//! [`MessageDecoder`] turns a byte stream into [`RawNetworkMessage`]s without blocking: bytes
+//! are pushed as they arrive from the transport, in chunks of any size, and complete messages
+//! are pulled out. Headers are validated before the payload is buffered, so a peer cannot make
+//! the decoder allocate more than the limit of the announced command, and the checksum is
+//! verified before the payload is parsed. When the stream does not start with the network magic
+//! the decoder skips ahead to the next occurrence of it.
+//!
+
+use core::fmt;
+
+use hashes::{Hash, sha256d};
+
+use crate::consensus::encode;
+use crate::network::constants::Network;
+use crate::network::message::{
+    CommandString, MAX_INV_SIZE, MAX_MSG_SIZE, NetworkMessage, RawNetworkMessage,
+};
+use crate::prelude::*;
+
+/// Size of a message header: magic, command, payload length and checksum.
+pub const HEADER_SIZE: usize = 24;
+
+/// The maximum number of addresses in an `addr` or `addrv2` message.
+pub const MAX_ADDR_TO_SEND: usize = 1_000;
+
+/// The maximum number of headers in a `headers` or `headers2` message.
+pub const MAX_HEADERS_RESULTS: usize = 2_000;
+
+/// The maximum number of hashes in a block locator.
+pub const MAX_LOCATOR_SIZE: usize = 101;
+
+/// The maximum size of an address in an `addrv2` message.
+const MAX_ADDRV2_SIZE: usize = 512;
+
+/// The maximum size of a compact size prefix.
+const MAX_VARINT_SIZE: usize = 9;
+
+/// How far ahead of the received data the buffer grows towards a message's announced size.
+const RECEIVE_BUFFER_STEP: usize = 256 * 1024;
+
+/// Returns the maximum payload size of a message with the given command.
+///
+/// Commands without a tighter bound are limited to [`MAX_MSG_SIZE`].
+pub fn max_payload_size(command: &str) -> usize {
+    match command {
+        "verack" | "getaddr" | "mempool" | "sendheaders" | "sendheaders2" | "sendaddrv2"
+        | "filterclear" => 0,
+        "ping" | "pong" | "feefilter" => 8,
+        "sendcmpct" => 9,
+        // Inventory entries are a type and a hash.
+        "inv" | "getdata" | "notfound" => MAX_VARINT_SIZE + MAX_INV_SIZE * 36,
+        // Entries are a timestamp, services and a 16 byte address and port.
+        "addr" => MAX_VARINT_SIZE + MAX_ADDR_TO_SEND * 30,
+        "addrv2" =>
+            MAX_VARINT_SIZE
+                + MAX_ADDR_TO_SEND
+                    * (4 + MAX_VARINT_SIZE + 1 + MAX_VARINT_SIZE + MAX_ADDRV2_SIZE + 2),
+        // Headers are followed by an empty transaction count.
+        "headers" => MAX_VARINT_SIZE + MAX_HEADERS_RESULTS * 81,
+        "getblocks" | "getheaders" | "getheaders2" =>
+            4 + MAX_VARINT_SIZE + MAX_LOCATOR_SIZE * 32 + 32,
+        _ => MAX_MSG_SIZE,
+    }
+}
+
+/// Error decoding a message from a byte stream.
+///
+/// The decoder recovers from all errors; the offending bytes are dropped and decoding continues
+/// with the next message.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The stream did not start with the network magic; bytes up to the next occurrence of the
+    /// magic were skipped.
+    WrongMagic(u32),
+    /// The command in the header is not printable ASCII padded with zeros.
+    InvalidCommand,
+    /// The payload is larger than allowed for its command; it was skipped without buffering.
+    Oversized {
+        /// The command of the message.
+        command: CommandString,
+        /// The payload size announced in the header.
+        size: usize,
+        /// The maximum payload size of the command.
+        max: usize,
+    },
+    /// The payload does not match the checksum in the header.
+    InvalidChecksum {
+        /// The command of the message.
+        command: CommandString,
+        /// The checksum of the payload.
+        expected: [u8; 4],
+        /// The checksum in the header.
+        actual: [u8; 4],
+    },
+    /// The payload could not be parsed.
+    Payload {
+        /// The command of the message.
+        command: CommandString,
+        /// The parse error.
+        error: encode::Error,
+    },
+    /// The message has more items than allowed for its command.
+    TooManyItems {
+        /// The command of the message.
+        command: CommandString,
+        /// The number of items in the message.
+        count: usize,
+        /// The maximum number of items.
+        max: usize,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::WrongMagic(magic) => write!(f, "unexpected network magic {:#x}", magic),
+            DecodeError::InvalidCommand => write!(f, "invalid command in message header"),
+            DecodeError::Oversized { ref command, size, max } =>
+                write!(f, "{} message of {} bytes exceeds the maximum of {}", command, size, max),
+            DecodeError::InvalidChecksum { ref command, .. } =>
+                write!(f, "invalid checksum of {} message", command),
+            DecodeError::Payload { ref command, ref error } =>
+                write!(f, "invalid {} message: {}", command, error),
+            DecodeError::TooManyItems { ref command, count, max } =>
+                write!(f, "{} message with {} items exceeds the maximum of {}", command, count, max),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            DecodeError::Payload { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Incremental decoder of [`RawNetworkMessage`]s from a byte stream.
+#[derive(Clone, Debug)]
+pub struct MessageDecoder {
+    magic: [u8; 4],
+    buffer: Vec<u8>,
+    discard: usize,
+    skipped: usize,
+}
+
+impl MessageDecoder {
+    /// Creates a decoder for messages of `network`.
+    pub fn new(network: Network) -> MessageDecoder {
+        MessageDecoder {
+            magic: network.magic().to_le_bytes(),
+            buffer: Vec::new(),
+            discard: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Appends bytes received from the transport.
+    pub fn push(&mut self, bytes: &[u8]) {
+        let discarded = self.discard.min(bytes.len());
+        self.discard -= discarded;
+        self.buffer.extend_from_slice(&bytes[discarded..]);
+    }
+
+    /// Returns the number of bytes buffered towards the next message.
+    pub fn buffered(&self) -> usize { self.buffer.len() }
+
+    /// Returns the number of bytes skipped while resynchronizing on the network magic.
+    pub fn skipped(&self) -> usize { self.skipped }
+
+    /// Returns the next complete message, or `None` if more bytes are needed.
+    ///
+    /// Call repeatedly until it returns `Ok(None)`: after an error decoding continues with the
+    /// bytes that follow the offending message.
+    pub fn next_message(&mut self) -> Result<Option<RawNetworkMessage>, DecodeError> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        if self.buffer[..4] != self.magic {
+            let magic = u32::from_le_bytes(self.buffer[..4].try_into().expect("4 bytes"));
+            self.resync();
+            return Err(DecodeError::WrongMagic(magic));
+        }
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let command = match parse_command(&self.buffer[4..16]) {
+            Some(command) => command,
+            None => {
+                self.resync();
+                return Err(DecodeError::InvalidCommand);
+            }
+        };
+        let size = u32::from_le_bytes(self.buffer[16..20].try_into().expect("4 bytes")) as usize;
+        let max = max_payload_size(command.as_ref());
+        if size > max {
+            if size > MAX_MSG_SIZE {
+                // No peer sends such a message, so the header is more likely garbage than the
+                // start of a payload worth skipping.
+                self.resync();
+            } else {
+                self.buffer.drain(..HEADER_SIZE);
+                let discarded = self.buffer.len().min(size);
+                self.buffer.drain(..discarded);
+                self.discard = size - discarded;
+            }
+            return Err(DecodeError::Oversized { command, size, max });
+        }
+        if self.buffer.len() < HEADER_SIZE + size {
+            // A bare header must not make us allocate its full announced size up front.
+            let wanted = (HEADER_SIZE + size).min(self.buffer.len() + RECEIVE_BUFFER_STEP);
+            self.buffer.reserve(wanted - self.buffer.len());
+            return Ok(None);
+        }
+
+        let actual: [u8; 4] = self.buffer[20..24].try_into().expect("4 bytes");
+        let payload = self.buffer[HEADER_SIZE..HEADER_SIZE + size].to_vec();
+        self.buffer.drain(..HEADER_SIZE + size);
+        let hash = sha256d::Hash::hash(&payload).to_byte_array();
+        let expected = [hash[0], hash[1], hash[2], hash[3]];
+        if expected != actual {
+            return Err(DecodeError::InvalidChecksum { command, expected, actual });
+        }
+
+        let payload = match NetworkMessage::consensus_decode_payload(command.clone(), payload) {
+            Ok(payload) => payload,
+            Err(error) => return Err(DecodeError::Payload { command, error }),
+        };
+        if let Some((count, max)) = item_count(&payload) {
+            if count > max {
+                return Err(DecodeError::TooManyItems { command, count, max });
+            }
+        }
+        Ok(Some(RawNetworkMessage { magic: u32::from_le_bytes(self.magic), payload }))
+    }
+
+    /// Drops the first byte and everything up to the next possible start of the magic.
+    fn resync(&mut self) {
+        let skip = self.buffer[1..]
+            .windows(4)
+            .position(|window| window == self.magic)
+            .map(|position| position + 1)
+            // Keep a trailing partial magic, it may be completed by the next bytes.
+            .unwrap_or_else(|| {
+                let keep =
+                    (1..4).rev().find(|&n| self.buffer.ends_with(&self.magic[..n])).unwrap_or(0);
+                (self.buffer.len() - keep).max(1)
+            });
+        self.buffer.drain(..skip);
+        self.skipped += skip;
+    }
+}
+
+/// Parses a command: printable ASCII followed by zero padding.
+fn parse_command(bytes: &[u8]) -> Option<CommandString> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    if len == 0 || !bytes[..len].iter().all(|b| b.is_ascii_graphic()) {
+        return None;
+    }
+    if bytes[len..].iter().any(|&b| b != 0) {
+        return None;
+    }
+    let command = core::str::from_utf8(&bytes[..len]).expect("ASCII");
+    CommandString::try_from(command).ok()
+}
+
+/// Returns the number of items in a list message and the maximum allowed.
+fn item_count(message: &NetworkMessage) -> Option<(usize, usize)> {
+    match *message {
+        NetworkMessage::Inv(ref inv)
+        | NetworkMessage::GetData(ref inv)
+        | NetworkMessage::NotFound(ref inv) => Some((inv.len(), MAX_INV_SIZE)),
+        NetworkMessage::Addr(ref addr) => Some((addr.len(), MAX_ADDR_TO_SEND)),
+        NetworkMessage::AddrV2(ref addr) => Some((addr.len(), MAX_ADDR_TO_SEND)),
+        NetworkMessage::Headers(ref headers) => Some((headers.len(), MAX_HEADERS_RESULTS)),
+        NetworkMessage::Headers2(ref headers) => Some((headers.headers.len(), MAX_HEADERS_RESULTS)),
+        NetworkMessage::GetBlocks(ref get) => Some((get.locator_hashes.len(), MAX_LOCATOR_SIZE)),
+        NetworkMessage::GetHeaders(ref get) | NetworkMessage::GetHeaders2(ref get) =>
+            Some((get.locator_hashes.len(), MAX_LOCATOR_SIZE)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::consensus::encode::serialize;
+    use crate::network::address::{AddrV2, AddrV2Message};
+    use crate::network::constants::ServiceFlags;
+    use crate::network::message_blockdata::Inventory;
+
+    fn raw(payload: NetworkMessage) -> Vec<u8> {
+        serialize(&RawNetworkMessage { magic: Network::Testnet.magic(), payload })
+    }
+
+    fn decode_all(decoder: &mut MessageDecoder) -> Vec<Result<NetworkMessage, String>> {
+        let mut results = Vec::new();
+        loop {
+            match decoder.next_message() {
+                Ok(Some(message)) => results.push(Ok(message.payload)),
+                Ok(None) => return results,
+                Err(e) => results.push(Err(e.to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_in_chunks() {
+        let mut stream = raw(NetworkMessage::Ping(7));
+        stream.extend(raw(NetworkMessage::Verack));
+        stream.extend(raw(NetworkMessage::Inv(vec![Inventory::Error])));
+
+        for chunk_size in [1, 5, 24, 31, stream.len()] {
+            let mut decoder = MessageDecoder::new(Network::Testnet);
+            let mut messages = Vec::new();
+            for chunk in stream.chunks(chunk_size) {
+                decoder.push(chunk);
+                messages.extend(decode_all(&mut decoder));
+            }
+            assert_eq!(
+                messages,
+                [
+                    Ok(NetworkMessage::Ping(7)),
+                    Ok(NetworkMessage::Verack),
+                    Ok(NetworkMessage::Inv(vec![Inventory::Error])),
+                ]
+            );
+            assert_eq!(decoder.buffered(), 0);
+            assert_eq!(decoder.skipped(), 0);
+        }
+    }
+
+    #[test]
+    fn resync_on_magic() {
+        let mut decoder = MessageDecoder::new(Network::Testnet);
+        // Garbage and another network's message before a valid message whose magic is split
+        // across two chunks.
+        let mut stream = vec![0xde, 0xad, 0xbe, 0xef, 0x00];
+        stream.extend(serialize(&RawNetworkMessage {
+            magic: Network::Dash.magic(),
+            payload: NetworkMessage::Verack,
+        }));
+        let message = raw(NetworkMessage::Pong(9));
+        decoder.push(&stream);
+        decoder.push(&message[..2]);
+        let results = decode_all(&mut decoder);
+        assert!(!results.is_empty() && results.iter().all(|r| r.is_err()));
+        assert_eq!(decoder.buffered(), 2);
+
+        decoder.push(&message[2..]);
+        assert_eq!(decode_all(&mut decoder), [Ok(NetworkMessage::Pong(9))]);
+        assert_eq!(decoder.skipped(), stream.len());
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let mut decoder = MessageDecoder::new(Network::Testnet);
+
+        // A corrupted payload fails the checksum and is skipped.
+        let mut corrupted = raw(NetworkMessage::Ping(1));
+        corrupted[HEADER_SIZE] ^= 1;
+        decoder.push(&corrupted);
+        assert!(matches!(decoder.next_message(), Err(DecodeError::InvalidChecksum { .. })));
+
+        // A command not followed by zero padding.
+        let mut bad_command = raw(NetworkMessage::Verack);
+        bad_command[15] = b'x';
+        decoder.push(&bad_command);
+        assert!(matches!(decoder.next_message(), Err(DecodeError::InvalidCommand)));
+        assert!(matches!(decoder.next_message(), Ok(None)));
+        decoder.push(&raw(NetworkMessage::Verack));
+        assert!(matches!(decoder.next_message(), Ok(Some(_))));
+
+        // A payload that does not parse as its command.
+        let mut short = raw(NetworkMessage::Unknown {
+            command: CommandString::try_from_static("ping").unwrap(),
+            payload: vec![1, 2, 3, 4],
+        });
+        decoder.push(&short);
+        assert!(matches!(decoder.next_message(), Err(DecodeError::Payload { .. })));
+
+        // An `addrv2` with more entries than allowed, small enough to fit the size limit.
+        let addr = AddrV2Message {
+            time: 0,
+            services: ServiceFlags::NONE,
+            addr: AddrV2::Ipv4(Ipv4Addr::LOCALHOST),
+            port: 9999,
+        };
+        decoder.push(&raw(NetworkMessage::AddrV2(vec![addr; MAX_ADDR_TO_SEND + 1])));
+        match decoder.next_message() {
+            Err(DecodeError::TooManyItems { count, max, .. }) =>
+                assert_eq!((count, max), (MAX_ADDR_TO_SEND + 1, MAX_ADDR_TO_SEND)),
+            result => panic!("unexpected {:?}", result),
+        }
+        // An `inv` with too many entries is too large to begin with.
+        decoder.push(&raw(NetworkMessage::Inv(vec![Inventory::Error; MAX_INV_SIZE + 1])));
+        assert!(matches!(decoder.next_message(), Err(DecodeError::Oversized { .. })));
+
+        // An oversized ping is skipped without being buffered, even when it arrives in pieces.
+        short = raw(NetworkMessage::Unknown {
+            command: CommandString::try_from_static("ping").unwrap(),
+            payload: vec![0; 1000],
+        });
+        decoder.push(&short[..100]);
+        match decoder.next_message() {
+            Err(DecodeError::Oversized { size, max, .. }) =>
+                assert_eq!((size, max), (short.len() - HEADER_SIZE, 8)),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(decoder.buffered(), 0);
+        decoder.push(&short[100..]);
+        decoder.push(&raw(NetworkMessage::Verack));
+        assert_eq!(decode_all(&mut decoder), [Ok(NetworkMessage::Verack)]);
+        assert_eq!(decoder.skipped(), bad_command.len());
+    }
+
+    #[test]
+    fn payload_limits() {
+        assert_eq!(max_payload_size("verack"), 0);
+        assert_eq!(max_payload_size("inv"), 1_800_009);
+        assert_eq!(max_payload_size("block"), MAX_MSG_SIZE);
+        // Full messages of the largest size fit.
+        let inv = vec![Inventory::Error; MAX_INV_SIZE];
+        assert!(serialize(&inv).len() <= max_payload_size("inv"));
+        let mut decoder = MessageDecoder::new(Network::Testnet);
+        decoder.push(&raw(NetworkMessage::GetData(inv.clone())));
+        assert_eq!(decode_all(&mut decoder), [Ok(NetworkMessage::GetData(inv))]);
+    }
+
+    #[test]
+    fn buffer_grows_with_data() {
+        let message = raw(NetworkMessage::Unknown {
+            command: CommandString::try_from_static("block").unwrap(),
+            payload: vec![0; 4 * RECEIVE_BUFFER_STEP],
+        });
+        let mut decoder = MessageDecoder::new(Network::Testnet);
+        decoder.push(&message[..HEADER_SIZE]);
+        assert!(matches!(decoder.next_message(), Ok(None)));
+        assert!(decoder.buffer.capacity() < 2 * RECEIVE_BUFFER_STEP);
+
+        for chunk in message[HEADER_SIZE..].chunks(100_000) {
+            assert!(decode_all(&mut decoder).is_empty());
+            decoder.push(chunk);
+        }
+        assert_eq!(decode_all(&mut decoder).len(), 1);
+        assert_eq!(decoder.buffered(), 0);
+    }
+}
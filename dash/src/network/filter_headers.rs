@@ -0,0 +1,795 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP157 filter header chain.
+//!
+//! A compact filter client cannot check filters against the proof of work, so it relies on the
+//! chain of filter headers: each header commits to the filter of its block and to the previous
+//! header. [`FilterHeaderChain`] follows the block header chain, verifies `cfheaders` responses
+//! against it and against the `cfcheckpt` checkpoints taken every [`CHECKPOINT_INTERVAL`]
+//! blocks, and verifies downloaded filters against the verified headers.
+//!
+//! When two peers serve different filter headers, only one of them can be honest. The chain
+//! reports the first block at which they differ as a [`Conflict`]; the client then downloads
+//! that block and its filter with [`Conflict::get_cfilters`] and calls
+//! [`FilterHeaderChain::resolve_conflict`] to find out which peer served a filter that does not
+//! match the block.
+//!
+
+use core::fmt;
+use std::collections::HashMap;
+
+use hashes::Hash;
+
+use crate::bip158::{BlockFilter, special_transaction_elements};
+use crate::blockdata::block::Block;
+use crate::hash_types::{BlockHash, FilterHash, FilterHeader};
+use crate::network::message_filter::{
+    CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFHeaders, GetCFilters,
+};
+use crate::prelude::*;
+
+/// The type of the basic filter.
+pub const FILTER_TYPE_BASIC: u8 = 0;
+
+/// The interval between two filter headers of a `cfcheckpt` message.
+pub const CHECKPOINT_INTERVAL: u32 = 1_000;
+
+/// The maximum number of filter hashes of a `cfheaders` message.
+pub const MAX_CFHEADERS_RESULTS: u32 = 2_000;
+
+/// The first block at which two sets of filter headers differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The height of the block.
+    pub height: u32,
+    /// The hash of the block.
+    pub block_hash: BlockHash,
+    /// The filter hash we have verified so far, or got from the first peer.
+    pub ours: FilterHash,
+    /// The conflicting filter hash.
+    pub theirs: FilterHash,
+}
+
+impl Conflict {
+    /// Returns the `getcfilters` message to ask a peer for the filter of the block.
+    pub fn get_cfilters(&self, filter_type: u8) -> GetCFilters {
+        GetCFilters { filter_type, start_height: self.height, stop_hash: self.block_hash }
+    }
+}
+
+/// Which side of a [`Conflict`] served a filter matching the block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Our filter headers are right, the conflicting peer lied.
+    Ours,
+    /// The conflicting filter headers are right; ours were dropped from the height of the
+    /// conflict on and must be downloaded again.
+    Theirs,
+}
+
+/// Error verifying filter headers or filters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FilterHeaderError {
+    /// The message is for another filter type.
+    WrongFilterType {
+        /// The filter type of the chain.
+        expected: u8,
+        /// The filter type of the message.
+        actual: u8,
+    },
+    /// The stop or block hash is not in our block header chain.
+    UnknownBlock(BlockHash),
+    /// The `cfheaders` message is empty, too long or starts before the genesis block.
+    InvalidRange,
+    /// The `cfheaders` message does not connect to the verified filter headers.
+    NotConnected {
+        /// The height of the first block of the message.
+        start_height: u32,
+    },
+    /// A filter header does not match the checkpoint at its height.
+    CheckpointMismatch {
+        /// The height of the checkpoint.
+        height: u32,
+    },
+    /// A `cfcheckpt` message has the wrong number of headers for its stop hash.
+    InvalidCheckpoints,
+    /// Peers disagree on a checkpoint; the interval ending at `height` must be compared with
+    /// [`FilterHeaderChain::find_conflict`].
+    CheckpointConflict {
+        /// The height of the first conflicting checkpoint.
+        height: u32,
+    },
+    /// The filter headers conflict with the verified ones.
+    Conflict(Conflict),
+    /// A filter does not match its verified filter header.
+    FilterMismatch(BlockHash),
+}
+
+impl fmt::Display for FilterHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FilterHeaderError::WrongFilterType { expected, actual } =>
+                write!(f, "wrong filter type {}, expected {}", actual, expected),
+            FilterHeaderError::UnknownBlock(ref hash) => write!(f, "unknown block {}", hash),
+            FilterHeaderError::InvalidRange => f.write_str("invalid filter header range"),
+            FilterHeaderError::NotConnected { start_height } =>
+                write!(f, "filter headers at height {} do not connect", start_height),
+            FilterHeaderError::CheckpointMismatch { height } =>
+                write!(f, "filter header at height {} does not match the checkpoint", height),
+            FilterHeaderError::InvalidCheckpoints =>
+                f.write_str("wrong number of filter header checkpoints"),
+            FilterHeaderError::CheckpointConflict { height } =>
+                write!(f, "conflicting filter header checkpoints at height {}", height),
+            FilterHeaderError::Conflict(ref conflict) => write!(
+                f,
+                "conflicting filter hashes {} and {} at height {}",
+                conflict.ours, conflict.theirs, conflict.height
+            ),
+            FilterHeaderError::FilterMismatch(ref hash) =>
+                write!(f, "filter of block {} does not match its filter header", hash),
+        }
+    }
+}
+
+impl std::error::Error for FilterHeaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// The chain of verified filter headers of one filter type.
+///
+/// The block header chain is fed with [`push_block_hash`](FilterHeaderChain::push_block_hash)
+/// as headers are validated, and rewound with [`rewind`](FilterHeaderChain::rewind) on a
+/// reorganization.
+#[derive(Debug, Clone)]
+pub struct FilterHeaderChain {
+    filter_type: u8,
+    /// Block hashes by height, starting at the genesis block.
+    block_hashes: Vec<BlockHash>,
+    heights: HashMap<BlockHash, u32>,
+    /// Verified filter hashes and headers by height, starting at the genesis block.
+    filter_hashes: Vec<FilterHash>,
+    filter_headers: Vec<FilterHeader>,
+    /// Filter headers at heights `CHECKPOINT_INTERVAL`, `2 * CHECKPOINT_INTERVAL`, ...
+    checkpoints: Vec<FilterHeader>,
+}
+
+impl FilterHeaderChain {
+    /// Creates a chain of filters of `filter_type` starting at the genesis block.
+    pub fn new(filter_type: u8, genesis_hash: BlockHash) -> FilterHeaderChain {
+        FilterHeaderChain {
+            filter_type,
+            block_hashes: vec![genesis_hash],
+            heights: HashMap::from([(genesis_hash, 0)]),
+            filter_hashes: Vec::new(),
+            filter_headers: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Returns the filter type of the chain.
+    pub fn filter_type(&self) -> u8 { self.filter_type }
+
+    /// Returns the height of the block header chain.
+    pub fn block_height(&self) -> u32 { self.block_hashes.len() as u32 - 1 }
+
+    /// Returns the height of the last verified filter header, if any.
+    pub fn filter_height(&self) -> Option<u32> { (self.filter_headers.len() as u32).checked_sub(1) }
+
+    /// Returns the height of a block of the chain.
+    pub fn height(&self, block_hash: &BlockHash) -> Option<u32> {
+        self.heights.get(block_hash).copied()
+    }
+
+    /// Returns the verified filter header at `height`.
+    pub fn filter_header(&self, height: u32) -> Option<FilterHeader> {
+        self.filter_headers.get(height as usize).copied()
+    }
+
+    /// Returns the verified filter hash at `height`.
+    pub fn filter_hash(&self, height: u32) -> Option<FilterHash> {
+        self.filter_hashes.get(height as usize).copied()
+    }
+
+    /// Returns the checkpoints received so far.
+    pub fn checkpoints(&self) -> &[FilterHeader] { &self.checkpoints }
+
+    /// Appends the next block of the block header chain.
+    pub fn push_block_hash(&mut self, block_hash: BlockHash) {
+        self.heights.insert(block_hash, self.block_hashes.len() as u32);
+        self.block_hashes.push(block_hash);
+    }
+
+    /// Disconnects the blocks above `height`, and their filter headers and checkpoints.
+    pub fn rewind(&mut self, height: u32) {
+        let len = height as usize + 1;
+        for hash in self.block_hashes.drain(len.min(self.block_hashes.len())..) {
+            self.heights.remove(&hash);
+        }
+        self.filter_hashes.truncate(len);
+        self.filter_headers.truncate(len);
+        self.checkpoints.truncate((height / CHECKPOINT_INTERVAL) as usize);
+    }
+
+    /// Returns the `getcfheaders` message for the next filter headers to download, if any.
+    pub fn next_request(&self) -> Option<GetCFHeaders> {
+        let start_height = self.filter_headers.len() as u32;
+        if start_height > self.block_height() {
+            return None;
+        }
+        let stop_height = self.block_height().min(start_height + MAX_CFHEADERS_RESULTS - 1);
+        Some(GetCFHeaders {
+            filter_type: self.filter_type,
+            start_height,
+            stop_hash: self.block_hashes[stop_height as usize],
+        })
+    }
+
+    /// Returns the `getcfcheckpt` message for the checkpoints up to the tip.
+    pub fn checkpoint_request(&self) -> GetCFCheckpt {
+        GetCFCheckpt {
+            filter_type: self.filter_type,
+            stop_hash: self.block_hashes[self.block_height() as usize],
+        }
+    }
+
+    fn check_filter_type(&self, filter_type: u8) -> Result<(), FilterHeaderError> {
+        if filter_type != self.filter_type {
+            return Err(FilterHeaderError::WrongFilterType {
+                expected: self.filter_type,
+                actual: filter_type,
+            });
+        }
+        Ok(())
+    }
+
+    fn stop_height(&self, stop_hash: &BlockHash) -> Result<u32, FilterHeaderError> {
+        self.height(stop_hash).ok_or(FilterHeaderError::UnknownBlock(*stop_hash))
+    }
+
+    /// Returns the height of the first block of a `cfheaders` message.
+    fn start_height(&self, message: &CFHeaders) -> Result<u32, FilterHeaderError> {
+        let stop_height = self.stop_height(&message.stop_hash)?;
+        let count = message.filter_hashes.len() as u32;
+        if count == 0 || count > MAX_CFHEADERS_RESULTS || count > stop_height + 1 {
+            return Err(FilterHeaderError::InvalidRange);
+        }
+        Ok(stop_height + 1 - count)
+    }
+
+    /// Returns the filter header preceding `height`, all zeros before the genesis block.
+    fn previous_header(&self, height: u32) -> Option<FilterHeader> {
+        match height {
+            0 => Some(FilterHeader::all_zeros()),
+            height => self.filter_header(height - 1),
+        }
+    }
+
+    /// Returns the checkpoint at `height`, if any.
+    fn checkpoint(&self, height: u32) -> Option<FilterHeader> {
+        if height == 0 || height % CHECKPOINT_INTERVAL != 0 {
+            return None;
+        }
+        self.checkpoints.get((height / CHECKPOINT_INTERVAL - 1) as usize).copied()
+    }
+
+    /// Verifies a `cfheaders` message and appends its new filter headers to the chain.
+    ///
+    /// The message must connect to the verified filter headers, and its headers must match the
+    /// checkpoints. Headers overlapping verified ones must be equal; otherwise the first
+    /// difference is returned as [`FilterHeaderError::Conflict`], and nothing is appended.
+    /// Returns the number of appended headers.
+    pub fn process_cfheaders(&mut self, message: &CFHeaders) -> Result<usize, FilterHeaderError> {
+        self.check_filter_type(message.filter_type)?;
+        let start_height = self.start_height(message)?;
+        match self.previous_header(start_height) {
+            Some(previous) if previous == message.previous_filter_header => (),
+            _ => return Err(FilterHeaderError::NotConnected { start_height }),
+        }
+
+        let mut headers = Vec::with_capacity(message.filter_hashes.len());
+        let mut previous = message.previous_filter_header;
+        for (height, filter_hash) in (start_height..).zip(&message.filter_hashes) {
+            if let Some(ours) = self.filter_hash(height) {
+                if ours != *filter_hash {
+                    return Err(FilterHeaderError::Conflict(Conflict {
+                        height,
+                        block_hash: self.block_hashes[height as usize],
+                        ours,
+                        theirs: *filter_hash,
+                    }));
+                }
+            }
+            previous = filter_hash.filter_header(&previous);
+            if self.checkpoint(height).is_some_and(|checkpoint| checkpoint != previous) {
+                return Err(FilterHeaderError::CheckpointMismatch { height });
+            }
+            headers.push(previous);
+        }
+
+        let known = (self.filter_headers.len() - start_height as usize).min(headers.len());
+        self.filter_hashes.extend_from_slice(&message.filter_hashes[known..]);
+        self.filter_headers.extend_from_slice(&headers[known..]);
+        Ok(headers.len() - known)
+    }
+
+    /// Verifies a `cfcheckpt` message and records its checkpoints.
+    ///
+    /// Checkpoints must match the verified filter headers and the checkpoints received before.
+    /// On [`FilterHeaderError::CheckpointConflict`] the client should download the filter
+    /// headers of the interval from both peers and compare them with
+    /// [`find_conflict`](FilterHeaderChain::find_conflict).
+    pub fn process_cfcheckpt(&mut self, message: &CFCheckpt) -> Result<(), FilterHeaderError> {
+        self.check_cfcheckpt(message)?;
+        for (i, checkpoint) in message.filter_headers.iter().enumerate() {
+            if self.checkpoints.get(i).is_some_and(|known| known != checkpoint) {
+                let height = (i as u32 + 1) * CHECKPOINT_INTERVAL;
+                return Err(FilterHeaderError::CheckpointConflict { height });
+            }
+        }
+        if message.filter_headers.len() > self.checkpoints.len() {
+            self.checkpoints = message.filter_headers.clone();
+        }
+        Ok(())
+    }
+
+    /// Verifies a `cfcheckpt` message and replaces the checkpoints received before with its own.
+    ///
+    /// Used after a [`FilterHeaderError::CheckpointConflict`] was resolved in favour of the peer
+    /// that sent `message`. Its checkpoints must still match the verified filter headers.
+    pub fn replace_checkpoints(&mut self, message: &CFCheckpt) -> Result<(), FilterHeaderError> {
+        self.check_cfcheckpt(message)?;
+        self.checkpoints = message.filter_headers.clone();
+        Ok(())
+    }
+
+    fn check_cfcheckpt(&self, message: &CFCheckpt) -> Result<(), FilterHeaderError> {
+        self.check_filter_type(message.filter_type)?;
+        let stop_height = self.stop_height(&message.stop_hash)?;
+        if message.filter_headers.len() != (stop_height / CHECKPOINT_INTERVAL) as usize {
+            return Err(FilterHeaderError::InvalidCheckpoints);
+        }
+        for (i, checkpoint) in message.filter_headers.iter().enumerate() {
+            let height = (i as u32 + 1) * CHECKPOINT_INTERVAL;
+            if self.filter_header(height).is_some_and(|header| header != *checkpoint) {
+                return Err(FilterHeaderError::CheckpointMismatch { height });
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares two `cfheaders` responses for the same range from different peers, and returns
+    /// the first block at which they differ, if any.
+    ///
+    /// Since every filter header commits to all previous ones, responses with the same previous
+    /// filter header differ from the first differing filter hash on.
+    pub fn find_conflict(
+        &self,
+        ours: &CFHeaders,
+        theirs: &CFHeaders,
+    ) -> Result<Option<Conflict>, FilterHeaderError> {
+        self.check_filter_type(ours.filter_type)?;
+        self.check_filter_type(theirs.filter_type)?;
+        let start_height = self.start_height(ours)?;
+        if self.start_height(theirs)? != start_height
+            || ours.stop_hash != theirs.stop_hash
+            || ours.previous_filter_header != theirs.previous_filter_header
+        {
+            return Err(FilterHeaderError::InvalidRange);
+        }
+        Ok((start_height..).zip(ours.filter_hashes.iter().zip(&theirs.filter_hashes)).find_map(
+            |(height, (ours, theirs))| {
+                (ours != theirs).then(|| Conflict {
+                    height,
+                    block_hash: self.block_hashes[height as usize],
+                    ours: *ours,
+                    theirs: *theirs,
+                })
+            },
+        ))
+    }
+
+    /// Resolves a [`Conflict`] with the block and the filter served by either side.
+    ///
+    /// The filter must hash to one side's filter hash. A filter is honest if it matches all
+    /// non-OP_RETURN output scripts and special transaction elements of the block, which every
+    /// basic filter served by Dash Core contains; the other side is lying. If we were lying, our
+    /// filter headers and checkpoints from the height of the conflict on are dropped.
+    ///
+    /// The spent scripts are not checked since they are not part of the block, so a filter that
+    /// only leaves out spent scripts is taken as honest.
+    pub fn resolve_conflict(
+        &mut self,
+        conflict: &Conflict,
+        filter: &CFilter,
+        block: &Block,
+    ) -> Result<Resolution, FilterHeaderError> {
+        self.check_filter_type(filter.filter_type)?;
+        if filter.block_hash != conflict.block_hash || block.block_hash() != conflict.block_hash {
+            return Err(FilterHeaderError::FilterMismatch(filter.block_hash));
+        }
+        let served_by_us = match FilterHash::hash(&filter.filter) {
+            hash if hash == conflict.ours => true,
+            hash if hash == conflict.theirs => false,
+            _ => return Err(FilterHeaderError::FilterMismatch(filter.block_hash)),
+        };
+
+        let mut elements = block
+            .txdata
+            .iter()
+            .flat_map(|tx| tx.output.iter())
+            .map(|output| &output.script_pubkey)
+            .filter(|script| !script.is_empty() && !script.is_op_return())
+            .map(|script| script.to_bytes())
+            .chain(block.txdata.iter().flat_map(special_transaction_elements))
+            .filter(|element| !element.is_empty())
+            .peekable();
+        let honest = elements.peek().is_none()
+            || BlockFilter::new(&filter.filter)
+                .match_all(&conflict.block_hash, elements)
+                .unwrap_or(false);
+
+        if honest == served_by_us {
+            Ok(Resolution::Ours)
+        } else {
+            self.filter_hashes.truncate(conflict.height as usize);
+            self.filter_headers.truncate(conflict.height as usize);
+            // The checkpoint at the height of the conflict commits to our filter as well.
+            self.checkpoints
+                .truncate((conflict.height.saturating_sub(1) / CHECKPOINT_INTERVAL) as usize);
+            Ok(Resolution::Theirs)
+        }
+    }
+
+    /// Verifies a downloaded filter against its verified filter header.
+    pub fn verify_filter(&self, message: &CFilter) -> Result<(), FilterHeaderError> {
+        self.check_filter_type(message.filter_type)?;
+        let height = self
+            .height(&message.block_hash)
+            .ok_or(FilterHeaderError::UnknownBlock(message.block_hash))?;
+        let (previous, expected) = match (self.previous_header(height), self.filter_header(height))
+        {
+            (Some(previous), Some(expected)) => (previous, expected),
+            _ => return Err(FilterHeaderError::UnknownBlock(message.block_hash)),
+        };
+        if BlockFilter::new(&message.filter).filter_header(&previous) != expected {
+            return Err(FilterHeaderError::FilterMismatch(message.block_hash));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockdata::block::{Header, Version};
+    use crate::blockdata::transaction::special_transaction::TransactionPayload;
+    use crate::blockdata::transaction::special_transaction::provider_update_revocation::ProviderUpdateRevocationPayload;
+    use crate::bls_sig_utils::BLSSignature;
+    use crate::hash_types::{InputsHash, TxMerkleNode, Txid};
+    use crate::pow::CompactTarget;
+    use crate::{OutPoint, PubkeyHash, ScriptBuf, Transaction, TxIn, TxOut};
+
+    fn block_hash(height: u32) -> BlockHash {
+        let mut hash = [0xbb; 32];
+        hash[..4].copy_from_slice(&height.to_le_bytes());
+        BlockHash::from_byte_array(hash)
+    }
+
+    fn filter(height: u32) -> Vec<u8> { height.to_be_bytes().to_vec() }
+
+    /// Returns a chain of `height` blocks without filter headers.
+    fn chain(height: u32) -> FilterHeaderChain {
+        let mut chain = FilterHeaderChain::new(FILTER_TYPE_BASIC, block_hash(0));
+        for height in 1..=height {
+            chain.push_block_hash(block_hash(height));
+        }
+        chain
+    }
+
+    /// Returns the honest filter headers of blocks `0..=height`.
+    fn headers(height: u32) -> Vec<FilterHeader> {
+        let mut previous = FilterHeader::all_zeros();
+        (0..=height)
+            .map(|h| {
+                previous = FilterHash::hash(&filter(h)).filter_header(&previous);
+                previous
+            })
+            .collect()
+    }
+
+    fn cfheaders(start: u32, stop: u32) -> CFHeaders {
+        CFHeaders {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash: block_hash(stop),
+            previous_filter_header: match start {
+                0 => FilterHeader::all_zeros(),
+                start => headers(start - 1)[start as usize - 1],
+            },
+            filter_hashes: (start..=stop).map(|h| FilterHash::hash(&filter(h))).collect(),
+        }
+    }
+
+    fn cfilter(height: u32, filter: Vec<u8>) -> CFilter {
+        CFilter { filter_type: FILTER_TYPE_BASIC, block_hash: block_hash(height), filter }
+    }
+
+    #[test]
+    fn sync_and_verify_filters() {
+        let mut chain = chain(2_500);
+        let request = chain.next_request().unwrap();
+        assert_eq!((request.start_height, request.stop_hash), (0, block_hash(1_999)));
+
+        assert_eq!(chain.process_cfheaders(&cfheaders(0, 1_999)), Ok(2_000));
+        assert_eq!(chain.next_request().unwrap().stop_hash, block_hash(2_500));
+        // Overlapping responses are accepted, and only new headers are added.
+        assert_eq!(chain.process_cfheaders(&cfheaders(1_500, 2_500)), Ok(501));
+        assert_eq!(chain.next_request(), None);
+        assert_eq!(chain.filter_height(), Some(2_500));
+        assert_eq!(chain.filter_header(2_500), headers(2_500).last().copied());
+
+        assert_eq!(chain.verify_filter(&cfilter(1_234, filter(1_234))), Ok(()));
+        assert_eq!(
+            chain.verify_filter(&cfilter(1_234, filter(1_235))),
+            Err(FilterHeaderError::FilterMismatch(block_hash(1_234)))
+        );
+        assert_eq!(
+            chain.verify_filter(&cfilter(2_501, filter(2_501))),
+            Err(FilterHeaderError::UnknownBlock(block_hash(2_501)))
+        );
+
+        chain.rewind(1_000);
+        assert_eq!(chain.block_height(), 1_000);
+        assert_eq!(chain.filter_height(), Some(1_000));
+        assert_eq!(chain.height(&block_hash(1_001)), None);
+    }
+
+    #[test]
+    fn invalid_cfheaders() {
+        let mut chain = chain(10);
+        let mut message = cfheaders(0, 5);
+        message.filter_type = 1;
+        assert_eq!(
+            chain.process_cfheaders(&message),
+            Err(FilterHeaderError::WrongFilterType { expected: 0, actual: 1 })
+        );
+        let mut message = cfheaders(0, 5);
+        message.stop_hash = block_hash(11);
+        assert_eq!(
+            chain.process_cfheaders(&message),
+            Err(FilterHeaderError::UnknownBlock(block_hash(11)))
+        );
+        let mut message = cfheaders(0, 5);
+        message.filter_hashes.push(FilterHash::all_zeros());
+        assert_eq!(chain.process_cfheaders(&message), Err(FilterHeaderError::InvalidRange));
+        // Not connected to the (missing) previous headers.
+        assert_eq!(
+            chain.process_cfheaders(&cfheaders(3, 5)),
+            Err(FilterHeaderError::NotConnected { start_height: 3 })
+        );
+        let mut message = cfheaders(0, 5);
+        message.previous_filter_header = FilterHeader::hash(&[1]);
+        assert_eq!(
+            chain.process_cfheaders(&message),
+            Err(FilterHeaderError::NotConnected { start_height: 0 })
+        );
+        assert_eq!(chain.filter_height(), None);
+    }
+
+    #[test]
+    fn checkpoints() {
+        let honest = headers(2_500);
+        let mut chain = chain(2_500);
+        let checkpoint = |filter_headers| CFCheckpt {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash: block_hash(2_500),
+            filter_headers,
+        };
+        assert_eq!(chain.checkpoint_request().stop_hash, block_hash(2_500));
+        assert_eq!(
+            chain.process_cfcheckpt(&checkpoint(vec![honest[1_000]])),
+            Err(FilterHeaderError::InvalidCheckpoints)
+        );
+        assert_eq!(
+            chain.process_cfcheckpt(&checkpoint(vec![honest[1_000], honest[2_000]])),
+            Ok(())
+        );
+        assert_eq!(chain.checkpoints(), &[honest[1_000], honest[2_000]]);
+        assert_eq!(
+            chain.process_cfcheckpt(&checkpoint(vec![honest[1_000], honest[1_999]])),
+            Err(FilterHeaderError::CheckpointConflict { height: 2_000 })
+        );
+
+        // A lying peer changes the filter of block 1500.
+        let mut lie = cfheaders(0, 1_999);
+        lie.filter_hashes[1_500] = FilterHash::all_zeros();
+        assert_eq!(chain.process_cfheaders(&lie), Ok(2_000));
+        let mut lie = cfheaders(2_000, 2_500);
+        lie.previous_filter_header = chain.filter_header(1_999).unwrap();
+        assert_eq!(
+            chain.process_cfheaders(&lie),
+            Err(FilterHeaderError::CheckpointMismatch { height: 2_000 })
+        );
+
+        // Checkpoints are also checked against verified headers.
+        let mut chain = self::chain(2_500);
+        chain.process_cfheaders(&cfheaders(0, 1_999)).unwrap();
+        assert_eq!(
+            chain.process_cfcheckpt(&checkpoint(vec![honest[999], honest[2_000]])),
+            Err(FilterHeaderError::CheckpointMismatch { height: 1_000 })
+        );
+    }
+
+    #[test]
+    fn conflicting_peers() {
+        let script = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([7; 20]));
+        let block = Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: block_hash(0),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1,
+                bits: CompactTarget::from_consensus(2),
+                nonce: 3,
+            },
+            txdata: vec![Transaction {
+                version: 1,
+                lock_time: 0,
+                input: vec![TxIn::default()],
+                output: vec![TxOut { value: 50, script_pubkey: script }],
+                special_transaction_payload: None,
+            }],
+        };
+        let honest_filter = BlockFilter::new_basic_filter(&block, |o| {
+            Err::<ScriptBuf, _>(crate::bip158::Error::UtxoMissing(*o))
+        })
+        .unwrap()
+        .content;
+        // An empty filter, hiding the payment.
+        let lying_filter = vec![0];
+
+        let mut chain = FilterHeaderChain::new(FILTER_TYPE_BASIC, block_hash(0));
+        chain.push_block_hash(block.block_hash());
+        let response = |content: &[u8]| CFHeaders {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash: block.block_hash(),
+            previous_filter_header: FilterHeader::all_zeros(),
+            filter_hashes: vec![FilterHash::hash(&filter(0)), FilterHash::hash(content)],
+        };
+        let honest = response(&honest_filter);
+        let lying = response(&lying_filter);
+
+        let conflict = chain.find_conflict(&honest, &lying).unwrap().unwrap();
+        assert_eq!(conflict.height, 1);
+        assert_eq!(chain.find_conflict(&honest, &honest), Ok(None));
+
+        // We first synced from the liar.
+        chain.process_cfheaders(&lying).unwrap();
+        let conflict = match chain.process_cfheaders(&honest) {
+            Err(FilterHeaderError::Conflict(conflict)) => conflict,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(conflict.block_hash, block.block_hash());
+        assert_eq!(conflict.get_cfilters(FILTER_TYPE_BASIC).start_height, 1);
+
+        let served = CFilter {
+            filter_type: FILTER_TYPE_BASIC,
+            block_hash: block.block_hash(),
+            filter: lying_filter,
+        };
+        assert_eq!(chain.resolve_conflict(&conflict, &served, &block), Ok(Resolution::Theirs));
+        assert_eq!(chain.filter_height(), Some(0));
+        assert_eq!(chain.process_cfheaders(&honest), Ok(1));
+
+        // Now the liar conflicts with us.
+        let conflict = match chain.process_cfheaders(&lying) {
+            Err(FilterHeaderError::Conflict(conflict)) => conflict,
+            other => panic!("unexpected {:?}", other),
+        };
+        let served = CFilter { filter: honest_filter, ..served };
+        assert_eq!(chain.resolve_conflict(&conflict, &served, &block), Ok(Resolution::Ours));
+        assert_eq!(chain.filter_height(), Some(1));
+    }
+
+    #[test]
+    fn conflicting_checkpoints() {
+        let script = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([7; 20]));
+        let revocation = ProviderUpdateRevocationPayload {
+            version: 1,
+            pro_tx_hash: Txid::from_byte_array([9; 32]),
+            reason: 0,
+            inputs_hash: InputsHash::all_zeros(),
+            payload_sig: BLSSignature::from([0; 96]),
+        };
+        let block = Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: block_hash(999),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1,
+                bits: CompactTarget::from_consensus(2),
+                nonce: 3,
+            },
+            txdata: vec![
+                Transaction {
+                    version: 1,
+                    lock_time: 0,
+                    input: vec![TxIn::default()],
+                    output: vec![TxOut { value: 50, script_pubkey: script }],
+                    special_transaction_payload: None,
+                },
+                Transaction {
+                    version: 3,
+                    lock_time: 0,
+                    input: vec![],
+                    output: vec![],
+                    special_transaction_payload: Some(
+                        TransactionPayload::ProviderUpdateRevocationPayloadType(revocation),
+                    ),
+                },
+            ],
+        };
+        let missing = |o: &OutPoint| Err::<ScriptBuf, _>(crate::bip158::Error::UtxoMissing(*o));
+        let honest_filter = BlockFilter::new_basic_filter(&block, missing).unwrap().content;
+        // Contains the output scripts, but hides the revocation.
+        let lying_filter = BlockFilter::new_script_filter(&block, missing).unwrap().content;
+
+        let mut chain = chain(999);
+        chain.push_block_hash(block.block_hash());
+        let response = |content: &[u8]| {
+            let mut response = cfheaders(0, 1_000);
+            response.stop_hash = block.block_hash();
+            response.filter_hashes[1_000] = FilterHash::hash(content);
+            response
+        };
+        let checkpoint = |content: &[u8]| CFCheckpt {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash: block.block_hash(),
+            filter_headers: vec![FilterHash::hash(content).filter_header(&headers(999)[999])],
+        };
+        let (honest, lying) = (response(&honest_filter), response(&lying_filter));
+        let (honest_checkpoint, lying_checkpoint) =
+            (checkpoint(&honest_filter), checkpoint(&lying_filter));
+
+        // We first got the checkpoints of the liar.
+        assert_eq!(chain.process_cfcheckpt(&lying_checkpoint), Ok(()));
+        assert_eq!(
+            chain.process_cfcheckpt(&honest_checkpoint),
+            Err(FilterHeaderError::CheckpointConflict { height: 1_000 })
+        );
+        let conflict = chain.find_conflict(&lying, &honest).unwrap().unwrap();
+        assert_eq!(conflict.height, 1_000);
+        let served = CFilter {
+            filter_type: FILTER_TYPE_BASIC,
+            block_hash: block.block_hash(),
+            filter: lying_filter,
+        };
+        assert_eq!(chain.resolve_conflict(&conflict, &served, &block), Ok(Resolution::Theirs));
+        assert!(chain.checkpoints().is_empty());
+        assert_eq!(chain.process_cfcheckpt(&honest_checkpoint), Ok(()));
+        assert_eq!(chain.process_cfheaders(&honest), Ok(1_001));
+
+        // Checkpoints can also be replaced, as long as they match the verified headers.
+        assert_eq!(
+            chain.replace_checkpoints(&lying_checkpoint),
+            Err(FilterHeaderError::CheckpointMismatch { height: 1_000 })
+        );
+        let mut chain = self::chain(999);
+        chain.push_block_hash(block.block_hash());
+        chain.process_cfcheckpt(&lying_checkpoint).unwrap();
+        assert_eq!(chain.replace_checkpoints(&honest_checkpoint), Ok(()));
+        assert_eq!(chain.checkpoints(), &honest_checkpoint.filter_headers[..]);
+    }
+}
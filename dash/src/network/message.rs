@@ -25,17 +25,22 @@ use io::Read as _;
 use crate::blockdata::{block, transaction};
 use crate::consensus::encode::{CheckedData, Decodable, Encodable, VarInt};
 use crate::consensus::{encode, serialize};
+use crate::ephemerealdata::spork::SporkMessage;
+use crate::governance::{GovernanceObject, GovernanceVote};
 use crate::io;
 use crate::merkle_tree::MerkleBlock;
 use crate::network::address::{AddrV2Message, Address};
 use crate::network::{
-    message_blockdata, message_bloom, message_compact_blocks, message_filter, message_network,
+    message_blockdata, message_bloom, message_coinjoin, message_compact_blocks, message_dkg,
+    message_filter, message_governance, message_headers2, message_masternode, message_network,
+    message_qrinfo, message_signing, message_sml,
 };
 use crate::prelude::*;
 
 /// The maximum number of [super::message_blockdata::Inventory] items in an `inv` message.
 ///
-/// This limit is not currently enforced by this implementation.
+/// This limit is enforced by [super::decoder::MessageDecoder], but not when decoding a
+/// [RawNetworkMessage] directly.
 pub const MAX_INV_SIZE: usize = 50_000;
 
 /// Maximum size, in bytes, of an encoded message
@@ -236,6 +241,74 @@ pub enum NetworkMessage {
     AddrV2(Vec<AddrV2Message>),
     /// `sendaddrv2`
     SendAddrV2,
+    /// `mnauth`
+    MnAuth(message_masternode::MnAuth),
+    /// `qsendrecsigs`
+    QSendRecSigs(bool),
+    /// `qwatch`
+    QWatch,
+    /// `senddsq`
+    SendDsq(bool),
+    /// `sendheaders2`
+    SendHeaders2,
+    /// `spork`
+    Spork(SporkMessage),
+    /// `getsporks`
+    GetSporks,
+    /// `getheaders2`
+    GetHeaders2(message_blockdata::GetHeadersMessage),
+    /// `headers2`
+    Headers2(message_headers2::Headers2Message),
+    /// `getmnlistd`
+    GetMnListD(message_sml::GetMnListDiff),
+    /// `mnlistdiff`, boxed as it embeds the coinbase transaction
+    MnListDiff(Box<message_sml::MnListDiff>),
+    /// `getqrinfo`
+    GetQRInfo(message_qrinfo::GetQRInfo),
+    /// `qrinfo`, boxed as it holds several masternode list diffs
+    QRInfo(Box<message_qrinfo::QRInfo>),
+    /// `govobj`
+    GovObject(GovernanceObject),
+    /// `govobjvote`
+    GovObjectVote(GovernanceVote),
+    /// `govsync`
+    GovSync(message_governance::GovSync),
+    /// `dsa`
+    CoinJoinAccept(message_coinjoin::CoinJoinAccept),
+    /// `dsq`
+    CoinJoinQueue(message_coinjoin::CoinJoinQueue),
+    /// `dsi`
+    CoinJoinEntry(message_coinjoin::CoinJoinEntry),
+    /// `dsf`
+    CoinJoinFinalTransaction(message_coinjoin::CoinJoinFinalTransaction),
+    /// `dss`
+    CoinJoinSignedInputs(Vec<transaction::txin::TxIn>),
+    /// `dssu`
+    CoinJoinStatusUpdate(message_coinjoin::CoinJoinStatusUpdate),
+    /// `dsc`
+    CoinJoinComplete(message_coinjoin::CoinJoinComplete),
+    /// `dstx`
+    CoinJoinBroadcastTx(message_coinjoin::CoinJoinBroadcastTx),
+    /// `qcontrib`
+    QuorumContribution(message_dkg::DKGContribution),
+    /// `qcomplaint`
+    QuorumComplaint(message_dkg::DKGComplaint),
+    /// `qjustify`
+    QuorumJustification(message_dkg::DKGJustification),
+    /// `qpcommit`
+    QuorumPrematureCommitment(message_dkg::DKGPrematureCommitment),
+    /// `qsigrec`
+    QSigRec(message_signing::RecoveredSig),
+    /// `qsigsesann`
+    QSigSesAnn(Vec<message_signing::SigSessionAnnouncement>),
+    /// `qsigsinv`
+    QSigSharesInv(Vec<message_signing::SigSharesInv>),
+    /// `qgetsigs`
+    QGetSigShares(Vec<message_signing::SigSharesInv>),
+    /// `qbsigs`
+    QBSigShares(Vec<message_signing::BatchedSigShares>),
+    /// `qsigshare`
+    QSigShare(Vec<message_signing::SigShare>),
 
     /// Any other message.
     Unknown {
@@ -290,6 +363,40 @@ impl NetworkMessage {
             NetworkMessage::WtxidRelay => "wtxidrelay",
             NetworkMessage::AddrV2(_) => "addrv2",
             NetworkMessage::SendAddrV2 => "sendaddrv2",
+            NetworkMessage::MnAuth(_) => "mnauth",
+            NetworkMessage::QSendRecSigs(_) => "qsendrecsigs",
+            NetworkMessage::QWatch => "qwatch",
+            NetworkMessage::SendDsq(_) => "senddsq",
+            NetworkMessage::SendHeaders2 => "sendheaders2",
+            NetworkMessage::Spork(_) => "spork",
+            NetworkMessage::GetSporks => "getsporks",
+            NetworkMessage::GetHeaders2(_) => "getheaders2",
+            NetworkMessage::Headers2(_) => "headers2",
+            NetworkMessage::GetMnListD(_) => "getmnlistd",
+            NetworkMessage::MnListDiff(_) => "mnlistdiff",
+            NetworkMessage::GetQRInfo(_) => "getqrinfo",
+            NetworkMessage::QRInfo(_) => "qrinfo",
+            NetworkMessage::GovObject(_) => "govobj",
+            NetworkMessage::GovObjectVote(_) => "govobjvote",
+            NetworkMessage::GovSync(_) => "govsync",
+            NetworkMessage::CoinJoinAccept(_) => "dsa",
+            NetworkMessage::CoinJoinQueue(_) => "dsq",
+            NetworkMessage::CoinJoinEntry(_) => "dsi",
+            NetworkMessage::CoinJoinFinalTransaction(_) => "dsf",
+            NetworkMessage::CoinJoinSignedInputs(_) => "dss",
+            NetworkMessage::CoinJoinStatusUpdate(_) => "dssu",
+            NetworkMessage::CoinJoinComplete(_) => "dsc",
+            NetworkMessage::CoinJoinBroadcastTx(_) => "dstx",
+            NetworkMessage::QuorumContribution(_) => "qcontrib",
+            NetworkMessage::QuorumComplaint(_) => "qcomplaint",
+            NetworkMessage::QuorumJustification(_) => "qjustify",
+            NetworkMessage::QuorumPrematureCommitment(_) => "qpcommit",
+            NetworkMessage::QSigRec(_) => "qsigrec",
+            NetworkMessage::QSigSesAnn(_) => "qsigsesann",
+            NetworkMessage::QSigSharesInv(_) => "qsigsinv",
+            NetworkMessage::QGetSigShares(_) => "qgetsigs",
+            NetworkMessage::QBSigShares(_) => "qbsigs",
+            NetworkMessage::QSigShare(_) => "qsigshare",
             NetworkMessage::Unknown { .. } => "unknown",
         }
     }
@@ -365,13 +472,47 @@ impl Encodable for RawNetworkMessage {
             NetworkMessage::Reject(ref dat) => serialize(dat),
             NetworkMessage::FeeFilter(ref data) => serialize(data),
             NetworkMessage::AddrV2(ref dat) => serialize(dat),
+            NetworkMessage::MnAuth(ref dat) => serialize(dat),
+            NetworkMessage::QSendRecSigs(ref dat) => serialize(dat),
+            NetworkMessage::SendDsq(ref dat) => serialize(dat),
+            NetworkMessage::GetHeaders2(ref dat) => serialize(dat),
+            NetworkMessage::Headers2(ref dat) => serialize(dat),
+            NetworkMessage::GetMnListD(ref dat) => serialize(dat),
+            NetworkMessage::MnListDiff(ref dat) => serialize(dat.as_ref()),
+            NetworkMessage::GetQRInfo(ref dat) => serialize(dat),
+            NetworkMessage::QRInfo(ref dat) => serialize(dat.as_ref()),
+            NetworkMessage::GovObject(ref dat) => serialize(dat),
+            NetworkMessage::GovObjectVote(ref dat) => serialize(dat),
+            NetworkMessage::GovSync(ref dat) => serialize(dat),
+            NetworkMessage::Spork(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinAccept(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinQueue(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinEntry(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinFinalTransaction(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinSignedInputs(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinStatusUpdate(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinComplete(ref dat) => serialize(dat),
+            NetworkMessage::CoinJoinBroadcastTx(ref dat) => serialize(dat),
+            NetworkMessage::QuorumContribution(ref dat) => serialize(dat),
+            NetworkMessage::QuorumComplaint(ref dat) => serialize(dat),
+            NetworkMessage::QuorumJustification(ref dat) => serialize(dat),
+            NetworkMessage::QuorumPrematureCommitment(ref dat) => serialize(dat),
+            NetworkMessage::QSigRec(ref dat) => serialize(dat),
+            NetworkMessage::QSigSesAnn(ref dat) => serialize(dat),
+            NetworkMessage::QSigSharesInv(ref dat) => serialize(dat),
+            NetworkMessage::QGetSigShares(ref dat) => serialize(dat),
+            NetworkMessage::QBSigShares(ref dat) => serialize(dat),
+            NetworkMessage::QSigShare(ref dat) => serialize(dat),
             NetworkMessage::Verack
             | NetworkMessage::SendHeaders
             | NetworkMessage::MemPool
             | NetworkMessage::GetAddr
             | NetworkMessage::WtxidRelay
             | NetworkMessage::FilterClear
-            | NetworkMessage::SendAddrV2 => vec![],
+            | NetworkMessage::SendAddrV2
+            | NetworkMessage::QWatch
+            | NetworkMessage::SendHeaders2
+            | NetworkMessage::GetSporks => vec![],
             NetworkMessage::Unknown { payload: ref data, .. } => serialize(data),
         })
         .consensus_encode(w)?;
@@ -407,14 +548,15 @@ impl Decodable for HeaderDeserializationWrapper {
     }
 }
 
-impl Decodable for RawNetworkMessage {
-    fn consensus_decode_from_finite_reader<R: io::Read + ?Sized>(
-        r: &mut R,
+impl NetworkMessage {
+    /// Decodes the payload of a message with the given command.
+    ///
+    /// The payload must already have been checked against the checksum in the message header.
+    /// Unknown commands are returned as [NetworkMessage::Unknown].
+    pub fn consensus_decode_payload(
+        cmd: CommandString,
+        raw_payload: Vec<u8>,
     ) -> Result<Self, encode::Error> {
-        let magic = Decodable::consensus_decode_from_finite_reader(r)?;
-        let cmd = CommandString::consensus_decode_from_finite_reader(r)?;
-        let raw_payload = CheckedData::consensus_decode_from_finite_reader(r)?.0;
-
         let mut mem_d = io::Cursor::new(raw_payload);
         let payload = match &cmd.0[..] {
             "version" =>
@@ -498,8 +640,112 @@ impl Decodable for RawNetworkMessage {
             "addrv2" =>
                 NetworkMessage::AddrV2(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
             "sendaddrv2" => NetworkMessage::SendAddrV2,
+            "mnauth" =>
+                NetworkMessage::MnAuth(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "qsendrecsigs" => NetworkMessage::QSendRecSigs(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qwatch" => NetworkMessage::QWatch,
+            "senddsq" =>
+                NetworkMessage::SendDsq(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "sendheaders2" => NetworkMessage::SendHeaders2,
+            "spork" =>
+                NetworkMessage::Spork(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "getsporks" => NetworkMessage::GetSporks,
+            "getheaders2" => NetworkMessage::GetHeaders2(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "headers2" => NetworkMessage::Headers2(Decodable::consensus_decode_from_finite_reader(
+                &mut mem_d,
+            )?),
+            "getmnlistd" => NetworkMessage::GetMnListD(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "mnlistdiff" => NetworkMessage::MnListDiff(Box::new(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            )),
+            "getqrinfo" => NetworkMessage::GetQRInfo(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qrinfo" => NetworkMessage::QRInfo(Box::new(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            )),
+            "govobj" => NetworkMessage::GovObject(Decodable::consensus_decode_from_finite_reader(
+                &mut mem_d,
+            )?),
+            "govobjvote" => NetworkMessage::GovObjectVote(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "govsync" =>
+                NetworkMessage::GovSync(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "dsa" => NetworkMessage::CoinJoinAccept(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "dsq" => NetworkMessage::CoinJoinQueue(Decodable::consensus_decode_from_finite_reader(
+                &mut mem_d,
+            )?),
+            "dsi" => NetworkMessage::CoinJoinEntry(Decodable::consensus_decode_from_finite_reader(
+                &mut mem_d,
+            )?),
+            "dsf" => NetworkMessage::CoinJoinFinalTransaction(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "dss" => NetworkMessage::CoinJoinSignedInputs(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "dssu" => NetworkMessage::CoinJoinStatusUpdate(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "dsc" => NetworkMessage::CoinJoinComplete(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "dstx" => NetworkMessage::CoinJoinBroadcastTx(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qcontrib" => NetworkMessage::QuorumContribution(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qcomplaint" => NetworkMessage::QuorumComplaint(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qjustify" => NetworkMessage::QuorumJustification(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qpcommit" => NetworkMessage::QuorumPrematureCommitment(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qsigrec" =>
+                NetworkMessage::QSigRec(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "qsigsesann" => NetworkMessage::QSigSesAnn(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qsigsinv" => NetworkMessage::QSigSharesInv(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qgetsigs" => NetworkMessage::QGetSigShares(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qbsigs" => NetworkMessage::QBSigShares(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
+            "qsigshare" => NetworkMessage::QSigShare(
+                Decodable::consensus_decode_from_finite_reader(&mut mem_d)?,
+            ),
             _ => NetworkMessage::Unknown { command: cmd, payload: mem_d.into_inner() },
         };
+        Ok(payload)
+    }
+}
+
+impl Decodable for RawNetworkMessage {
+    fn consensus_decode_from_finite_reader<R: io::Read + ?Sized>(
+        r: &mut R,
+    ) -> Result<Self, encode::Error> {
+        let magic = Decodable::consensus_decode_from_finite_reader(r)?;
+        let cmd = CommandString::consensus_decode_from_finite_reader(r)?;
+        let raw_payload = CheckedData::consensus_decode_from_finite_reader(r)?.0;
+
+        let payload = NetworkMessage::consensus_decode_payload(cmd, raw_payload)?;
         Ok(RawNetworkMessage { magic, payload })
     }
 
@@ -524,16 +770,36 @@ mod test {
     use crate::blockdata::block::{self, Block};
     use crate::blockdata::script::ScriptBuf;
     use crate::blockdata::transaction::Transaction;
+    use crate::bls_sig_utils::{BLSPublicKey, BLSSecretKey, BLSSignature};
     use crate::consensus::encode::{deserialize, deserialize_partial, serialize};
+    use crate::ephemerealdata::spork::SporkId;
+    use crate::governance::{GovernanceObjectType, VoteOutcome, VoteSignal};
     use crate::internal_macros::hex;
     use crate::network::address::{AddrV2, AddrV2Message, Address};
     use crate::network::constants::ServiceFlags;
     use crate::network::message_blockdata::{GetBlocksMessage, GetHeadersMessage, Inventory};
     use crate::network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+    use crate::network::message_coinjoin::{
+        CoinJoinAccept, CoinJoinBroadcastTx, CoinJoinComplete, CoinJoinEntry,
+        CoinJoinFinalTransaction, CoinJoinQueue, CoinJoinStatusUpdate, PoolMessage, PoolState,
+        PoolStatusUpdate,
+    };
     use crate::network::message_compact_blocks::{GetBlockTxn, SendCmpct};
+    use crate::network::message_dkg::{
+        DKGComplaint, DKGContribution, DKGJustification, DKGPrematureCommitment,
+        EncryptedContributions,
+    };
     use crate::network::message_filter::{
         CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFHeaders, GetCFilters,
     };
+    use crate::network::message_governance::GovSync;
+    use crate::network::message_headers2::Headers2Message;
+    use crate::network::message_masternode::MnAuth;
+    use crate::network::message_qrinfo::GetQRInfo;
+    use crate::network::message_signing::{
+        BatchedSigShares, RecoveredSig, SigSessionAnnouncement, SigShare, SigSharesInv,
+    };
+    use crate::network::message_sml::GetMnListDiff;
 
     fn hash(slice: [u8; 32]) -> Hash { Hash::from_slice(&slice).unwrap() }
 
@@ -576,7 +842,7 @@ mod test {
                 hash_x11([50u8; 32]).into(),
             )),
             NetworkMessage::MemPool,
-            NetworkMessage::Tx(tx),
+            NetworkMessage::Tx(tx.clone()),
             NetworkMessage::Block(block),
             NetworkMessage::Headers(vec![header]),
             NetworkMessage::SendHeaders,
@@ -650,6 +916,170 @@ mod test {
             }),
             NetworkMessage::BlockTxn(blocktxn),
             NetworkMessage::SendCmpct(SendCmpct { send_compact: true, version: 8333 }),
+            NetworkMessage::MnAuth(MnAuth {
+                pro_reg_tx_hash: hash([7u8; 32]).into(),
+                signature: BLSSignature::from([9u8; 96]),
+            }),
+            NetworkMessage::QSendRecSigs(true),
+            NetworkMessage::QWatch,
+            NetworkMessage::SendDsq(false),
+            NetworkMessage::SendHeaders2,
+            NetworkMessage::GetSporks,
+            NetworkMessage::GetHeaders2(GetHeadersMessage::new(
+                vec![hash_x11([12u8; 32]).into()],
+                hash_x11([0u8; 32]).into(),
+            )),
+            NetworkMessage::Headers2(Headers2Message::compress(&[header, header])),
+            NetworkMessage::GetMnListD(GetMnListDiff {
+                base_block_hash: hash_x11([0u8; 32]).into(),
+                block_hash: hash_x11([13u8; 32]).into(),
+            }),
+            NetworkMessage::GetQRInfo(GetQRInfo {
+                base_block_hashes: vec![hash_x11([14u8; 32]).into()],
+                block_request_hash: hash_x11([15u8; 32]).into(),
+                extra_share: false,
+            }),
+            NetworkMessage::GovObject(GovernanceObject {
+                parent_hash: hash([0u8; 32]).into(),
+                revision: 1,
+                time: 1_700_000_000,
+                collateral_hash: hash([16u8; 32]).into(),
+                data: b"{}".to_vec(),
+                object_type: GovernanceObjectType::Proposal,
+                masternode_outpoint: crate::OutPoint::null(),
+                signature: vec![],
+            }),
+            NetworkMessage::GovObjectVote(GovernanceVote {
+                masternode_outpoint: crate::OutPoint { txid: hash([17u8; 32]).into(), vout: 1 },
+                parent_hash: hash([18u8; 32]).into(),
+                vote_outcome: VoteOutcome::Yes,
+                vote_signal: VoteSignal::Funding,
+                time: 1_700_000_000,
+                signature: vec![19u8; 65],
+            }),
+            NetworkMessage::Spork(SporkMessage {
+                spork_id: SporkId::ChainLocksEnabled,
+                value: 0,
+                time_signed: 1_700_000_000,
+                signature: vec![21u8; 65],
+            }),
+            NetworkMessage::CoinJoinAccept(CoinJoinAccept {
+                denomination: 1 << 2,
+                collateral_tx: tx.clone(),
+            }),
+            NetworkMessage::CoinJoinQueue(CoinJoinQueue {
+                denomination: 1 << 2,
+                pro_tx_hash: hash([22u8; 32]).into(),
+                time: 1_700_000_000,
+                ready: true,
+                signature: vec![23u8; 96],
+            }),
+            NetworkMessage::CoinJoinEntry(CoinJoinEntry {
+                inputs: tx.input.clone(),
+                collateral_tx: tx.clone(),
+                outputs: tx.output.clone(),
+            }),
+            NetworkMessage::CoinJoinFinalTransaction(CoinJoinFinalTransaction {
+                session_id: 24,
+                tx: tx.clone(),
+            }),
+            NetworkMessage::CoinJoinSignedInputs(tx.input.clone()),
+            NetworkMessage::CoinJoinStatusUpdate(CoinJoinStatusUpdate {
+                session_id: 24,
+                state: PoolState::Signing,
+                status_update: PoolStatusUpdate::Accepted,
+                message_id: PoolMessage::MsgNoErr,
+            }),
+            NetworkMessage::CoinJoinComplete(CoinJoinComplete {
+                session_id: 24,
+                message_id: PoolMessage::MsgSuccess,
+            }),
+            NetworkMessage::CoinJoinBroadcastTx(CoinJoinBroadcastTx {
+                tx: tx.clone(),
+                pro_tx_hash: hash([22u8; 32]).into(),
+                signature: vec![23u8; 96],
+                signature_time: 1_700_000_000,
+            }),
+            NetworkMessage::GovSync(GovSync {
+                prop_hash: hash([0u8; 32]).into(),
+                filter: FilterLoad {
+                    filter: vec![20u8; 8],
+                    hash_funcs: 1,
+                    tweak: 2,
+                    flags: BloomFlags::All,
+                },
+            }),
+            NetworkMessage::QuorumContribution(DKGContribution {
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                pro_tx_hash: hash([25u8; 32]).into(),
+                vvec: vec![BLSPublicKey::from([26u8; 48])],
+                contributions: EncryptedContributions {
+                    ephemeral_pub_key: BLSPublicKey::from([27u8; 48]),
+                    iv_seed: [28u8; 32],
+                    blobs: vec![vec![29u8; 48]; 3],
+                },
+                sig: BLSSignature::from([30u8; 96]),
+            }),
+            NetworkMessage::QuorumComplaint(DKGComplaint {
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                pro_tx_hash: hash([25u8; 32]).into(),
+                bad_members: vec![false, true, false],
+                complain_for_members: vec![true, false, false],
+                sig: BLSSignature::from([31u8; 96]),
+            }),
+            NetworkMessage::QuorumJustification(DKGJustification {
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                pro_tx_hash: hash([25u8; 32]).into(),
+                contributions: vec![(0, BLSSecretKey::from([32u8; 32]))],
+                sig: BLSSignature::from([33u8; 96]),
+            }),
+            NetworkMessage::QuorumPrematureCommitment(DKGPrematureCommitment {
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                pro_tx_hash: hash([25u8; 32]).into(),
+                valid_members: vec![true, true, false],
+                quorum_public_key: BLSPublicKey::from([34u8; 48]),
+                quorum_vvec_hash: hash([35u8; 32]).into(),
+                quorum_sig: BLSSignature::from([36u8; 96]),
+                sig: BLSSignature::from([37u8; 96]),
+            }),
+            NetworkMessage::QSigRec(RecoveredSig {
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                id: hash([38u8; 32]).into(),
+                msg_hash: hash([39u8; 32]),
+                sig: BLSSignature::from([40u8; 96]),
+            }),
+            NetworkMessage::QSigSesAnn(vec![SigSessionAnnouncement {
+                session_id: 1_000,
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                id: hash([38u8; 32]).into(),
+                msg_hash: hash([39u8; 32]),
+            }]),
+            NetworkMessage::QSigSharesInv(vec![SigSharesInv {
+                session_id: 1_000,
+                inv: vec![false, true, false],
+            }]),
+            NetworkMessage::QGetSigShares(vec![SigSharesInv {
+                session_id: 1_000,
+                inv: vec![true, true, true],
+            }]),
+            NetworkMessage::QBSigShares(vec![BatchedSigShares {
+                session_id: 1_000,
+                sig_shares: vec![(1, BLSSignature::from([41u8; 96]))],
+            }]),
+            NetworkMessage::QSigShare(vec![SigShare {
+                llmq_type: 100,
+                quorum_hash: hash([24u8; 32]).into(),
+                quorum_member: 1,
+                id: hash([38u8; 32]).into(),
+                msg_hash: hash([39u8; 32]),
+                sig_share: BLSSignature::from([42u8; 96]),
+            }]),
         ];
 
         for msg in msgs {
@@ -659,6 +1089,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn masternode_control_messages_test() {
+        let mnauth = MnAuth {
+            pro_reg_tx_hash: hash([7u8; 32]).into(),
+            signature: BLSSignature::from([9u8; 96]),
+        };
+        let msgs = vec![
+            (NetworkMessage::MnAuth(mnauth.clone()), "mnauth", serialize(&mnauth)),
+            (NetworkMessage::QSendRecSigs(true), "qsendrecsigs", vec![1]),
+            (NetworkMessage::QWatch, "qwatch", vec![]),
+            (NetworkMessage::SendDsq(false), "senddsq", vec![0]),
+            (NetworkMessage::SendHeaders2, "sendheaders2", vec![]),
+            (NetworkMessage::GetSporks, "getsporks", vec![]),
+        ];
+
+        for (msg, cmd, payload) in msgs {
+            assert_eq!(msg.cmd(), cmd);
+            let raw_msg = RawNetworkMessage { magic: 0xbd6b0cbf, payload: msg };
+            let encoded = serialize(&raw_msg);
+            assert_eq!(&encoded[24..], &payload[..]);
+            assert_eq!(deserialize::<RawNetworkMessage>(&encoded).unwrap(), raw_msg);
+        }
+    }
+
     #[test]
     fn commandstring_test() {
         // Test converting.
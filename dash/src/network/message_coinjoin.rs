@@ -0,0 +1,448 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash CoinJoin network messages.
+//!
+//! A mixing session runs between a client and a masternode: the client asks to join with `dsa`,
+//! masternodes advertise sessions with `dsq`, each participant submits its inputs and outputs
+//! with `dsi`, the masternode sends the merged transaction with `dsf`, participants return
+//! their signatures with `dss`, and the masternode broadcasts the result as `dstx`. `dssu` and
+//! `dsc` report the session's progress.
+//!
+
+use hashes::{Hash, sha256d};
+
+use crate::blockdata::transaction::Transaction;
+use crate::blockdata::transaction::txin::TxIn;
+use crate::blockdata::transaction::txout::TxOut;
+#[cfg(feature = "bls")]
+use crate::bls_sig_utils::{BLSPublicKey, BLSSignature};
+use crate::consensus::{Decodable, Encodable, encode};
+use crate::hash_types::ProTxHash;
+use crate::internal_macros::impl_consensus_encoding;
+use crate::io;
+use crate::prelude::*;
+
+/// How long a `dsq` queue entry stays valid around its timestamp, in seconds.
+pub const COINJOIN_QUEUE_TIMEOUT: i64 = 30;
+
+macro_rules! coinjoin_enum {
+    ($(#[$attr:meta])* $name:ident { $($(#[$vattr:meta])* $variant:ident = $value:literal,)* }) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($(#[$vattr])* $variant,)*
+            /// A value this library does not know about
+            Unknown(i32),
+        }
+
+        impl $name {
+            /// Returns the numeric value used on the wire.
+            pub fn to_i32(self) -> i32 {
+                match self {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(value) => value,
+                }
+            }
+
+            /// Parses the numeric value used on the wire.
+            pub fn from_i32(value: i32) -> $name {
+                match value {
+                    $($value => $name::$variant,)*
+                    value => $name::Unknown(value),
+                }
+            }
+        }
+
+        impl Encodable for $name {
+            fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+                self.to_i32().consensus_encode(w)
+            }
+        }
+
+        impl Decodable for $name {
+            fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+                Ok($name::from_i32(i32::consensus_decode(r)?))
+            }
+        }
+    };
+}
+
+coinjoin_enum! {
+    /// The state of a mixing session
+    PoolState {
+        /// No session
+        Idle = 0,
+        /// Waiting for participants
+        Queue = 1,
+        /// Collecting the participants' inputs and outputs
+        AcceptingEntries = 2,
+        /// Collecting the participants' signatures
+        Signing = 3,
+        /// The session failed
+        Error = 4,
+    }
+}
+
+coinjoin_enum! {
+    /// Whether the masternode accepted the client's last request
+    PoolStatusUpdate {
+        /// The request was rejected
+        Rejected = 0,
+        /// The request was accepted
+        Accepted = 1,
+    }
+}
+
+coinjoin_enum! {
+    /// A status or error reported by the masternode
+    PoolMessage {
+        /// The masternode already has the entry
+        ErrAlreadyHave = 0,
+        /// The denomination is invalid or does not match the session
+        ErrDenom = 1,
+        /// The session is full
+        ErrEntriesFull = 2,
+        /// An input is already spent
+        ErrExistingTx = 3,
+        /// The entry pays fees
+        ErrFees = 4,
+        /// The collateral transaction is invalid
+        ErrInvalidCollateral = 5,
+        /// An input is invalid
+        ErrInvalidInput = 6,
+        /// A script is invalid
+        ErrInvalidScript = 7,
+        /// The transaction is invalid
+        ErrInvalidTx = 8,
+        /// The entry has too many inputs or outputs
+        ErrMaximum = 9,
+        /// The masternode is not in the masternode list
+        ErrMnList = 10,
+        /// The masternode is not in the required mode
+        ErrMode = 11,
+        /// The queue is full
+        ErrQueueFull = 14,
+        /// The masternode mixed too recently
+        ErrRecent = 15,
+        /// The session is invalid
+        ErrSession = 16,
+        /// An input is missing
+        ErrMissingTx = 17,
+        /// The client's protocol version is too old
+        ErrVersion = 18,
+        /// No error
+        MsgNoErr = 19,
+        /// The session completed
+        MsgSuccess = 20,
+        /// The entries were added to the session
+        MsgEntriesAdded = 21,
+        /// The entry's inputs and outputs differ in number
+        ErrSizeMismatch = 22,
+    }
+}
+
+/// `dsa` message
+///
+/// Asks a masternode to start or join a mixing session for `denomination`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinAccept {
+    /// Denomination bit of the session
+    pub denomination: u32,
+    /// Signed transaction the masternode may broadcast if the client misbehaves
+    pub collateral_tx: Transaction,
+}
+impl_consensus_encoding!(CoinJoinAccept, denomination, collateral_tx);
+
+/// `dsq` message
+///
+/// A mixing session advertised by a masternode and signed with its operator key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinQueue {
+    /// Denomination bit of the session
+    pub denomination: u32,
+    /// Hash of the masternode's `ProRegTx`
+    pub pro_tx_hash: ProTxHash,
+    /// Time the entry was created, in seconds since the epoch
+    pub time: i64,
+    /// Whether the session is full and about to start
+    pub ready: bool,
+    /// Operator key signature over [`CoinJoinQueue::signature_hash`]
+    pub signature: Vec<u8>,
+}
+impl_consensus_encoding!(CoinJoinQueue, denomination, pro_tx_hash, time, ready, signature);
+
+impl CoinJoinQueue {
+    /// Computes the hash signed by the masternode: the serialized entry without its signature.
+    pub fn signature_hash(&self) -> sha256d::Hash {
+        let mut engine = sha256d::Hash::engine();
+        self.denomination.consensus_encode(&mut engine).expect("engines don't error");
+        self.pro_tx_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.time.consensus_encode(&mut engine).expect("engines don't error");
+        self.ready.consensus_encode(&mut engine).expect("engines don't error");
+        sha256d::Hash::from_engine(engine)
+    }
+
+    /// Returns whether the entry is too far from `now` to be used.
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.time.abs_diff(now) > COINJOIN_QUEUE_TIMEOUT as u64
+    }
+
+    /// Verifies the entry was signed by `operator_key`.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, operator_key: &BLSPublicKey) -> bool {
+        verify_bls(&self.signature, operator_key, self.signature_hash())
+    }
+
+    /// Signs the entry with the masternode's operator key, replacing any previous signature.
+    #[cfg(feature = "bls")]
+    pub fn sign(
+        &mut self,
+        operator_key: &blsful::SecretKey<blsful::Bls12381G2Impl>,
+    ) -> Result<(), blsful::BlsError> {
+        self.signature = sign_bls(operator_key, self.signature_hash())?;
+        Ok(())
+    }
+}
+
+/// `dsi` message
+///
+/// A participant's contribution to a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinEntry {
+    /// Denominated inputs, unsigned
+    pub inputs: Vec<TxIn>,
+    /// Signed transaction the masternode may broadcast if the client misbehaves
+    pub collateral_tx: Transaction,
+    /// Denominated outputs paying the participant
+    pub outputs: Vec<TxOut>,
+}
+impl_consensus_encoding!(CoinJoinEntry, inputs, collateral_tx, outputs);
+
+/// `dsf` message
+///
+/// The merged transaction, for every participant to check and sign.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinFinalTransaction {
+    /// Session identifier
+    pub session_id: i32,
+    /// The unsigned mixing transaction
+    pub tx: Transaction,
+}
+impl_consensus_encoding!(CoinJoinFinalTransaction, session_id, tx);
+
+/// `dssu` message
+///
+/// Reports the state of a session to a participant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinStatusUpdate {
+    /// Session identifier
+    pub session_id: i32,
+    /// State of the session
+    pub state: PoolState,
+    /// Whether the participant's last request was accepted
+    pub status_update: PoolStatusUpdate,
+    /// Reason for the status
+    pub message_id: PoolMessage,
+}
+impl_consensus_encoding!(CoinJoinStatusUpdate, session_id, state, status_update, message_id);
+
+/// `dsc` message
+///
+/// Tells a participant the session ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinComplete {
+    /// Session identifier
+    pub session_id: i32,
+    /// Outcome of the session
+    pub message_id: PoolMessage,
+}
+impl_consensus_encoding!(CoinJoinComplete, session_id, message_id);
+
+/// `dstx` message
+///
+/// A completed mixing transaction, relayed with the operator signature of the masternode that
+/// coordinated it so that it is not mistaken for spam.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinJoinBroadcastTx {
+    /// The signed mixing transaction
+    pub tx: Transaction,
+    /// Hash of the coordinating masternode's `ProRegTx`
+    pub pro_tx_hash: ProTxHash,
+    /// Operator key signature over [`CoinJoinBroadcastTx::signature_hash`]
+    pub signature: Vec<u8>,
+    /// Time the transaction was signed, in seconds since the epoch
+    pub signature_time: i64,
+}
+impl_consensus_encoding!(CoinJoinBroadcastTx, tx, pro_tx_hash, signature, signature_time);
+
+impl CoinJoinBroadcastTx {
+    /// Computes the hash signed by the masternode: the serialized message without its
+    /// signature.
+    pub fn signature_hash(&self) -> sha256d::Hash {
+        let mut engine = sha256d::Hash::engine();
+        self.tx.consensus_encode(&mut engine).expect("engines don't error");
+        self.pro_tx_hash.consensus_encode(&mut engine).expect("engines don't error");
+        self.signature_time.consensus_encode(&mut engine).expect("engines don't error");
+        sha256d::Hash::from_engine(engine)
+    }
+
+    /// Verifies the message was signed by `operator_key`.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, operator_key: &BLSPublicKey) -> bool {
+        verify_bls(&self.signature, operator_key, self.signature_hash())
+    }
+
+    /// Signs the message with the masternode's operator key, replacing any previous signature.
+    #[cfg(feature = "bls")]
+    pub fn sign(
+        &mut self,
+        operator_key: &blsful::SecretKey<blsful::Bls12381G2Impl>,
+    ) -> Result<(), blsful::BlsError> {
+        self.signature = sign_bls(operator_key, self.signature_hash())?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bls")]
+fn verify_bls(signature: &[u8], operator_key: &BLSPublicKey, hash: sha256d::Hash) -> bool {
+    match <[u8; 96]>::try_from(signature) {
+        Ok(bytes) => BLSSignature::from(bytes).verify(operator_key, hash.as_byte_array()),
+        Err(_) => false,
+    }
+}
+
+#[cfg(feature = "bls")]
+fn sign_bls(
+    operator_key: &blsful::SecretKey<blsful::Bls12381G2Impl>,
+    hash: sha256d::Hash,
+) -> Result<Vec<u8>, blsful::BlsError> {
+    let signature = operator_key.sign(blsful::SignatureSchemes::Basic, hash.as_byte_array())?;
+    Ok(BLSSignature::from(&signature).to_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScriptBuf;
+    use crate::blockdata::transaction::OutPoint;
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::hash_types::Txid;
+
+    fn tx() -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: Txid::hash(&[1]), vout: 0 },
+                ..Default::default()
+            }],
+            output: vec![TxOut { value: 100_001, script_pubkey: ScriptBuf::new() }],
+            special_transaction_payload: None,
+        }
+    }
+
+    fn queue() -> CoinJoinQueue {
+        CoinJoinQueue {
+            denomination: 1 << 4,
+            pro_tx_hash: ProTxHash::hash(&[2]),
+            time: 1_700_000_000,
+            ready: false,
+            signature: vec![],
+        }
+    }
+
+    #[test]
+    fn message_encoding() {
+        let queue = CoinJoinQueue { signature: vec![3; 96], ..queue() };
+        assert_eq!(serialize(&queue).len(), 4 + 32 + 8 + 1 + 97);
+        assert_eq!(deserialize::<CoinJoinQueue>(&serialize(&queue)).unwrap(), queue);
+
+        let status = CoinJoinStatusUpdate {
+            session_id: 7,
+            state: PoolState::AcceptingEntries,
+            status_update: PoolStatusUpdate::Accepted,
+            message_id: PoolMessage::MsgEntriesAdded,
+        };
+        let encoded = serialize(&status);
+        assert_eq!(encoded, [7, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 21, 0, 0, 0]);
+        assert_eq!(deserialize::<CoinJoinStatusUpdate>(&encoded).unwrap(), status);
+
+        let complete = CoinJoinComplete { session_id: 7, message_id: PoolMessage::Unknown(99) };
+        assert_eq!(deserialize::<CoinJoinComplete>(&serialize(&complete)).unwrap(), complete);
+
+        let entry = CoinJoinEntry { inputs: tx().input, collateral_tx: tx(), outputs: tx().output };
+        assert_eq!(deserialize::<CoinJoinEntry>(&serialize(&entry)).unwrap(), entry);
+    }
+
+    #[test]
+    fn queue_expiry() {
+        let queue = queue();
+        assert!(!queue.is_expired(queue.time + COINJOIN_QUEUE_TIMEOUT));
+        assert!(!queue.is_expired(queue.time - COINJOIN_QUEUE_TIMEOUT));
+        assert!(queue.is_expired(queue.time + COINJOIN_QUEUE_TIMEOUT + 1));
+        assert!(queue.is_expired(queue.time - COINJOIN_QUEUE_TIMEOUT - 1));
+    }
+
+    #[test]
+    fn signature_hashes_exclude_signature() {
+        let queue = queue();
+        let signed = CoinJoinQueue { signature: vec![3; 96], ..queue.clone() };
+        assert_eq!(queue.signature_hash(), signed.signature_hash());
+
+        let dstx = CoinJoinBroadcastTx {
+            tx: tx(),
+            pro_tx_hash: ProTxHash::hash(&[2]),
+            signature: vec![],
+            signature_time: 1_700_000_000,
+        };
+        let signed = CoinJoinBroadcastTx { signature: vec![3; 96], ..dstx.clone() };
+        assert_eq!(dstx.signature_hash(), signed.signature_hash());
+        assert_ne!(
+            dstx.signature_hash(),
+            CoinJoinBroadcastTx { signature_time: 0, ..dstx.clone() }.signature_hash()
+        );
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn sign_and_verify() {
+        use blsful::{Bls12381G2Impl, SecretKey};
+
+        let secret = SecretKey::<Bls12381G2Impl>::from_hash(b"coinjoin operator");
+        let operator_key = BLSPublicKey::from(&secret.public_key());
+
+        let mut queue = queue();
+        queue.sign(&secret).unwrap();
+        assert!(queue.verify(&operator_key));
+        queue.ready = true;
+        assert!(!queue.verify(&operator_key));
+
+        let mut dstx = CoinJoinBroadcastTx {
+            tx: tx(),
+            pro_tx_hash: ProTxHash::hash(&[2]),
+            signature: vec![],
+            signature_time: 1_700_000_000,
+        };
+        dstx.sign(&secret).unwrap();
+        assert!(dstx.verify(&operator_key));
+        assert!(
+            !CoinJoinBroadcastTx { signature: vec![3; 96], ..dstx.clone() }.verify(&operator_key)
+        );
+        let other = BLSPublicKey::from(
+            &SecretKey::<Bls12381G2Impl>::from_hash(b"other operator").public_key(),
+        );
+        assert!(!dstx.verify(&other));
+    }
+}
@@ -0,0 +1,441 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash LLMQ DKG session messages.
+//!
+//! These are the messages quorum members exchange during the distributed key generation
+//! phases of [DIP6](https://github.com/dashpay/dips/blob/master/dip-0006.md): `qcontrib`
+//! (contribution), `qcomplaint` (complaining), `qjustify` (justification) and `qpcommit`
+//! (commitment). Members aggregate matching premature commitments into the
+//! [`QuorumFinalizationCommitment`] that is eventually mined.
+//!
+
+use hashes::Hash;
+
+use crate::blockdata::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+use crate::bls_sig_utils::{BLSPublicKey, BLSSecretKey, BLSSignature};
+use crate::consensus::encode::{
+    self, Decodable, Encodable, read_compact_size, read_fixed_bitset, write_compact_size,
+    write_fixed_bitset,
+};
+use crate::hash_types::{
+    DKGMessageSignHash, ProTxHash, QuorumCommitmentHash, QuorumHash, QuorumVVecHash,
+};
+use crate::internal_macros::impl_consensus_encoding;
+use crate::io;
+use crate::prelude::*;
+
+/// Secret key shares encrypted to each quorum member with BLS-IES.
+///
+/// Each blob is the AES-256-CBC encryption of the share for the member at that index, keyed by a
+/// Diffie-Hellman secret between `ephemeral_pub_key` and the member's operator key, with an IV
+/// derived from `iv_seed`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EncryptedContributions {
+    /// The ephemeral public key the shares are encrypted with
+    pub ephemeral_pub_key: BLSPublicKey,
+    /// Seed the per-member IVs are derived from
+    pub iv_seed: [u8; 32],
+    /// One encrypted secret key share per quorum member
+    pub blobs: Vec<Vec<u8>>,
+}
+impl_consensus_encoding!(EncryptedContributions, ephemeral_pub_key, iv_seed, blobs);
+
+/// `qcontrib` message
+///
+/// A member's verification vector, the public commitment to its secret polynomial, together with
+/// the secret key shares it computed for every member.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DKGContribution {
+    /// The type of the quorum being formed
+    pub llmq_type: u8,
+    /// The hash of the block the quorum is formed at
+    pub quorum_hash: QuorumHash,
+    /// The member sending the contribution
+    pub pro_tx_hash: ProTxHash,
+    /// The verification vector, one public key per threshold coefficient
+    pub vvec: Vec<BLSPublicKey>,
+    /// The encrypted secret key shares
+    pub contributions: EncryptedContributions,
+    /// Signature by the member's operator key over [`DKGContribution::sign_hash`]
+    pub sig: BLSSignature,
+}
+impl_consensus_encoding!(
+    DKGContribution,
+    llmq_type,
+    quorum_hash,
+    pro_tx_hash,
+    vvec,
+    contributions,
+    sig
+);
+
+/// `qcomplaint` message
+///
+/// Lists the members whose contributions the sender considers bad, and the members it
+/// complains about because their secret key share did not match their verification vector.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DKGComplaint {
+    /// The type of the quorum being formed
+    pub llmq_type: u8,
+    /// The hash of the block the quorum is formed at
+    pub quorum_hash: QuorumHash,
+    /// The member sending the complaint
+    pub pro_tx_hash: ProTxHash,
+    /// Members that sent no or invalid contributions
+    pub bad_members: Vec<bool>,
+    /// Members whose secret key share for the sender is invalid
+    pub complain_for_members: Vec<bool>,
+    /// Signature by the member's operator key over [`DKGComplaint::sign_hash`]
+    pub sig: BLSSignature,
+}
+
+/// `qjustify` message
+///
+/// Answers complaints by revealing, in plain text, the secret key shares the sender gave to the
+/// complaining members, identified by their index in the quorum.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DKGJustification {
+    /// The type of the quorum being formed
+    pub llmq_type: u8,
+    /// The hash of the block the quorum is formed at
+    pub quorum_hash: QuorumHash,
+    /// The member sending the justification
+    pub pro_tx_hash: ProTxHash,
+    /// The revealed secret key shares, by member index
+    pub contributions: Vec<(u32, BLSSecretKey)>,
+    /// Signature by the member's operator key over [`DKGJustification::sign_hash`]
+    pub sig: BLSSignature,
+}
+impl_consensus_encoding!(DKGJustification, llmq_type, quorum_hash, pro_tx_hash, contributions, sig);
+
+/// `qpcommit` message
+///
+/// A member's view of the DKG outcome: which members are valid, and the quorum public key and
+/// verification vector hash that result from their contributions.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DKGPrematureCommitment {
+    /// The type of the quorum being formed
+    pub llmq_type: u8,
+    /// The hash of the block the quorum is formed at
+    pub quorum_hash: QuorumHash,
+    /// The member sending the commitment
+    pub pro_tx_hash: ProTxHash,
+    /// Members whose contributions were accepted
+    pub valid_members: Vec<bool>,
+    /// The resulting quorum public key
+    pub quorum_public_key: BLSPublicKey,
+    /// The hash of the resulting quorum verification vector
+    pub quorum_vvec_hash: QuorumVVecHash,
+    /// Signature share over the commitment hash by the member's quorum secret key share
+    pub quorum_sig: BLSSignature,
+    /// Signature by the member's operator key over the commitment hash
+    pub sig: BLSSignature,
+}
+
+fn encode_dyn_bitset<W: io::Write + ?Sized>(w: &mut W, bits: &[bool]) -> Result<usize, io::Error> {
+    Ok(write_compact_size(w, bits.len() as u32)? + write_fixed_bitset(w, bits, bits.len())?)
+}
+
+fn decode_dyn_bitset<R: io::Read + ?Sized>(r: &mut R) -> Result<Vec<bool>, encode::Error> {
+    let size = read_compact_size(r)?;
+    Ok(read_fixed_bitset(r, size as usize)?)
+}
+
+impl Encodable for DKGComplaint {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.llmq_type.consensus_encode(w)?;
+        len += self.quorum_hash.consensus_encode(w)?;
+        len += self.pro_tx_hash.consensus_encode(w)?;
+        len += encode_dyn_bitset(w, &self.bad_members)?;
+        len += encode_dyn_bitset(w, &self.complain_for_members)?;
+        len += self.sig.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for DKGComplaint {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        Ok(DKGComplaint {
+            llmq_type: Decodable::consensus_decode(r)?,
+            quorum_hash: Decodable::consensus_decode(r)?,
+            pro_tx_hash: Decodable::consensus_decode(r)?,
+            bad_members: decode_dyn_bitset(r)?,
+            complain_for_members: decode_dyn_bitset(r)?,
+            sig: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+impl Encodable for DKGPrematureCommitment {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.llmq_type.consensus_encode(w)?;
+        len += self.quorum_hash.consensus_encode(w)?;
+        len += self.pro_tx_hash.consensus_encode(w)?;
+        len += encode_dyn_bitset(w, &self.valid_members)?;
+        len += self.quorum_public_key.consensus_encode(w)?;
+        len += self.quorum_vvec_hash.consensus_encode(w)?;
+        len += self.quorum_sig.consensus_encode(w)?;
+        len += self.sig.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for DKGPrematureCommitment {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        Ok(DKGPrematureCommitment {
+            llmq_type: Decodable::consensus_decode(r)?,
+            quorum_hash: Decodable::consensus_decode(r)?,
+            pro_tx_hash: Decodable::consensus_decode(r)?,
+            valid_members: decode_dyn_bitset(r)?,
+            quorum_public_key: Decodable::consensus_decode(r)?,
+            quorum_vvec_hash: Decodable::consensus_decode(r)?,
+            quorum_sig: Decodable::consensus_decode(r)?,
+            sig: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+/// Hashes `message` with its signature zeroed out, which is what DKG members sign.
+fn unsigned_message_hash<T: Encodable + Clone>(
+    message: &T,
+    clear_sig: impl FnOnce(&mut T),
+) -> DKGMessageSignHash {
+    let mut unsigned = message.clone();
+    clear_sig(&mut unsigned);
+    let mut engine = DKGMessageSignHash::engine();
+    unsigned.consensus_encode(&mut engine).expect("engines don't error");
+    DKGMessageSignHash::from_engine(engine)
+}
+
+impl DKGContribution {
+    /// The hash signed by `sig`: the contribution serialized with an all-zero signature.
+    pub fn sign_hash(&self) -> DKGMessageSignHash {
+        unsigned_message_hash(self, |c| c.sig = BLSSignature::from([0; 96]))
+    }
+
+    /// Verifies `sig` against the sender's operator key.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, operator_public_key: &BLSPublicKey) -> bool {
+        self.sig.verify(operator_public_key, self.sign_hash().as_byte_array())
+    }
+}
+
+impl DKGComplaint {
+    /// The hash signed by `sig`: the complaint serialized with an all-zero signature.
+    pub fn sign_hash(&self) -> DKGMessageSignHash {
+        unsigned_message_hash(self, |c| c.sig = BLSSignature::from([0; 96]))
+    }
+
+    /// Verifies `sig` against the sender's operator key.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, operator_public_key: &BLSPublicKey) -> bool {
+        self.sig.verify(operator_public_key, self.sign_hash().as_byte_array())
+    }
+}
+
+impl DKGJustification {
+    /// The hash signed by `sig`: the justification serialized with an all-zero signature.
+    pub fn sign_hash(&self) -> DKGMessageSignHash {
+        unsigned_message_hash(self, |j| j.sig = BLSSignature::from([0; 96]))
+    }
+
+    /// Verifies `sig` against the sender's operator key.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, operator_public_key: &BLSPublicKey) -> bool {
+        self.sig.verify(operator_public_key, self.sign_hash().as_byte_array())
+    }
+}
+
+impl DKGPrematureCommitment {
+    /// The commitment hash signed by both `quorum_sig` and `sig`.
+    ///
+    /// See [`crate::llmq::build_commitment_hash`].
+    pub fn commitment_hash(&self) -> QuorumCommitmentHash {
+        crate::llmq::build_commitment_hash(
+            self.llmq_type,
+            &self.quorum_hash,
+            &self.valid_members,
+            &self.quorum_public_key,
+            &self.quorum_vvec_hash,
+        )
+    }
+
+    /// Returns whether this premature commitment agrees with the outcome recorded in the final
+    /// `commitment`, i.e. whether it could have contributed to it.
+    pub fn matches(&self, commitment: &QuorumFinalizationCommitment) -> bool {
+        self.llmq_type == commitment.llmq_type
+            && self.quorum_hash == commitment.quorum_hash
+            && self.valid_members == commitment.valid_members
+            && self.quorum_public_key == commitment.quorum_public_key
+            && self.quorum_vvec_hash == commitment.quorum_vvec_hash
+    }
+
+    /// Verifies `sig` against the sender's operator key.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, operator_public_key: &BLSPublicKey) -> bool {
+        self.sig.verify(operator_public_key, self.commitment_hash().as_byte_array())
+    }
+
+    /// Verifies `quorum_sig` against the sender's public key share, as computed from the
+    /// verification vectors of the valid members.
+    #[cfg(feature = "bls")]
+    pub fn verify_quorum_sig(&self, public_key_share: &BLSPublicKey) -> bool {
+        self.quorum_sig.verify(public_key_share, self.commitment_hash().as_byte_array())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{deserialize, serialize};
+
+    fn contribution() -> DKGContribution {
+        DKGContribution {
+            llmq_type: 100,
+            quorum_hash: QuorumHash::hash(b"quorum"),
+            pro_tx_hash: ProTxHash::hash(b"member"),
+            vvec: vec![BLSPublicKey::from([1; 48]), BLSPublicKey::from([2; 48])],
+            contributions: EncryptedContributions {
+                ephemeral_pub_key: BLSPublicKey::from([3; 48]),
+                iv_seed: [4; 32],
+                blobs: vec![vec![5; 48], vec![6; 48], vec![7; 48]],
+            },
+            sig: BLSSignature::from([8; 96]),
+        }
+    }
+
+    fn premature_commitment() -> DKGPrematureCommitment {
+        DKGPrematureCommitment {
+            llmq_type: 100,
+            quorum_hash: QuorumHash::hash(b"quorum"),
+            pro_tx_hash: ProTxHash::hash(b"member"),
+            valid_members: vec![true, true, false],
+            quorum_public_key: BLSPublicKey::from([1; 48]),
+            quorum_vvec_hash: QuorumVVecHash::hash(b"vvec"),
+            quorum_sig: BLSSignature::from([2; 96]),
+            sig: BLSSignature::from([3; 96]),
+        }
+    }
+
+    #[test]
+    fn contribution_encoding() {
+        let contribution = contribution();
+        let bytes = serialize(&contribution);
+        assert_eq!(bytes.len(), 1 + 32 + 32 + 1 + 2 * 48 + 48 + 32 + 1 + 3 * 49 + 96);
+        assert_eq!(bytes[0], 100);
+        assert_eq!(bytes[65], 2);
+        assert_eq!(deserialize::<DKGContribution>(&bytes).unwrap(), contribution);
+
+        // The sign hash covers everything but the signature.
+        let mut unsigned = serialize(&contribution);
+        let sig_start = unsigned.len() - 96;
+        unsigned[sig_start..].fill(0);
+        assert_eq!(contribution.sign_hash(), DKGMessageSignHash::hash(&unsigned));
+        let mut resigned = contribution.clone();
+        resigned.sig = BLSSignature::from([9; 96]);
+        assert_eq!(resigned.sign_hash(), contribution.sign_hash());
+        resigned.vvec.pop();
+        assert_ne!(resigned.sign_hash(), contribution.sign_hash());
+    }
+
+    #[test]
+    fn complaint_and_justification_encoding() {
+        let complaint = DKGComplaint {
+            llmq_type: 100,
+            quorum_hash: QuorumHash::hash(b"quorum"),
+            pro_tx_hash: ProTxHash::hash(b"member"),
+            bad_members: vec![false, true, false],
+            complain_for_members: vec![false, false, true],
+            sig: BLSSignature::from([1; 96]),
+        };
+        let bytes = serialize(&complaint);
+        assert_eq!(&bytes[65..69], &[3, 0b010, 3, 0b100]);
+        assert_eq!(deserialize::<DKGComplaint>(&bytes).unwrap(), complaint);
+        let mut unsigned = bytes.clone();
+        unsigned[69..].fill(0);
+        assert_eq!(complaint.sign_hash(), DKGMessageSignHash::hash(&unsigned));
+
+        let justification = DKGJustification {
+            llmq_type: 100,
+            quorum_hash: QuorumHash::hash(b"quorum"),
+            pro_tx_hash: ProTxHash::hash(b"member"),
+            contributions: vec![(2, BLSSecretKey::from([7; 32]))],
+            sig: BLSSignature::from([1; 96]),
+        };
+        let bytes = serialize(&justification);
+        assert_eq!(&bytes[65..70], &[1, 2, 0, 0, 0]);
+        assert_eq!(bytes.len(), 65 + 1 + 36 + 96);
+        assert_eq!(deserialize::<DKGJustification>(&bytes).unwrap(), justification);
+        let mut unsigned = bytes.clone();
+        unsigned[102..].fill(0);
+        assert_eq!(justification.sign_hash(), DKGMessageSignHash::hash(&unsigned));
+    }
+
+    #[test]
+    fn premature_commitment_links_to_final_commitment() {
+        let premature = premature_commitment();
+        let bytes = serialize(&premature);
+        assert_eq!(bytes.len(), 65 + 2 + 48 + 32 + 96 + 96);
+        assert_eq!(deserialize::<DKGPrematureCommitment>(&bytes).unwrap(), premature);
+
+        let mut commitment = QuorumFinalizationCommitment {
+            version: 1,
+            llmq_type: premature.llmq_type,
+            quorum_hash: premature.quorum_hash,
+            quorum_index: None,
+            signers: vec![true, true, false],
+            valid_members: premature.valid_members.clone(),
+            quorum_public_key: premature.quorum_public_key,
+            quorum_vvec_hash: premature.quorum_vvec_hash,
+            quorum_sig: BLSSignature::from([4; 96]),
+            sig: BLSSignature::from([5; 96]),
+        };
+        assert!(premature.matches(&commitment));
+        assert_eq!(premature.commitment_hash(), commitment.commitment_hash());
+
+        commitment.valid_members[2] = true;
+        assert!(!premature.matches(&commitment));
+        assert_ne!(premature.commitment_hash(), commitment.commitment_hash());
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn verify_signatures() {
+        use blsful::{Bls12381G2Impl, SecretKey, SignatureSchemes};
+
+        let operator_key = SecretKey::<Bls12381G2Impl>::from_hash(b"operator");
+        let operator_public_key = BLSPublicKey::from(&operator_key.public_key());
+        let sign = |hash: &[u8; 32]| {
+            BLSSignature::from(&operator_key.sign(SignatureSchemes::Basic, hash).unwrap())
+        };
+
+        let mut contribution = contribution();
+        contribution.sig = sign(contribution.sign_hash().as_byte_array());
+        assert!(contribution.verify(&operator_public_key));
+        contribution.vvec.pop();
+        assert!(!contribution.verify(&operator_public_key));
+
+        let mut premature = premature_commitment();
+        premature.sig = sign(premature.commitment_hash().as_byte_array());
+        premature.quorum_sig = premature.sig;
+        assert!(premature.verify(&operator_public_key));
+        assert!(premature.verify_quorum_sig(&operator_public_key));
+        assert!(!premature.verify(&BLSPublicKey::from(
+            &SecretKey::<Bls12381G2Impl>::from_hash(b"x").public_key()
+        )));
+    }
+}
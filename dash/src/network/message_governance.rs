@@ -0,0 +1,68 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash governance network messages.
+//!
+//! Governance objects and votes are relayed as-is in the `govobj` and `govobjvote` messages;
+//! this module defines the `govsync` request used to fetch them.
+//!
+
+use hashes::Hash;
+
+use crate::hash_types::GovernanceObjectHash;
+use crate::internal_macros::impl_consensus_encoding;
+use crate::network::message_bloom::FilterLoad;
+
+/// `govsync` message
+///
+/// Requests governance objects from a peer. With an all-zeros `prop_hash` the peer sends the
+/// inventory of all its objects, otherwise the object `prop_hash` and its votes, skipping the
+/// votes already matching `filter`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GovSync {
+    /// The object requested, all zeros for every object
+    pub prop_hash: GovernanceObjectHash,
+    /// Bloom filter of the votes the requester already has
+    pub filter: FilterLoad,
+}
+impl_consensus_encoding!(GovSync, prop_hash, filter);
+
+impl GovSync {
+    /// Returns whether this requests every governance object rather than a single one.
+    pub fn is_full_sync(&self) -> bool { self.prop_hash == GovernanceObjectHash::all_zeros() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::network::message_bloom::BloomFlags;
+
+    #[test]
+    fn govsync_encoding() {
+        let msg = GovSync {
+            prop_hash: GovernanceObjectHash::all_zeros(),
+            filter: FilterLoad {
+                filter: vec![0; 4],
+                hash_funcs: 1,
+                tweak: 2,
+                flags: BloomFlags::None,
+            },
+        };
+        assert!(msg.is_full_sync());
+        let encoded = serialize(&msg);
+        assert_eq!(encoded.len(), 32 + 5 + 4 + 4 + 1);
+        assert_eq!(deserialize::<GovSync>(&encoded).unwrap(), msg);
+    }
+}
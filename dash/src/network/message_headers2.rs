@@ -0,0 +1,421 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Compressed block header network messages.
+//!
+//! This module defines the `headers2` message of
+//! [DIP25](https://github.com/dashpay/dips/blob/master/dip-0025.md). Each header is preceded by
+//! a bitfield telling which fields are omitted: the version may be replaced by an index into
+//! the most recently seen distinct versions, the previous block hash is implied by the previous
+//! header, the time may be sent as a 16-bit offset and the target may be repeated. Compression
+//! state is kept per message in a [`CompressionContext`].
+//!
+
+use core::fmt;
+
+use crate::blockdata::block::{Header, Version};
+use crate::consensus::encode::{self, Decodable, Encodable, VarInt};
+use crate::hash_types::{BlockHash, TxMerkleNode};
+use crate::io;
+use crate::pow::CompactTarget;
+use crate::prelude::*;
+
+/// Number of distinct versions remembered by a [`CompressionContext`].
+pub const MAX_CACHED_VERSIONS: usize = 7;
+
+const VERSION_MASK: u8 = 0b0000_0111;
+const PREV_BLOCK_HASH: u8 = 0b0000_1000;
+const TIMESTAMP: u8 = 0b0001_0000;
+const BITS: u8 = 0b0010_0000;
+
+/// The version field of a [`CompressedHeader`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CompressedVersion {
+    /// The version is sent in full.
+    Full(Version),
+    /// One-based index into the most recently seen distinct versions.
+    Cached(u8),
+}
+
+/// The time field of a [`CompressedHeader`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CompressedTime {
+    /// The time is sent in full.
+    Full(u32),
+    /// Offset in seconds from the previous header's time.
+    Offset(i16),
+}
+
+/// A block header with the fields implied by the previous header omitted.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CompressedHeader {
+    /// Block version, possibly as an index into the version cache
+    pub version: CompressedVersion,
+    /// Previous block hash, omitted if it is the hash of the previous header
+    pub prev_blockhash: Option<BlockHash>,
+    /// Merkle root of the block's transactions
+    pub merkle_root: TxMerkleNode,
+    /// Block time, possibly relative to the previous header
+    pub time: CompressedTime,
+    /// Target, omitted if it is the same as the previous header's
+    pub bits: Option<CompactTarget>,
+    /// Block nonce
+    pub nonce: u32,
+}
+
+impl CompressedHeader {
+    fn bitfield(&self) -> u8 {
+        let mut bitfield = match self.version {
+            CompressedVersion::Full(_) => 0,
+            CompressedVersion::Cached(index) => index & VERSION_MASK,
+        };
+        if self.prev_blockhash.is_some() {
+            bitfield |= PREV_BLOCK_HASH;
+        }
+        if let CompressedTime::Full(_) = self.time {
+            bitfield |= TIMESTAMP;
+        }
+        if self.bits.is_some() {
+            bitfield |= BITS;
+        }
+        bitfield
+    }
+}
+
+impl Encodable for CompressedHeader {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = self.bitfield().consensus_encode(w)?;
+        if let CompressedVersion::Full(version) = self.version {
+            len += version.consensus_encode(w)?;
+        }
+        if let Some(prev_blockhash) = self.prev_blockhash {
+            len += prev_blockhash.consensus_encode(w)?;
+        }
+        len += self.merkle_root.consensus_encode(w)?;
+        len += match self.time {
+            CompressedTime::Full(time) => time.consensus_encode(w)?,
+            CompressedTime::Offset(offset) => offset.consensus_encode(w)?,
+        };
+        if let Some(bits) = self.bits {
+            len += bits.consensus_encode(w)?;
+        }
+        len += self.nonce.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for CompressedHeader {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let bitfield = u8::consensus_decode(r)?;
+        let version = match bitfield & VERSION_MASK {
+            0 => CompressedVersion::Full(Decodable::consensus_decode(r)?),
+            index => CompressedVersion::Cached(index),
+        };
+        let prev_blockhash = if bitfield & PREV_BLOCK_HASH != 0 {
+            Some(Decodable::consensus_decode(r)?)
+        } else {
+            None
+        };
+        let merkle_root = Decodable::consensus_decode(r)?;
+        let time = if bitfield & TIMESTAMP != 0 {
+            CompressedTime::Full(Decodable::consensus_decode(r)?)
+        } else {
+            CompressedTime::Offset(Decodable::consensus_decode(r)?)
+        };
+        let bits = if bitfield & BITS != 0 { Some(Decodable::consensus_decode(r)?) } else { None };
+        let nonce = Decodable::consensus_decode(r)?;
+        Ok(CompressedHeader { version, prev_blockhash, merkle_root, time, bits, nonce })
+    }
+}
+
+/// An error returned when a compressed header cannot be expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecompressionError {
+    /// A field was omitted but there is no previous header to take it from.
+    MissingPreviousHeader,
+    /// The version index does not refer to a cached version.
+    UnknownVersionIndex(u8),
+}
+
+impl fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecompressionError::MissingPreviousHeader =>
+                write!(f, "compressed header omits fields but there is no previous header"),
+            DecompressionError::UnknownVersionIndex(index) =>
+                write!(f, "compressed header refers to unknown cached version {}", index),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { None }
+}
+
+/// Compression state shared by consecutive headers of one `headers2` message.
+///
+/// Holds the previous header and the most recently seen distinct versions, most recent first.
+/// Compressing and decompressing the same headers in order with fresh contexts yields the same
+/// state on both sides.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct CompressionContext {
+    versions: Vec<Version>,
+    previous: Option<Header>,
+}
+
+impl CompressionContext {
+    /// Creates a context for the first header of a message.
+    pub fn new() -> Self { CompressionContext::default() }
+
+    /// Compresses `header`, which must follow the previously compressed header.
+    pub fn compress(&mut self, header: &Header) -> CompressedHeader {
+        let previous = match self.previous.replace(*header) {
+            Some(previous) => previous,
+            None => {
+                self.save_version(header.version);
+                return CompressedHeader {
+                    version: CompressedVersion::Full(header.version),
+                    prev_blockhash: Some(header.prev_blockhash),
+                    merkle_root: header.merkle_root,
+                    time: CompressedTime::Full(header.time),
+                    bits: Some(header.bits),
+                    nonce: header.nonce,
+                };
+            }
+        };
+
+        let version = match self.versions.iter().position(|version| *version == header.version) {
+            Some(position) => {
+                self.move_to_front(position);
+                CompressedVersion::Cached(position as u8 + 1)
+            }
+            None => {
+                self.save_version(header.version);
+                CompressedVersion::Full(header.version)
+            }
+        };
+        let time = match i16::try_from(i64::from(header.time) - i64::from(previous.time)) {
+            Ok(offset) => CompressedTime::Offset(offset),
+            Err(_) => CompressedTime::Full(header.time),
+        };
+        CompressedHeader {
+            version,
+            prev_blockhash: (header.prev_blockhash != previous.block_hash())
+                .then_some(header.prev_blockhash),
+            merkle_root: header.merkle_root,
+            time,
+            bits: (header.bits != previous.bits).then_some(header.bits),
+            nonce: header.nonce,
+        }
+    }
+
+    /// Expands `compressed`, which must follow the previously decompressed header.
+    pub fn decompress(
+        &mut self,
+        compressed: &CompressedHeader,
+    ) -> Result<Header, DecompressionError> {
+        let version = match compressed.version {
+            CompressedVersion::Full(version) => {
+                self.save_version(version);
+                version
+            }
+            CompressedVersion::Cached(index) => {
+                let position = usize::from(index)
+                    .checked_sub(1)
+                    .filter(|position| *position < self.versions.len())
+                    .ok_or(DecompressionError::UnknownVersionIndex(index))?;
+                self.move_to_front(position);
+                self.versions[0]
+            }
+        };
+        let previous = self.previous.as_ref();
+        let prev_blockhash = match (compressed.prev_blockhash, previous) {
+            (Some(prev_blockhash), _) => prev_blockhash,
+            (None, Some(previous)) => previous.block_hash(),
+            (None, None) => return Err(DecompressionError::MissingPreviousHeader),
+        };
+        let time = match (compressed.time, previous) {
+            (CompressedTime::Full(time), _) => time,
+            (CompressedTime::Offset(offset), Some(previous)) =>
+                (i64::from(previous.time) + i64::from(offset)) as u32,
+            (CompressedTime::Offset(_), None) =>
+                return Err(DecompressionError::MissingPreviousHeader),
+        };
+        let bits = match (compressed.bits, previous) {
+            (Some(bits), _) => bits,
+            (None, Some(previous)) => previous.bits,
+            (None, None) => return Err(DecompressionError::MissingPreviousHeader),
+        };
+
+        let header = Header {
+            version,
+            prev_blockhash,
+            merkle_root: compressed.merkle_root,
+            time,
+            bits,
+            nonce: compressed.nonce,
+        };
+        self.previous = Some(header);
+        Ok(header)
+    }
+
+    fn save_version(&mut self, version: Version) {
+        self.versions.insert(0, version);
+        self.versions.truncate(MAX_CACHED_VERSIONS);
+    }
+
+    fn move_to_front(&mut self, position: usize) { self.versions[..=position].rotate_right(1); }
+}
+
+/// `headers2` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Headers2Message {
+    /// Compressed headers, each relative to the one before it
+    pub headers: Vec<CompressedHeader>,
+}
+
+impl Headers2Message {
+    /// Compresses consecutive headers into a `headers2` message.
+    pub fn compress(headers: &[Header]) -> Self {
+        let mut context = CompressionContext::new();
+        Headers2Message { headers: headers.iter().map(|header| context.compress(header)).collect() }
+    }
+
+    /// Expands the headers of this message.
+    pub fn decompress(&self) -> Result<Vec<Header>, DecompressionError> {
+        let mut context = CompressionContext::new();
+        self.headers.iter().map(|header| context.decompress(header)).collect()
+    }
+}
+
+impl Encodable for Headers2Message {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = VarInt(self.headers.len() as u64).consensus_encode(w)?;
+        for header in &self.headers {
+            len += header.consensus_encode(w)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Headers2Message {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let len = VarInt::consensus_decode(r)?.0;
+        // The smallest compressed header is 43 bytes; don't trust the count for allocation.
+        let mut headers = Vec::with_capacity(core::cmp::min(len as usize, 2000));
+        for _ in 0..len {
+            headers.push(Decodable::consensus_decode(r)?);
+        }
+        Ok(Headers2Message { headers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+
+    fn chain(len: u32) -> Vec<Header> {
+        let mut headers: Vec<Header> = Vec::new();
+        for i in 0..len {
+            let prev_blockhash =
+                headers.last().map_or_else(BlockHash::all_zeros, Header::block_hash);
+            headers.push(Header {
+                version: Version::from_consensus(0x20000000 | (i as i32 % 3)),
+                prev_blockhash,
+                merkle_root: TxMerkleNode::hash(&i.to_le_bytes()),
+                time: 1_700_000_000 + i * 150,
+                bits: CompactTarget::from_consensus(0x1b0404cb),
+                nonce: i,
+            });
+        }
+        headers
+    }
+
+    #[test]
+    fn round_trip() {
+        let headers = chain(20);
+        let msg = Headers2Message::compress(&headers);
+
+        assert_eq!(msg.headers[0].version, CompressedVersion::Full(headers[0].version));
+        assert!(msg.headers[0].prev_blockhash.is_some());
+        for compressed in &msg.headers[1..] {
+            assert!(compressed.prev_blockhash.is_none());
+            assert!(compressed.bits.is_none());
+            assert_eq!(compressed.time, CompressedTime::Offset(150));
+        }
+        assert_eq!(msg.headers[3].version, CompressedVersion::Cached(3));
+
+        let encoded = serialize(&msg);
+        // One full header plus 19 headers of bitfield, merkle root, time offset and nonce,
+        // two of which carry a new version.
+        assert_eq!(encoded.len(), 1 + 81 + 19 * 39 + 2 * 4);
+        let decoded: Headers2Message = deserialize(&encoded).unwrap();
+        assert_eq!(decoded, msg);
+        assert_eq!(decoded.decompress().unwrap(), headers);
+    }
+
+    #[test]
+    fn uncompressible_fields() {
+        let mut headers = chain(4);
+        headers[1].time = headers[0].time + 40_000;
+        headers[2].bits = CompactTarget::from_consensus(0x1b0404cc);
+        headers[3].prev_blockhash = BlockHash::hash(&[1]);
+
+        let msg = Headers2Message::compress(&headers);
+        assert_eq!(msg.headers[1].time, CompressedTime::Full(headers[1].time));
+        assert_eq!(msg.headers[2].bits, Some(headers[2].bits));
+        assert_eq!(msg.headers[3].prev_blockhash, Some(headers[3].prev_blockhash));
+        let decoded: Headers2Message = deserialize(&serialize(&msg)).unwrap();
+        assert_eq!(decoded.decompress().unwrap(), headers);
+    }
+
+    #[test]
+    fn version_cache_eviction() {
+        let mut headers = chain(10);
+        for (i, header) in headers.iter_mut().enumerate() {
+            header.version = Version::from_consensus(i as i32 + 1);
+        }
+        // Version 1 was evicted after seven newer versions; version 4 is still cached.
+        headers[8].version = Version::from_consensus(1);
+        headers[9].version = Version::from_consensus(4);
+
+        let msg = Headers2Message::compress(&headers);
+        assert_eq!(msg.headers[8].version, CompressedVersion::Full(Version::from_consensus(1)));
+        assert_eq!(msg.headers[9].version, CompressedVersion::Cached(6));
+        assert_eq!(msg.decompress().unwrap(), headers);
+    }
+
+    #[test]
+    fn decompression_errors() {
+        let headers = chain(3);
+        let msg = Headers2Message::compress(&headers);
+
+        let mut cached = msg.headers[1];
+        cached.version = CompressedVersion::Cached(1);
+        assert_eq!(
+            CompressionContext::new().decompress(&cached),
+            Err(DecompressionError::UnknownVersionIndex(1))
+        );
+
+        assert_eq!(
+            CompressionContext::new().decompress(&msg.headers[1]),
+            Err(DecompressionError::MissingPreviousHeader)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash masternode network messages.
+//!
+//! This module defines the `mnauth` message, with which a masternode proves its identity to a
+//! peer after the handshake, and the helpers to sign and verify it.
+//!
+
+use hashes::{Hash, HashEngine, sha256d};
+
+use crate::bls_sig_utils::{BLSPublicKey, BLSSignature};
+use crate::consensus::Encodable;
+use crate::hash_types::ProTxHash;
+use crate::internal_macros::impl_consensus_encoding;
+use crate::network::constants::MNAUTH_NODE_VERSION;
+
+/// `mnauth` message
+///
+/// Sent by a masternode to prove it controls the operator key of its registration. The
+/// signature covers the challenge the receiving peer sent in its `version` message.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MnAuth {
+    /// Hash of the masternode's `ProRegTx`
+    pub pro_reg_tx_hash: ProTxHash,
+    /// Operator key signature over [`mnauth_sign_hash`]
+    pub signature: BLSSignature,
+}
+impl_consensus_encoding!(MnAuth, pro_reg_tx_hash, signature);
+
+/// Computes the hash signed in an `mnauth` message.
+///
+/// `challenge` is the challenge of the peer the message is sent to and `inbound` tells whether
+/// the signer accepted the connection, which binds the signature to the connection direction.
+/// `node_version` is the signer's protocol version and is only committed to when both peers
+/// are at or above [`MNAUTH_NODE_VERSION`].
+pub fn mnauth_sign_hash(
+    operator_key: &BLSPublicKey,
+    challenge: &[u8; 32],
+    inbound: bool,
+    node_version: Option<u32>,
+) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    operator_key.consensus_encode(&mut engine).expect("engines don't error");
+    engine.input(challenge);
+    inbound.consensus_encode(&mut engine).expect("engines don't error");
+    if let Some(version) = node_version.filter(|version| *version >= MNAUTH_NODE_VERSION) {
+        (version as i32).consensus_encode(&mut engine).expect("engines don't error");
+    }
+    sha256d::Hash::from_engine(engine)
+}
+
+#[cfg(feature = "bls")]
+impl MnAuth {
+    /// Builds an `mnauth` message for the peer that sent `received_challenge`.
+    ///
+    /// `inbound` is whether the peer connected to us; `node_version` is our protocol version if
+    /// both peers are at or above [`MNAUTH_NODE_VERSION`].
+    pub fn sign(
+        pro_reg_tx_hash: ProTxHash,
+        operator_key: &blsful::SecretKey<blsful::Bls12381G2Impl>,
+        received_challenge: &[u8; 32],
+        inbound: bool,
+        node_version: Option<u32>,
+    ) -> Result<MnAuth, blsful::BlsError> {
+        let public_key = BLSPublicKey::from(&operator_key.public_key());
+        let hash = mnauth_sign_hash(&public_key, received_challenge, inbound, node_version);
+        let signature = operator_key.sign(blsful::SignatureSchemes::Basic, hash.as_byte_array())?;
+        Ok(MnAuth { pro_reg_tx_hash, signature: BLSSignature::from(&signature) })
+    }
+
+    /// Verifies an `mnauth` message received from a peer.
+    ///
+    /// `sent_challenge` is the challenge we sent the peer and `inbound` is whether the peer
+    /// connected to us; the signer used the opposite direction. `peer_version` is the peer's
+    /// protocol version if both peers are at or above [`MNAUTH_NODE_VERSION`].
+    pub fn verify(
+        &self,
+        operator_key: &BLSPublicKey,
+        sent_challenge: &[u8; 32],
+        inbound: bool,
+        peer_version: Option<u32>,
+    ) -> bool {
+        let hash = mnauth_sign_hash(operator_key, sent_challenge, !inbound, peer_version);
+        self.signature.verify(operator_key, hash.as_byte_array())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+
+    #[test]
+    fn mnauth_encoding() {
+        let msg = MnAuth {
+            pro_reg_tx_hash: ProTxHash::hash(&[1]),
+            signature: BLSSignature::from([2u8; 96]),
+        };
+        let encoded = serialize(&msg);
+        assert_eq!(encoded.len(), 32 + 96);
+        assert_eq!(deserialize::<MnAuth>(&encoded).unwrap(), msg);
+    }
+
+    #[test]
+    fn sign_hash_commits_to_version() {
+        let key = BLSPublicKey::from([3u8; 48]);
+        let challenge = [4u8; 32];
+        let legacy = mnauth_sign_hash(&key, &challenge, true, None);
+        assert_eq!(legacy, mnauth_sign_hash(&key, &challenge, true, Some(MNAUTH_NODE_VERSION - 1)));
+        assert_ne!(legacy, mnauth_sign_hash(&key, &challenge, false, None));
+        assert_ne!(legacy, mnauth_sign_hash(&key, &challenge, true, Some(MNAUTH_NODE_VERSION)));
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn sign_and_verify() {
+        use blsful::{Bls12381G2Impl, SecretKey};
+
+        let secret = SecretKey::<Bls12381G2Impl>::from_hash(b"mnauth operator");
+        let operator_key = BLSPublicKey::from(&secret.public_key());
+        let challenge = [5u8; 32];
+        let version = Some(crate::network::constants::PROTOCOL_VERSION);
+
+        // The masternode accepted the connection; the verifier is the outbound side.
+        let msg = MnAuth::sign(ProTxHash::hash(&[6]), &secret, &challenge, true, version).unwrap();
+        assert!(msg.verify(&operator_key, &challenge, false, version));
+        assert!(!msg.verify(&operator_key, &challenge, true, version));
+        assert!(!msg.verify(&operator_key, &[0u8; 32], false, version));
+        assert!(!msg.verify(&operator_key, &challenge, false, None));
+
+        let other = BLSPublicKey::from(
+            &SecretKey::<Bls12381G2Impl>::from_hash(b"other operator").public_key(),
+        );
+        assert!(!msg.verify(&other, &challenge, false, version));
+    }
+}
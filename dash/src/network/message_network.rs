@@ -58,10 +58,17 @@ pub struct VersionMessage {
     /// if the sender is bandwidth-limited and would like to support bloom
     /// filtering. Defaults to false.
     pub relay: bool,
+    /// Random challenge the receiving masternode signs in its `mnauth` reply. Only present
+    /// from protocol version [`constants::MNAUTH_CHALLENGE_VERSION`].
+    pub mnauth_challenge: Option<[u8; 32]>,
+    /// Whether the sender opened this connection as a masternode. Only present from protocol
+    /// version [`constants::MASTERNODE_CONNECTION_VERSION`]; encoding it also encodes a zero
+    /// `mnauth_challenge` if none is set.
+    pub masternode_connection: Option<bool>,
 }
 
 impl VersionMessage {
-    /// Constructs a new `version` message with `relay` set to false
+    /// Constructs a new `version` message with `relay` set to false and no Dash-specific fields
     pub fn new(
         services: ServiceFlags,
         timestamp: i64,
@@ -81,22 +88,105 @@ impl VersionMessage {
             user_agent,
             start_height,
             relay: false,
+            mnauth_challenge: None,
+            masternode_connection: None,
         }
     }
 }
 
-impl_consensus_encoding!(
-    VersionMessage,
-    version,
-    services,
-    timestamp,
-    receiver,
-    sender,
-    nonce,
-    user_agent,
-    start_height,
-    relay
-);
+impl Encodable for VersionMessage {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.version.consensus_encode(w)?;
+        len += self.services.consensus_encode(w)?;
+        len += self.timestamp.consensus_encode(w)?;
+        len += self.receiver.consensus_encode(w)?;
+        len += self.sender.consensus_encode(w)?;
+        len += self.nonce.consensus_encode(w)?;
+        len += self.user_agent.consensus_encode(w)?;
+        len += self.start_height.consensus_encode(w)?;
+        len += self.relay.consensus_encode(w)?;
+        if self.version < constants::MNAUTH_CHALLENGE_VERSION {
+            return Ok(len);
+        }
+        if self.mnauth_challenge.is_some() || self.masternode_connection.is_some() {
+            len += self.mnauth_challenge.unwrap_or_default().consensus_encode(w)?;
+        }
+        if let Some(masternode_connection) = self.masternode_connection {
+            if self.version >= constants::MASTERNODE_CONNECTION_VERSION {
+                len += masternode_connection.consensus_encode(w)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for VersionMessage {
+    fn consensus_decode_from_finite_reader<R: io::Read + ?Sized>(
+        r: &mut R,
+    ) -> Result<Self, encode::Error> {
+        let version: u32 = Decodable::consensus_decode_from_finite_reader(r)?;
+        let services = Decodable::consensus_decode_from_finite_reader(r)?;
+        let timestamp = Decodable::consensus_decode_from_finite_reader(r)?;
+        let receiver = Decodable::consensus_decode_from_finite_reader(r)?;
+        let sender = Decodable::consensus_decode_from_finite_reader(r)?;
+        let nonce = Decodable::consensus_decode_from_finite_reader(r)?;
+        let user_agent = Decodable::consensus_decode_from_finite_reader(r)?;
+        let start_height = Decodable::consensus_decode_from_finite_reader(r)?;
+        let relay = Decodable::consensus_decode_from_finite_reader(r)?;
+
+        // The Dash-specific fields are trailing and optional: peers may omit them even when
+        // their protocol version allows them.
+        let mut mnauth_challenge = None;
+        let mut masternode_connection = None;
+        if version >= constants::MNAUTH_CHALLENGE_VERSION {
+            let mut challenge = [0u8; 32];
+            if read_optional(r, &mut challenge)? {
+                mnauth_challenge = Some(challenge);
+                let mut flag = [0u8; 1];
+                if version >= constants::MASTERNODE_CONNECTION_VERSION
+                    && read_optional(r, &mut flag)?
+                {
+                    masternode_connection = Some(flag[0] != 0);
+                }
+            }
+        }
+
+        Ok(VersionMessage {
+            version,
+            services,
+            timestamp,
+            receiver,
+            sender,
+            nonce,
+            user_agent,
+            start_height,
+            relay,
+            mnauth_challenge,
+            masternode_connection,
+        })
+    }
+
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        use crate::io::Read as _;
+        let mut r = r.take(encode::MAX_VEC_SIZE as u64);
+        Self::consensus_decode_from_finite_reader(r.by_ref())
+    }
+}
+
+/// Fills `buf` if the reader has data left, returning false if it is already exhausted.
+fn read_optional<R: io::Read + ?Sized>(r: &mut R, buf: &mut [u8]) -> Result<bool, encode::Error> {
+    loop {
+        match r.read(&mut buf[..1]) {
+            Ok(0) => return Ok(false),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    r.read_exact(&mut buf[1..])?;
+    Ok(true)
+}
 
 /// message rejection reason as a code
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -167,7 +257,8 @@ mod tests {
     use super::{Reject, RejectReason, VersionMessage};
     use crate::consensus::encode::{deserialize, serialize};
     use crate::internal_macros::hex;
-    use crate::network::constants::ServiceFlags;
+    use crate::network::address::Address;
+    use crate::network::constants::{self, ServiceFlags};
 
     #[test]
     fn version_message_test() {
@@ -189,6 +280,55 @@ mod tests {
         assert!(real_decode.relay);
 
         assert_eq!(serialize(&real_decode), from_sat);
+        assert_eq!(real_decode.mnauth_challenge, None);
+        assert_eq!(real_decode.masternode_connection, None);
+    }
+
+    #[test]
+    fn dash_version_message_test() {
+        let address = Address::new(&"127.0.0.1:9999".parse().unwrap(), ServiceFlags::NONE);
+        let mut msg = VersionMessage::new(
+            ServiceFlags::NETWORK | ServiceFlags::HEADERS_COMPRESSED,
+            1700000000,
+            address.clone(),
+            address,
+            42,
+            "/Dash Core:20.0.0/".to_string(),
+            1000,
+        );
+        msg.relay = true;
+        let base = serialize(&msg);
+
+        msg.mnauth_challenge = Some([7u8; 32]);
+        msg.masternode_connection = Some(true);
+        let encoded = serialize(&msg);
+        assert_eq!(encoded.len(), base.len() + 33);
+        assert_eq!(&encoded[base.len()..base.len() + 32], &[7u8; 32]);
+        assert_eq!(encoded[base.len() + 32], 1);
+        assert_eq!(deserialize::<VersionMessage>(&encoded).unwrap(), msg);
+
+        // A challenge without the masternode flag, as sent by older peers.
+        let decoded: VersionMessage = deserialize(&encoded[..encoded.len() - 1]).unwrap();
+        assert_eq!(decoded.mnauth_challenge, Some([7u8; 32]));
+        assert_eq!(decoded.masternode_connection, None);
+
+        // Only the masternode flag set still encodes an (empty) challenge before it.
+        msg.mnauth_challenge = None;
+        let decoded: VersionMessage = deserialize(&serialize(&msg)).unwrap();
+        assert_eq!(decoded.mnauth_challenge, Some([0u8; 32]));
+        assert_eq!(decoded.masternode_connection, Some(true));
+
+        // Peers below the mnauth protocol version never carry the Dash fields.
+        msg.version = constants::MNAUTH_CHALLENGE_VERSION - 1;
+        msg.mnauth_challenge = Some([7u8; 32]);
+        let encoded = serialize(&msg);
+        assert_eq!(encoded.len(), base.len());
+        assert!(deserialize::<VersionMessage>(&[&encoded[..], &[0u8; 32]].concat()).is_err());
+
+        // A truncated challenge is an error rather than being silently dropped.
+        msg.version = constants::PROTOCOL_VERSION;
+        let encoded = serialize(&msg);
+        assert!(deserialize::<VersionMessage>(&encoded[..encoded.len() - 10]).is_err());
     }
 
     #[test]
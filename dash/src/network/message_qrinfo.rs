@@ -0,0 +1,304 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash quorum rotation network messages.
+//!
+//! This module defines the `getqrinfo` and `qrinfo` messages of
+//! [DIP24](https://github.com/dashpay/dips/blob/master/dip-0024.md). Rotated quorums are built
+//! from members chosen at several past cycles, so verifying them needs the masternode lists and
+//! the [`QuorumSnapshot`]s at `h - c`, `h - 2c`, `h - 3c` and optionally `h - 4c`, where `c` is
+//! the DKG interval.
+//!
+
+use core::fmt;
+
+use crate::blockdata::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+use crate::consensus::encode::{
+    self, Decodable, Encodable, read_compact_size, read_fixed_bitset, write_compact_size,
+    write_fixed_bitset,
+};
+use crate::hash_types::BlockHash;
+use crate::internal_macros::impl_consensus_encoding;
+use crate::io;
+use crate::network::message_sml::MnListDiff;
+use crate::prelude::*;
+
+/// `getqrinfo` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetQRInfo {
+    /// Blocks the requester already has masternode lists for
+    pub base_block_hashes: Vec<BlockHash>,
+    /// Block to return the rotation info at
+    pub block_request_hash: BlockHash,
+    /// Whether to also return the snapshot and diff at `h - 4c`
+    pub extra_share: bool,
+}
+impl_consensus_encoding!(GetQRInfo, base_block_hashes, block_request_hash, extra_share);
+
+/// How the skip list of a [`QuorumSnapshot`] is to be read.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MnSkipListMode {
+    /// No masternodes were skipped; the skip list is empty.
+    NoSkipping,
+    /// The skip list holds the masternodes that were skipped.
+    SkipFirst,
+    /// The skip list holds the masternodes that were not skipped.
+    SkipExcept,
+    /// All masternodes were skipped.
+    SkipAll,
+    /// A mode not known to this library.
+    Unknown(i32),
+}
+
+impl From<MnSkipListMode> for i32 {
+    fn from(mode: MnSkipListMode) -> i32 {
+        match mode {
+            MnSkipListMode::NoSkipping => 0,
+            MnSkipListMode::SkipFirst => 1,
+            MnSkipListMode::SkipExcept => 2,
+            MnSkipListMode::SkipAll => 3,
+            MnSkipListMode::Unknown(mode) => mode,
+        }
+    }
+}
+
+impl From<i32> for MnSkipListMode {
+    fn from(mode: i32) -> MnSkipListMode {
+        match mode {
+            0 => MnSkipListMode::NoSkipping,
+            1 => MnSkipListMode::SkipFirst,
+            2 => MnSkipListMode::SkipExcept,
+            3 => MnSkipListMode::SkipAll,
+            mode => MnSkipListMode::Unknown(mode),
+        }
+    }
+}
+
+impl fmt::Display for MnSkipListMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MnSkipListMode::NoSkipping => f.write_str("no skipping"),
+            MnSkipListMode::SkipFirst => f.write_str("skip first"),
+            MnSkipListMode::SkipExcept => f.write_str("skip except"),
+            MnSkipListMode::SkipAll => f.write_str("skip all"),
+            MnSkipListMode::Unknown(mode) => write!(f, "unknown ({})", mode),
+        }
+    }
+}
+
+impl Encodable for MnSkipListMode {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        i32::from(*self).consensus_encode(w)
+    }
+}
+
+impl Decodable for MnSkipListMode {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        Ok(i32::consensus_decode(r)?.into())
+    }
+}
+
+/// The quorum members chosen at one past cycle.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct QuorumSnapshot {
+    /// How to read the skip list
+    pub skip_list_mode: MnSkipListMode,
+    /// For every masternode in the list, whether it was an active quorum member
+    pub active_quorum_members: Vec<bool>,
+    /// Indexes of the skipped masternodes, or of the non-skipped ones, depending on the mode
+    pub skip_list: Vec<i32>,
+}
+
+impl Encodable for QuorumSnapshot {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.skip_list_mode.consensus_encode(w)?;
+        len += write_compact_size(w, self.active_quorum_members.len() as u32)?;
+        len +=
+            write_fixed_bitset(w, &self.active_quorum_members, self.active_quorum_members.len())?;
+        len += self.skip_list.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for QuorumSnapshot {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let skip_list_mode = MnSkipListMode::consensus_decode(r)?;
+        let active_quorum_members_count = read_compact_size(r)?;
+        let active_quorum_members = read_fixed_bitset(r, active_quorum_members_count as usize)?;
+        let skip_list = Vec::<i32>::consensus_decode(r)?;
+        Ok(QuorumSnapshot { skip_list_mode, active_quorum_members, skip_list })
+    }
+}
+
+/// `qrinfo` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct QRInfo {
+    /// Snapshot of the quorum members chosen at `h - c`
+    pub quorum_snapshot_at_h_minus_c: QuorumSnapshot,
+    /// Snapshot of the quorum members chosen at `h - 2c`
+    pub quorum_snapshot_at_h_minus_2c: QuorumSnapshot,
+    /// Snapshot of the quorum members chosen at `h - 3c`
+    pub quorum_snapshot_at_h_minus_3c: QuorumSnapshot,
+    /// Masternode list diff to the chain tip
+    pub mn_list_diff_tip: MnListDiff,
+    /// Masternode list diff to the last cycle start `h`
+    pub mn_list_diff_h: MnListDiff,
+    /// Masternode list diff to `h - c`
+    pub mn_list_diff_at_h_minus_c: MnListDiff,
+    /// Masternode list diff to `h - 2c`
+    pub mn_list_diff_at_h_minus_2c: MnListDiff,
+    /// Masternode list diff to `h - 3c`
+    pub mn_list_diff_at_h_minus_3c: MnListDiff,
+    /// Snapshot and masternode list diff at `h - 4c`, present if requested with `extra_share`
+    pub quorum_snapshot_and_mn_list_diff_at_h_minus_4c: Option<(QuorumSnapshot, MnListDiff)>,
+    /// The last commitment of every quorum index
+    pub last_commitment_per_index: Vec<QuorumFinalizationCommitment>,
+    /// Snapshots for the cycles of [`Self::last_commitment_per_index`]
+    pub quorum_snapshot_list: Vec<QuorumSnapshot>,
+    /// Masternode list diffs for the cycles of [`Self::last_commitment_per_index`]
+    pub mn_list_diff_list: Vec<MnListDiff>,
+}
+
+impl Encodable for QRInfo {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.quorum_snapshot_at_h_minus_c.consensus_encode(w)?;
+        len += self.quorum_snapshot_at_h_minus_2c.consensus_encode(w)?;
+        len += self.quorum_snapshot_at_h_minus_3c.consensus_encode(w)?;
+        len += self.mn_list_diff_tip.consensus_encode(w)?;
+        len += self.mn_list_diff_h.consensus_encode(w)?;
+        len += self.mn_list_diff_at_h_minus_c.consensus_encode(w)?;
+        len += self.mn_list_diff_at_h_minus_2c.consensus_encode(w)?;
+        len += self.mn_list_diff_at_h_minus_3c.consensus_encode(w)?;
+        match &self.quorum_snapshot_and_mn_list_diff_at_h_minus_4c {
+            Some((snapshot, diff)) => {
+                len += true.consensus_encode(w)?;
+                len += snapshot.consensus_encode(w)?;
+                len += diff.consensus_encode(w)?;
+            }
+            None => len += false.consensus_encode(w)?,
+        }
+        len += self.last_commitment_per_index.consensus_encode(w)?;
+        len += self.quorum_snapshot_list.consensus_encode(w)?;
+        len += self.mn_list_diff_list.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for QRInfo {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let quorum_snapshot_at_h_minus_c = Decodable::consensus_decode(r)?;
+        let quorum_snapshot_at_h_minus_2c = Decodable::consensus_decode(r)?;
+        let quorum_snapshot_at_h_minus_3c = Decodable::consensus_decode(r)?;
+        let mn_list_diff_tip = Decodable::consensus_decode(r)?;
+        let mn_list_diff_h = Decodable::consensus_decode(r)?;
+        let mn_list_diff_at_h_minus_c = Decodable::consensus_decode(r)?;
+        let mn_list_diff_at_h_minus_2c = Decodable::consensus_decode(r)?;
+        let mn_list_diff_at_h_minus_3c = Decodable::consensus_decode(r)?;
+        let quorum_snapshot_and_mn_list_diff_at_h_minus_4c = if bool::consensus_decode(r)? {
+            Some((Decodable::consensus_decode(r)?, Decodable::consensus_decode(r)?))
+        } else {
+            None
+        };
+        Ok(QRInfo {
+            quorum_snapshot_at_h_minus_c,
+            quorum_snapshot_at_h_minus_2c,
+            quorum_snapshot_at_h_minus_3c,
+            mn_list_diff_tip,
+            mn_list_diff_h,
+            mn_list_diff_at_h_minus_c,
+            mn_list_diff_at_h_minus_2c,
+            mn_list_diff_at_h_minus_3c,
+            quorum_snapshot_and_mn_list_diff_at_h_minus_4c,
+            last_commitment_per_index: Decodable::consensus_decode(r)?,
+            quorum_snapshot_list: Decodable::consensus_decode(r)?,
+            mn_list_diff_list: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::internal_macros::hex;
+    use crate::network::message_sml::tests::diff;
+
+    fn snapshot(mode: MnSkipListMode, skip_list: Vec<i32>) -> QuorumSnapshot {
+        QuorumSnapshot {
+            skip_list_mode: mode,
+            active_quorum_members: vec![true, false, true, true, false, false, false, false, true],
+            skip_list,
+        }
+    }
+
+    #[test]
+    fn quorum_snapshot_encoding() {
+        let snapshot = snapshot(MnSkipListMode::SkipFirst, vec![3, -1]);
+        let encoded = serialize(&snapshot);
+        assert_eq!(encoded, hex!("01000000090d010203000000ffffffff"));
+        assert_eq!(deserialize::<QuorumSnapshot>(&encoded).unwrap(), snapshot);
+
+        let unknown = QuorumSnapshot {
+            skip_list_mode: MnSkipListMode::Unknown(7),
+            active_quorum_members: vec![],
+            skip_list: vec![],
+        };
+        assert_eq!(deserialize::<QuorumSnapshot>(&serialize(&unknown)).unwrap(), unknown);
+    }
+
+    #[test]
+    fn qrinfo_round_trip() {
+        let mut info = QRInfo {
+            quorum_snapshot_at_h_minus_c: snapshot(MnSkipListMode::NoSkipping, vec![]),
+            quorum_snapshot_at_h_minus_2c: snapshot(MnSkipListMode::SkipExcept, vec![1, 2]),
+            quorum_snapshot_at_h_minus_3c: snapshot(MnSkipListMode::SkipAll, vec![]),
+            mn_list_diff_tip: diff(),
+            mn_list_diff_h: diff(),
+            mn_list_diff_at_h_minus_c: diff(),
+            mn_list_diff_at_h_minus_2c: diff(),
+            mn_list_diff_at_h_minus_3c: diff(),
+            quorum_snapshot_and_mn_list_diff_at_h_minus_4c: None,
+            last_commitment_per_index: diff().new_quorums,
+            quorum_snapshot_list: vec![snapshot(MnSkipListMode::NoSkipping, vec![])],
+            mn_list_diff_list: vec![diff()],
+        };
+        let without_extra_share = serialize(&info);
+        let decoded: QRInfo = deserialize(&without_extra_share).unwrap();
+        assert_eq!(serialize(&decoded), without_extra_share);
+        assert!(decoded.quorum_snapshot_and_mn_list_diff_at_h_minus_4c.is_none());
+
+        info.quorum_snapshot_and_mn_list_diff_at_h_minus_4c =
+            Some((snapshot(MnSkipListMode::SkipFirst, vec![0]), diff()));
+        let with_extra_share = serialize(&info);
+        let decoded: QRInfo = deserialize(&with_extra_share).unwrap();
+        assert_eq!(serialize(&decoded), with_extra_share);
+        assert_eq!(
+            decoded.quorum_snapshot_and_mn_list_diff_at_h_minus_4c.unwrap().0,
+            snapshot(MnSkipListMode::SkipFirst, vec![0])
+        );
+
+        let request = GetQRInfo {
+            base_block_hashes: vec![BlockHash::hash(&[1]), BlockHash::hash(&[2])],
+            block_request_hash: BlockHash::hash(&[3]),
+            extra_share: true,
+        };
+        let encoded = serialize(&request);
+        assert_eq!(encoded.len(), 1 + 64 + 32 + 1);
+        assert_eq!(deserialize::<GetQRInfo>(&encoded).unwrap(), request);
+    }
+}
@@ -0,0 +1,453 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash LLMQ signing session messages.
+//!
+//! Quorum members sign a request by exchanging signature shares until one of them holds
+//! `threshold` shares and recovers the quorum signature, which is then relayed in `qsigrec`.
+//! Shares travel either individually in `qsigshare`, or within a signing session: members
+//! announce sessions with `qsigsesann`, advertise and request the shares they have with
+//! `qsigsinv` and `qgetsigs`, and send them batched in `qbsigs`. Sessions are identified by an
+//! id local to the connection.
+//!
+
+use hashes::{Hash, sha256d};
+
+use crate::bls_sig_utils::BLSSignature;
+use crate::consensus::encode::{
+    self, Decodable, Encodable, fixed_bitset_len, read_compact_size, read_fixed_bitset,
+    write_compact_size, write_fixed_bitset,
+};
+use crate::hash_types::{QuorumHash, QuorumSignHash, QuorumSigningRequestId};
+use crate::internal_macros::impl_consensus_encoding;
+use crate::io;
+use crate::prelude::*;
+
+/// Maximum number of entries in a [`SigSharesInv`], mirroring Dash Core.
+pub const MAX_SIG_SHARES_INV_SIZE: u32 = 400;
+
+/// `qsigrec` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RecoveredSig {
+    /// The type of the signing quorum
+    pub llmq_type: u8,
+    /// The hash identifying the signing quorum
+    pub quorum_hash: QuorumHash,
+    /// The request id
+    pub id: QuorumSigningRequestId,
+    /// The hash of the signed message
+    pub msg_hash: sha256d::Hash,
+    /// The recovered quorum signature
+    pub sig: BLSSignature,
+}
+impl_consensus_encoding!(RecoveredSig, llmq_type, quorum_hash, id, msg_hash, sig);
+
+impl RecoveredSig {
+    /// The hash signed by the quorum, see [`crate::llmq::build_sign_hash`].
+    pub fn sign_hash(&self) -> QuorumSignHash {
+        crate::llmq::build_sign_hash(
+            self.llmq_type,
+            &self.quorum_hash,
+            &self.id,
+            self.msg_hash.as_byte_array(),
+        )
+    }
+
+    /// Verifies the recovered signature against the quorum public key.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, quorum_public_key: &crate::bls_sig_utils::BLSPublicKey) -> bool {
+        self.sig.verify(quorum_public_key, self.sign_hash().as_byte_array())
+    }
+}
+
+/// `qsigshare` message entry, a single member's signature share
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigShare {
+    /// The type of the signing quorum
+    pub llmq_type: u8,
+    /// The hash identifying the signing quorum
+    pub quorum_hash: QuorumHash,
+    /// The index of the signing member in the quorum
+    pub quorum_member: u16,
+    /// The request id
+    pub id: QuorumSigningRequestId,
+    /// The hash of the signed message
+    pub msg_hash: sha256d::Hash,
+    /// The signature share
+    pub sig_share: BLSSignature,
+}
+impl_consensus_encoding!(SigShare, llmq_type, quorum_hash, quorum_member, id, msg_hash, sig_share);
+
+impl SigShare {
+    /// The hash signed by the share, the same the quorum signature signs.
+    pub fn sign_hash(&self) -> QuorumSignHash {
+        crate::llmq::build_sign_hash(
+            self.llmq_type,
+            &self.quorum_hash,
+            &self.id,
+            self.msg_hash.as_byte_array(),
+        )
+    }
+
+    /// Verifies the share against the member's public key share.
+    #[cfg(feature = "bls")]
+    pub fn verify(&self, public_key_share: &crate::bls_sig_utils::BLSPublicKey) -> bool {
+        self.sig_share.verify(public_key_share, self.sign_hash().as_byte_array())
+    }
+}
+
+/// `qsigsesann` message entry, announcing a signing session
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigSessionAnnouncement {
+    /// The id the sender gives the session
+    pub session_id: u32,
+    /// The type of the signing quorum
+    pub llmq_type: u8,
+    /// The hash identifying the signing quorum
+    pub quorum_hash: QuorumHash,
+    /// The request id
+    pub id: QuorumSigningRequestId,
+    /// The hash of the message being signed
+    pub msg_hash: sha256d::Hash,
+}
+
+impl SigSessionAnnouncement {
+    /// The hash signed in this session.
+    pub fn sign_hash(&self) -> QuorumSignHash {
+        crate::llmq::build_sign_hash(
+            self.llmq_type,
+            &self.quorum_hash,
+            &self.id,
+            self.msg_hash.as_byte_array(),
+        )
+    }
+}
+
+/// `qsigsinv` and `qgetsigs` message entry, the members whose shares of a session the sender
+/// has or wants
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigSharesInv {
+    /// The session id, as announced by the receiver
+    pub session_id: u32,
+    /// One entry per quorum member
+    pub inv: Vec<bool>,
+}
+
+/// `qbsigs` message entry, signature shares of a session by member index
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BatchedSigShares {
+    /// The session id, as announced by the sender
+    pub session_id: u32,
+    /// The shares, keyed by the index of the signing member in the quorum
+    pub sig_shares: Vec<(u16, BLSSignature)>,
+}
+
+/// Writes `n` as Dash Core's `VARINT`, a base-128 encoding with the most significant group first.
+fn write_varint<W: io::Write + ?Sized>(w: &mut W, mut n: u32) -> Result<usize, io::Error> {
+    let mut tmp = [0u8; 5];
+    let mut len = 0;
+    loop {
+        tmp[len] = (n & 0x7f) as u8 | if len > 0 { 0x80 } else { 0 };
+        if n <= 0x7f {
+            break;
+        }
+        n = (n >> 7) - 1;
+        len += 1;
+    }
+    tmp[..=len].reverse();
+    w.write_all(&tmp[..=len])?;
+    Ok(len + 1)
+}
+
+/// Reads a `VARINT` written by [`write_varint`].
+fn read_varint<R: io::Read + ?Sized>(r: &mut R) -> Result<u32, encode::Error> {
+    let mut n: u32 = 0;
+    loop {
+        let byte = u8::consensus_decode(r)?;
+        if n > u32::MAX >> 7 {
+            return Err(encode::Error::ParseFailed("VARINT too large"));
+        }
+        n = (n << 7) | u32::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+        n = n.checked_add(1).ok_or(encode::Error::ParseFailed("VARINT too large"))?;
+    }
+}
+
+fn varint_len(mut n: u32) -> usize {
+    let mut len = 1;
+    while n > 0x7f {
+        n = (n >> 7) - 1;
+        len += 1;
+    }
+    len
+}
+
+impl Encodable for SigSessionAnnouncement {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += write_varint(w, self.session_id)?;
+        len += self.llmq_type.consensus_encode(w)?;
+        len += self.quorum_hash.consensus_encode(w)?;
+        len += self.id.consensus_encode(w)?;
+        len += self.msg_hash.consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for SigSessionAnnouncement {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        Ok(SigSessionAnnouncement {
+            session_id: read_varint(r)?,
+            llmq_type: Decodable::consensus_decode(r)?,
+            quorum_hash: Decodable::consensus_decode(r)?,
+            id: Decodable::consensus_decode(r)?,
+            msg_hash: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+impl Encodable for SigSharesInv {
+    /// The inventory is written as Dash Core's `AUTOBITSET`: a marker byte followed by either a
+    /// fixed bitset (0) or, if shorter, the `VARINT` gaps between set bits terminated by 0 (1).
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += write_varint(w, self.session_id)?;
+        len += write_compact_size(w, self.inv.len() as u32)?;
+
+        let mut gaps = Vec::new();
+        let mut last = -1i64;
+        for (i, _) in self.inv.iter().enumerate().filter(|(_, set)| **set) {
+            gaps.push((i as i64 - last) as u32);
+            last = i as i64;
+        }
+        let fixed_len = fixed_bitset_len(&self.inv, self.inv.len());
+        let gaps_len = gaps.iter().map(|gap| varint_len(*gap)).sum::<usize>() + 1;
+        if fixed_len < gaps_len {
+            len += 0u8.consensus_encode(w)?;
+            len += write_fixed_bitset(w, &self.inv, self.inv.len())?;
+        } else {
+            len += 1u8.consensus_encode(w)?;
+            for gap in gaps {
+                len += write_varint(w, gap)?;
+            }
+            len += write_varint(w, 0)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for SigSharesInv {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let session_id = read_varint(r)?;
+        let size = read_compact_size(r)?;
+        if size > MAX_SIG_SHARES_INV_SIZE {
+            return Err(encode::Error::ParseFailed("sig shares inventory too large"));
+        }
+        let inv = match u8::consensus_decode(r)? {
+            0 => read_fixed_bitset(r, size as usize)?,
+            1 => {
+                let mut inv = vec![false; size as usize];
+                let mut next = 0u64;
+                loop {
+                    let gap = read_varint(r)?;
+                    if gap == 0 {
+                        break;
+                    }
+                    let index = next + u64::from(gap) - 1;
+                    if index >= u64::from(size) {
+                        return Err(encode::Error::ParseFailed(
+                            "sig shares inventory out of bounds",
+                        ));
+                    }
+                    inv[index as usize] = true;
+                    next = index + 1;
+                }
+                inv
+            }
+            _ => return Err(encode::Error::ParseFailed("invalid bitset encoding")),
+        };
+        Ok(SigSharesInv { session_id, inv })
+    }
+}
+
+impl Encodable for BatchedSigShares {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        Ok(write_varint(w, self.session_id)? + self.sig_shares.consensus_encode(w)?)
+    }
+}
+
+impl Decodable for BatchedSigShares {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        Ok(BatchedSigShares {
+            session_id: read_varint(r)?,
+            sig_shares: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{deserialize, serialize};
+    use crate::internal_macros::hex;
+
+    #[test]
+    fn varint() {
+        for (n, bytes) in [
+            (0u32, &[0x00][..]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x80, 0x00]),
+            (0x1234, &[0xa3, 0x34]),
+            (0xffff, &[0x82, 0xfe, 0x7f]),
+            (u32::MAX, &[0x8e, 0xfe, 0xfe, 0xfe, 0x7f]),
+        ] {
+            let mut buf = Vec::new();
+            assert_eq!(write_varint(&mut buf, n).unwrap(), bytes.len());
+            assert_eq!(buf, bytes);
+            assert_eq!(varint_len(n), bytes.len());
+            assert_eq!(read_varint(&mut &buf[..]).unwrap(), n);
+        }
+        assert!(read_varint(&mut &[0x8f, 0xfe, 0xfe, 0xfe, 0x7f][..]).is_err());
+    }
+
+    #[test]
+    fn sig_shares_inv_encoding() {
+        // A sparse inventory is written as gaps between set bits.
+        let sparse =
+            SigSharesInv { session_id: 300, inv: (0..50).map(|i| i == 3 || i == 40).collect() };
+        let bytes = serialize(&sparse);
+        assert_eq!(bytes, hex!("812c3201042500"));
+        assert_eq!(deserialize::<SigSharesInv>(&bytes).unwrap(), sparse);
+
+        // A dense one as a fixed bitset.
+        let dense = SigSharesInv { session_id: 1, inv: (0..10).map(|i| i != 2).collect() };
+        let bytes = serialize(&dense);
+        assert_eq!(bytes, hex!("010a00fb03"));
+        assert_eq!(deserialize::<SigSharesInv>(&bytes).unwrap(), dense);
+
+        // Indexes past the declared size are rejected.
+        assert!(deserialize::<SigSharesInv>(&hex!("0102010300")).is_err());
+        assert!(deserialize::<SigSharesInv>(&hex!("01fd9101010100")).is_err());
+    }
+
+    #[test]
+    fn signing_messages_roundtrip() {
+        let recovered = RecoveredSig {
+            llmq_type: 100,
+            quorum_hash: QuorumHash::hash(b"quorum"),
+            id: QuorumSigningRequestId::hash(b"request"),
+            msg_hash: sha256d::Hash::hash(b"message"),
+            sig: BLSSignature::from([1; 96]),
+        };
+        let bytes = serialize(&recovered);
+        assert_eq!(bytes.len(), 1 + 32 + 32 + 32 + 96);
+        assert_eq!(deserialize::<RecoveredSig>(&bytes).unwrap(), recovered);
+        assert_eq!(
+            recovered.sign_hash(),
+            crate::llmq::build_sign_hash(
+                crate::llmq::LLMQType::LlmqtypeTest,
+                &recovered.quorum_hash,
+                &recovered.id,
+                recovered.msg_hash.as_byte_array()
+            )
+        );
+
+        let share = SigShare {
+            llmq_type: 100,
+            quorum_hash: recovered.quorum_hash,
+            quorum_member: 2,
+            id: recovered.id,
+            msg_hash: recovered.msg_hash,
+            sig_share: BLSSignature::from([2; 96]),
+        };
+        let bytes = serialize(&vec![share.clone()]);
+        assert_eq!(&bytes[34..36], &[2, 0]);
+        assert_eq!(deserialize::<Vec<SigShare>>(&bytes).unwrap(), vec![share.clone()]);
+        assert_eq!(share.sign_hash(), recovered.sign_hash());
+
+        let announcement = SigSessionAnnouncement {
+            session_id: 128,
+            llmq_type: 100,
+            quorum_hash: recovered.quorum_hash,
+            id: recovered.id,
+            msg_hash: recovered.msg_hash,
+        };
+        let bytes = serialize(&announcement);
+        assert_eq!(&bytes[..3], &[0x80, 0x00, 100]);
+        assert_eq!(deserialize::<SigSessionAnnouncement>(&bytes).unwrap(), announcement);
+
+        let batch = BatchedSigShares {
+            session_id: 5,
+            sig_shares: vec![(0, BLSSignature::from([3; 96])), (7, BLSSignature::from([4; 96]))],
+        };
+        let bytes = serialize(&batch);
+        assert_eq!(bytes.len(), 1 + 1 + 2 * (2 + 96));
+        assert_eq!(deserialize::<BatchedSigShares>(&bytes).unwrap(), batch);
+    }
+
+    #[cfg(feature = "bls")]
+    #[test]
+    fn recover_and_verify() {
+        use blsful::inner_types::Scalar;
+        use blsful::{Bls12381G2Impl, SecretKey, SignatureSchemes};
+
+        use crate::bls_sig_utils::BLSPublicKey;
+        use crate::hash_types::ProTxHash;
+        use crate::llmq::threshold::{member_id, recover_threshold_signature};
+
+        // f(x) = 7 + 11x, so any two members can sign.
+        let share_key = |member: &ProTxHash| {
+            SecretKey::<Bls12381G2Impl>(
+                Scalar::from(7u64) + Scalar::from(11u64) * member_id(member),
+            )
+        };
+        let quorum_public_key =
+            BLSPublicKey::from(&SecretKey::<Bls12381G2Impl>(Scalar::from(7u64)).public_key());
+        let members: Vec<ProTxHash> = (0u8..3).map(|i| ProTxHash::hash(&[i])).collect();
+
+        let mut recovered = RecoveredSig {
+            llmq_type: 100,
+            quorum_hash: QuorumHash::hash(b"quorum"),
+            id: QuorumSigningRequestId::hash(b"request"),
+            msg_hash: sha256d::Hash::hash(b"message"),
+            sig: BLSSignature::from([0; 96]),
+        };
+        let shares: Vec<SigShare> = [2u16, 0]
+            .iter()
+            .map(|&index| {
+                let mut share = SigShare {
+                    llmq_type: recovered.llmq_type,
+                    quorum_hash: recovered.quorum_hash,
+                    quorum_member: index,
+                    id: recovered.id,
+                    msg_hash: recovered.msg_hash,
+                    sig_share: BLSSignature::from([0; 96]),
+                };
+                let key = share_key(&members[index as usize]);
+                share.sig_share = BLSSignature::from(
+                    &key.sign(SignatureSchemes::Basic, share.sign_hash().as_byte_array()).unwrap(),
+                );
+                assert!(share.verify(&BLSPublicKey::from(&key.public_key())));
+                share
+            })
+            .collect();
+
+        let by_member: Vec<_> =
+            shares.iter().map(|s| (members[s.quorum_member as usize], s.sig_share)).collect();
+        recovered.sig = recover_threshold_signature(&by_member, 2).unwrap();
+        assert!(recovered.verify(&quorum_public_key));
+    }
+}
@@ -0,0 +1,298 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Dash simplified masternode list network messages.
+//!
+//! This module defines the `getmnlistd` and `mnlistdiff` messages of
+//! [DIP4](https://github.com/dashpay/dips/blob/master/dip-0004.md), with which light clients
+//! sync the deterministic masternode list and the active quorums. Messages are encoded in the
+//! format used from protocol version [`MNLISTDIFF_CHAINLOCKS_VERSION`] on.
+//!
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+use crate::blockdata::transaction::Transaction;
+use crate::blockdata::transaction::special_transaction::quorum_commitment::QuorumFinalizationCommitment;
+use crate::bls_sig_utils::{BLSPublicKey, BLSSignature};
+use crate::consensus::encode::{self, Decodable, Encodable};
+use crate::hash_types::{BlockHash, ProTxHash, PubkeyHash, QuorumHash};
+use crate::internal_macros::impl_consensus_encoding;
+use crate::io;
+use crate::merkle_tree::PartialMerkleTree;
+use crate::prelude::*;
+
+/// Protocol version from which `mnlistdiff` carries the quorum ChainLock signatures.
+pub const MNLISTDIFF_CHAINLOCKS_VERSION: u32 = 70230;
+
+/// `getmnlistd` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetMnListDiff {
+    /// Block the requester's masternode list is at, or all zeros for the full list
+    pub base_block_hash: BlockHash,
+    /// Block to compute the masternode list at
+    pub block_hash: BlockHash,
+}
+impl_consensus_encoding!(GetMnListDiff, base_block_hash, block_hash);
+
+/// Masternode type of a [`MasternodeListEntry`].
+pub const MASTERNODE_TYPE_REGULAR: u16 = 0;
+/// Masternode type of an evonode, which also serves Platform.
+pub const MASTERNODE_TYPE_EVO: u16 = 1;
+
+/// An entry of the simplified masternode list.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MasternodeListEntry {
+    /// Entry version; 1 uses legacy BLS operator keys, 2 basic ones and adds the type
+    pub version: u16,
+    /// Hash of the masternode's `ProRegTx`
+    pub pro_reg_tx_hash: ProTxHash,
+    /// Hash of the block at which the masternode was confirmed
+    pub confirmed_hash: BlockHash,
+    /// Address the masternode serves the network on
+    pub service_address: SocketAddr,
+    /// BLS operator key
+    pub operator_public_key: BLSPublicKey,
+    /// Hash of the voting key
+    pub key_id_voting: PubkeyHash,
+    /// Whether the masternode is not PoSe banned
+    pub is_valid: bool,
+    /// Masternode type, only encoded from version 2
+    pub mn_type: u16,
+    /// Platform HTTP port and node id, only encoded for evonodes
+    pub platform: Option<(u16, [u8; 20])>,
+}
+
+impl Encodable for MasternodeListEntry {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.version.consensus_encode(w)?;
+        len += self.pro_reg_tx_hash.consensus_encode(w)?;
+        len += self.confirmed_hash.consensus_encode(w)?;
+        let ip = match self.service_address.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        len += ip.octets().consensus_encode(w)?;
+        len += self.service_address.port().to_be_bytes().consensus_encode(w)?;
+        len += self.operator_public_key.consensus_encode(w)?;
+        len += self.key_id_voting.consensus_encode(w)?;
+        len += self.is_valid.consensus_encode(w)?;
+        if self.version >= 2 {
+            len += self.mn_type.consensus_encode(w)?;
+            if self.mn_type == MASTERNODE_TYPE_EVO {
+                let (http_port, node_id) = self.platform.unwrap_or_default();
+                len += http_port.consensus_encode(w)?;
+                len += node_id.consensus_encode(w)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for MasternodeListEntry {
+    fn consensus_decode<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let version = u16::consensus_decode(r)?;
+        let pro_reg_tx_hash = ProTxHash::consensus_decode(r)?;
+        let confirmed_hash = BlockHash::consensus_decode(r)?;
+        let ip = Ipv6Addr::from(<[u8; 16]>::consensus_decode(r)?);
+        let port = u16::from_be_bytes(Decodable::consensus_decode(r)?);
+        let ip = match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        };
+        let operator_public_key = BLSPublicKey::consensus_decode(r)?;
+        let key_id_voting = PubkeyHash::consensus_decode(r)?;
+        let is_valid = bool::consensus_decode(r)?;
+        let (mn_type, platform) = if version >= 2 {
+            let mn_type = u16::consensus_decode(r)?;
+            let platform = if mn_type == MASTERNODE_TYPE_EVO {
+                Some((u16::consensus_decode(r)?, Decodable::consensus_decode(r)?))
+            } else {
+                None
+            };
+            (mn_type, platform)
+        } else {
+            (MASTERNODE_TYPE_REGULAR, None)
+        };
+        Ok(MasternodeListEntry {
+            version,
+            pro_reg_tx_hash,
+            confirmed_hash,
+            service_address: SocketAddr::new(ip, port),
+            operator_public_key,
+            key_id_voting,
+            is_valid,
+            mn_type,
+            platform,
+        })
+    }
+}
+
+/// A quorum removed from the active set.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DeletedQuorum {
+    /// LLMQ type of the quorum
+    pub llmq_type: u8,
+    /// Hash of the quorum
+    pub quorum_hash: QuorumHash,
+}
+impl_consensus_encoding!(DeletedQuorum, llmq_type, quorum_hash);
+
+/// A ChainLock signature shared by the new quorums at the given indexes.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct QuorumCLSigObject {
+    /// ChainLock signature of the block each quorum was created at
+    pub signature: BLSSignature,
+    /// Indexes into [`MnListDiff::new_quorums`] of the quorums the signature is for
+    pub index_set: Vec<u16>,
+}
+impl_consensus_encoding!(QuorumCLSigObject, signature, index_set);
+
+/// `mnlistdiff` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MnListDiff {
+    /// Diff version
+    pub version: u16,
+    /// Block the diff starts from
+    pub base_block_hash: BlockHash,
+    /// Block the diff ends at
+    pub block_hash: BlockHash,
+    /// Proof that the coinbase transaction is part of the block
+    pub coinbase_merkle_tree: PartialMerkleTree,
+    /// Coinbase transaction of the block, committing to the masternode list and quorums
+    pub coinbase_tx: Transaction,
+    /// Masternodes removed from the list
+    pub deleted_masternodes: Vec<ProTxHash>,
+    /// Masternodes added or changed
+    pub new_masternodes: Vec<MasternodeListEntry>,
+    /// Quorums removed from the active set
+    pub deleted_quorums: Vec<DeletedQuorum>,
+    /// Quorums added to the active set
+    pub new_quorums: Vec<QuorumFinalizationCommitment>,
+    /// ChainLock signatures of the blocks the new quorums were created at
+    pub quorums_chainlock_signatures: Vec<QuorumCLSigObject>,
+}
+impl_consensus_encoding!(
+    MnListDiff,
+    version,
+    base_block_hash,
+    block_hash,
+    coinbase_merkle_tree,
+    coinbase_tx,
+    deleted_masternodes,
+    new_masternodes,
+    deleted_quorums,
+    new_quorums,
+    quorums_chainlock_signatures
+);
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use hashes::Hash;
+
+    use super::*;
+    use crate::blockdata::script::ScriptBuf;
+    use crate::blockdata::transaction::txin::TxIn;
+    use crate::blockdata::transaction::txout::TxOut;
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::hash_types::QuorumVVecHash;
+
+    pub(crate) fn entry(version: u16, mn_type: u16) -> MasternodeListEntry {
+        MasternodeListEntry {
+            version,
+            pro_reg_tx_hash: ProTxHash::hash(&[version as u8, mn_type as u8]),
+            confirmed_hash: BlockHash::all_zeros(),
+            service_address: "1.2.3.4:9999".parse().unwrap(),
+            operator_public_key: BLSPublicKey::from([1u8; 48]),
+            key_id_voting: PubkeyHash::hash(&[2]),
+            is_valid: true,
+            mn_type,
+            platform: (mn_type == MASTERNODE_TYPE_EVO).then_some((443, [3u8; 20])),
+        }
+    }
+
+    pub(crate) fn diff() -> MnListDiff {
+        let coinbase_tx = Transaction {
+            version: 3,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![TxOut { value: 1, script_pubkey: ScriptBuf::new() }],
+            special_transaction_payload: None,
+        };
+        MnListDiff {
+            version: 1,
+            base_block_hash: BlockHash::all_zeros(),
+            block_hash: BlockHash::hash(&[1]),
+            coinbase_merkle_tree: PartialMerkleTree::from_txids(&[coinbase_tx.txid()], &[true]),
+            coinbase_tx,
+            deleted_masternodes: vec![ProTxHash::hash(&[9])],
+            new_masternodes: vec![entry(1, 0), entry(2, 0), entry(2, 1)],
+            deleted_quorums: vec![DeletedQuorum {
+                llmq_type: 1,
+                quorum_hash: QuorumHash::hash(&[4]),
+            }],
+            new_quorums: vec![QuorumFinalizationCommitment {
+                version: 2,
+                llmq_type: 103,
+                quorum_hash: QuorumHash::hash(&[5]),
+                quorum_index: Some(1),
+                signers: vec![true, false, true],
+                valid_members: vec![true, true, true],
+                quorum_public_key: BLSPublicKey::from([6u8; 48]),
+                quorum_vvec_hash: QuorumVVecHash::hash(&[7]),
+                quorum_sig: BLSSignature::from([8u8; 96]),
+                sig: BLSSignature::from([9u8; 96]),
+            }],
+            quorums_chainlock_signatures: vec![QuorumCLSigObject {
+                signature: BLSSignature::from([10u8; 96]),
+                index_set: vec![0],
+            }],
+        }
+    }
+
+    #[test]
+    fn masternode_list_entry_encoding() {
+        // Version 1 entries have no type; evonodes carry the platform fields.
+        assert_eq!(serialize(&entry(1, 0)).len(), 2 + 32 + 32 + 18 + 48 + 20 + 1);
+        assert_eq!(serialize(&entry(2, 0)).len(), 2 + 32 + 32 + 18 + 48 + 20 + 1 + 2);
+        assert_eq!(serialize(&entry(2, 1)).len(), 2 + 32 + 32 + 18 + 48 + 20 + 1 + 2 + 22);
+
+        for entry in [entry(1, 0), entry(2, 0), entry(2, 1)] {
+            let encoded = serialize(&entry);
+            let mut ip = [0u8; 16];
+            ip[10..].copy_from_slice(&[0xff, 0xff, 1, 2, 3, 4]);
+            assert_eq!(&encoded[66..82], &ip);
+            assert_eq!(&encoded[82..84], &[0x27, 0x0f]);
+            assert_eq!(deserialize::<MasternodeListEntry>(&encoded).unwrap(), entry);
+        }
+    }
+
+    #[test]
+    fn mnlistdiff_round_trip() {
+        let diff = diff();
+        let encoded = serialize(&diff);
+        let decoded: MnListDiff = deserialize(&encoded).unwrap();
+        // The partial merkle tree pads its flag bits to whole bytes when decoding.
+        assert_eq!(serialize(&decoded), encoded);
+        assert_eq!(decoded.new_masternodes, diff.new_masternodes);
+        assert_eq!(decoded.new_quorums, diff.new_quorums);
+        assert_eq!(decoded.coinbase_merkle_tree.num_transactions(), 1);
+
+        let request = GetMnListDiff {
+            base_block_hash: BlockHash::all_zeros(),
+            block_hash: BlockHash::hash(&[1]),
+        };
+        assert_eq!(deserialize::<GetMnListDiff>(&serialize(&request)).unwrap(), request);
+    }
+}
@@ -32,19 +32,45 @@ pub mod constants;
 #[cfg(feature = "std")]
 pub mod address;
 #[cfg(feature = "std")]
+pub mod addrman;
+#[cfg(feature = "std")]
 pub use self::address::Address;
 #[cfg(feature = "std")]
+pub mod decoder;
+#[cfg(feature = "std")]
+pub mod filter_headers;
+#[cfg(feature = "std")]
 pub mod message;
 #[cfg(feature = "std")]
 pub mod message_blockdata;
 #[cfg(feature = "std")]
 pub mod message_bloom;
 #[cfg(feature = "std")]
+pub mod message_coinjoin;
+#[cfg(feature = "std")]
 pub mod message_compact_blocks;
 #[cfg(feature = "std")]
+pub mod message_dkg;
+#[cfg(feature = "std")]
 pub mod message_filter;
 #[cfg(feature = "std")]
+pub mod message_governance;
+#[cfg(feature = "std")]
+pub mod message_headers2;
+#[cfg(feature = "std")]
+pub mod message_masternode;
+#[cfg(feature = "std")]
 pub mod message_network;
+#[cfg(feature = "std")]
+pub mod message_qrinfo;
+#[cfg(feature = "std")]
+pub mod message_signing;
+#[cfg(feature = "std")]
+pub mod message_sml;
+#[cfg(feature = "std")]
+pub mod peer;
+#[cfg(feature = "std")]
+pub mod seeds;
 
 /// Network error
 #[derive(Debug)]
@@ -0,0 +1,796 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Sans-IO peer connection state machine.
+//!
+//! A [`Peer`] tracks one P2P connection without doing any IO itself. The caller feeds it the
+//! bytes read from the transport with [`Peer::receive`] and calls [`Peer::tick`] periodically;
+//! both return a [`PeerOutput`] with the messages to write back and the [`PeerEvent`]s for the
+//! application. Time is passed in by the caller as the duration since the UNIX epoch, so the
+//! same state machine runs over std sockets, an async runtime or in-memory pipes in tests.
+//!
+//! The peer performs the `version`/`verack` handshake, including Dash's `mnauth` challenge and
+//! masternode connection flag, negotiates `sendaddrv2`, `sendheaders`, `sendheaders2`,
+//! `sendcmpct` and `feefilter`, keeps the connection alive with `ping`/`pong`, and scores
+//! misbehavior until the peer has to be disconnected.
+//!
+
+use core::fmt;
+use core::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::Amount;
+use crate::consensus::encode::serialize;
+use crate::network::address::Address;
+use crate::network::constants::{
+    ADDRV2_PROTO_VERSION, MIN_PEER_PROTO_VERSION, Network, PROTOCOL_VERSION, ServiceFlags,
+};
+use crate::network::decoder::{DecodeError, MessageDecoder};
+use crate::network::message::{NetworkMessage, RawNetworkMessage};
+use crate::network::message_compact_blocks::SendCmpct;
+use crate::network::message_network::VersionMessage;
+use crate::prelude::*;
+
+/// How often a connected peer is pinged.
+pub const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+
+/// How long a peer may take to answer a ping before it is disconnected.
+pub const TIMEOUT_INTERVAL: Duration = Duration::from_secs(20 * 60);
+
+/// How long a peer may take to complete the handshake before it is disconnected.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Misbehavior score at which a peer is disconnected.
+pub const DISCOURAGEMENT_THRESHOLD: u32 = 100;
+
+/// Compact blocks protocol version used by Dash.
+const COMPACT_BLOCKS_VERSION: u64 = 1;
+
+/// Local settings for a peer connection.
+#[derive(Clone, Debug)]
+pub struct PeerConfig {
+    /// Network the peer must be on.
+    pub network: Network,
+    /// Protocol version to advertise.
+    pub protocol_version: u32,
+    /// Oldest protocol version accepted from the peer.
+    pub min_protocol_version: u32,
+    /// Services to advertise.
+    pub services: ServiceFlags,
+    /// User agent to advertise.
+    pub user_agent: String,
+    /// Height of our best block.
+    pub start_height: i32,
+    /// Whether the peer should relay transactions before a bloom filter is loaded.
+    pub relay: bool,
+    /// Challenge for the peer to sign in `mnauth`, if we want it to authenticate as a masternode.
+    pub mnauth_challenge: Option<[u8; 32]>,
+    /// Whether we open the connection as a masternode.
+    pub masternode_connection: bool,
+    /// Whether to ask for new blocks to be announced with headers, compressed if the peer can.
+    pub send_headers: bool,
+    /// Whether to support compact blocks, which are then requested after new blocks are
+    /// announced.
+    pub compact_blocks: bool,
+    /// Whether to ask the peer to send new blocks as compact blocks right away, without
+    /// announcing them first. Only used with `compact_blocks`.
+    pub high_bandwidth_compact_blocks: bool,
+    /// Minimum fee rate, in duffs per kB, of transactions the peer should relay to us.
+    pub fee_filter: Option<i64>,
+    /// How often to ping the peer once connected.
+    pub ping_interval: Duration,
+    /// How long to wait for a pong.
+    pub ping_timeout: Duration,
+    /// How long to wait for the handshake to complete.
+    pub handshake_timeout: Duration,
+    /// Misbehavior score at which to disconnect.
+    pub discouragement_threshold: u32,
+}
+
+impl PeerConfig {
+    /// Creates a configuration for `network` with Dash Core's defaults.
+    pub fn new(network: Network) -> PeerConfig {
+        PeerConfig {
+            network,
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PEER_PROTO_VERSION,
+            services: ServiceFlags::NONE,
+            user_agent: String::from("/rust-dashcore/"),
+            start_height: 0,
+            relay: true,
+            mnauth_challenge: None,
+            masternode_connection: false,
+            send_headers: true,
+            compact_blocks: false,
+            high_bandwidth_compact_blocks: false,
+            fee_filter: None,
+            ping_interval: PING_INTERVAL,
+            ping_timeout: TIMEOUT_INTERVAL,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            discouragement_threshold: DISCOURAGEMENT_THRESHOLD,
+        }
+    }
+}
+
+/// Which side opened the connection.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// The peer connected to us; it sends `version` first.
+    Inbound,
+    /// We connected to the peer; we send `version` first.
+    Outbound,
+}
+
+/// Why a peer was disconnected.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    /// The peer echoed our own `version` nonce, so we connected to ourselves.
+    SelfConnection,
+    /// The peer's protocol version is too old.
+    ObsoleteVersion(u32),
+    /// A message carried the magic of another network.
+    WrongNetwork(u32),
+    /// A message header announced a payload larger than allowed for its command.
+    MessageTooLarge(usize),
+    /// The handshake did not complete in time.
+    HandshakeTimeout,
+    /// A ping was not answered in time.
+    PingTimeout,
+    /// The misbehavior score reached the threshold.
+    Misbehavior(u32),
+    /// The peer broke the protocol in a way that warrants an immediate disconnect.
+    ProtocolViolation(&'static str),
+    /// The application asked to disconnect.
+    Requested,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisconnectReason::SelfConnection => write!(f, "connected to self"),
+            DisconnectReason::ObsoleteVersion(version) =>
+                write!(f, "obsolete protocol version {}", version),
+            DisconnectReason::WrongNetwork(magic) => write!(f, "wrong network magic {:#x}", magic),
+            DisconnectReason::MessageTooLarge(size) => write!(f, "message of {} bytes", size),
+            DisconnectReason::HandshakeTimeout => write!(f, "handshake timed out"),
+            DisconnectReason::PingTimeout => write!(f, "ping timed out"),
+            DisconnectReason::Misbehavior(score) => write!(f, "misbehavior score {}", score),
+            DisconnectReason::ProtocolViolation(reason) => write!(f, "{}", reason),
+            DisconnectReason::Requested => write!(f, "disconnect requested"),
+        }
+    }
+}
+
+/// Something the application should know about.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum PeerEvent {
+    /// The handshake completed; the peer's `version` is available from [`Peer::remote_version`].
+    Connected,
+    /// A message not handled by the state machine itself.
+    Message(NetworkMessage),
+    /// The peer answered a ping.
+    Pong {
+        /// Time between sending the ping and receiving the pong.
+        latency: Duration,
+    },
+    /// The peer misbehaved.
+    Misbehaved {
+        /// Score added for this offense.
+        score: u32,
+        /// Total score of the peer.
+        total: u32,
+        /// What the peer did.
+        reason: &'static str,
+    },
+    /// The peer must be disconnected. No further output is produced.
+    Disconnected(DisconnectReason),
+}
+
+/// What a [`Peer`] wants done after an input.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PeerOutput {
+    /// Messages to send to the peer, in order.
+    pub messages: Vec<RawNetworkMessage>,
+    /// Events for the application, in order.
+    pub events: Vec<PeerEvent>,
+}
+
+impl PeerOutput {
+    /// Returns the messages serialized back to back, ready to be written to the transport.
+    pub fn to_bytes(&self) -> Vec<u8> { self.messages.iter().flat_map(serialize).collect() }
+
+    /// Returns whether there is nothing to send and nothing to report.
+    pub fn is_empty(&self) -> bool { self.messages.is_empty() && self.events.is_empty() }
+}
+
+/// The state of one P2P connection.
+#[derive(Clone, Debug)]
+pub struct Peer {
+    config: PeerConfig,
+    direction: Direction,
+    remote: Address,
+    nonce: u64,
+    created: Duration,
+    decoder: MessageDecoder,
+    remote_version: Option<VersionMessage>,
+    verack_received: bool,
+    disconnected: Option<DisconnectReason>,
+    wants_headers: bool,
+    wants_compressed_headers: bool,
+    wants_addrv2: bool,
+    compact_blocks: Option<SendCmpct>,
+    fee_filter: Option<i64>,
+    ping_count: u64,
+    ping_sent: Option<(u64, Duration)>,
+    last_ping: Option<Duration>,
+    latency: Option<Duration>,
+    misbehavior: u32,
+}
+
+impl Peer {
+    fn new(
+        config: PeerConfig,
+        direction: Direction,
+        remote: Address,
+        nonce: u64,
+        now: Duration,
+    ) -> Peer {
+        let decoder = MessageDecoder::new(config.network);
+        Peer {
+            config,
+            direction,
+            remote,
+            nonce,
+            created: now,
+            decoder,
+            remote_version: None,
+            verack_received: false,
+            disconnected: None,
+            wants_headers: false,
+            wants_compressed_headers: false,
+            wants_addrv2: false,
+            compact_blocks: None,
+            fee_filter: None,
+            ping_count: 0,
+            ping_sent: None,
+            last_ping: None,
+            latency: None,
+            misbehavior: 0,
+        }
+    }
+
+    /// Starts a connection we opened to `remote`, returning the `version` message to send.
+    ///
+    /// `nonce` must be random; it detects connections to ourselves and seeds the ping nonces.
+    pub fn outbound(
+        config: PeerConfig,
+        remote: Address,
+        nonce: u64,
+        now: Duration,
+    ) -> (Peer, PeerOutput) {
+        let peer = Peer::new(config, Direction::Outbound, remote, nonce, now);
+        let mut out = PeerOutput::default();
+        peer.send_version(now, &mut out);
+        (peer, out)
+    }
+
+    /// Starts a connection `remote` opened to us; we answer its `version` with ours.
+    pub fn inbound(config: PeerConfig, remote: Address, nonce: u64, now: Duration) -> Peer {
+        Peer::new(config, Direction::Inbound, remote, nonce, now)
+    }
+
+    /// Which side opened the connection.
+    pub fn direction(&self) -> Direction { self.direction }
+
+    /// The address of the peer.
+    pub fn remote(&self) -> &Address { &self.remote }
+
+    /// The peer's `version` message, once received.
+    pub fn remote_version(&self) -> Option<&VersionMessage> { self.remote_version.as_ref() }
+
+    /// Returns whether the handshake completed and the peer is not disconnected.
+    pub fn is_connected(&self) -> bool {
+        self.disconnected.is_none() && self.remote_version.is_some() && self.verack_received
+    }
+
+    /// Why the peer was disconnected, if it was.
+    pub fn disconnect_reason(&self) -> Option<&DisconnectReason> { self.disconnected.as_ref() }
+
+    /// Whether the peer asked for blocks to be announced with `headers`.
+    pub fn wants_headers(&self) -> bool { self.wants_headers }
+
+    /// Whether the peer asked for blocks to be announced with `headers2`.
+    pub fn wants_compressed_headers(&self) -> bool { self.wants_compressed_headers }
+
+    /// Whether the peer asked for addresses in `addrv2`.
+    pub fn wants_addrv2(&self) -> bool { self.wants_addrv2 }
+
+    /// The peer's compact block preferences, if it sent `sendcmpct`.
+    pub fn compact_blocks(&self) -> Option<SendCmpct> { self.compact_blocks }
+
+    /// The minimum fee rate, in duffs per kB, of transactions the peer wants.
+    pub fn fee_filter(&self) -> Option<i64> { self.fee_filter }
+
+    /// The round trip time of the last answered ping.
+    pub fn latency(&self) -> Option<Duration> { self.latency }
+
+    /// The peer's misbehavior score.
+    pub fn misbehavior_score(&self) -> u32 { self.misbehavior }
+
+    /// Wraps `payload` for sending to this peer.
+    pub fn message(&self, payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage { magic: self.config.network.magic(), payload }
+    }
+
+    /// Processes bytes received from the peer, which need not align with message boundaries.
+    pub fn receive(&mut self, bytes: &[u8], now: Duration) -> PeerOutput {
+        let mut out = PeerOutput::default();
+        if self.disconnected.is_some() {
+            return out;
+        }
+        self.decoder.push(bytes);
+        while self.disconnected.is_none() {
+            match self.decoder.next_message() {
+                Ok(Some(message)) => self.handle(message.payload, now, &mut out),
+                Ok(None) => break,
+                Err(DecodeError::WrongMagic(magic)) =>
+                    self.disconnect(DisconnectReason::WrongNetwork(magic), &mut out),
+                Err(DecodeError::Oversized { size, .. }) =>
+                    self.disconnect(DisconnectReason::MessageTooLarge(size), &mut out),
+                Err(DecodeError::InvalidChecksum { .. }) => {}
+                Err(DecodeError::TooManyItems { .. }) =>
+                    self.misbehave(20, "oversized message", &mut out),
+                Err(_) => self.misbehave(10, "malformed message", &mut out),
+            }
+        }
+        self.maybe_ping(now, &mut out);
+        out
+    }
+
+    /// Drives the timers: handshake and ping timeouts, and sending pings.
+    pub fn tick(&mut self, now: Duration) -> PeerOutput {
+        let mut out = PeerOutput::default();
+        if self.disconnected.is_some() {
+            return out;
+        }
+        if !self.is_connected() {
+            if now.saturating_sub(self.created) >= self.config.handshake_timeout {
+                self.disconnect(DisconnectReason::HandshakeTimeout, &mut out);
+            }
+            return out;
+        }
+        if let Some((_, sent)) = self.ping_sent {
+            if now.saturating_sub(sent) >= self.config.ping_timeout {
+                self.disconnect(DisconnectReason::PingTimeout, &mut out);
+                return out;
+            }
+        }
+        self.maybe_ping(now, &mut out);
+        out
+    }
+
+    /// Adds `score` to the peer's misbehavior score, for offenses only the application can
+    /// detect, such as an invalid block.
+    pub fn misbehaving(&mut self, score: u32, reason: &'static str) -> PeerOutput {
+        let mut out = PeerOutput::default();
+        if self.disconnected.is_none() {
+            self.misbehave(score, reason, &mut out);
+        }
+        out
+    }
+
+    /// Disconnects the peer at the application's request.
+    pub fn disconnect_requested(&mut self) -> PeerOutput {
+        let mut out = PeerOutput::default();
+        if self.disconnected.is_none() {
+            self.disconnect(DisconnectReason::Requested, &mut out);
+        }
+        out
+    }
+
+    fn handle(&mut self, message: NetworkMessage, now: Duration, out: &mut PeerOutput) {
+        match message {
+            NetworkMessage::Version(version) => self.handle_version(version, now, out),
+            NetworkMessage::Verack =>
+                if self.remote_version.is_none() {
+                    self.misbehave(1, "verack before version", out);
+                } else if !self.verack_received {
+                    self.verack_received = true;
+                    self.on_connected(out);
+                },
+            NetworkMessage::SendAddrV2 =>
+                if self.verack_received {
+                    self.disconnect(
+                        DisconnectReason::ProtocolViolation("sendaddrv2 after verack"),
+                        out,
+                    );
+                } else {
+                    self.wants_addrv2 = true;
+                },
+            _ if !self.is_connected() => self.misbehave(1, "message before handshake", out),
+            NetworkMessage::SendHeaders => self.wants_headers = true,
+            NetworkMessage::SendHeaders2 => self.wants_compressed_headers = true,
+            NetworkMessage::SendCmpct(send_cmpct) =>
+                if send_cmpct.version == COMPACT_BLOCKS_VERSION {
+                    self.compact_blocks = Some(send_cmpct);
+                },
+            NetworkMessage::FeeFilter(fee_rate) =>
+                if (0..=Amount::MAX_MONEY.to_sat() as i64).contains(&fee_rate) {
+                    self.fee_filter = Some(fee_rate);
+                },
+            NetworkMessage::Ping(nonce) =>
+                out.messages.push(self.message(NetworkMessage::Pong(nonce))),
+            NetworkMessage::Pong(nonce) => match self.ping_sent {
+                Some((sent_nonce, sent)) if sent_nonce == nonce => {
+                    let latency = now.saturating_sub(sent);
+                    self.ping_sent = None;
+                    self.latency = Some(latency);
+                    out.events.push(PeerEvent::Pong { latency });
+                }
+                // A zero nonce cancels the outstanding ping; other nonces are stale.
+                Some(_) if nonce == 0 => self.ping_sent = None,
+                _ => {}
+            },
+            message => out.events.push(PeerEvent::Message(message)),
+        }
+    }
+
+    fn handle_version(&mut self, version: VersionMessage, now: Duration, out: &mut PeerOutput) {
+        if self.remote_version.is_some() {
+            self.misbehave(1, "duplicate version", out);
+            return;
+        }
+        if version.nonce == self.nonce {
+            self.disconnect(DisconnectReason::SelfConnection, out);
+            return;
+        }
+        if version.version < self.config.min_protocol_version {
+            self.disconnect(DisconnectReason::ObsoleteVersion(version.version), out);
+            return;
+        }
+        if self.direction == Direction::Inbound {
+            self.send_version(now, out);
+        }
+        if version.version.min(self.config.protocol_version) >= ADDRV2_PROTO_VERSION {
+            out.messages.push(self.message(NetworkMessage::SendAddrV2));
+        }
+        out.messages.push(self.message(NetworkMessage::Verack));
+        self.remote_version = Some(version);
+        if self.verack_received {
+            self.on_connected(out);
+        }
+    }
+
+    fn send_version(&self, now: Duration, out: &mut PeerOutput) {
+        let sender = Address::new(
+            &SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            self.config.services,
+        );
+        let mut version = VersionMessage::new(
+            self.config.services,
+            now.as_secs() as i64,
+            self.remote.clone(),
+            sender,
+            self.nonce,
+            self.config.user_agent.clone(),
+            self.config.start_height,
+        );
+        version.version = self.config.protocol_version;
+        version.relay = self.config.relay;
+        version.mnauth_challenge = self.config.mnauth_challenge;
+        version.masternode_connection = Some(self.config.masternode_connection);
+        out.messages.push(self.message(NetworkMessage::Version(version)));
+    }
+
+    fn on_connected(&mut self, out: &mut PeerOutput) {
+        let services = self.remote_version.as_ref().map_or(ServiceFlags::NONE, |v| v.services);
+        out.events.push(PeerEvent::Connected);
+        if self.config.send_headers {
+            out.messages.push(self.message(NetworkMessage::SendHeaders));
+            if services.has(ServiceFlags::HEADERS_COMPRESSED) {
+                out.messages.push(self.message(NetworkMessage::SendHeaders2));
+            }
+        }
+        if self.config.compact_blocks {
+            out.messages.push(self.message(NetworkMessage::SendCmpct(SendCmpct {
+                send_compact: self.config.high_bandwidth_compact_blocks,
+                version: COMPACT_BLOCKS_VERSION,
+            })));
+        }
+        if let Some(fee_rate) = self.config.fee_filter {
+            out.messages.push(self.message(NetworkMessage::FeeFilter(fee_rate)));
+        }
+    }
+
+    fn maybe_ping(&mut self, now: Duration, out: &mut PeerOutput) {
+        if !self.is_connected() || self.ping_sent.is_some() {
+            return;
+        }
+        if self.last_ping.is_some_and(|last| now.saturating_sub(last) < self.config.ping_interval) {
+            return;
+        }
+        let nonce = self.next_ping_nonce();
+        self.ping_sent = Some((nonce, now));
+        self.last_ping = Some(now);
+        out.messages.push(self.message(NetworkMessage::Ping(nonce)));
+    }
+
+    /// Derives a non-zero ping nonce from the connection nonce with SplitMix64.
+    fn next_ping_nonce(&mut self) -> u64 {
+        loop {
+            self.ping_count += 1;
+            let mut z =
+                self.nonce.wrapping_add(self.ping_count.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            if z != 0 {
+                return z;
+            }
+        }
+    }
+
+    fn misbehave(&mut self, score: u32, reason: &'static str, out: &mut PeerOutput) {
+        self.misbehavior = self.misbehavior.saturating_add(score);
+        out.events.push(PeerEvent::Misbehaved { score, total: self.misbehavior, reason });
+        if self.misbehavior >= self.config.discouragement_threshold {
+            self.disconnect(DisconnectReason::Misbehavior(self.misbehavior), out);
+        }
+    }
+
+    fn disconnect(&mut self, reason: DisconnectReason, out: &mut PeerOutput) {
+        self.disconnected = Some(reason.clone());
+        out.events.push(PeerEvent::Disconnected(reason));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::message::MAX_MSG_SIZE;
+
+    const START: Duration = Duration::from_secs(1_700_000_000);
+
+    fn address() -> Address {
+        Address::new(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 19999), ServiceFlags::NONE)
+    }
+
+    fn config() -> PeerConfig {
+        let mut config = PeerConfig::new(Network::Testnet);
+        config.services = ServiceFlags::NETWORK | ServiceFlags::HEADERS_COMPRESSED;
+        config.mnauth_challenge = Some([7; 32]);
+        config
+    }
+
+    /// Delivers `output` to `peer` one byte at a time, as a slow transport would.
+    fn deliver(output: &PeerOutput, peer: &mut Peer, now: Duration) -> PeerOutput {
+        let mut result = PeerOutput::default();
+        for byte in output.to_bytes() {
+            let out = peer.receive(&[byte], now);
+            result.messages.extend(out.messages);
+            result.events.extend(out.events);
+        }
+        result
+    }
+
+    fn commands(output: &PeerOutput) -> Vec<&'static str> {
+        output.messages.iter().map(|m| m.cmd()).collect()
+    }
+
+    /// Connects an outbound and an inbound peer over an in-memory pipe.
+    fn connect(outbound: PeerConfig, inbound: PeerConfig) -> (Peer, Peer) {
+        let (mut a, hello) = Peer::outbound(outbound, address(), 1, START);
+        let mut b = Peer::inbound(inbound, address(), 2, START);
+        assert_eq!(commands(&hello), ["version"]);
+
+        let reply = deliver(&hello, &mut b, START);
+        assert_eq!(commands(&reply).first(), Some(&"version"));
+        assert_eq!(commands(&reply).last(), Some(&"verack"));
+        assert!(!b.is_connected());
+
+        let after_verack = deliver(&reply, &mut a, START);
+        assert!(a.is_connected());
+        assert_eq!(after_verack.events, [PeerEvent::Connected]);
+
+        let last = deliver(&after_verack, &mut b, START);
+        assert!(b.is_connected());
+        assert!(last.events.contains(&PeerEvent::Connected));
+        deliver(&last, &mut a, START);
+        (a, b)
+    }
+
+    #[test]
+    fn handshake() {
+        let mut inbound = config();
+        inbound.compact_blocks = true;
+        inbound.high_bandwidth_compact_blocks = true;
+        inbound.fee_filter = Some(1000);
+        let (a, b) = connect(config(), inbound);
+
+        let version = b.remote_version().unwrap();
+        assert_eq!(version.nonce, 1);
+        assert_eq!(version.mnauth_challenge, Some([7; 32]));
+        assert_eq!(version.masternode_connection, Some(false));
+        assert_eq!(a.remote_version().unwrap().user_agent, "/rust-dashcore/");
+
+        // Both asked for (compressed) headers; the inbound side also for compact blocks and a
+        // fee filter. Both sides negotiate addrv2.
+        assert!(a.wants_headers() && a.wants_compressed_headers());
+        assert!(b.wants_headers() && b.wants_compressed_headers());
+        assert_eq!(a.compact_blocks(), Some(SendCmpct { send_compact: true, version: 1 }));
+        assert_eq!(a.fee_filter(), Some(1000));
+        assert_eq!(b.compact_blocks(), None);
+        assert!(a.wants_addrv2() && b.wants_addrv2());
+        assert_eq!(a.misbehavior_score() + b.misbehavior_score(), 0);
+
+        // Compact blocks are announced first unless high bandwidth mode is asked for.
+        let mut inbound = config();
+        inbound.compact_blocks = true;
+        let (a, _) = connect(config(), inbound);
+        assert_eq!(a.compact_blocks(), Some(SendCmpct { send_compact: false, version: 1 }));
+    }
+
+    #[test]
+    fn addrv2_negotiation() {
+        let mut outbound = config();
+        outbound.protocol_version = ADDRV2_PROTO_VERSION;
+        let mut inbound = config();
+        inbound.protocol_version = ADDRV2_PROTO_VERSION;
+        let (a, b) = connect(outbound, inbound);
+        assert!(a.wants_addrv2() && b.wants_addrv2());
+
+        let mut inbound = config();
+        inbound.protocol_version = ADDRV2_PROTO_VERSION - 1;
+        let (a, b) = connect(config(), inbound);
+        assert!(!a.wants_addrv2() && !b.wants_addrv2());
+
+        // sendaddrv2 after the handshake is a protocol violation.
+        let (mut a, mut b) = connect(config(), config());
+        let late =
+            PeerOutput { messages: vec![a.message(NetworkMessage::SendAddrV2)], events: vec![] };
+        let out = deliver(&late, &mut b, START);
+        assert_eq!(
+            out.events,
+            [PeerEvent::Disconnected(DisconnectReason::ProtocolViolation(
+                "sendaddrv2 after verack"
+            ))]
+        );
+        assert_eq!(
+            a.disconnect_requested().events,
+            [PeerEvent::Disconnected(DisconnectReason::Requested)]
+        );
+    }
+
+    #[test]
+    fn ping_pong() {
+        // Each side pinged on connecting; `a` got its pong back, `b` did not.
+        let (mut a, mut b) = connect(config(), config());
+        assert_eq!(a.latency(), Some(Duration::ZERO));
+        assert_eq!(b.latency(), None);
+
+        // The next ping waits for the interval.
+        assert!(a.tick(START + Duration::from_secs(60)).is_empty());
+        let sent = START + PING_INTERVAL;
+        let ping = a.tick(sent);
+        assert_eq!(commands(&ping), ["ping"]);
+        let NetworkMessage::Ping(nonce) = ping.messages[0].payload else { unreachable!() };
+        assert_ne!(nonce, 0);
+
+        let pong = deliver(&ping, &mut b, sent);
+        assert_eq!(commands(&pong), ["pong"]);
+        let out = deliver(&pong, &mut a, sent + Duration::from_millis(250));
+        assert_eq!(out.events, [PeerEvent::Pong { latency: Duration::from_millis(250) }]);
+        assert_eq!(a.latency(), Some(Duration::from_millis(250)));
+
+        // A stale pong does not answer a ping, and an unanswered ping times out.
+        let sent = sent + PING_INTERVAL;
+        let ping = a.tick(sent);
+        let NetworkMessage::Ping(nonce) = ping.messages[0].payload else { unreachable!() };
+        let stale = PeerOutput {
+            messages: vec![b.message(NetworkMessage::Pong(nonce ^ 1))],
+            events: vec![],
+        };
+        assert!(deliver(&stale, &mut a, sent).events.is_empty());
+        assert_eq!(
+            a.tick(sent + TIMEOUT_INTERVAL).events,
+            [PeerEvent::Disconnected(DisconnectReason::PingTimeout)]
+        );
+        assert!(!a.is_connected());
+        assert!(a.tick(sent + 2 * TIMEOUT_INTERVAL).is_empty());
+
+        assert_eq!(
+            b.tick(START + TIMEOUT_INTERVAL).events,
+            [PeerEvent::Disconnected(DisconnectReason::PingTimeout)]
+        );
+    }
+
+    #[test]
+    fn handshake_failures() {
+        // Our own nonce coming back means we connected to ourselves.
+        let (_, hello) = Peer::outbound(config(), address(), 5, START);
+        let mut b = Peer::inbound(config(), address(), 5, START);
+        let out = b.receive(&hello.to_bytes(), START);
+        assert_eq!(out.events, [PeerEvent::Disconnected(DisconnectReason::SelfConnection)]);
+        assert!(out.messages.is_empty());
+
+        let mut old = config();
+        old.protocol_version = 70210;
+        let (_, hello) = Peer::outbound(old, address(), 1, START);
+        let mut b = Peer::inbound(config(), address(), 2, START);
+        assert_eq!(
+            b.receive(&hello.to_bytes(), START).events,
+            [PeerEvent::Disconnected(DisconnectReason::ObsoleteVersion(70210))]
+        );
+
+        // A peer on another network.
+        let (_, hello) = Peer::outbound(PeerConfig::new(Network::Dash), address(), 1, START);
+        let mut b = Peer::inbound(config(), address(), 2, START);
+        assert_eq!(
+            b.receive(&hello.to_bytes(), START).events,
+            [PeerEvent::Disconnected(DisconnectReason::WrongNetwork(Network::Dash.magic()))]
+        );
+
+        // A silent peer.
+        let (mut a, _) = Peer::outbound(config(), address(), 1, START);
+        assert!(a.tick(START + Duration::from_secs(59)).is_empty());
+        assert_eq!(
+            a.tick(START + HANDSHAKE_TIMEOUT).events,
+            [PeerEvent::Disconnected(DisconnectReason::HandshakeTimeout)]
+        );
+    }
+
+    #[test]
+    fn misbehavior() {
+        let mut b = Peer::inbound(config(), address(), 2, START);
+        let early =
+            PeerOutput { messages: vec![b.message(NetworkMessage::GetAddr)], events: vec![] };
+        let out = b.receive(&early.to_bytes(), START);
+        assert_eq!(
+            out.events,
+            [PeerEvent::Misbehaved { score: 1, total: 1, reason: "message before handshake" }]
+        );
+
+        // Oversized and corrupted messages.
+        let mut bytes = early.to_bytes();
+        bytes[16..20].copy_from_slice(&(MAX_MSG_SIZE as u32 + 1).to_le_bytes());
+        let mut c = Peer::inbound(config(), address(), 2, START);
+        assert_eq!(
+            c.receive(&bytes, START).events,
+            [PeerEvent::Disconnected(DisconnectReason::MessageTooLarge(MAX_MSG_SIZE + 1))]
+        );
+        let mut bytes = early.to_bytes();
+        bytes[20] ^= 1;
+        assert!(b.receive(&bytes, START).is_empty());
+
+        // Application messages pass through once connected, and reported offenses add up.
+        let (mut a, mut b) = connect(config(), config());
+        let inv = PeerOutput { messages: vec![a.message(NetworkMessage::GetAddr)], events: vec![] };
+        assert_eq!(
+            deliver(&inv, &mut b, START).events,
+            [PeerEvent::Message(NetworkMessage::GetAddr)]
+        );
+        assert_eq!(b.misbehaving(60, "invalid block").events.len(), 1);
+        let out = b.misbehaving(40, "invalid block");
+        assert_eq!(
+            out.events,
+            [
+                PeerEvent::Misbehaved { score: 40, total: 100, reason: "invalid block" },
+                PeerEvent::Disconnected(DisconnectReason::Misbehavior(100)),
+            ]
+        );
+        assert!(b.receive(&inv.to_bytes(), START).is_empty());
+    }
+}
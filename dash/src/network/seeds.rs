@@ -0,0 +1,345 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Fixed seeds.
+//!
+//! When DNS seeds are unreachable, Dash Core bootstraps from a list of fixed seed nodes compiled
+//! into `chainparamsseeds.h` from `contrib/seeds/nodes_main.txt` and `nodes_test.txt`. The
+//! lists are stored as concatenated BIP155 addresses, each followed by a big-endian port.
+//!
+//! This module decodes such lists with [`decode_seeds`], encodes them with [`encode_seeds`] and
+//! parses the text format with [`parse_seeds`], so that an updated list can be loaded at
+//! runtime. The text format has one address per line, optionally followed by `:port`, with `#`
+//! starting a comment. IPv6 addresses with a port are written in brackets. IPv4, IPv6, CJDNS
+//! (`fc00::/8`), Tor v3 (`.onion`) and I2P (`.b32.i2p`) addresses are supported; Tor addresses
+//! need the `tor` feature to verify their checksum.
+//!
+
+use core::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[cfg(feature = "tor")]
+use sha3::{Digest, Sha3_256};
+
+use crate::consensus::encode::{self, Decodable, Encodable};
+use crate::network::address::{AddrV2, AddrV2Message};
+use crate::network::constants::{Network, ServiceFlags};
+use crate::prelude::*;
+
+/// Fixed seeds of the main network.
+///
+/// Empty until generated from Dash Core's `nodes_main.txt` with [`encode_seeds`].
+pub const FIXED_SEEDS_MAIN: &[u8] = &[];
+
+/// Fixed seeds of the test network.
+///
+/// Empty until generated from Dash Core's `nodes_test.txt` with [`encode_seeds`].
+pub const FIXED_SEEDS_TEST: &[u8] = &[];
+
+/// How long before now seed addresses claim to have been seen, like Dash Core's one week.
+const SEED_AGE: u32 = 7 * 24 * 60 * 60;
+
+/// Error parsing a list of seeds.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SeedError {
+    /// The list could not be read.
+    Io(std::io::Error),
+    /// A line is not a valid address.
+    InvalidEntry {
+        /// The line number, starting at 1.
+        line: usize,
+        /// The offending entry.
+        entry: String,
+    },
+}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SeedError::Io(ref e) => write!(f, "cannot read seeds: {}", e),
+            SeedError::InvalidEntry { line, ref entry } =>
+                write!(f, "invalid seed {:?} on line {}", entry, line),
+        }
+    }
+}
+
+impl std::error::Error for SeedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            SeedError::Io(ref e) => Some(e),
+            SeedError::InvalidEntry { .. } => None,
+        }
+    }
+}
+
+/// Returns the compiled-in fixed seeds of `network`, as seen a week before `now`.
+pub fn fixed_seeds(network: Network, now: u32) -> Vec<AddrV2Message> {
+    let data = match network {
+        Network::Dash => FIXED_SEEDS_MAIN,
+        Network::Testnet => FIXED_SEEDS_TEST,
+        Network::Devnet | Network::Regtest => &[],
+    };
+    decode_seeds(data, now).expect("valid fixed seeds")
+}
+
+/// Decodes a list of BIP155 addresses and ports, as seen a week before `now`.
+///
+/// The addresses are assumed to offer [`ServiceFlags::NETWORK`], which is what we look for in
+/// a bootstrap node.
+pub fn decode_seeds(mut data: &[u8], now: u32) -> Result<Vec<AddrV2Message>, encode::Error> {
+    let mut seeds = Vec::new();
+    while !data.is_empty() {
+        let addr = AddrV2::consensus_decode(&mut data)?;
+        let port = u16::from_be_bytes(<[u8; 2]>::consensus_decode(&mut data)?);
+        seeds.push(AddrV2Message {
+            time: now.saturating_sub(SEED_AGE),
+            services: ServiceFlags::NETWORK,
+            addr,
+            port,
+        });
+    }
+    Ok(seeds)
+}
+
+/// Encodes the addresses and ports of `seeds` in the format read by [`decode_seeds`].
+pub fn encode_seeds(seeds: &[AddrV2Message]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for seed in seeds {
+        seed.addr.consensus_encode(&mut data).expect("in-memory writers don't error");
+        data.extend_from_slice(&seed.port.to_be_bytes());
+    }
+    data
+}
+
+/// Parses a `nodes_main.txt`-style list of seeds of `network`, as seen a week before `now`.
+///
+/// Entries without a port get the default port of `network`.
+pub fn parse_seeds(
+    text: &str,
+    network: Network,
+    now: u32,
+) -> Result<Vec<AddrV2Message>, SeedError> {
+    let mut seeds = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let (addr, port) = parse_seed(entry, network.default_port())
+            .ok_or_else(|| SeedError::InvalidEntry { line: i + 1, entry: entry.to_owned() })?;
+        seeds.push(AddrV2Message {
+            time: now.saturating_sub(SEED_AGE),
+            services: ServiceFlags::NETWORK,
+            addr,
+            port,
+        });
+    }
+    Ok(seeds)
+}
+
+/// Reads a list of seeds of `network` from a file, see [`parse_seeds`].
+pub fn load_seeds<P: AsRef<Path>>(
+    path: P,
+    network: Network,
+    now: u32,
+) -> Result<Vec<AddrV2Message>, SeedError> {
+    let text = std::fs::read_to_string(path).map_err(SeedError::Io)?;
+    parse_seeds(&text, network, now)
+}
+
+/// Parses a single seed entry.
+fn parse_seed(entry: &str, default_port: u16) -> Option<(AddrV2, u16)> {
+    // A bracketed IPv6 address, or anything with at most one colon, may be followed by a port.
+    let (host, port) = if let Some(rest) = entry.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':')?)),
+        }
+    } else {
+        match entry.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            _ => (entry, None),
+        }
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+
+    let addr = if let Some(name) = host.strip_suffix(".onion") {
+        parse_torv3(name)?
+    } else if let Some(name) = host.strip_suffix(".b32.i2p") {
+        let bytes = base32_decode(name)?;
+        AddrV2::I2p(bytes.try_into().ok()?)
+    } else {
+        match host.parse::<IpAddr>().ok()? {
+            IpAddr::V4(ip) => AddrV2::Ipv4(ip),
+            IpAddr::V6(ip) if ip.octets()[0] == 0xfc => AddrV2::Cjdns(ip),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => AddrV2::Ipv4(ip),
+                None => AddrV2::Ipv6(ip),
+            },
+        }
+    };
+    Some((addr, port))
+}
+
+/// Parses the name of a Tor v3 address, without `.onion`.
+#[cfg(feature = "tor")]
+fn parse_torv3(name: &str) -> Option<AddrV2> {
+    let bytes = base32_decode(name)?;
+    // A v3 address is the public key, a two byte checksum and the version 3.
+    if bytes.len() != 35 || bytes[34] != 3 || bytes[32..34] != torv3_checksum(&bytes[..32]) {
+        return None;
+    }
+    Some(AddrV2::TorV3(bytes[..32].try_into().expect("32 bytes")))
+}
+
+/// Tor addresses are rejected since their checksum can't be verified without the `tor` feature.
+#[cfg(not(feature = "tor"))]
+fn parse_torv3(_name: &str) -> Option<AddrV2> { None }
+
+/// Computes the checksum of a Tor v3 address: the first two bytes of
+/// `SHA3-256(".onion checksum" || pubkey || version)`.
+#[cfg(feature = "tor")]
+fn torv3_checksum(pubkey: &[u8]) -> [u8; 2] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b".onion checksum");
+    hasher.update(pubkey);
+    hasher.update([3]);
+    let hash = hasher.finalize();
+    [hash[0], hash[1]]
+}
+
+/// Decodes unpadded lowercase RFC 4648 base32, as used by Tor and I2P names.
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = match c {
+            b'a'..=b'z' => c - b'a',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | u32::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // Leftover bits must be zero padding.
+    if buffer != 0 {
+        return None;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn ipv6(s: &str) -> Ipv6Addr { s.parse().unwrap() }
+
+    #[test]
+    fn compiled_in_seeds_decode() {
+        for network in [Network::Dash, Network::Testnet, Network::Devnet, Network::Regtest] {
+            for seed in fixed_seeds(network, NOW) {
+                assert_eq!(seed.time, NOW - SEED_AGE);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_nodes_file() {
+        let text = "\
+# Dash seeds
+1.2.3.4
+5.6.7.8:19999   # with port
+[2a01:4f8::1]:9998
+2a01:4f8::2
+fc32:17ea:e415:c3bf:9808:149d:b5a2:c9aa
+ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p:0
+";
+        let seeds = parse_seeds(text, Network::Dash, NOW).unwrap();
+        let parsed: Vec<(AddrV2, u16)> = seeds.iter().map(|s| (s.addr.clone(), s.port)).collect();
+        assert_eq!(parsed[0], (AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)), 9999));
+        assert_eq!(parsed[1], (AddrV2::Ipv4(Ipv4Addr::new(5, 6, 7, 8)), 19999));
+        assert_eq!(parsed[2], (AddrV2::Ipv6(ipv6("2a01:4f8::1")), 9998));
+        assert_eq!(parsed[3], (AddrV2::Ipv6(ipv6("2a01:4f8::2")), 9999));
+        assert_eq!(
+            parsed[4],
+            (AddrV2::Cjdns(ipv6("fc32:17ea:e415:c3bf:9808:149d:b5a2:c9aa")), 9999)
+        );
+        match parsed[5] {
+            (AddrV2::I2p(hash), 0) => assert_eq!(hash[..4], [0xa2, 0x89, 0x4d, 0xab]),
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert!(seeds.iter().all(|s| s.time == NOW - SEED_AGE));
+
+        // The default port follows the network.
+        let testnet = parse_seeds("1.2.3.4", Network::Testnet, NOW).unwrap();
+        assert_eq!(testnet[0].port, 19999);
+
+        // Round trip through the compiled-in format.
+        assert_eq!(decode_seeds(&encode_seeds(&seeds), NOW).unwrap(), seeds);
+    }
+
+    #[test]
+    #[cfg(feature = "tor")]
+    fn parse_onion() {
+        let text = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+        let seeds = parse_seeds(text, Network::Dash, NOW).unwrap();
+        match (&seeds[0].addr, seeds[0].port) {
+            (AddrV2::TorV3(key), 9999) => assert_eq!(key[..4], [0x79, 0xbc, 0xc6, 0x25]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(decode_seeds(&encode_seeds(&seeds), NOW).unwrap(), seeds);
+    }
+
+    #[test]
+    fn invalid_entries() {
+        for entry in [
+            "1.2.3",
+            "1.2.3.4:99999",
+            "[2a01:4f8::1",
+            "[2a01:4f8::1]9999",
+            "example.com",
+            #[cfg(not(feature = "tor"))]
+            "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion",
+            // Version 2 instead of 3.
+            "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryc.onion",
+            // A different public key under the same checksum.
+            "qg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion",
+            "short.onion",
+            "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkd.b32.i2p",
+        ] {
+            let text = format!("1.2.3.4\n{}\n", entry);
+            match parse_seeds(&text, Network::Dash, NOW) {
+                Err(SeedError::InvalidEntry { line: 2, entry: e }) => assert_eq!(e, entry),
+                other => panic!("{} parsed as {:?}", entry, other),
+            }
+        }
+        assert!(decode_seeds(&[1, 4, 1, 2, 3, 4, 0x27], NOW).is_err());
+    }
+}
@@ -0,0 +1,537 @@
+// Rust Dash Library
+// Written for Dash in 2024 by
+//     The Dash Core Developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Wallet rescans with compact block filters.
+//!
+//! A [`WalletScanner`] watches scripts, either added one by one or derived from a
+//! [`ScriptProvider`] with a gap limit, and finds the blocks relevant to them by matching BIP158
+//! filters with [`WalletScanner::match_filter`]. The matching blocks are then downloaded and
+//! passed to [`WalletScanner::process_block`], which extracts the relevant transactions, tracks
+//! the wallet's unspent outputs and derives more scripts as the used ones approach the end of
+//! the gap limit window.
+//!
+//! Dash Core's filters also contain masternode and asset lock data (see
+//! [`BlockFilterWriter::add_special_transaction_elements`]), so payout script, owner and voting
+//! key hash and credit output matches are found as well.
+//!
+//! Blocks must be processed in order, and as soon as a block derives new scripts, the filters
+//! of the following blocks must be matched again, since they may pay to the new scripts.
+//!
+//! [`BlockFilterWriter::add_special_transaction_elements`]:
+//!     crate::bip158::BlockFilterWriter::add_special_transaction_elements
+//!
+
+use core::fmt;
+
+use hashes::Hash;
+
+use crate::Transaction;
+use crate::bip158::{self, BlockFilter};
+use crate::blockdata::block::Block;
+use crate::blockdata::script::{Script, ScriptBuf};
+use crate::blockdata::transaction::OutPoint;
+use crate::blockdata::transaction::special_transaction::TransactionPayload;
+use crate::blockdata::transaction::txout::TxOut;
+use crate::consensus::encode::serialize;
+use crate::hash_types::{BlockHash, PubkeyHash, Txid};
+use crate::prelude::*;
+
+/// Derives the scripts of a chain of addresses, such as the external or the change chain of a
+/// BIP44 account.
+pub trait ScriptProvider {
+    /// Returns the script at `index` of the chain.
+    fn script(&self, index: u32) -> ScriptBuf;
+}
+
+impl<F: Fn(u32) -> ScriptBuf> ScriptProvider for F {
+    fn script(&self, index: u32) -> ScriptBuf { self(index) }
+}
+
+/// Where a watched script comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptSource {
+    /// Added with [`WalletScanner::watch_script`].
+    Imported,
+    /// Derived from a chain added with [`WalletScanner::add_chain`].
+    Derived {
+        /// The index of the chain, in the order chains were added.
+        chain: usize,
+        /// The index of the script in the chain.
+        index: u32,
+    },
+}
+
+/// An unspent output of the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletOutput {
+    /// The outpoint.
+    pub outpoint: OutPoint,
+    /// The output.
+    pub txout: TxOut,
+    /// The height of the block of the transaction.
+    pub height: u32,
+}
+
+/// What [`WalletScanner::process_block`] found in a block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockScan {
+    /// The relevant transactions, in block order.
+    pub transactions: Vec<Transaction>,
+    /// The outputs paying to watched scripts.
+    pub received: Vec<WalletOutput>,
+    /// The wallet outputs spent by the block.
+    pub spent: Vec<WalletOutput>,
+    /// Whether new scripts were derived, so that the filters of the following blocks must be
+    /// matched again.
+    pub derived_scripts: bool,
+}
+
+/// A chain of derived scripts.
+struct Chain {
+    provider: Box<dyn ScriptProvider>,
+    gap_limit: u32,
+    /// The number of derived scripts.
+    derived: u32,
+    last_used: Option<u32>,
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Chain")
+            .field("gap_limit", &self.gap_limit)
+            .field("derived", &self.derived)
+            .field("last_used", &self.last_used)
+            .finish()
+    }
+}
+
+/// Finds the transactions of a wallet with compact block filters.
+#[derive(Debug, Default)]
+pub struct WalletScanner {
+    scripts: BTreeMap<ScriptBuf, ScriptSource>,
+    key_hashes: BTreeSet<PubkeyHash>,
+    chains: Vec<Chain>,
+    utxos: BTreeMap<OutPoint, WalletOutput>,
+    /// The registrations of masternodes related to the wallet, whose updates are relevant.
+    pro_tx_hashes: BTreeSet<Txid>,
+}
+
+impl WalletScanner {
+    /// Creates a scanner watching nothing.
+    pub fn new() -> WalletScanner { WalletScanner::default() }
+
+    /// Watches a script.
+    pub fn watch_script(&mut self, script: ScriptBuf) {
+        self.scripts.entry(script).or_insert(ScriptSource::Imported);
+    }
+
+    /// Watches a key hash used as masternode owner or voting key.
+    pub fn watch_key_hash(&mut self, key_hash: PubkeyHash) { self.key_hashes.insert(key_hash); }
+
+    /// Watches the scripts of a chain, keeping `gap_limit` unused scripts after the last used
+    /// one. Returns the index of the chain.
+    pub fn add_chain<P: ScriptProvider + 'static>(&mut self, provider: P, gap_limit: u32) -> usize {
+        self.chains.push(Chain {
+            provider: Box::new(provider),
+            gap_limit,
+            derived: 0,
+            last_used: None,
+        });
+        let chain = self.chains.len() - 1;
+        self.derive(chain);
+        chain
+    }
+
+    /// Derives the scripts of `chain` up to the gap limit; returns whether any were derived.
+    fn derive(&mut self, chain: usize) -> bool {
+        let Chain { ref provider, gap_limit, ref mut derived, last_used } = self.chains[chain];
+        let target = last_used.map_or(0, |used| used + 1).saturating_add(gap_limit);
+        let start = *derived;
+        while *derived < target {
+            let source = ScriptSource::Derived { chain, index: *derived };
+            self.scripts.entry(provider.script(*derived)).or_insert(source);
+            *derived += 1;
+        }
+        *derived > start
+    }
+
+    /// Marks a script as used; returns whether new scripts were derived.
+    fn mark_used(&mut self, script: &Script) -> bool {
+        match self.scripts.get(script) {
+            Some(&ScriptSource::Derived { chain, index }) => {
+                let last_used = &mut self.chains[chain].last_used;
+                if last_used.map_or(true, |used| index > used) {
+                    *last_used = Some(index);
+                }
+                self.derive(chain)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the source of a watched script.
+    pub fn script_source(&self, script: &Script) -> Option<ScriptSource> {
+        self.scripts.get(script).copied()
+    }
+
+    /// Returns the number of watched scripts.
+    pub fn script_count(&self) -> usize { self.scripts.len() }
+
+    /// Returns the index of the last used script of `chain`.
+    pub fn last_used(&self, chain: usize) -> Option<u32> {
+        self.chains.get(chain).and_then(|chain| chain.last_used)
+    }
+
+    /// Returns the unspent outputs of the wallet.
+    pub fn utxos(&self) -> impl Iterator<Item = &WalletOutput> { self.utxos.values() }
+
+    /// Returns the sum of the unspent outputs, in duffs.
+    pub fn balance(&self) -> u64 { self.utxos.values().map(|utxo| utxo.txout.value).sum() }
+
+    /// Returns whether `filter` of the block `block_hash` matches anything watched.
+    ///
+    /// Unspent outputs are matched by their serialized outpoint, which filters contain for
+    /// ProRegTx collaterals.
+    pub fn match_filter(
+        &self,
+        block_hash: &BlockHash,
+        filter: &BlockFilter,
+    ) -> Result<bool, bip158::Error> {
+        let outpoints: Vec<Vec<u8>> = self.utxos.keys().map(serialize).collect();
+        let scripts = self.scripts.keys().map(|script| script.as_bytes());
+        let key_hashes = self.key_hashes.iter().map(|hash| hash.as_byte_array().as_slice());
+        let pro_tx_hashes = self.pro_tx_hashes.iter().map(|hash| hash.as_byte_array().as_slice());
+        let outpoints = outpoints.iter().map(Vec::as_slice);
+        filter
+            .match_any(block_hash, scripts.chain(key_hashes).chain(pro_tx_hashes).chain(outpoints))
+    }
+
+    /// Returns the hashes of the blocks whose filters match anything watched.
+    ///
+    /// Since processing a block may derive new scripts, prefer matching filters one at a time
+    /// with [`match_filter`](WalletScanner::match_filter) while processing blocks.
+    pub fn scan_filters<'a, I>(&self, filters: I) -> Result<Vec<BlockHash>, bip158::Error>
+    where
+        I: IntoIterator<Item = (BlockHash, &'a BlockFilter)>,
+    {
+        let mut matches = Vec::new();
+        for (block_hash, filter) in filters {
+            if self.match_filter(&block_hash, filter)? {
+                matches.push(block_hash);
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Extracts the relevant transactions of the block at `height` and updates the wallet.
+    ///
+    /// Scripts derived while processing the block may be paid by earlier transactions of the
+    /// block, so it is processed again until no more scripts are derived.
+    pub fn process_block(&mut self, block: &Block, height: u32) -> BlockScan {
+        let mut scan = BlockScan::default();
+        let mut relevant = vec![false; block.txdata.len()];
+        let mut derived_scripts = false;
+        loop {
+            scan.derived_scripts = false;
+            for (tx, relevant) in block.txdata.iter().zip(&mut relevant) {
+                *relevant |= self.process_transaction(tx, height, &mut scan);
+            }
+            if !scan.derived_scripts {
+                break;
+            }
+            derived_scripts = true;
+        }
+        scan.derived_scripts = derived_scripts;
+        scan.transactions = block
+            .txdata
+            .iter()
+            .zip(relevant)
+            .filter(|&(_, relevant)| relevant)
+            .map(|(tx, _)| tx.clone())
+            .collect();
+        scan
+    }
+
+    /// Updates the wallet with `tx`; returns whether it is relevant.
+    ///
+    /// Processing a transaction again only adds what newly derived scripts match.
+    fn process_transaction(&mut self, tx: &Transaction, height: u32, scan: &mut BlockScan) -> bool {
+        let txid = tx.txid();
+        let mut relevant = false;
+
+        if !tx.is_coin_base() {
+            for input in &tx.input {
+                if let Some(spent) = self.utxos.remove(&input.previous_output) {
+                    scan.spent.push(spent);
+                    relevant = true;
+                }
+            }
+        }
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            if self.scripts.contains_key(&output.script_pubkey) {
+                relevant = true;
+                let outpoint = OutPoint { txid, vout: vout as u32 };
+                if scan.received.iter().any(|received| received.outpoint == outpoint) {
+                    continue;
+                }
+                scan.derived_scripts |= self.mark_used(&output.script_pubkey);
+                let received = WalletOutput { outpoint, txout: output.clone(), height };
+                self.utxos.insert(received.outpoint, received.clone());
+                scan.received.push(received);
+            }
+        }
+
+        relevant | self.process_special_payload(tx, scan)
+    }
+
+    /// Matches the masternode and asset lock data of `tx`; returns whether it is relevant.
+    fn process_special_payload(&mut self, tx: &Transaction, scan: &mut BlockScan) -> bool {
+        let mut watched = |scanner: &mut WalletScanner, script: &Script| {
+            let found = scanner.scripts.contains_key(script);
+            if found {
+                scan.derived_scripts |= scanner.mark_used(script);
+            }
+            found
+        };
+        match tx.special_transaction_payload {
+            Some(TransactionPayload::ProviderRegistrationPayloadType(ref payload)) => {
+                let relevant = self.key_hashes.contains(&payload.owner_key_hash)
+                    | self.key_hashes.contains(&payload.voting_key_hash)
+                    | self.utxos.contains_key(&payload.collateral_outpoint)
+                    | watched(self, &payload.script_payout);
+                if relevant {
+                    self.pro_tx_hashes.insert(tx.txid());
+                }
+                relevant
+            }
+            Some(TransactionPayload::ProviderUpdateServicePayloadType(ref payload)) =>
+                self.pro_tx_hashes.contains(&payload.pro_tx_hash)
+                    | watched(self, &payload.script_payout),
+            Some(TransactionPayload::ProviderUpdateRegistrarPayloadType(ref payload)) =>
+                self.pro_tx_hashes.contains(&payload.pro_tx_hash)
+                    | self.key_hashes.contains(&payload.voting_key_hash)
+                    | watched(self, &payload.script_payout),
+            Some(TransactionPayload::ProviderUpdateRevocationPayloadType(ref payload)) =>
+                self.pro_tx_hashes.contains(&payload.pro_tx_hash),
+            Some(TransactionPayload::AssetLockPayloadType(ref payload)) => payload
+                .credit_outputs
+                .iter()
+                .fold(false, |relevant, output| watched(self, &output.script_pubkey) | relevant),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockdata::block::{Header, Version};
+    use crate::blockdata::transaction::special_transaction::asset_lock::AssetLockPayload;
+    use crate::blockdata::transaction::special_transaction::provider_registration::ProviderRegistrationPayload;
+    use crate::blockdata::transaction::special_transaction::provider_update_service::ProviderUpdateServicePayload;
+    use crate::blockdata::transaction::txin::TxIn;
+    use crate::bls_sig_utils::{BLSPublicKey, BLSSignature};
+    use crate::hash_types::{InputsHash, TxMerkleNode};
+    use crate::pow::CompactTarget;
+
+    fn script(chain: u8, index: u32) -> ScriptBuf {
+        let mut hash = [chain; 20];
+        hash[..4].copy_from_slice(&index.to_le_bytes());
+        ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(hash))
+    }
+
+    fn tx(input: Vec<OutPoint>, output: Vec<ScriptBuf>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: input
+                .into_iter()
+                .map(|previous_output| TxIn { previous_output, ..Default::default() })
+                .collect(),
+            output: output
+                .into_iter()
+                .map(|script_pubkey| TxOut { value: 1_000, script_pubkey })
+                .collect(),
+            special_transaction_payload: None,
+        }
+    }
+
+    fn block(nonce: u32, txdata: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1,
+                bits: CompactTarget::from_consensus(2),
+                nonce,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    fn coinbase() -> Transaction { tx(vec![OutPoint::null()], vec![script(0, 0)]) }
+
+    fn filter(block: &Block) -> BlockFilter {
+        BlockFilter::new_basic_filter(block, |_| Ok(ScriptBuf::new())).unwrap()
+    }
+
+    #[test]
+    fn gap_limit() {
+        let mut scanner = WalletScanner::new();
+        let external = scanner.add_chain(|index| script(1, index), 5);
+        let change = scanner.add_chain(|index| script(2, index), 2);
+        assert_eq!(scanner.script_count(), 7);
+        assert_eq!(
+            scanner.script_source(&script(1, 4)),
+            Some(ScriptSource::Derived { chain: external, index: 4 })
+        );
+        assert_eq!(scanner.script_source(&script(1, 5)), None);
+
+        // Paying to the last script of the window derives five more, which are found in the
+        // same block.
+        let funding = tx(vec![], vec![script(1, 4)]);
+        let next = tx(vec![], vec![script(1, 9), script(2, 0)]);
+        let scan = scanner.process_block(&block(1, vec![coinbase(), funding, next]), 10);
+        assert!(scan.derived_scripts);
+        assert_eq!(scan.transactions.len(), 2);
+        assert_eq!(scan.received.len(), 3);
+        assert_eq!(scanner.last_used(external), Some(9));
+        assert_eq!(scanner.last_used(change), Some(0));
+        assert_eq!(scanner.script_count(), 15 + 3);
+        assert_eq!(scanner.balance(), 3_000);
+
+        // The same in reverse order: the payment to index 9 and its spend are found once index 4
+        // derived it.
+        let mut scanner = WalletScanner::new();
+        let external = scanner.add_chain(|index| script(1, index), 5);
+        let next = tx(vec![], vec![script(1, 9)]);
+        let spend = tx(vec![OutPoint { txid: next.txid(), vout: 0 }], vec![script(3, 0)]);
+        let funding = tx(vec![], vec![script(1, 4)]);
+        let scan = scanner.process_block(&block(2, vec![coinbase(), next, spend, funding]), 10);
+        assert!(scan.derived_scripts);
+        assert_eq!(scan.transactions.len(), 3);
+        assert_eq!(scan.received.len(), 2);
+        assert_eq!(scan.spent.len(), 1);
+        assert_eq!(scanner.last_used(external), Some(9));
+        assert_eq!(scanner.script_count(), 15);
+        assert_eq!(scanner.balance(), 1_000);
+    }
+
+    #[test]
+    fn filters_and_spends() {
+        let mut scanner = WalletScanner::new();
+        scanner.add_chain(|index| script(1, index), 2);
+        scanner.watch_script(script(3, 0));
+        assert_eq!(scanner.script_source(&script(3, 0)), Some(ScriptSource::Imported));
+
+        let funding = tx(vec![], vec![script(3, 0), script(9, 0)]);
+        let funding_block = block(1, vec![coinbase(), funding.clone()]);
+        let spend = tx(vec![OutPoint { txid: funding.txid(), vout: 0 }], vec![script(9, 1)]);
+        let spend_block = block(2, vec![coinbase(), spend]);
+        let other_block = block(3, vec![coinbase(), tx(vec![], vec![script(9, 2)])]);
+
+        let filters =
+            [&funding_block, &other_block].map(|block| (block.block_hash(), filter(block)));
+        let matches = scanner.scan_filters(filters.iter().map(|(hash, f)| (*hash, f))).unwrap();
+        assert_eq!(matches, vec![funding_block.block_hash()]);
+
+        let scan = scanner.process_block(&funding_block, 1);
+        assert!(!scan.derived_scripts);
+        assert_eq!(scan.received[0].outpoint, OutPoint { txid: funding.txid(), vout: 0 });
+        assert_eq!(scanner.utxos().count(), 1);
+
+        // The spent script is in the filter of the spending block.
+        let spend_filter =
+            BlockFilter::new_basic_filter(&spend_block, |_| Ok(script(3, 0))).unwrap();
+        assert!(scanner.match_filter(&spend_block.block_hash(), &spend_filter).unwrap());
+        let scan = scanner.process_block(&spend_block, 2);
+        assert_eq!(scan.transactions.len(), 1);
+        assert_eq!(scan.spent.len(), 1);
+        assert!(scan.received.is_empty());
+        assert_eq!(scanner.balance(), 0);
+    }
+
+    #[test]
+    fn special_transactions() {
+        let mut scanner = WalletScanner::new();
+        scanner.add_chain(|index| script(1, index), 2);
+
+        let mut asset_lock = tx(vec![], vec![]);
+        asset_lock.special_transaction_payload =
+            Some(TransactionPayload::AssetLockPayloadType(AssetLockPayload {
+                version: 1,
+                credit_outputs: vec![TxOut { value: 1_000, script_pubkey: script(1, 1) }],
+            }));
+        let mut update_service = tx(vec![], vec![]);
+        update_service.special_transaction_payload = Some(
+            TransactionPayload::ProviderUpdateServicePayloadType(ProviderUpdateServicePayload {
+                version: 1,
+                pro_tx_hash: Txid::all_zeros(),
+                ip_address: 0,
+                port: 9999,
+                script_payout: script(1, 0),
+                inputs_hash: InputsHash::all_zeros(),
+                payload_sig: BLSSignature::from([0; 96]),
+            }),
+        );
+        let block = block(1, vec![coinbase(), asset_lock, update_service]);
+
+        assert!(scanner.match_filter(&block.block_hash(), &filter(&block)).unwrap());
+        let scan = scanner.process_block(&block, 1);
+        assert_eq!(scan.transactions.len(), 2);
+        assert!(scan.received.is_empty());
+        assert!(scan.derived_scripts);
+        assert_eq!(scanner.last_used(0), Some(1));
+    }
+
+    #[test]
+    fn external_collateral() {
+        let mut scanner = WalletScanner::new();
+        scanner.add_chain(|index| script(1, index), 2);
+        let funding = tx(vec![], vec![script(1, 0)]);
+        scanner.process_block(&block(1, vec![coinbase(), funding.clone()]), 1);
+        let collateral = OutPoint { txid: funding.txid(), vout: 0 };
+
+        // A ProRegTx referencing our output as collateral, with no other data of ours.
+        let mut registration = tx(vec![OutPoint { txid: Txid::all_zeros(), vout: 0 }], vec![]);
+        registration.special_transaction_payload = Some(
+            TransactionPayload::ProviderRegistrationPayloadType(ProviderRegistrationPayload {
+                version: 1,
+                provider_type: 0,
+                provider_mode: 0,
+                collateral_outpoint: collateral,
+                ip_address: 0,
+                port: 9999,
+                owner_key_hash: PubkeyHash::from_byte_array([7; 20]),
+                operator_public_key: BLSPublicKey::from([0; 48]),
+                voting_key_hash: PubkeyHash::from_byte_array([7; 20]),
+                operator_reward: 0,
+                script_payout: script(9, 0),
+                inputs_hash: InputsHash::all_zeros(),
+                payload_sig: vec![],
+            }),
+        );
+        let block = block(2, vec![coinbase(), registration.clone()]);
+
+        assert!(scanner.match_filter(&block.block_hash(), &filter(&block)).unwrap());
+        let scan = scanner.process_block(&block, 2);
+        assert_eq!(scan.transactions, vec![registration.clone()]);
+        assert!(scanner.utxos().any(|utxo| utxo.outpoint == collateral));
+        assert!(scanner.pro_tx_hashes.contains(&registration.txid()));
+    }
+}